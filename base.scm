@@ -3,7 +3,7 @@
 
 (define p (make-soft-port
            (vector
-            (lambda (c) (rs-send-str c))
+            (lambda (c) (rs-send-str (string c)))
             (lambda (s) (rs-send-str s))
             (lambda () (#f))
             (lambda () (#f))
@@ -18,6 +18,99 @@
 (define (char-after) (rs-char-after (curr-buf) (curr-pos)))
 (define (insert-str s) (rs-insert-str (curr-buf) (curr-pos) s))
 
+;; highlight text between two offsets with a background color like "yellow", returns an id for
+;; del-extmark. The highlight moves along as the buffer is edited.
+(define* (set-extmark start end color #:optional (buf (curr-buf)))
+  (rs-set-extmark buf start end color))
+(define* (set-virt-text off text #:optional (buf (curr-buf)))
+  (rs-set-virt-text buf off text))
+(define* (del-extmark id #:optional (buf (curr-buf))) (rs-del-extmark buf id))
+(define* (buf-id #:optional (buf (curr-buf))) (rs-buf-id buf))
+;; #f once the buffer is closed
+(define (buf-by-id id) (rs-buf-by-id id))
+;; alist of the id and name of every open buffer
+(define (list-buffers) (rs-list-buffers))
+;; alist of x, y, width and height of the focused window
+(define (window-geometry) (rs-window-geometry))
+;; the windows as (row first second) for side by side or (col first second) for one above the
+;; other, with (leaf "path") for a window on a file. set-layout! also takes rows and columns of more
+;; than two and lays out the windows like that once the current command is done, for example
+;; (set-layout! '(row (leaf "src/main.rs") (col (leaf "notes.md") (leaf "todo.md"))))
+(define (window-layout) (rs-window-layout))
+(define (set-layout! layout) (rs-set-layout! layout))
+
+;; cursor of the focused window as (line . col), counting from 0
+(define (cursor) (rs-cursor))
+(define (set-cursor line col) (rs-set-cursor line col))
+;; first line shown in the focused window and how many lines it shows
+(define (topline) (rs-topline))
+(define (win-height) (rs-win-height))
+;; scroll down by n lines, or up if n is negative
+(define (scroll n) (rs-scroll n))
+
+(define* (start-job cmd on-line #:optional (on-exit (lambda (code) #f)))
+  (rs-start-job cmd on-line on-exit))
+(define (kill-job id) (rs-kill-job id))
+
+;; keys run in normal mode after the current command finishes
+(define (normal keys) (rs-normal keys))
+;; end the current undo step, so that u stops here
+(define (undo-break) (rs-undo-break))
+
+;; Only these, and showing messages with display, are safe from threads made with
+;; call-with-new-thread. They're done by the main loop once it gets to them.
+(define (post-insert id off str) (rs-post-insert id off str))
+(define (post-quit) (rs-post-quit))
+(define (post-call thunk) (rs-post-call thunk))
+
+;; define the ex command :name, which must start with an uppercase letter. proc gets the arguments
+;; as a string, complete gets the argument being typed and returns a list of candidates.
+(define* (define-command name proc #:optional (complete #f))
+  (rs-define-command name proc complete))
+
+;; highlight what rules, an alist of regexes and highlight groups like "Comment" or "Keyword",
+;; match in buffers of filetype. A filetype the editor doesn't know is the file's extension:
+;; (define-highlighting "zig" '(("//.*" . "Comment") ("\\b(fn|const|var)\\b" . "Keyword")))
+(define (define-highlighting filetype rules)
+  (for-each (lambda (rule) (rs-define-syntax filetype (car rule) (cdr rule))) rules))
+
+;; Commands from the command line run through eval-suspendable, so they can stop and wait for the
+;; user with prompt-input. The rest of the command is resumed once the prompt is answered.
+(define suspend-tag (make-prompt-tag "suspend"))
+(define (suspendable thunk)
+  (call-with-prompt suspend-tag
+    thunk
+    (lambda (k prompt)
+      (rs-read-string prompt (lambda (s) (suspendable (lambda () (k s)))))
+      *unspecified*)))
+(define (eval-suspendable expr env)
+  (suspendable (lambda () (eval expr env))))
+
+;; the line typed after prompt, or #f if cancelled. Only works in commands run from the command
+;; line, use rs-read-string with a callback elsewhere.
+(define (prompt-input prompt) (abort-to-prompt suspend-tag prompt))
+
+
+;; Files loaded by :source with 'secure' set run in a module of their own, made by (ice-9 sandbox).
+;; It has the pure procedures of Guile and the editor's procedures that stay inside the editor:
+;; nothing that opens files, runs processes or jobs, types keys (which could run :!), or loads
+;; other modules, since those could bring the rest back.
+(use-modules (ice-9 sandbox))
+(define plugin-bindings
+  (append all-pure-and-impure-bindings
+          '(((guile) display write newline)
+            ((guile-user)
+             curr-buf curr-pos char-after insert-str set-extmark set-virt-text del-extmark buf-id
+             buf-by-id list-buffers window-geometry window-layout cursor set-cursor topline win-height
+             scroll undo-break define-command define-highlighting prompt-input))))
+(define (load-sandboxed path)
+  (let ((module (make-sandbox-module plugin-bindings)))
+    (call-with-input-file path
+      (lambda (port)
+        (let loop ((form (read port)))
+          (unless (eof-object? form)
+            (eval form module)
+            (loop (read port))))))))
 
 (define (lorem-ipsum) "Lorem ipsum dolor sit amet, consectetur ...")
 
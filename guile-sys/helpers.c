@@ -23,3 +23,7 @@ guile_sys_sizeof_scm(void)
 SCM guile_sys_unspecified(void) {
 	return SCM_UNSPECIFIED;
 }
+
+SCM guile_sys_eol(void) {
+	return SCM_EOL;
+}
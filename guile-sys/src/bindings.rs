@@ -14,3 +14,4 @@ unsafe impl Sync for SCM {}
 pub const SCM_BOOL_T: SCM = SCM(0x404 as _);
 pub const SCM_BOOL_F: SCM = SCM(0x4 as _);
 pub const SCM_UNSPECIFIED: SCM = SCM(0x804 as _);
+pub const SCM_EOL: SCM = SCM(0x304 as _);
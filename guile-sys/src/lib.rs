@@ -30,5 +30,7 @@ mod test {
     fn consts_correct() {
         let ty = unsafe { guile_sys_unspecified() };
         assert_eq!(ty, SCM_UNSPECIFIED);
+        let eol = unsafe { guile_sys_eol() };
+        assert_eq!(eol, SCM_EOL);
     }
 }
@@ -3,5 +3,6 @@
 SCM guile_sys_bool_true(void);
 SCM guile_sys_bool_false(void);
 SCM guile_sys_unspecified(void);
+SCM guile_sys_eol(void);
 
 int guile_sys_sizeof_scm(void);
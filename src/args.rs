@@ -0,0 +1,174 @@
+//! Command line arguments.
+use std::path::PathBuf;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum FileArg {
+    Path(PathBuf),
+    /// `-`, read the buffer from standard input
+    Stdin,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Args {
+    pub file: Option<FileArg>,
+    /// `-R`, don't allow changes
+    pub readonly: bool,
+    /// `--pager`, read-only with keys for paging like `less`
+    pub pager: bool,
+    /// `--startuptime FILE`, write how long startup took to FILE
+    pub startuptime: Option<PathBuf>,
+    /// `--headless`, run [`Args::commands`] without a UI and exit
+    pub headless: bool,
+    /// ex commands to run after loading the file, from `-c CMD` and `-S SCRIPT`
+    pub commands: Vec<String>,
+    /// `--listen SOCKET`, run ex commands sent to SOCKET
+    pub listen: Option<PathBuf>,
+    /// `--server SOCKET`, the editor [`Args::remote_send`] sends to
+    pub server: Option<PathBuf>,
+    /// `--remote-send CMD`, send CMD to a running editor instead of starting one
+    pub remote_send: Vec<String>,
+}
+
+pub const USAGE: &str = "usage: edit [-R] [--pager] [--headless] [-c CMD]... [-S SCRIPT]... \
+[--startuptime FILE] [--listen SOCKET] [file | -]
+       edit --server SOCKET --remote-send CMD...";
+
+#[derive(Debug)]
+pub struct ArgError(String);
+
+impl std::fmt::Display for ArgError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}\n{USAGE}", self.0)
+    }
+}
+
+impl std::error::Error for ArgError {}
+
+/// parse arguments, not including the program name
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, ArgError> {
+    let mut out = Args::default();
+    let mut only_files = false;
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let file = match arg.as_str() {
+            "--" if !only_files => {
+                only_files = true;
+                continue;
+            }
+            "-" if !only_files => FileArg::Stdin,
+            "-R" if !only_files => {
+                out.readonly = true;
+                continue;
+            }
+            "--pager" if !only_files => {
+                out.pager = true;
+                out.readonly = true;
+                continue;
+            }
+            "--startuptime" if !only_files => {
+                let file = args.next().ok_or_else(|| ArgError("--startuptime needs a file".into()))?;
+                out.startuptime = Some(file.into());
+                continue;
+            }
+            "--listen" if !only_files => {
+                let socket = args.next().ok_or_else(|| ArgError("--listen needs a socket".into()))?;
+                out.listen = Some(socket.into());
+                continue;
+            }
+            "--server" if !only_files => {
+                let socket = args.next().ok_or_else(|| ArgError("--server needs a socket".into()))?;
+                out.server = Some(socket.into());
+                continue;
+            }
+            "--remote-send" if !only_files => {
+                let cmd = args.next().ok_or_else(|| ArgError("--remote-send needs a command".into()))?;
+                out.remote_send.push(cmd);
+                continue;
+            }
+            "--headless" if !only_files => {
+                out.headless = true;
+                continue;
+            }
+            "-c" if !only_files => {
+                let cmd = args.next().ok_or_else(|| ArgError("-c needs a command".into()))?;
+                out.commands.push(cmd);
+                continue;
+            }
+            "-S" if !only_files => {
+                let script = args.next().ok_or_else(|| ArgError("-S needs a script".into()))?;
+                out.commands.push(format!("source {script}"));
+                continue;
+            }
+            flag if flag.starts_with('-') && !only_files => {
+                return Err(ArgError(format!("unknown option: {flag}")));
+            }
+            path => FileArg::Path(path.into()),
+        };
+        if out.file.is_some() {
+            return Err(ArgError("only one file can be opened".into()));
+        }
+        out.file = Some(file);
+    }
+    if !out.remote_send.is_empty() && out.server.is_none() {
+        return Err(ArgError("--remote-send needs --server".into()));
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(s: &str) -> Result<Args, ArgError> {
+        parse(s.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn files() {
+        assert_eq!(args("").unwrap().file, None);
+        assert_eq!(args("-").unwrap().file, Some(FileArg::Stdin));
+        assert_eq!(args("a.txt").unwrap().file, Some(FileArg::Path("a.txt".into())));
+        assert_eq!(args("-- -").unwrap().file, Some(FileArg::Path("-".into())));
+        assert!(args("a b").is_err());
+        assert!(args("--bogus").is_err());
+    }
+
+    #[test]
+    fn pager() {
+        let a = args("--pager -").unwrap();
+        assert!(a.pager && a.readonly);
+        assert_eq!(a.file, Some(FileArg::Stdin));
+        let a = args("-R file").unwrap();
+        assert!(a.readonly && !a.pager);
+    }
+
+    #[test]
+    fn option_with_value() {
+        let a = args("--startuptime times.log file").unwrap();
+        assert_eq!(a.startuptime, Some("times.log".into()));
+        assert_eq!(a.file, Some(FileArg::Path("file".into())));
+        assert!(args("--startuptime").is_err());
+    }
+
+    #[test]
+    fn headless_commands() {
+        let a = ["--headless", "-c", "set ro", "-S", "my script.scm", "-c", "w", "file"];
+        let a = parse(a.map(String::from)).unwrap();
+        assert!(a.headless);
+        assert_eq!(a.commands, ["set ro", "source my script.scm", "w"]);
+        assert_eq!(a.file, Some(FileArg::Path("file".into())));
+        assert!(args("-c").is_err());
+    }
+
+    #[test]
+    fn remote() {
+        let a = parse(["--listen", "/tmp/edit.sock", "file"].map(String::from)).unwrap();
+        assert_eq!(a.listen, Some("/tmp/edit.sock".into()));
+        let a = ["--server", "/tmp/edit.sock", "--remote-send", "e /tmp/a", "--remote-send", "12"];
+        let a = parse(a.map(String::from)).unwrap();
+        assert_eq!(a.server, Some("/tmp/edit.sock".into()));
+        assert_eq!(a.remote_send, ["e /tmp/a", "12"]);
+        assert!(args("--remote-send q").is_err());
+        assert!(args("--listen").is_err());
+    }
+}
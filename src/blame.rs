@@ -0,0 +1,154 @@
+//! `:GitBlame`, showing who last changed the cursor line as virtual text after it.
+//!
+//! `git blame --porcelain` runs as a job when the command is given. Once it's done, the
+//! annotation follows the cursor from line to line until `:GitBlame` is given again.
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::buffer::extmark::{Extmark, VIRT_TEXT_COLOR};
+use crate::buffer::BufferInner;
+use crate::job::JobId;
+use crate::render::BufId;
+
+pub const NAMESPACE: &str = "blame";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Commit {
+    pub hash: String,
+    pub author: String,
+    /// seconds since the epoch
+    pub time: i64,
+    pub summary: String,
+}
+
+impl Commit {
+    /// the annotation shown after a line
+    pub fn annotation(&self) -> String {
+        if self.hash.bytes().all(|b| b == b'0') {
+            return "not committed yet".into();
+        }
+        let hash = &self.hash[..self.hash.len().min(8)];
+        format!("{}, {} • {} ({hash})", self.author, date(self.time), self.summary)
+    }
+}
+
+/// blame of one buffer
+pub struct Blame {
+    pub buf: BufId,
+    /// the running `git blame` and its output so far
+    pub job: Option<(JobId, Vec<String>)>,
+    /// commit of every line
+    pub lines: Vec<Commit>,
+    /// line the annotation is shown on
+    shown: Option<usize>,
+}
+
+impl Blame {
+    pub fn new(buf: BufId, job: JobId) -> Self {
+        Blame { buf, job: Some((job, Vec::new())), lines: Vec::new(), shown: None }
+    }
+
+    /// put the annotation on the cursor line of `buf` if it moved
+    pub fn update(&mut self, buf: &mut BufferInner) {
+        let y = buf.cursor.pos.y;
+        if self.shown == Some(y) {
+            return;
+        }
+        buf.extmarks_mut().clear(NAMESPACE);
+        self.shown = None;
+        let Some(commit) = self.lines.get(y).filter(|_| y < buf.linecnt()) else {
+            return;
+        };
+        let off = buf.line_offsets(y..y).start;
+        buf.extmarks_mut().add(Extmark::virt_text(NAMESPACE, off, commit.annotation(), VIRT_TEXT_COLOR));
+        self.shown = Some(y);
+    }
+}
+
+/// `git blame` command line for `path`
+pub fn command(path: &Path) -> String {
+    format!("git blame --porcelain -- {}", crate::job::quote(path))
+}
+
+/// the commit of every line, from `git blame --porcelain` output. Information about a commit is
+/// only given the first time it appears.
+pub fn parse_porcelain(lines: &[String]) -> Vec<Commit> {
+    let mut commits: HashMap<String, Commit> = HashMap::new();
+    let mut out = Vec::new();
+    let mut current: Option<Commit> = None;
+    for line in lines {
+        if line.starts_with('\t') {
+            let Some(commit) = current.take() else { continue };
+            let commit = commits.entry(commit.hash.clone()).or_insert(commit);
+            out.push(commit.clone());
+        } else if let Some(cur) = &mut current {
+            let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+            match key {
+                "author" => cur.author = value.to_owned(),
+                "author-time" => cur.time = value.parse().unwrap_or(0),
+                "summary" => cur.summary = value.to_owned(),
+                _ => (),
+            }
+        } else {
+            let hash = line.split(' ').next().unwrap_or_default();
+            current = Some(commits.get(hash).cloned().unwrap_or_else(|| Commit {
+                hash: hash.to_owned(),
+                author: String::new(),
+                time: 0,
+                summary: String::new(),
+            }));
+        }
+    }
+    out
+}
+
+/// `YYYY-MM-DD` of a unix time, in UTC
+fn date(time: i64) -> String {
+    // from Howard Hinnant's days_from_civil algorithms
+    let z = time.div_euclid(86400) + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn dates() {
+        assert_eq!(date(0), "1970-01-01");
+        assert_eq!(date(951782400), "2000-02-29");
+        assert_eq!(date(1700000000), "2023-11-14");
+    }
+
+    #[test]
+    fn porcelain() {
+        let out = "\
+1111111111111111111111111111111111111111 1 1 2
+author Ann
+author-time 1700000000
+summary First commit
+filename a.txt
+\tone
+1111111111111111111111111111111111111111 2 2
+\ttwo
+0000000000000000000000000000000000000000 3 3 1
+author Not Committed Yet
+author-time 1700000001
+summary Version of a.txt from a.txt
+filename a.txt
+\tthree";
+        let lines: Vec<String> = out.lines().map(String::from).collect();
+        let commits = parse_porcelain(&lines);
+        assert_eq!(commits.len(), 3);
+        assert_eq!(commits[1].annotation(), "Ann, 2023-11-14 • First commit (11111111)");
+        assert_eq!(commits[2].annotation(), "not committed yet");
+    }
+}
@@ -0,0 +1,161 @@
+//! Extmarks, ranges of a buffer that move along with the text around them as it's edited. They
+//! decorate the buffer without changing it, with a highlight or with text drawn after the line.
+//!
+//! Text inserted right at the start of a mark pushes it along, and text inserted right at its end
+//! isn't part of it. Deleting the text of a mark leaves it empty rather than removing it.
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use crate::tui::{BasicColor, Color};
+
+/// dimmed color for virtual text that isn't more specific
+pub const VIRT_TEXT_COLOR: Color = Color { fg: BasicColor::Gray, italic: true, ..Color::new() };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ExtmarkId(u64);
+
+impl ExtmarkId {
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for ExtmarkId {
+    fn from(value: u64) -> Self {
+        ExtmarkId(value)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Extmark {
+    /// who placed the mark, so that all of its marks can be cleared at once
+    pub namespace: &'static str,
+    /// byte offsets in the buffer
+    pub range: Range<usize>,
+    pub highlight: Option<Color>,
+    /// drawn after the end of the line the mark starts on
+    pub virt_text: Option<(String, Color)>,
+    /// drawn in the sign column of the line the mark starts on
+    pub sign: Option<(char, Color)>,
+}
+
+impl Extmark {
+    pub fn highlight(namespace: &'static str, range: Range<usize>, color: Color) -> Self {
+        Extmark { namespace, range, highlight: Some(color), virt_text: None, sign: None }
+    }
+
+    /// text shown after the end of the line with `off`
+    pub fn virt_text(namespace: &'static str, off: usize, text: String, color: Color) -> Self {
+        Extmark { namespace, range: off..off, highlight: None, virt_text: Some((text, color)), sign: None }
+    }
+
+    fn inserted(&mut self, off: usize, len: usize) {
+        let empty = self.range.is_empty();
+        if self.range.start >= off {
+            self.range.start += len;
+        }
+        if self.range.end > off || (empty && self.range.end == off) {
+            self.range.end += len;
+        }
+    }
+
+    fn deleted(&mut self, del: &Range<usize>) {
+        let shift = |p: &mut usize| {
+            if *p >= del.end {
+                *p -= del.len();
+            } else if *p > del.start {
+                *p = del.start;
+            }
+        };
+        shift(&mut self.range.start);
+        shift(&mut self.range.end);
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Extmarks {
+    marks: BTreeMap<ExtmarkId, Extmark>,
+    next: u64,
+}
+
+impl Extmarks {
+    pub fn add(&mut self, mark: Extmark) -> ExtmarkId {
+        let id = ExtmarkId(self.next);
+        self.next += 1;
+        self.marks.insert(id, mark);
+        id
+    }
+
+    pub fn get(&self, id: ExtmarkId) -> Option<&Extmark> {
+        self.marks.get(&id)
+    }
+
+    pub fn remove(&mut self, id: ExtmarkId) -> Option<Extmark> {
+        self.marks.remove(&id)
+    }
+
+    /// remove every mark of `namespace`
+    pub fn clear(&mut self, namespace: &str) {
+        self.marks.retain(|_, m| m.namespace != namespace);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (ExtmarkId, &Extmark)> {
+        self.marks.iter().map(|(id, m)| (*id, m))
+    }
+
+    /// `len` bytes were inserted at `off`
+    pub(super) fn inserted(&mut self, off: usize, len: usize) {
+        self.marks.values_mut().for_each(|m| m.inserted(off, len));
+    }
+
+    pub(super) fn deleted(&mut self, range: Range<usize>) {
+        if !range.is_empty() {
+            self.marks.values_mut().for_each(|m| m.deleted(&range));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::BufferInner;
+    use crate::prelude::*;
+
+    fn mark(range: Range<usize>) -> Extmark {
+        Extmark::highlight("test", range, Color::new())
+    }
+
+    #[test]
+    fn follow_edits() {
+        let mut marks = Extmarks::default();
+        let word = marks.add(mark(4..7));
+        let point = marks.add(mark(8..8));
+        marks.inserted(0, 2);
+        assert_eq!(marks.get(word).unwrap().range, 6..9);
+        // at the start moves it, at the end doesn't extend it
+        marks.inserted(6, 1);
+        marks.inserted(10, 1);
+        assert_eq!(marks.get(word).unwrap().range, 7..10);
+        assert_eq!(marks.get(point).unwrap().range, 12..12);
+        marks.deleted(8..11);
+        assert_eq!(marks.get(word).unwrap().range, 7..8);
+        assert_eq!(marks.get(point).unwrap().range, 9..9);
+        marks.deleted(0..20);
+        assert_eq!(marks.get(word).unwrap().range, 0..0);
+        marks.clear("test");
+        assert!(marks.get(word).is_none());
+    }
+
+    #[test]
+    fn buffer_edits() {
+        let mut buf = BufferInner::from_str("one two\nthree\n");
+        let id = buf.extmarks_mut().add(mark(4..7));
+        buf.insert_str("zero ");
+        assert_eq!(buf.extmarks().get(id).unwrap().range, 9..12);
+        buf.cursor.set_pos(DocPos { x: 0, y: 1 });
+        buf.delete_char_before();
+        buf.delete_range(0..2);
+        assert_eq!(buf.extmarks().get(id).unwrap().range, 7..10);
+        assert_eq!(&buf.to_string()[7..10], "two");
+    }
+}
@@ -0,0 +1,83 @@
+//! The focused buffer, as seen from outside of [`crate::render::Ctx`], most importantly by Guile's
+//! `rs-curr-buf`.
+//!
+//! [`crate::render::Ctx::set_focused_buf`] is the only place focus changes, and it calls
+//! [`set`] every time, including when windows are split or switched. The command line is never
+//! focused here, so Guile run from `:` sees the buffer that was being edited.
+//!
+//! The focused window is kept here as well, for Guile's cursor and scrolling procedures, and the
+//! layout of the windows as of the last focus change or redraw.
+//!
+//! Focus is per thread, since each thread running an editor has its own context.
+use std::cell::RefCell;
+use std::sync::{Arc, Weak};
+
+use super::Buffer;
+use crate::render::BufId;
+use crate::window::org::Layout;
+use crate::window::Window;
+
+thread_local! {
+    static FOCUSED: RefCell<Option<(BufId, Weak<Buffer>)>> = const { RefCell::new(None) };
+    static WINDOW: RefCell<Weak<Window>> = const { RefCell::new(Weak::new()) };
+    static LAYOUT: RefCell<Option<Layout>> = const { RefCell::new(None) };
+}
+
+pub fn set(buf: &Arc<Buffer>) {
+    FOCUSED.with_borrow_mut(|f| *f = Some((buf.id(), Arc::downgrade(buf))));
+}
+
+/// the focused buffer, unless it was closed
+pub fn get() -> Option<Arc<Buffer>> {
+    FOCUSED.with_borrow(|f| f.as_ref()?.1.upgrade())
+}
+
+pub fn set_window(win: &Arc<Window>) {
+    WINDOW.with_borrow_mut(|w| *w = Arc::downgrade(win));
+}
+
+/// the focused window, unless it was closed
+pub fn window() -> Option<Arc<Window>> {
+    WINDOW.with_borrow(Weak::upgrade)
+}
+
+pub fn set_layout(layout: Layout) {
+    LAYOUT.with_borrow_mut(|l| *l = Some(layout));
+}
+
+pub fn layout() -> Option<Layout> {
+    LAYOUT.with_borrow(Clone::clone)
+}
+
+/// id of the focused buffer, even if it was closed
+pub fn id() -> Option<BufId> {
+    FOCUSED.with_borrow(|f| Some(f.as_ref()?.0))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn follows_focus() {
+        let a = Buffer::from_string("a");
+        let b = Buffer::from_string("b");
+        set(&a);
+        assert!(Arc::ptr_eq(&get().unwrap(), &a));
+        set(&b);
+        assert!(Arc::ptr_eq(&get().unwrap(), &b));
+        assert_eq!(id(), Some(b.id()));
+        let b_id = b.id();
+        drop(b);
+        assert!(get().is_none());
+        assert_eq!(id(), Some(b_id));
+    }
+
+    #[test]
+    fn per_thread() {
+        let a = Buffer::from_string("a");
+        set(&a);
+        std::thread::spawn(|| assert!(get().is_none())).join().unwrap();
+        assert!(Arc::ptr_eq(&get().unwrap(), &a));
+    }
+}
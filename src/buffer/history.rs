@@ -0,0 +1,303 @@
+//! Undo history of a buffer.
+//!
+//! Every change to the text is recorded as an insertion or a deletion at a byte offset. Changes are
+//! collected into one group until [`undo_break`] is called, so that undoing reverts a whole insert
+//! session, ex command or operator at once. Breaks are shared by all buffers, which lets code that
+//! doesn't know which buffers a command touched end its undo step.
+use std::cell::Cell;
+use std::collections::HashSet;
+
+use super::DocPos;
+
+thread_local! {
+    /// increased by every break, changes made with the same epoch are in the same group
+    static EPOCH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// end the current undo step of every buffer, the next change starts a new one
+pub fn undo_break() {
+    EPOCH.set(EPOCH.get() + 1);
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Insert { off: usize, text: String },
+    Delete { off: usize, text: String },
+}
+
+impl Change {
+    /// the change that reverts this one
+    pub fn inverse(&self) -> Change {
+        match self {
+            Change::Insert { off, text } => Change::Delete { off: *off, text: text.clone() },
+            Change::Delete { off, text } => Change::Insert { off: *off, text: text.clone() },
+        }
+    }
+
+    pub fn off(&self) -> usize {
+        match self {
+            Change::Insert { off, .. } | Change::Delete { off, .. } => *off,
+        }
+    }
+}
+
+/// changes that are undone together
+#[derive(Debug, Clone)]
+struct Group {
+    changes: Vec<Change>,
+    /// where the cursor was before the first change
+    cursor: DocPos,
+    epoch: usize,
+}
+
+/// a state of the text, made by applying a group of changes to its parent
+#[derive(Debug)]
+struct Node {
+    group: Group,
+    parent: Option<usize>,
+    /// the child redo goes to, the one made or visited last
+    next: Option<usize>,
+}
+
+/// Undo tree of a buffer. Making a change after undoing starts a new branch instead of forgetting
+/// what was undone, so every state of the text can still be reached. States are numbered in the
+/// order they were made, `0` being the text as it was read, which is what `:earlier` and `:later`
+/// count in.
+#[derive(Debug)]
+pub struct History {
+    nodes: Vec<Node>,
+    /// the node of the current text, `None` for the text as it was read
+    current: Option<usize>,
+    /// the child of the text as it was read
+    first: Option<usize>,
+    /// the state of every file write, in order. Reading the file counts as the first one.
+    writes: Vec<usize>,
+    /// whether changes are recorded at all
+    enabled: bool,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History { nodes: Vec::new(), current: None, first: None, writes: vec![0], enabled: true }
+    }
+}
+
+/// the changes that take the text from one state to another, and where the cursor goes after them
+pub type Step = (Vec<Change>, DocPos);
+
+impl History {
+    /// bytes the tree takes up, with the text of its changes
+    pub fn mem_usage(&self) -> usize {
+        use std::mem::size_of;
+        let groups = self.nodes.iter().map(|n| &n.group.changes);
+        let text: usize = groups.clone().flatten().map(|c| match c {
+            Change::Insert { text, .. } | Change::Delete { text, .. } => text.capacity(),
+        }).sum();
+        let changes: usize = groups.map(|g| g.capacity() * size_of::<Change>()).sum();
+        text + changes + self.nodes.capacity() * size_of::<Node>() + self.writes.capacity() * size_of::<usize>()
+    }
+
+    /// forget every change and don't record any more, for buffers whose text is made by the editor
+    pub fn disable(&mut self) {
+        *self = History { enabled: false, ..Default::default() };
+    }
+
+    /// record `change`, made with the cursor at `cursor`
+    pub fn record(&mut self, change: Change, cursor: DocPos) {
+        if !self.enabled {
+            return;
+        }
+        let epoch = EPOCH.get();
+        match self.current.map(|i| &mut self.nodes[i].group) {
+            Some(group) if group.epoch == epoch => {
+                // typing a character at a time shouldn't make a change for each of them
+                if let (Some(Change::Insert { off, text }), Change::Insert { off: new, text: more }) =
+                    (group.changes.last_mut(), &change)
+                {
+                    if *off + text.len() == *new {
+                        text.push_str(more);
+                        return;
+                    }
+                }
+                group.changes.push(change)
+            }
+            _ => {
+                let id = self.nodes.len();
+                let group = Group { changes: vec![change], cursor, epoch };
+                self.nodes.push(Node { group, parent: self.current, next: None });
+                *self.next_mut(self.current) = Some(id);
+                self.current = Some(id);
+            }
+        }
+    }
+
+    /// record that the current text was written to the file
+    pub fn written(&mut self) {
+        self.writes.push(self.state());
+    }
+
+    fn next_mut(&mut self, node: Option<usize>) -> &mut Option<usize> {
+        match node {
+            Some(i) => &mut self.nodes[i].next,
+            None => &mut self.first,
+        }
+    }
+
+    /// number of the current state
+    fn state(&self) -> usize {
+        self.current.map_or(0, |i| i + 1)
+    }
+
+    /// go to the parent of the current state
+    pub fn undo(&mut self) -> Option<Step> {
+        let parent = self.nodes[self.current?].parent;
+        Some(self.goto(parent))
+    }
+
+    /// go to the child of the current state that was made or visited last
+    pub fn redo(&mut self) -> Option<Step> {
+        let next = match self.current {
+            Some(i) => self.nodes[i].next,
+            None => self.first,
+        };
+        Some(self.goto(Some(next?)))
+    }
+
+    /// go `count` states forward in time, or back if it's negative. With `writes`, go to the state
+    /// of a file write instead, where going back from changes that weren't written yet goes to the
+    /// last write. Going forward past the last write goes to the newest state. A count of zero
+    /// stays where it is.
+    pub fn travel(&mut self, count: isize, writes: bool) -> Option<Step> {
+        if count == 0 {
+            return None;
+        }
+        let state = self.state();
+        let target = if !writes {
+            state.saturating_add_signed(count).min(self.nodes.len())
+        } else if count < 0 {
+            let mut before: Vec<usize> = self.writes.iter().copied().filter(|&w| w < state).collect();
+            before.sort_unstable();
+            before.dedup();
+            before.iter().rev().nth(count.unsigned_abs() - 1).copied().unwrap_or(0)
+        } else {
+            let mut after: Vec<usize> = self.writes.iter().copied().filter(|&w| w > state).collect();
+            after.sort_unstable();
+            after.dedup();
+            after.get((count as usize).saturating_sub(1)).copied().unwrap_or(self.nodes.len())
+        };
+        (target != state).then(|| self.goto(target.checked_sub(1)))
+    }
+
+    /// the changes that take the text from the current state to `target`, reverting back to where
+    /// their branches meet and then redoing the changes of its branch. The cursor goes where it was
+    /// before the last group of changes undone or redone.
+    fn goto(&mut self, target: Option<usize>) -> Step {
+        undo_break();
+        let ancestors = |mut node: Option<usize>| {
+            let mut out = vec![node];
+            while let Some(i) = node {
+                node = self.nodes[i].parent;
+                out.push(node);
+            }
+            out
+        };
+        let up = ancestors(self.current);
+        let down = ancestors(target);
+        let shared: HashSet<_> = up.iter().collect();
+        let common = down.iter().find(|n| shared.contains(n)).copied().flatten();
+        let mut changes = Vec::new();
+        let mut cursor = DocPos::default();
+        for i in up.into_iter().map_while(|n| n.filter(|&n| Some(n) != common)) {
+            let group = &self.nodes[i].group;
+            changes.extend(group.changes.iter().rev().map(Change::inverse));
+            cursor = group.cursor;
+        }
+        let down: Vec<usize> = down.into_iter().map_while(|n| n.filter(|&n| Some(n) != common)).collect();
+        for &i in down.iter().rev() {
+            *self.next_mut(self.nodes[i].parent) = Some(i);
+            let group = &self.nodes[i].group;
+            changes.extend(group.changes.iter().cloned());
+            cursor = group.cursor;
+        }
+        self.current = target;
+        (changes, cursor)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn insert(off: usize, text: &str) -> Change {
+        Change::Insert { off, text: text.into() }
+    }
+
+    fn record(h: &mut History, change: Change) {
+        undo_break();
+        h.record(change, DocPos::default());
+    }
+
+    #[test]
+    fn groups() {
+        let mut h = History::default();
+        undo_break();
+        h.record(insert(0, "a"), DocPos::default());
+        h.record(insert(1, "b"), DocPos { x: 1, y: 0 });
+        h.record(Change::Delete { off: 0, text: "a".into() }, DocPos { x: 2, y: 0 });
+        record(&mut h, insert(0, "c"));
+        assert_eq!(h.undo().unwrap().0, [Change::Delete { off: 0, text: "c".into() }]);
+        let (changes, cursor) = h.undo().unwrap();
+        assert_eq!(changes, [insert(0, "a"), Change::Delete { off: 0, text: "ab".into() }]);
+        assert_eq!(cursor, DocPos::default());
+        assert!(h.undo().is_none());
+        assert_eq!(h.redo().unwrap().0.len(), 2);
+    }
+
+    #[test]
+    fn branches() {
+        let mut h = History::default();
+        record(&mut h, insert(0, "a"));
+        record(&mut h, insert(1, "b"));
+        h.undo();
+        record(&mut h, insert(1, "c"));
+        assert_eq!(h.travel(0, false), None);
+        // back to "ab" through "a"
+        let (changes, _) = h.travel(-1, false).unwrap();
+        assert_eq!(changes, [Change::Delete { off: 1, text: "c".into() }, insert(1, "b")]);
+        assert_eq!(h.redo(), None);
+        assert_eq!(h.undo().unwrap().0, [Change::Delete { off: 1, text: "b".into() }]);
+        // redo goes down the branch that was visited last
+        assert_eq!(h.redo().unwrap().0, [insert(1, "b")]);
+        assert_eq!(h.travel(5, false).unwrap().0.len(), 2);
+        assert_eq!(h.travel(1, false), None);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut h = History::default();
+        record(&mut h, insert(0, "a"));
+        h.disable();
+        record(&mut h, insert(1, "b"));
+        assert!(h.undo().is_none());
+        assert_eq!(h.mem_usage(), History::default().mem_usage());
+    }
+
+    #[test]
+    fn writes() {
+        let mut h = History::default();
+        record(&mut h, insert(0, "a"));
+        h.written();
+        record(&mut h, insert(1, "b"));
+        record(&mut h, insert(2, "c"));
+        h.written();
+        record(&mut h, insert(3, "d"));
+        assert_eq!(h.travel(0, true), None);
+        // to the last write, then the one before it, then the text as it was read
+        assert_eq!(h.travel(-1, true).unwrap().0.len(), 1);
+        assert_eq!(h.travel(-1, true).unwrap().0.len(), 2);
+        assert_eq!(h.travel(-1, true).unwrap().0.len(), 1);
+        assert_eq!(h.travel(-1, true), None);
+        assert_eq!(h.travel(2, true).unwrap().0.len(), 3);
+        assert_eq!(h.travel(1, true).unwrap().0, [insert(3, "d")]);
+    }
+}
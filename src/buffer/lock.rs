@@ -0,0 +1,52 @@
+//! Advisory locks on the files being edited, so that opening a file that's already open in
+//! another editor gets a warning instead of two sets of changes overwriting each other.
+//!
+//! These are `flock` locks, so they only keep out other programs that also take them.
+use std::fs::File;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+
+/// exclusive lock on a file, released when dropped
+#[derive(Debug)]
+pub struct FileLock {
+    file: File,
+}
+
+impl FileLock {
+    /// lock `path`, or `None` if something else holds a lock on it
+    pub fn try_new(path: &Path) -> std::io::Result<Option<Self>> {
+        let file = File::open(path)?;
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => Ok(Some(FileLock { file })),
+            Err(Errno::EWOULDBLOCK) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl Drop for FileLock {
+    fn drop(&mut self) {
+        let _ = flock(self.file.as_raw_fd(), FlockArg::Unlock);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn held_elsewhere() {
+        let path = std::env::temp_dir().join(format!("edit-lock-test-{}", std::process::id()));
+        std::fs::write(&path, "text").unwrap();
+        let lock = FileLock::try_new(&path).unwrap();
+        assert!(lock.is_some());
+        // separate opens don't share flock locks, so this behaves like another process
+        assert!(FileLock::try_new(&path).unwrap().is_none());
+        drop(lock);
+        assert!(FileLock::try_new(&path).unwrap().is_some());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
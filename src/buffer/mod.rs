@@ -1,4 +1,4 @@
-use crate::{prelude::*, render::BufId, term::TermPos, window::WindowInner};
+use crate::{error::FileOp, prelude::*, render::BufId, term::TermPos, window::WindowInner};
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 use std::{cell::Cell, ops::RangeBounds};
 use std::{
@@ -40,6 +40,33 @@ impl DocPos {
     }
 }
 
+/// Range of a document between two positions, the end is exclusive. This is the position based
+/// counterpart of a `Range<usize>` of offsets, which every backend can convert to and from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DocRange {
+    pub start: DocPos,
+    pub end: DocPos,
+}
+
+impl DocRange {
+    /// range between two positions in either order
+    pub fn new(a: DocPos, b: DocPos) -> Self {
+        if a <= b {
+            Self { start: a, end: b }
+        } else {
+            Self { start: b, end: a }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    pub fn contains(&self, pos: DocPos) -> bool {
+        self.start <= pos && pos < self.end
+    }
+}
+
 /// trait for file offset. We don't implement this for [`DocPos`] since it has too many edge cases.
 pub trait FileOff: Copy {
     /// file offsets are constrained to representing one byte past the end of the file so that we
@@ -85,6 +112,11 @@ type BufferCore = simplebuffer::SimpleBuffer;
 // mod piecetable;
 
 pub use rope::RopeBuffer;
+pub mod extmark;
+pub mod focus;
+pub mod history;
+pub mod lock;
+pub mod registry;
 mod rope;
 mod simplebuffer;
 
@@ -107,14 +139,56 @@ pub trait BufCore: Sized {
     fn len(&self) -> usize;
     fn clear(&mut self, ctx: &mut Cursor);
 
+    /// `off` moved back to the start of the character it's in, and to at most the end
+    fn floor_char_boundary(&self, off: usize) -> usize;
+    /// `off` moved forward to the end of the character it's in, and to at most the end
+    fn ceil_char_boundary(&self, off: usize) -> usize;
+
     /// return [`DocPos`] corresponding to the offset, panics if `off > self.len()`
     fn offset_to_pos(&self, off: usize) -> DocPos;
     fn try_pos_to_offset(&self, pos: DocPos) -> Option<usize>;
     fn pos_to_offset(&self, pos: DocPos) -> usize;
+    /// the memory the text and its index take up, for `:memory`
+    fn mem_usage(&self) -> MemUsage;
 
     fn line(&self, idx: usize) -> &str {
         self.get_lines(idx..(idx + 1))[0]
     }
+
+    /// like [`BufCore::offset_to_pos`], except that the end of the buffer is the position after the
+    /// last character rather than on it, so that it can end a [`DocRange`]
+    fn offset_to_end_pos(&self, off: usize) -> DocPos {
+        let pos = self.offset_to_pos(off);
+        if off == self.len() && off > 0 {
+            DocPos { x: pos.x + 1, ..pos }
+        } else {
+            pos
+        }
+    }
+
+    fn offsets_to_range(&self, rng: Range<usize>) -> DocRange {
+        DocRange {
+            start: self.offset_to_end_pos(rng.start),
+            end: self.offset_to_end_pos(rng.end),
+        }
+    }
+
+    fn range_to_offsets(&self, rng: DocRange) -> Range<usize> {
+        self.pos_to_offset(rng.start)..self.pos_to_offset(rng.end)
+    }
+}
+
+/// bytes of memory a buffer holds on to, see [`BufCore::mem_usage`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemUsage {
+    /// storage of the text, including what's allocated but unused
+    pub text: usize,
+    /// what finds lines in the text: the starts of lines, or the nodes of a tree
+    pub index: usize,
+    /// nodes of the tree the text is kept in, 0 if it isn't a tree
+    pub nodes: usize,
+    /// the undo tree, filled in by [`BufferInner::mem_usage`]
+    pub undo: usize,
 }
 
 impl std::fmt::Display for BufferCore {
@@ -131,6 +205,17 @@ impl std::default::Default for BufferCore {
     }
 }
 
+/// `[New]` for a file that doesn't exist yet, `[readonly]` for one that can't be written
+pub fn file_flag(path: &std::path::Path) -> Option<&'static str> {
+    if !path.exists() {
+        Some("[New]")
+    } else if nix::unistd::access(path, nix::unistd::AccessFlags::W_OK).is_err() {
+        Some("[readonly]")
+    } else {
+        None
+    }
+}
+
 pub struct Buffer {
     id: BufId,
     inner: RwLock<BufferInner>
@@ -142,10 +227,17 @@ impl Buffer {
     }
 
     pub fn new() -> Arc<Self> {
-        Buffer {
+        registry::register(Buffer {
             id: BufId::new(),
             inner: BufferInner::new().into()
-        }.into()
+        })
+    }
+
+    /// empty buffer whose text is filled in by the editor, so it keeps no undo history
+    pub fn scratch() -> Arc<Self> {
+        let buf = Self::new();
+        buf.get_mut().disable_undo();
+        buf
     }
 
     pub fn get(&self) -> RwLockReadGuard<BufferInner> {
@@ -157,7 +249,34 @@ impl Buffer {
     }
 
     pub fn open(file: &std::path::Path) -> std::io::Result<Arc<Self>> {
-        Ok(Buffer { inner: BufferInner::open(file)?.into(), id: BufId::new() }.into())
+        Ok(registry::register(Buffer { inner: BufferInner::open(file)?.into(), id: BufId::new() }))
+    }
+
+    /// the buffer for editing `file`, which is empty if the file doesn't exist yet
+    pub fn edit(file: &std::path::Path) -> Result<Arc<Self>, EditorError> {
+        match Self::open(file) {
+            Ok(buf) => Ok(buf),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::new_file(file.to_owned())),
+            Err(error) => Err(EditorError::Io { path: file.to_owned(), op: FileOp::Open, error }),
+        }
+    }
+
+    /// empty buffer for a file that doesn't exist yet
+    pub fn new_file(path: std::path::PathBuf) -> Arc<Self> {
+        let buf = Self::new();
+        buf.get_mut().set_path(path);
+        buf
+    }
+
+    /// buffer that isn't backed by a file
+    pub fn from_string(s: impl AsRef<str>) -> Arc<Self> {
+        registry::register(Buffer { inner: BufferInner::from_string(s).into(), id: BufId::new() })
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        registry::unregister(self.id);
     }
 }
 
@@ -168,6 +287,27 @@ pub struct BufferInner {
     prev: Option<Arc<Buffer>>,
     pub cursor: Cursor,
     text: BufferCore,
+    /// incremented on every modification of the text
+    version: usize,
+    /// [`Self::version`] when the buffer was last read from or written to its file
+    saved_version: usize,
+    /// held while the file is open, with the `filelock` option
+    lock: Option<lock::FileLock>,
+    extmarks: extmark::Extmarks,
+    history: history::History,
+    /// whether commands may change the text, see [`BufferInner::set_modifiable`]
+    modifiable: bool,
+    /// whether the text had a line longer than [`LONG_LINE`] when it was read
+    long_lines: bool,
+}
+
+/// bytes a line can have before syntax highlighting is turned off for its buffer, since matching
+/// rules against minified code on every draw would cost too much
+pub const LONG_LINE: usize = 3000;
+
+/// whether `text` has a line longer than [`LONG_LINE`]
+fn has_long_line(text: &BufferCore) -> bool {
+    (0..text.linecnt()).any(|l| text.line(l).len() > LONG_LINE)
 }
 
 impl Display for BufferInner {
@@ -183,33 +323,64 @@ impl BufferInner {
             text: BufferCore::new(),
             next: None,
             prev: None,
+            version: 0,
+            saved_version: 0,
+            lock: None,
+            extmarks: Default::default(),
+            history: Default::default(),
+            modifiable: true,
+            long_lines: false,
         }
     }
 
     pub fn open(file: &std::path::Path) -> std::io::Result<Self> {
+        let text = BufferCore::open(file)?;
         Ok(BufferInner {
             cursor: Cursor::new(),
-            text: BufferCore::open(file)?,
+            long_lines: has_long_line(&text),
+            text,
             next: None,
             prev: None,
+            version: 0,
+            saved_version: 0,
+            lock: None,
+            extmarks: Default::default(),
+            history: Default::default(),
+            modifiable: true,
         })
     }
 
     pub fn from_string(s: impl AsRef<str>) -> Self {
+        let text = BufferCore::from_str(s);
         BufferInner {
             cursor: Cursor::new(),
-            text: BufferCore::from_str(s),
+            long_lines: has_long_line(&text),
+            text,
             next: None,
             prev: None,
+            version: 0,
+            saved_version: 0,
+            lock: None,
+            extmarks: Default::default(),
+            history: Default::default(),
+            modifiable: true,
         }
     }
 
     pub fn from_str(s: &str) -> Self {
+        let text = BufferCore::from_str(s);
         BufferInner {
             cursor: Cursor::new(),
-            text: BufferCore::from_str(s),
+            long_lines: has_long_line(&text),
+            text,
             next: None,
             prev: None,
+            version: 0,
+            saved_version: 0,
+            lock: None,
+            extmarks: Default::default(),
+            history: Default::default(),
+            modifiable: true,
         }
     }
 
@@ -232,23 +403,43 @@ impl BufferInner {
         if self.text.len() == 0 {
             return None;
         }
-        let len = self.text.line(self.cursor.pos.y).len();
-        let res = self.text.delete_char(self.text.pos_to_offset(self.cursor.pos));
-        if Some(self.cursor.pos.x) == len.checked_sub(1) {
-            self.cursor.pos.x = self.cursor.pos.x.saturating_sub(1);
-        };
+        let x = self.cursor.pos.x;
+        let line = self.text.line(self.cursor.pos.y);
+        let is_last = line.char_indices().last().is_some_and(|(i, _)| i == x);
+        let prev = line.char_indices().take_while(|(i, _)| *i < x).last().map_or(0, |(i, _)| i);
+        self.version += 1;
+        crate::busy::tick();
+        let off = self.text.pos_to_offset(self.cursor.pos);
+        let res = self.text.delete_char(off);
+        self.extmarks.deleted(off..(off + res.len_utf8()));
+        self.history.record(history::Change::Delete { off, text: res.into() }, self.cursor.pos);
+        if is_last {
+            self.cursor.pos.x = prev;
+        }
         Some(res)
     }
 
     /// delete the character before the cursor's current position. This is the behavior of
     /// backspace in insert mode.
     pub fn delete_char_before(&mut self) -> Option<char> {
-        let off = self.text.pos_to_offset(self.cursor.pos).checked_sub(1)?;
+        if self.cursor.coladd > 0 {
+            self.cursor.coladd -= 1;
+            return None;
+        }
+        // the line after a trailing newline has no offset of its own, it's the end of the buffer
+        let start = self.text.try_pos_to_offset(self.cursor.pos).unwrap_or(self.text.len());
+        let (off, _) = self.text.chars_bck(start.checked_sub(1)?).next()?;
         let new_pos = self
             .text
             .offset_to_pos(off);
+        let cursor = self.cursor.pos;
         self.cursor.set_pos(new_pos);
-        Some(self.text.delete_char(off))
+        self.version += 1;
+        crate::busy::tick();
+        let res = self.text.delete_char(off);
+        self.extmarks.deleted(off..(off + res.len_utf8()));
+        self.history.record(history::Change::Delete { off, text: res.into() }, cursor);
+        Some(res)
     }
 
     pub fn linecnt(&self) -> usize {
@@ -264,9 +455,106 @@ impl BufferInner {
     }
 
     pub fn insert_str(&mut self, s: &str) {
+        // typing past the end of the line with `virtualedit` fills the gap
+        let padded;
+        let s = match std::mem::take(&mut self.cursor.coladd) {
+            0 => s,
+            n => {
+                padded = format!("{}{s}", " ".repeat(n));
+                &padded
+            }
+        };
+        self.version += 1;
+        crate::busy::tick();
+        let off = self.text.pos_to_offset(self.cursor.pos);
+        self.extmarks.inserted(off, s.len());
+        self.history.record(history::Change::Insert { off, text: s.into() }, self.cursor.pos);
         self.text.insert_str(&mut self.cursor, s)
     }
 
+    /// insert text at the end of the buffer without moving the cursor
+    pub fn append(&mut self, s: &str) {
+        let cursor = self.cursor;
+        // there's no position after a trailing newline, so take it off and put it back in front
+        let s = if self.len() > 0 && self.char_at(self.len() - 1) == '\n' {
+            self.delete_range((self.len() - 1)..);
+            format!("\n{s}")
+        } else {
+            s.to_owned()
+        };
+        let y = self.linecnt().saturating_sub(1);
+        let x = if self.linecnt() > 0 { self.line(y).len() } else { 0 };
+        self.cursor.set_pos(DocPos { x, y });
+        self.insert_str(&s);
+        self.cursor = cursor;
+    }
+
+    /// insert text at byte offset `off`, before the end of the buffer, leaving the cursor on the
+    /// same character and as far past the end of its line as it was
+    pub fn insert_at(&mut self, off: usize, s: &str) {
+        let cursor = self.cursor;
+        let at = self.pos_to_offset(cursor.pos);
+        self.cursor.set_pos(self.offset_to_pos(off));
+        self.insert_str(s);
+        self.cursor = cursor;
+        if off <= at {
+            self.cursor.pos = self.offset_to_pos(at + s.len());
+        }
+    }
+
+    /// replace whole `lines` with `text`, which should end with a newline. The cursor stays on the
+    /// same line if it still exists.
+    pub fn replace_lines(&mut self, lines: Range<usize>, text: &str) {
+        let cursor = self.cursor;
+        self.replace_range(self.line_offsets(lines), text);
+        let y = cursor.pos.y.min(self.linecnt().saturating_sub(1));
+        let x = if self.linecnt() > 0 { cursor.pos.x.min(self.line(y).len()) } else { 0 };
+        self.cursor.set_pos(DocPos { x, y });
+        self.cursor.topline = cursor.topline.min(y);
+    }
+
+    /// replace the text in the byte range `range` with `text`, moving the cursor
+    pub fn replace_range(&mut self, range: Range<usize>, text: &str) {
+        let start = range.start;
+        self.delete_range(range);
+        // there's no cursor position after the end of the buffer
+        if start == self.len() {
+            self.append(text);
+        } else {
+            self.cursor.set_pos(self.offset_to_pos(start));
+            self.insert_str(text);
+        }
+    }
+
+    /// replace whole `lines` with `new`, a string for each line without its newline. The last line
+    /// of the buffer keeps not ending in a newline if it didn't. Returns how many lines changed.
+    pub fn set_lines(&mut self, lines: Range<usize>, new: &[String]) -> usize {
+        let old = self.get_lines(lines.clone());
+        let changed = old.iter().zip(new).filter(|(a, b)| **a != b.as_str()).count() + old.len().abs_diff(new.len());
+        if changed == 0 {
+            return 0;
+        }
+        let mut text: String = new.iter().map(|l| format!("{l}\n")).collect();
+        let end = self.line_offsets(lines.clone()).end;
+        if end == self.len() && (end == 0 || self.char_at(end - 1) != '\n') {
+            text.pop();
+        }
+        self.replace_lines(lines, &text);
+        changed
+    }
+
+    /// byte range of whole `lines`, including the newline of the last one
+    pub fn line_offsets(&self, lines: Range<usize>) -> Range<usize> {
+        let line_off = |y: usize| {
+            if y < self.linecnt() {
+                self.pos_to_offset(DocPos { x: 0, y })
+            } else {
+                self.len()
+            }
+        };
+        line_off(lines.start)..line_off(lines.end)
+    }
+
     pub fn path(&self) -> Option<&std::path::Path> {
         self.text.path()
     }
@@ -275,12 +563,79 @@ impl BufferInner {
         self.text.set_path(path)
     }
 
+    pub fn filetype(&self) -> Option<&'static str> {
+        crate::filetype::detect(self.path()?)
+    }
+
+    /// modification counter, changes whenever the text does
+    pub fn version(&self) -> usize {
+        self.version
+    }
+
+    /// the memory the text, its index and its undo history take up
+    pub fn mem_usage(&self) -> MemUsage {
+        MemUsage { undo: self.history.mem_usage(), ..self.text.mem_usage() }
+    }
+
+    /// stop keeping undo history, for text that's replaced by the editor rather than edited
+    pub fn disable_undo(&mut self) {
+        self.history.disable();
+    }
+
+    /// refuse edits from commands, like for a manual page. The editor itself can still change the
+    /// text.
+    pub fn set_modifiable(&mut self, modifiable: bool) {
+        self.modifiable = modifiable;
+    }
+
+    /// whether the text had lines too long to highlight when it was read, see [`LONG_LINE`]
+    pub fn has_long_lines(&self) -> bool {
+        self.long_lines
+    }
+
+    pub fn is_modifiable(&self) -> bool {
+        self.modifiable
+    }
+
+    /// whether the text changed since it was last read or written
+    pub fn is_modified(&self) -> bool {
+        self.version != self.saved_version
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.lock.is_some()
+    }
+
+    pub fn set_lock(&mut self, lock: lock::FileLock) {
+        self.lock = Some(lock);
+    }
+
+    /// let other editors have the file, even while the buffer is still around
+    pub fn unlock(&mut self) {
+        self.lock = None;
+    }
+
+    pub fn extmarks(&self) -> &extmark::Extmarks {
+        &self.extmarks
+    }
+
+    pub fn extmarks_mut(&mut self) -> &mut extmark::Extmarks {
+        &mut self.extmarks
+    }
+
+    /// record that the text was written to the buffer's file
+    pub fn mark_saved(&mut self) {
+        self.saved_version = self.version;
+        self.history.written();
+    }
+
     pub fn len(&self) -> usize {
         self.text.len()
     }
 
     pub fn clear(&mut self) {
-        self.text.clear(&mut self.cursor)
+        self.delete_range(..);
+        self.cursor = Cursor::new();
     }
 
     pub fn char_at(&self, off: usize) -> char {
@@ -288,13 +643,16 @@ impl BufferInner {
     }
 
     pub fn line(&self, idx: usize) -> &str {
+        // an empty buffer still has an empty first line
+        if idx == 0 && self.len() == 0 {
+            return "";
+        }
         self.get_lines(idx..(idx + 1))[0]
     }
 
     /// push a character onto the end
     pub fn push(&mut self, c: char) {
-        self.text
-            .insert_str(&mut self.cursor, c.encode_utf8(&mut [0; 4]))
+        self.insert_str(c.encode_utf8(&mut [0; 4]))
     }
 
     /// pop a character from the end
@@ -319,14 +677,72 @@ impl BufferInner {
             std::ops::Bound::Unbounded => self.text.len(),
         }
         .min(self.text.len());
+        // only whole characters
+        let (start, end) = (self.text.floor_char_boundary(start), self.text.ceil_char_boundary(end));
         let init_off = self.text.pos_to_offset(self.cursor.pos);
 
+        self.version += 1;
+        crate::busy::tick();
         let deleted = self.text.delete_range(start..end);
+        self.extmarks.deleted(start..(start + deleted.len()));
+        if !deleted.is_empty() {
+            self.history.record(history::Change::Delete { off: start, text: deleted.clone() }, self.cursor.pos);
+        }
         let new_pos = init_off - init_off.saturating_sub(start).min(deleted.len());
         self.cursor.set_pos(self.text.offset_to_pos(new_pos));
         deleted
     }
 
+    /// revert the last undo step, putting the cursor back where it was before it. Returns whether
+    /// there was anything to undo.
+    pub fn undo(&mut self) -> bool {
+        let step = self.history.undo();
+        self.apply_step(step)
+    }
+
+    /// make the last undone step again. Returns whether there was anything to redo.
+    pub fn redo(&mut self) -> bool {
+        let step = self.history.redo();
+        self.apply_step(step)
+    }
+
+    /// go forward `count` changes in time, or back if it's negative, across branches of the undo
+    /// tree. With `writes`, count file writes instead. Returns whether the text changed state.
+    pub fn travel(&mut self, count: isize, writes: bool) -> bool {
+        let step = self.history.travel(count, writes);
+        self.apply_step(step)
+    }
+
+    fn apply_step(&mut self, step: Option<history::Step>) -> bool {
+        let Some((changes, cursor)) = step else {
+            return false;
+        };
+        for change in &changes {
+            self.apply(change);
+        }
+        let cursor = self.try_pos_to_offset(cursor).map_or(DocPos::default(), |_| cursor);
+        self.cursor.set_pos(cursor);
+        true
+    }
+
+    /// make a change without recording it
+    fn apply(&mut self, change: &history::Change) {
+        self.version += 1;
+        crate::busy::tick();
+        match change {
+            history::Change::Insert { off, text } => {
+                self.extmarks.inserted(*off, text.len());
+                let mut cursor = Cursor::new();
+                cursor.set_pos(self.text.offset_to_pos(*off));
+                self.text.insert_str(&mut cursor, text);
+            }
+            history::Change::Delete { off, text } => {
+                self.text.delete_range(*off..off + text.len());
+                self.extmarks.deleted(*off..off + text.len());
+            }
+        }
+    }
+
     pub fn normalize_range(&self, range: impl RangeBounds<usize>) -> Range<usize> {
         let start = match range.start_bound() {
             std::ops::Bound::Included(p) => *p,
@@ -352,11 +768,13 @@ impl BufferInner {
         let _ = write!(tui.refbox(win.inner_bounds()), "{}", self.text);
     }
 
-    pub fn chars_bck(&self, off: usize) -> impl Iterator<Item = char> + '_ {
+    /// the characters from the one `off` is in back to the start, with their offsets
+    pub fn chars_bck(&self, off: usize) -> impl Iterator<Item = (usize, char)> + '_ {
         self.text.chars_bck(off)
     }
 
-    pub fn chars_fwd(&self, off: usize) -> impl Iterator<Item = char> + '_ {
+    /// the characters from the one `off` is in to the end, with their offsets
+    pub fn chars_fwd(&self, off: usize) -> impl Iterator<Item = (usize, char)> + '_ {
         self.text.chars_fwd(off)
     }
 
@@ -372,6 +790,14 @@ impl BufferInner {
         self.text.try_pos_to_offset(pos)
     }
 
+    pub fn offsets_to_range(&self, rng: Range<usize>) -> DocRange {
+        self.text.offsets_to_range(rng)
+    }
+
+    pub fn range_to_offsets(&self, rng: DocRange) -> Range<usize> {
+        self.text.range_to_offsets(rng)
+    }
+
     /// current offset of cursor
     pub fn coff(&self) -> usize {
         self.pos_to_offset(self.cursor.pos)
@@ -386,27 +812,30 @@ pub struct Cursor {
     /// translation more convienent, especially when the buffer is stored as an array of lines
     /// rather than a flat byte array (although it seems like this would slow transversal?).
     pub pos: DocPos,
-    pub virtcol: usize,
+    /// display column that moving up and down tries to keep, `None` for the column of `pos`. It's
+    /// set by moving sideways.
+    pub virtcol: Option<usize>,
+    /// columns past the end of the line with `virtualedit`, where `pos` is at the end of the line
+    pub coladd: usize,
     pub topline: usize,
 }
 
 impl Cursor {
-    /// gets the relative position of the cursor when displayed in win
-    pub fn win_pos(&self, _win: &WindowInner) -> TermPos {
+    /// gets the relative position of the cursor when displayed in win, where `line` is the line
+    /// it's on and tabs go to multiples of `tabstop`
+    pub fn win_pos(&self, line: &str, tabstop: usize) -> TermPos {
         let y = self
             .pos
             .y
             .checked_sub(self.topline)
             .expect("tried to move cursor above window") as u32;
-        // let y = y + win.bounds().start.y;
-        // let x = self.pos.x as u32 + win.bounds().start.x;
-        let x = self.pos.x as u32;
+        let x = (crate::window::x_to_col(line, self.pos.x, tabstop) + self.coladd) as u32;
         TermPos { x, y }
     }
 
     /// gets the absolute position of the cursor relative to the origin of the window.
-    pub fn term_pos(&self, win: &WindowInner) -> TermPos {
-        let TermPos { x, y } = self.win_pos(win);
+    pub fn term_pos(&self, win: &WindowInner, line: &str) -> TermPos {
+        let TermPos { x, y } = self.win_pos(line, win.tabstop);
         let x = x + win.inner_bounds().start.x;
         let y = y + win.inner_bounds().start.y;
         TermPos { x, y }
@@ -415,20 +844,26 @@ impl Cursor {
     pub fn new() -> Self {
         Self {
             pos: DocPos { x: 0, y: 0 },
-            virtcol: 0,
+            virtcol: None,
+            coladd: 0,
             topline: 0,
         }
     }
 
-    pub fn draw(&self, win: &WindowInner, tui: &mut TermGrid) {
-        tui.set_cursorpos(self.term_pos(win));
+    /// put the terminal's cursor where this one is, or on the last column of the window when it's
+    /// further right, since lines aren't scrolled sideways
+    pub fn draw(&self, win: &WindowInner, line: &str, tui: &mut TermGrid) {
+        let pos = self.term_pos(win, line);
+        let x = pos.x.min(win.inner_bounds().end.x.saturating_sub(1));
+        tui.set_cursorpos(TermPos { x, ..pos });
     }
 
     /// sets the position and virtual positon to pos, updating topline if moved above but not if
     /// too far below
     pub fn set_pos(&mut self, pos: DocPos) {
         self.pos = pos;
-        self.virtcol = pos.x;
+        self.virtcol = None;
+        self.coladd = 0;
         if self.topline > pos.y {
             self.topline = pos.y
         }
@@ -549,8 +984,44 @@ pub mod test {
         start..end
     }
 
+    /// small deterministic generator for property tests, so a failure can be reproduced from its
+    /// seed
+    pub struct Rng(u64);
+
+    impl Rng {
+        pub fn new(seed: u64) -> Self {
+            Self(seed.wrapping_mul(0x9e37_79b9_7f4a_7c15) | 1)
+        }
+
+        pub fn next(&mut self) -> u64 {
+            // xorshift64
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        /// uniform in `0..n`
+        pub fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+
+        /// text of up to `max_len` characters, with plenty of line breaks and a multibyte character
+        pub fn text(&mut self, max_len: usize) -> String {
+            const CHARS: &[char] = &['a', 'b', ' ', '\n', 'é'];
+            let len = self.below(max_len + 1);
+            (0..len).map(|_| CHARS[self.below(CHARS.len())]).collect()
+        }
+
+        /// offset of a random character boundary in `s`, including the end
+        pub fn boundary(&mut self, s: &str) -> usize {
+            let boundaries: Vec<_> = (0..=s.len()).filter(|&i| s.is_char_boundary(i)).collect();
+            boundaries[self.below(boundaries.len())]
+        }
+    }
+
     /// get [`DocPos`] of offset in `&str`
-    fn str_doc_pos_off(s: &str, off: usize) -> DocPos {
+    pub fn str_doc_pos_off(s: &str, off: usize) -> DocPos {
         let off = off.min(s.len());
         s.lines_inclusive()
             .map(str::len)
@@ -595,6 +1066,257 @@ pub mod test {
         assert_eq!(str_doc_pos_off("as\ndf", 6), DocPos { x: 2, y: 1 });
     }
 
+    #[test]
+    fn offset_pos_round_trip() {
+        for seed in 0..300 {
+            let mut rng = Rng::new(seed);
+            let s = rng.text(40);
+            let buf = BufferCore::from_str(&s);
+            for off in (0..=s.len()).filter(|&i| s.is_char_boundary(i)) {
+                let pos = buf.offset_to_end_pos(off);
+                assert_eq!(buf.pos_to_offset(pos), off, "seed {seed}: {s:?} at {off}");
+                if off < s.len() {
+                    assert_eq!(pos, str_doc_pos_off(&s, off), "seed {seed}: {s:?} at {off}");
+                }
+            }
+            let (a, b) = (rng.boundary(&s), rng.boundary(&s));
+            let rng = a.min(b)..a.max(b);
+            let doc_rng = buf.offsets_to_range(rng.clone());
+            assert!(doc_rng.start <= doc_rng.end);
+            assert_eq!(buf.range_to_offsets(doc_rng), rng, "seed {seed}: {s:?}");
+        }
+    }
+
+    /// check a backend against a `String` after every edit of a random sequence
+    fn fuzz_edits<B: BufCore>() {
+        for seed in 0..500 {
+            let mut rng = Rng::new(seed);
+            let mut model = rng.text(20);
+            let mut buf = B::from_str(&model);
+            let mut cursor = Cursor::new();
+            let mut log = Vec::new();
+            for _ in 0..20 {
+                match rng.below(8) {
+                    0..=3 => {
+                        let off = rng.boundary(&model);
+                        let s = rng.text(5);
+                        log.push(format!("insert {s:?} at {off}"));
+                        cursor.pos = buf.offset_to_end_pos(off);
+                        buf.insert_str(&mut cursor, &s);
+                        model.insert_str(off, &s);
+                        assert_eq!(buf.pos_to_offset(cursor.pos), off + s.len(), "seed {seed}: {log:?}");
+                    }
+                    4 | 5 => {
+                        let (a, b) = (rng.boundary(&model), rng.boundary(&model));
+                        let rng = a.min(b)..a.max(b);
+                        log.push(format!("delete {rng:?}"));
+                        let deleted = buf.delete_range(rng.clone());
+                        assert_eq!(deleted, model[rng.clone()], "seed {seed}: {log:?}");
+                        model.replace_range(rng, "");
+                    }
+                    6 if !model.is_empty() => {
+                        let last = model.char_indices().last().map_or(0, |(i, _)| i);
+                        let off = rng.boundary(&model[..last]);
+                        log.push(format!("delete char at {off}"));
+                        assert_eq!(buf.delete_char(off), model.remove(off), "seed {seed}: {log:?}");
+                    }
+                    _ => {
+                        log.push("clear".to_string());
+                        buf.clear(&mut cursor);
+                        model.clear();
+                        assert_eq!(cursor.pos, DocPos::default());
+                    }
+                }
+                let mut out = Vec::new();
+                buf.serialize(&mut out).unwrap();
+                assert_eq!(String::from_utf8(out).unwrap(), model, "seed {seed}: {log:?}");
+                assert_eq!(buf.len(), model.len());
+                assert_eq!(buf.linecnt(), model.lines().count(), "seed {seed}: {log:?}");
+                assert_eq!(buf.get_lines(0..buf.linecnt()), model.lines().collect::<Vec<_>>());
+                for off in (0..=model.len()).filter(|&i| model.is_char_boundary(i)) {
+                    let pos = buf.offset_to_end_pos(off);
+                    assert_eq!(buf.pos_to_offset(pos), off, "seed {seed}: {log:?} at {off}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fuzz_simple_buffer() {
+        fuzz_edits::<simplebuffer::SimpleBuffer>();
+    }
+
+    #[test]
+    fn modified_until_saved() {
+        let mut buf = BufferInner::from_str("abc\n");
+        assert!(!buf.is_modified());
+        buf.insert_str("x");
+        assert!(buf.is_modified());
+        buf.mark_saved();
+        assert!(!buf.is_modified());
+    }
+
+    /// `a` `é` `漢` on the first line and `ü` `🙂` `b` on the second, at byte offsets 0, 1, 3, 7,
+    /// 9 and 13
+    const MULTIBYTE: &str = "aé漢\nü🙂b\n";
+
+    /// text that motions and iterators have to get right beyond ASCII words, shared with the tests
+    /// of the motions
+    pub const CORPUS: &[&str] = &[
+        "naïve café, déjà vu",
+        "emoji 🦀 in👍🏽 text 🙂.",
+        "\tindented\twith\ttabs\n\t\tand more",
+        "windows\r\nline endings\r\n\r\nhere\r\n",
+        "Sentence one. Sentence two!  Über drei?\nNext line",
+        "mixed.punct(漢字)_ünder  ",
+        "",
+    ];
+
+    /// a file of `lines` lines of words, numbers and multibyte characters
+    pub fn long_text(lines: usize) -> String {
+        (0..lines).map(|i| format!("line {i}: naïve\t🦀 word.\n")).collect()
+    }
+
+    #[test]
+    fn corpus_iterators() {
+        for &text in CORPUS {
+            let buf = BufferInner::from_str(text);
+            let rope = RopeBuffer::from_str(text);
+            for (off, c) in text.char_indices() {
+                let fwd: Vec<_> = text[off..].char_indices().map(|(i, c)| (off + i, c)).collect();
+                let bck: Vec<_> = text[..off + c.len_utf8()].char_indices().rev().collect();
+                assert_eq!(buf.chars_fwd(off).collect::<Vec<_>>(), fwd, "{text:?} at {off}");
+                assert_eq!(buf.chars_bck(off).collect::<Vec<_>>(), bck, "{text:?} at {off}");
+
+                let pos = rope.offset_to_pos(off);
+                let at = |(i, c): (usize, char)| (str_doc_pos_off(text, i), c);
+                let fwd: Vec<_> = fwd.into_iter().map(at).collect();
+                let bck: Vec<_> = bck.into_iter().map(at).collect();
+                assert_eq!(rope.chars_fwd(pos).collect::<Vec<_>>(), fwd, "rope {text:?} at {off}");
+                assert_eq!(rope.chars_bck(pos).collect::<Vec<_>>(), bck, "rope {text:?} at {off}");
+            }
+        }
+    }
+
+    #[test]
+    fn long_text_iterators() {
+        let text = long_text(100_000);
+        let buf = BufferInner::from_str(&text);
+        let rope = RopeBuffer::from_str(&text);
+        assert!(buf.chars_fwd(0).eq(text.char_indices()));
+        assert!(buf.chars_bck(text.len() - 1).eq(text.char_indices().rev()));
+
+        let mut pos = DocPos { x: 0, y: 0 };
+        for ((doc, c), (_, expected)) in rope.chars_fwd(pos).zip(text.char_indices()) {
+            assert_eq!((doc, c), (pos, expected));
+            pos = if c == '\n' { DocPos { x: 0, y: pos.y + 1 } } else { DocPos { x: pos.x + c.len_utf8(), y: pos.y } };
+        }
+        assert_eq!(pos.y, 100_000);
+        let last = rope.offset_to_pos(text.len() - 1);
+        assert_eq!(rope.chars_bck(last).count(), text.chars().count());
+    }
+
+    #[test]
+    fn undo_redo() {
+        let mut buf = BufferInner::from_str("abc\n");
+        history::undo_break();
+        buf.cursor.set_pos(DocPos { x: 1, y: 0 });
+        buf.insert_str("x");
+        buf.insert_str("y\n");
+        buf.delete_range(0..1);
+        history::undo_break();
+        buf.cursor.set_pos(DocPos { x: 0, y: 1 });
+        buf.delete_char();
+        assert_eq!(buf.to_string(), "xy\nc\n");
+        assert!(buf.undo());
+        assert_eq!(buf.to_string(), "xy\nbc\n");
+        assert!(buf.undo());
+        assert_eq!(buf.to_string(), "abc\n");
+        assert_eq!(buf.cursor.pos, DocPos { x: 1, y: 0 });
+        assert!(!buf.undo());
+        assert!(buf.redo());
+        assert_eq!(buf.to_string(), "xy\nbc\n");
+        assert_eq!(buf.cursor.pos, DocPos { x: 1, y: 0 });
+    }
+
+    #[test]
+    fn set_lines() {
+        let mut buf = BufferInner::from_str("a\nb\nc");
+        assert_eq!(buf.set_lines(1..3, &["b".into(), "C".into()]), 1);
+        assert_eq!(buf.to_string(), "a\nb\nC");
+        assert_eq!(buf.set_lines(0..1, &["a".into()]), 0);
+        assert_eq!(buf.set_lines(0..2, &["x".into()]), 2);
+        assert_eq!(buf.to_string(), "x\nC");
+    }
+
+    #[test]
+    fn multibyte_offsets() {
+        let b = BufferCore::from_str(MULTIBYTE);
+        assert_eq!((b.len(), b.linecnt()), (15, 2));
+        assert_eq!(b.get_lines(0..2), ["aé漢", "ü🙂b"]);
+        assert_eq!(b.line(1), "ü🙂b");
+        assert_eq!((b.floor_char_boundary(4), b.ceil_char_boundary(4)), (3, 6));
+        assert_eq!(b.ceil_char_boundary(100), 15);
+        // offsets in the middle of a character mean the whole character
+        assert_eq!(b.get_char(2), 'é');
+        assert_eq!(b.get_char(11), '🙂');
+        assert_eq!(b.get_range(2..4), "é漢");
+        assert_eq!(b.offset_to_pos(5), DocPos { x: 3, y: 0 });
+        assert_eq!(b.offset_to_pos(10), DocPos { x: 2, y: 1 });
+        assert_eq!(b.pos_to_offset(DocPos { x: 4, y: 0 }), 3);
+        assert_eq!(b.try_pos_to_offset(DocPos { x: 3, y: 1 }), Some(9));
+        assert_eq!(b.offset_to_end_pos(15), DocPos { x: 8, y: 1 });
+        assert_eq!(b.chars_fwd(4).map(|(_, c)| c).collect::<String>(), "漢\nü🙂b\n");
+        assert_eq!(b.chars_fwd(4).nth(2), Some((7, 'ü')));
+        assert_eq!(b.chars_bck(4).collect::<Vec<_>>(), [(3, '漢'), (1, 'é'), (0, 'a')]);
+        assert_eq!(b.chars_bck(1).map(|(_, c)| c).collect::<String>(), "éa");
+    }
+
+    #[test]
+    fn multibyte_edits() {
+        let mut b = BufferCore::from_str(MULTIBYTE);
+        assert_eq!(b.delete_char(4), '漢');
+        assert_eq!(b.delete_range(1..5), "é\nü");
+        assert_buf_eq(&b, "a🙂b\n");
+        let mut cursor = Cursor::new();
+        cursor.set_pos(DocPos { x: 3, y: 0 });
+        b.insert_str(&mut cursor, "c");
+        assert_buf_eq(&b, "ac🙂b\n");
+        assert_eq!(cursor.pos, DocPos { x: 2, y: 0 });
+        b.clear(&mut cursor);
+        assert_eq!(b.len(), 0);
+    }
+
+    #[test]
+    fn multibyte_buffer_edits() {
+        let mut buf = BufferInner::from_str(MULTIBYTE);
+        buf.cursor.set_pos(DocPos { x: 1, y: 1 });
+        assert_eq!(buf.delete_char(), Some('ü'));
+        buf.cursor.set_pos(DocPos { x: 3, y: 0 });
+        assert_eq!(buf.delete_char_before(), Some('é'));
+        assert_eq!(buf.delete_range(2..=2), "漢");
+        assert_eq!(buf.to_string(), "a\n🙂b\n");
+    }
+
+    #[test]
+    fn open_errors() {
+        let dir = std::env::temp_dir();
+        let e = Buffer::edit(&dir).err().unwrap();
+        assert_eq!(e.to_string(), format!("E17: {dir:?} is a directory"));
+        let missing = dir.join(format!("edit-missing-{}", std::process::id()));
+        assert_eq!(Buffer::edit(&missing).unwrap().get().path(), Some(&*missing));
+        assert_eq!(file_flag(&missing), Some("[New]"));
+    }
+
+    #[test]
+    fn named_after_file() {
+        assert_eq!(Buffer::new().get().name(), "[No Name]");
+        let buf = Buffer::new_file("dir/new.txt".into());
+        let buf = buf.get();
+        assert_eq!((buf.name(), buf.path()), ("new.txt", Some(std::path::Path::new("dir/new.txt"))));
+        assert_eq!(buf.len(), 0);
+    }
+
     macro_rules! get_lines_test {
         ($(#[$meta:meta])* $name:ident, $bufdef:tt, $lines:expr) => {
             #[test]
@@ -666,7 +1388,7 @@ pub mod test {
             fn $name() {
                 let buf = BufferInner::from_str($str);
                 let mut it_test = buf.chars_fwd($start);
-                for c in $str[$start..].chars() {
+                for c in $str[$start..].char_indices().map(|(i, c)| ($start + i, c)) {
                     assert_eq!(
                         it_test.next(),
                         Some(c),
@@ -675,6 +1397,13 @@ pub mod test {
                 }
                 assert_eq!(it_test.next(), None, "end of iter");
                 assert_eq!(it_test.next(), None, "end of iter 2");
+
+                let rope = RopeBuffer::from_str($str);
+                let expected: Vec<_> = $str[$start..]
+                    .char_indices()
+                    .map(|(i, c)| (str_doc_pos_off($str, $start + i), c))
+                    .collect();
+                assert_eq!(rope.chars_fwd(rope.offset_to_pos($start)).collect::<Vec<_>>(), expected, "rope");
             }
         };
     }
@@ -694,7 +1423,7 @@ pub mod test {
                 let buf = mkbuf!($init);
                 let bufstr = buf.to_string();
                 let mut it_test = buf.chars_bck($start);
-                for c in bufstr[..($start + 1).min(bufstr.len())].chars().rev() {
+                for c in bufstr[..($start + 1).min(bufstr.len())].char_indices().rev() {
                     dbg!(c);
                     assert_eq!(
                         it_test.next(),
@@ -704,6 +1433,14 @@ pub mod test {
                 }
                 assert_eq!(it_test.next(), None, "end of iter");
                 assert_eq!(it_test.next(), None, "end of iter 2");
+
+                let rope = RopeBuffer::from_str(&bufstr);
+                let expected: Vec<_> = bufstr[..($start + 1).min(bufstr.len())]
+                    .char_indices()
+                    .rev()
+                    .map(|(i, c)| (str_doc_pos_off(&bufstr, i), c))
+                    .collect();
+                assert_eq!(rope.chars_bck(rope.offset_to_pos($start)).collect::<Vec<_>>(), expected, "rope");
             }
         };
     }
@@ -807,6 +1544,48 @@ pub mod test {
         assert_eq!(buf.len(), 0);
     }
 
+    #[test]
+    fn append_keeps_cursor() {
+        let mut buf = BufferInner::from_str("one\ntwo\n");
+        buf.cursor.set_pos(DocPos { x: 1, y: 0 });
+        buf.append("three\n");
+        assert_eq!(&buf.to_string(), "one\ntwo\nthree\n");
+        assert_eq!(buf.cursor.pos, DocPos { x: 1, y: 0 });
+        buf.append("four");
+        assert_eq!(&buf.to_string(), "one\ntwo\nthree\nfour");
+
+        let mut buf = BufferInner::new();
+        buf.append("a\n");
+        buf.append("b\n");
+        assert_eq!(&buf.to_string(), "a\nb\n");
+    }
+
+    #[test]
+    fn insert_at_keeps_cursor() {
+        let mut buf = BufferInner::from_str("one\ntwo\n");
+        buf.cursor.set_pos(DocPos { x: 2, y: 1 });
+        buf.cursor.coladd = 3;
+        buf.insert_at(7, "x");
+        assert_eq!(&buf.to_string(), "one\ntwox\n");
+        assert_eq!((buf.cursor.pos, buf.cursor.coladd), (DocPos { x: 2, y: 1 }, 3));
+        buf.insert_at(0, "zero\n");
+        assert_eq!(&buf.to_string(), "zero\none\ntwox\n");
+        assert_eq!((buf.cursor.pos, buf.cursor.coladd), (DocPos { x: 2, y: 2 }, 3));
+    }
+
+    #[test]
+    fn replace_lines() {
+        let mut buf = BufferInner::from_str("one\ntwo\nthree\n");
+        buf.cursor.set_pos(DocPos { x: 2, y: 2 });
+        buf.replace_lines(1..2, "2\n2.5\n");
+        assert_eq!(&buf.to_string(), "one\n2\n2.5\nthree\n");
+        assert_eq!(buf.cursor.pos, DocPos { x: 2, y: 2 });
+        buf.replace_lines(4..4, "four\n");
+        assert_eq!(&buf.to_string(), "one\n2\n2.5\nthree\nfour\n");
+        buf.replace_lines(0..2, "");
+        assert_eq!(&buf.to_string(), "2.5\nthree\nfour\n");
+    }
+
     macro_rules! delete_range_test {
         ($name:ident, $str:literal, $range:expr, $cursor:expr) => {
             #[test]
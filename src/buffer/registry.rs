@@ -0,0 +1,70 @@
+//! Every live buffer, by [`BufId`].
+//!
+//! Buffers register themselves when they're created and are removed when the last [`Arc`] to them
+//! is dropped, so looking up an id never keeps a closed buffer alive. `:bdelete` removes a buffer
+//! right away with [`release`], so that whatever still holds it, like a running job writing to
+//! it, can't bring it back to Guile or `:b`. This is global rather than part of
+//! [`crate::render::Ctx`] so that Guile can resolve ids without a context.
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, Weak};
+
+use super::Buffer;
+use crate::render::BufId;
+
+static BUFFERS: Mutex<BTreeMap<BufId, Weak<Buffer>>> = Mutex::new(BTreeMap::new());
+
+fn buffers() -> std::sync::MutexGuard<'static, BTreeMap<BufId, Weak<Buffer>>> {
+    BUFFERS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+pub(super) fn register(buf: Buffer) -> Arc<Buffer> {
+    let buf = Arc::new(buf);
+    buffers().insert(buf.id(), Arc::downgrade(&buf));
+    buf
+}
+
+/// called when a buffer is dropped
+pub(super) fn unregister(id: BufId) {
+    buffers().remove(&id);
+}
+
+/// forget the buffer with `id` before it's dropped
+pub fn release(id: BufId) {
+    buffers().remove(&id);
+}
+
+/// the buffer with `id`, if it's still open
+pub fn get(id: BufId) -> Option<Arc<Buffer>> {
+    buffers().get(&id)?.upgrade()
+}
+
+/// all open buffers, ordered by id
+pub fn all() -> Vec<Arc<Buffer>> {
+    // collected before the lock is released, so a buffer being dropped here can unregister itself
+    let all: Vec<_> = buffers().values().map(Weak::clone).collect();
+    all.iter().filter_map(Weak::upgrade).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lookup_until_dropped() {
+        let buf = Buffer::from_string("text");
+        let id = buf.id();
+        assert!(Arc::ptr_eq(&get(id).unwrap(), &buf));
+        assert!(all().iter().any(|b| b.id() == id));
+        drop(buf);
+        assert!(get(id).is_none());
+        assert!(!all().iter().any(|b| b.id() == id));
+    }
+
+    #[test]
+    fn released_while_held() {
+        let buf = Buffer::from_string("text");
+        release(buf.id());
+        assert!(get(buf.id()).is_none());
+        assert!(!all().iter().any(|b| Arc::ptr_eq(b, &buf)));
+    }
+}
@@ -1,19 +1,18 @@
 use std::borrow::Cow;
-use std::cell::Cell;
-use std::collections::VecDeque;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashSet, VecDeque};
 use std::ffi::OsStr;
 use std::fmt::Debug;
 use std::fmt::Display;
 use std::io::ErrorKind;
 use std::io::Write;
-use std::iter::Rev;
 use std::ops::Range;
 use std::path::Path;
 use std::path::PathBuf;
 use std::rc::Rc;
 use std::str::Chars;
 
-use crate::buffer::DocPos;
+use crate::buffer::{DocPos, DocRange, MemUsage};
 
 /// normal operations are done as a standard character-wise rope.
 ///
@@ -130,11 +129,11 @@ impl Rope {
         };
         assert!(r.len() <= s.len());
         // dbg!(&s[r.clone()]);
-        let lf_cnt = dbg!(s[r.clone()]
+        let lf_cnt = s[r.clone()]
             .as_bytes()
             .iter()
             .filter(|c| **c == b'\n')
-            .count());
+            .count();
         let ret = if lf_cnt >= 1 {
             let split_idx = s[r.clone()].rfind('\n').expect("multiline string has lf");
             if split_idx == r.len() - 1 {
@@ -249,7 +248,6 @@ impl Rope {
         if pos.y > self.lf_cnt {
             return None;
         };
-        eprintln!("indexing {pos:?} into {:?}", self.to_string());
         match &self.inner {
             NodeInner::Leaf(s, r) => {
                 let line_start_offset: usize = if pos.y > 0 {
@@ -265,23 +263,23 @@ impl Rope {
                 } else {
                     0
                 };
-                if pos.x > s[r.clone()][line_start_offset..].lines().nth(0)?.len() {
+                // not `lines`, which has nothing after a trailing LF
+                let line = s[r.clone()][line_start_offset..].split('\n').next().expect("split is never empty");
+                if pos.x > line.len() {
                     None
                 } else {
                     Some(line_start_offset + pos.x)
                 }
             }
             NodeInner::NonLeaf { l, r, weight } => l.doc_pos_to_offset(pos).or_else(|| {
-                r.doc_pos_to_offset(DocPos {
-                    x: pos.x
-                        - if pos.y == 0 {
-                            l.num_trailing_chars()
-                        } else {
-                            0
-                        },
-                    y: pos.y - l.lf_cnt,
-                })
-                .map(|off| off + weight)
+                // the last line of the left child continues in the right child
+                let x = if pos.y == l.lf_cnt {
+                    pos.x.checked_sub(l.num_trailing_chars())?
+                } else {
+                    pos.x
+                };
+                r.doc_pos_to_offset(DocPos { x, y: pos.y.checked_sub(l.lf_cnt)? })
+                    .map(|off| off + weight)
             }),
             NodeInner::None => {
                 if pos == (DocPos { x: 0, y: 0 }) {
@@ -293,6 +291,45 @@ impl Rope {
         }
     }
 
+    /// Find DocPos from offset, `None` if it is past the end
+    fn offset_to_doc_pos(&self, off: usize) -> Option<DocPos> {
+        match &self.inner {
+            NodeInner::Leaf(s, r) => {
+                let before = s[r.clone()].get(..off)?;
+                let y = before.bytes().filter(|&c| c == b'\n').count();
+                let x = off - before.rfind('\n').map_or(0, |i| i + 1);
+                Some(DocPos { x, y })
+            }
+            NodeInner::NonLeaf { l, r, weight } => {
+                if off < *weight {
+                    return l.offset_to_doc_pos(off);
+                }
+                let pos = r.offset_to_doc_pos(off - weight)?;
+                Some(if pos.y == 0 {
+                    DocPos { x: pos.x + l.num_trailing_chars(), y: l.lf_cnt }
+                } else {
+                    DocPos { x: pos.x, y: pos.y + l.lf_cnt }
+                })
+            }
+            NodeInner::None => (off == 0).then(DocPos::default),
+        }
+    }
+
+    /// the leaf `off` is in and the offset where it starts, `None` past the end
+    fn leaf_at(&self, off: usize) -> Option<(&Rc<str>, &Range<usize>, usize)> {
+        match &self.inner {
+            NodeInner::Leaf(s, r) => (off < r.len()).then_some((s, r, 0)),
+            NodeInner::NonLeaf { l, r, weight } => {
+                if off < *weight {
+                    return l.leaf_at(off);
+                }
+                let (s, range, start) = r.leaf_at(off - weight)?;
+                Some((s, range, start + weight))
+            }
+            NodeInner::None => None,
+        }
+    }
+
     /// Insert at byte offset. Uses `&str` since converting to `Rc<str>` will require reallocation
     /// anyway
     fn insert_offset(self, idx: usize, s: &str) -> Self {
@@ -320,41 +357,73 @@ impl Rope {
         Self::merge(l, r)
     }
 
+    /// the characters from `pos` on
     fn forward_iter(&self, pos: DocPos) -> RopeForwardIter {
         let off = self.doc_pos_to_offset(pos).expect("valid position");
-        let mut ret = RopeForwardIter {
-            stack: VecDeque::new(),
-            curr: None,
-            pos,
-        };
-        let mut curr_idx = 0;
-        ret.stack.push_front(self);
-        while let Some(n) = ret.stack.pop_front() {
-            assert!(curr_idx <= off);
-            match &n.inner {
-                NodeInner::Leaf(s, r) => {
-                    assert!(curr_idx + r.len() > off);
-                    ret.curr = Some(s[r.clone()].chars());
-                    if curr_idx > off {
-                        ret.curr.as_mut().expect("just set").nth(off - curr_idx - 1);
-                    }
-                    break;
+        let mut stack = VecDeque::new();
+        let (mut node, mut base) = (self, 0);
+        // the leaf `off` is in, keeping what comes after it for later
+        let curr = loop {
+            match &node.inner {
+                NodeInner::NonLeaf { r, weight, .. } if off >= base + weight => {
+                    base += weight;
+                    node = r;
                 }
-                NodeInner::NonLeaf { l, r, weight } => {
-                    ret.stack.push_front(&r);
-                    if curr_idx + weight < off {
-                        ret.stack.push_front(&l);
-                        curr_idx += weight;
-                    }
+                NodeInner::NonLeaf { l, r, .. } => {
+                    stack.push_front(&**r);
+                    node = l;
                 }
-                NodeInner::None => (),
+                NodeInner::Leaf(s, r) => break s[r.start + off - base..r.end].chars(),
+                NodeInner::None => break "".chars(),
             }
-        }
-        ret
+        };
+        RopeForwardIter { stack, curr: Some(curr), pos }
     }
 
-    fn backward_iter(&self, _pos: DocPos) -> RopeBackwardIter {
-        todo!()
+    /// the characters before `pos` going backwards, starting with the one at `pos`
+    fn backward_iter(&self, pos: DocPos) -> RopeBackwardIter {
+        let off = self.doc_pos_to_offset(pos).expect("valid position");
+        // the end of the character at `off`
+        let end = match self.leaf_at(off) {
+            Some((s, r, start)) => off + s[r.start + off - start..r.end].chars().next().map_or(0, char::len_utf8),
+            None => off,
+        };
+        let mut stack = VecDeque::new();
+        let (mut node, mut base) = (self, 0);
+        // the leaf `end` is in, keeping what comes before it for later
+        let curr = loop {
+            match &node.inner {
+                NodeInner::NonLeaf { l, r, weight } if end > base + weight => {
+                    stack.push_front(&**l);
+                    base += weight;
+                    node = r;
+                }
+                NodeInner::NonLeaf { l, .. } => node = l,
+                NodeInner::Leaf(s, r) => break &s[r.start..r.start + end - base],
+                NodeInner::None => break "",
+            }
+        };
+        let after = self.offset_to_doc_pos(end).expect("end is in the rope");
+        RopeBackwardIter { rope: self, stack, curr: Some(curr), off: end, pos: after }
+    }
+
+    /// add the nodes of this rope and the text they hold on to to `usage`. Leaves share their
+    /// strings, so each string is only counted once.
+    fn count_mem(&self, seen: &mut HashSet<*const u8>, usage: &mut MemUsage) {
+        usage.nodes += 1;
+        usage.index += std::mem::size_of::<Rope>();
+        match &self.inner {
+            NodeInner::Leaf(s, _) => {
+                if seen.insert(s.as_ptr()) {
+                    usage.text += s.len();
+                }
+            }
+            NodeInner::NonLeaf { l, r, .. } => {
+                l.count_mem(seen, usage);
+                r.count_mem(seen, usage);
+            }
+            NodeInner::None => (),
+        }
     }
 
     fn leaves(&self) -> RopeLeafFwdIter {
@@ -496,15 +565,20 @@ impl Iterator for RopeForwardIter<'_> {
                 y: self.pos.y + 1,
             }
         } else {
-            self.pos.x += 1;
+            self.pos.x += ret_c.len_utf8();
         }
         Some((ret_p, ret_c))
     }
 }
 
 pub struct RopeBackwardIter<'a> {
+    rope: &'a Rope,
     stack: VecDeque<&'a Rope>,
-    curr: Option<Rev<Chars<'a>>>,
+    /// what's left of the current leaf
+    curr: Option<&'a str>,
+    /// offset of the end of the characters that are left
+    off: usize,
+    /// position of the character after the next one
     pos: DocPos,
 }
 
@@ -512,38 +586,41 @@ impl Iterator for RopeBackwardIter<'_> {
     type Item = (DocPos, char);
 
     fn next(&mut self) -> Option<Self::Item> {
-        let ret_c = {
-            if let Some(c) = self.curr.as_mut()?.next() {
-                Some(c)
-            } else {
-                while let Some(front) = self.stack.pop_front() {
-                    match &front.inner {
-                        NodeInner::Leaf(s, r) => {
-                            self.curr = Some(s[r.clone()].chars().rev());
-                            break;
-                        }
-                        NodeInner::NonLeaf { l, r, weight: _ } => {
-                            self.stack.push_front(&l);
-                            self.stack.push_front(&r);
-                        }
-                        NodeInner::None => (),
+        let ret_c = loop {
+            let curr = self.curr.as_mut()?;
+            if let Some(c) = curr.chars().next_back() {
+                *curr = &curr[..curr.len() - c.len_utf8()];
+                break c;
+            }
+            self.curr = None;
+            while let Some(front) = self.stack.pop_front() {
+                match &front.inner {
+                    NodeInner::Leaf(s, r) => {
+                        self.curr = Some(&s[r.clone()]);
+                        break;
                     }
+                    NodeInner::NonLeaf { l, r, weight: _ } => {
+                        self.stack.push_front(&l);
+                        self.stack.push_front(&r);
+                    }
+                    NodeInner::None => (),
                 }
-                self.curr.as_mut()?.next()
             }
-        }?;
+        };
 
-        let ret_p = self.pos;
-        if ret_c == '\n' {
-            self.pos = DocPos {
-                x: 0,
-                y: self.pos.y - 1,
+        self.off -= ret_c.len_utf8();
+        // a line break ends the line before, which is as long as it is. Its start is usually in the
+        // same leaf, otherwise it takes a lookup in the tree.
+        self.pos = if ret_c == '\n' {
+            let rest = self.curr.unwrap_or_default();
+            match rest.rfind('\n') {
+                Some(i) => DocPos { x: rest.len() - i - 1, y: self.pos.y - 1 },
+                None => self.rope.offset_to_doc_pos(self.off).expect("offset in rope"),
             }
         } else {
-            self.pos.x -= 1;
-        }
-        Some((ret_p, ret_c));
-        todo!();
+            DocPos { x: self.pos.x - ret_c.len_utf8(), ..self.pos }
+        };
+        Some((self.pos, ret_c))
     }
 }
 
@@ -613,14 +690,53 @@ impl RopeBuffer {
     //     self.data = new;
     // }
 
+    pub fn mem_usage(&self) -> MemUsage {
+        let mut usage = MemUsage::default();
+        self.data.count_mem(&mut HashSet::new(), &mut usage);
+        usage
+    }
+
     pub fn get_off(&self, pos: DocPos) -> usize {
-        self.cache.pos_docpos(pos).unwrap_or_else(|| {
+        self.cache.leaf_offset(pos).unwrap_or_else(|| {
             let off = self.data.doc_pos_to_offset(pos).unwrap();
-            self.cache.cache_pos(pos, off);
+            self.remember_leaf(off);
             off
         })
     }
 
+    /// unlike [`crate::buffer::BufCore::offset_to_pos`], the end of the buffer is after the last
+    /// character, so this doesn't need an `offset_to_end_pos`
+    pub fn offset_to_pos(&self, off: usize) -> DocPos {
+        self.cache.leaf_pos(off).unwrap_or_else(|| {
+            let pos = self.data.offset_to_doc_pos(off).expect("offset in buffer");
+            self.remember_leaf(off);
+            pos
+        })
+    }
+
+    /// keep the leaf `off` is in for the lookups after this one, which tend to be close by
+    fn remember_leaf(&self, off: usize) {
+        if let Some((text, range, start)) = self.data.leaf_at(off) {
+            let pos = self.data.offset_to_doc_pos(start).expect("leaf is in the rope");
+            self.cache.cache_leaf(LeafHint { text: Rc::clone(text), range: range.clone(), off: start, pos });
+        }
+    }
+
+    pub fn pos_to_offset(&self, pos: DocPos) -> usize {
+        self.get_off(pos)
+    }
+
+    pub fn offsets_to_range(&self, rng: Range<usize>) -> DocRange {
+        DocRange {
+            start: self.offset_to_pos(rng.start),
+            end: self.offset_to_pos(rng.end),
+        }
+    }
+
+    pub fn range_to_offsets(&self, rng: DocRange) -> Range<usize> {
+        self.pos_to_offset(rng.start)..self.pos_to_offset(rng.end)
+    }
+
     pub fn get_lines(&self, _lines: Range<usize>) -> Vec<Cow<str>> {
         todo!()
     }
@@ -654,22 +770,57 @@ impl RopeBuffer {
     }
 }
 
+/// the leaf of the last lookup, where the start of its text is in the rope. Editing and moving
+/// around mostly stay in the same leaf, and it answers those lookups without walking the tree.
+struct LeafHint {
+    text: Rc<str>,
+    range: Range<usize>,
+    off: usize,
+    pos: DocPos,
+}
+
+impl LeafHint {
+    fn leaf(&self) -> &str {
+        &self.text[self.range.clone()]
+    }
+
+    /// the offset of `pos`, if it's in the leaf
+    fn offset_of(&self, pos: DocPos) -> Option<usize> {
+        let leaf = self.leaf();
+        let (line, x) = match pos.y.checked_sub(self.pos.y)? {
+            0 => (0, pos.x.checked_sub(self.pos.x)?),
+            n => (leaf.match_indices('\n').nth(n - 1)?.0 + 1, pos.x),
+        };
+        // past the end of the leaf the line may go on in the next one
+        let len = leaf[line..].split('\n').next().expect("split is never empty").len();
+        (x <= len).then_some(self.off + line + x)
+    }
+
+    /// the position of `off`, if it's in the leaf
+    fn pos_of(&self, off: usize) -> Option<DocPos> {
+        let before = self.leaf().get(..off.checked_sub(self.off)?)?;
+        if before.len() == self.range.len() {
+            return None;
+        }
+        Some(match before.rfind('\n') {
+            Some(i) => DocPos { x: before.len() - i - 1, y: self.pos.y + before.matches('\n').count() },
+            None => DocPos { x: self.pos.x + before.len(), y: self.pos.y },
+        })
+    }
+}
+
 #[derive(Default)]
 struct RopeBufferCache {
     linecnt: Cell<Option<usize>>,
     endpos: Cell<Option<DocPos>>,
-
-    /// single map of a DocPos to offset
-    ///
-    /// TODO: make this work within a line or maybe leaf - need to remember width of character
-    pos: Cell<Option<(DocPos, usize)>>,
+    leaf: RefCell<Option<LeafHint>>,
 }
 
 impl RopeBufferCache {
     fn invalidate(&self) {
         self.linecnt.set(None);
         self.endpos.set(None);
-        self.pos.set(None);
+        self.leaf.replace(None);
     }
 
     fn linecnt(&self) -> Option<usize> {
@@ -688,16 +839,16 @@ impl RopeBufferCache {
         self.endpos.set(Some(endpos))
     }
 
-    fn pos_docpos(&self, pos: DocPos) -> Option<usize> {
-        self.pos.get().filter(|(p, _)| p == &pos).map(|(_, i)| i)
+    fn leaf_offset(&self, pos: DocPos) -> Option<usize> {
+        self.leaf.borrow().as_ref()?.offset_of(pos)
     }
 
-    fn pos_offset(&self, off: usize) -> Option<DocPos> {
-        self.pos.get().filter(|(_, i)| i == &off).map(|(p, _)| p)
+    fn leaf_pos(&self, off: usize) -> Option<DocPos> {
+        self.leaf.borrow().as_ref()?.pos_of(off)
     }
 
-    fn cache_pos(&self, pos: DocPos, off: usize) {
-        self.pos.set(Some((pos, off)));
+    fn cache_leaf(&self, leaf: LeafHint) {
+        self.leaf.replace(Some(leaf));
     }
 }
 
@@ -715,6 +866,15 @@ mod test {
         assert_eq!(Rope::from("asdf").validate().to_string(), "asdf");
     }
 
+    #[test]
+    fn mem_usage() {
+        let buf = RopeBuffer::from_str("ab\ncd\nef");
+        let usage = buf.mem_usage();
+        assert_eq!(usage.text, 8);
+        assert!(usage.nodes > 1);
+        assert_eq!(usage.index, usage.nodes * std::mem::size_of::<Rope>());
+    }
+
     #[test]
     fn insert_into_rope_simple() {
         assert_eq!(
@@ -810,4 +970,78 @@ mod test {
             Some(7)
         );
     }
+
+    #[test]
+    fn cached_lookups() {
+        use crate::buffer::test::Rng;
+        for seed in 0..100 {
+            let mut rng = Rng::new(seed);
+            let mut s = String::new();
+            let mut rope = Rope::new();
+            for _ in 0..rng.below(6) {
+                let piece = rng.text(12);
+                let at = rng.boundary(&s);
+                s.insert_str(at, &piece);
+                rope = rope.insert_offset(at, &piece);
+            }
+            let offs: Vec<_> = (0..=s.len()).filter(|&i| s.is_char_boundary(i)).collect();
+            let expected: Vec<_> = offs.iter().map(|&off| rope.offset_to_doc_pos(off).unwrap()).collect();
+            let buf = RopeBuffer { data: rope, ..RopeBuffer::from_str("") };
+            // forwards and backwards, then jumping around, which is a mix of hits and misses
+            let order = offs.iter().enumerate().chain(offs.iter().enumerate().rev());
+            let jumps = (0..offs.len()).map(|_| rng.below(offs.len())).map(|i| (i, &offs[i]));
+            for (i, &off) in order.chain(jumps) {
+                assert_eq!(buf.offset_to_pos(off), expected[i], "seed {seed}: {s:?} at {off}");
+                assert_eq!(buf.pos_to_offset(expected[i]), off, "seed {seed}: {s:?} at {off}");
+            }
+        }
+    }
+
+    #[test]
+    fn iterators() {
+        use crate::buffer::test::{str_doc_pos_off, Rng};
+        for seed in 0..100 {
+            let mut rng = Rng::new(seed);
+            let mut s = String::new();
+            let mut rope = Rope::new();
+            for _ in 0..rng.below(6) {
+                let piece = rng.text(12);
+                let at = rng.boundary(&s);
+                s.insert_str(at, &piece);
+                rope = rope.insert_offset(at, &piece);
+            }
+            let chars: Vec<_> = s.char_indices().map(|(i, c)| (str_doc_pos_off(&s, i), c)).collect();
+            for (i, &(pos, _)) in chars.iter().enumerate() {
+                assert_eq!(rope.forward_iter(pos).collect::<Vec<_>>(), chars[i..], "seed {seed}: {s:?} at {pos:?}");
+                let back: Vec<_> = chars[..=i].iter().rev().copied().collect();
+                assert_eq!(rope.backward_iter(pos).collect::<Vec<_>>(), back, "seed {seed}: {s:?} at {pos:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn offset_pos_round_trip() {
+        use crate::buffer::test::{str_doc_pos_off, Rng};
+        for seed in 0..300 {
+            let mut rng = Rng::new(seed);
+            // build from several insertions so there are many leaves
+            let mut s = String::new();
+            let mut rope = Rope::new();
+            for _ in 0..rng.below(5) {
+                let piece = rng.text(10);
+                let at = rng.boundary(&s);
+                s.insert_str(at, &piece);
+                rope = rope.insert_offset(at, &piece);
+            }
+            assert_eq!(rope.validate().to_string(), s);
+            for off in (0..=s.len()).filter(|&i| s.is_char_boundary(i)) {
+                let pos = rope.offset_to_doc_pos(off).expect("offset in rope");
+                assert_eq!(rope.doc_pos_to_offset(pos), Some(off), "seed {seed}: {s:?} at {off}");
+                if off < s.len() {
+                    assert_eq!(pos, str_doc_pos_off(&s, off), "seed {seed}: {s:?} at {off}");
+                }
+            }
+            assert_eq!(rope.offset_to_doc_pos(s.len() + 1), None);
+        }
+    }
 }
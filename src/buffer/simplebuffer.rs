@@ -23,7 +23,7 @@ impl super::BufCore for SimpleBuffer {
             data: String::new(),
             lines: Vec::new().into(),
             outdated_lines: true.into(),
-            name: "[ new simple buffer ]".to_string(),
+            name: "[No Name]".to_string(),
             path: None,
         }
     }
@@ -72,25 +72,37 @@ impl super::BufCore for SimpleBuffer {
     }
 
     fn delete_char(&mut self, off: usize) -> char {
-        let c = self.data.remove(off);
+        let c = self.data.remove(self.floor_char_boundary(off));
         *self.outdated_lines.get_mut() = true;
         c
     }
 
+    fn mem_usage(&self) -> super::MemUsage {
+        super::MemUsage {
+            text: self.data.capacity(),
+            index: self.lines.read().unwrap().capacity() * std::mem::size_of::<usize>(),
+            ..Default::default()
+        }
+    }
+
     fn linecnt(&self) -> usize {
         self.line_nums().len()
     }
 
     fn insert_str(&mut self, ctx: &mut Cursor, s: &str) {
         let off = self.pos_to_offset(ctx.pos);
+        // the cursor may have been in the middle of a character
+        let x = off - self.line_nums().get(ctx.pos.y).copied().unwrap_or(0);
         self.data.insert_str(off, s);
         *self.outdated_lines.get_mut() = true;
         let new_off = off + s.len();
-        if s.contains('\n') {
+        // inserting after a trailing newline starts a new line, so the cursor can't stay on the
+        // line it was given
+        if s.contains('\n') || self.data[..off].ends_with('\n') {
             self.update_bufctx(ctx, new_off);
         } else {
-            ctx.pos.x += s.len();
-            ctx.virtcol = ctx.pos.x;
+            ctx.pos.x = x + s.len();
+            ctx.virtcol = None;
         }
     }
 
@@ -109,10 +121,14 @@ impl super::BufCore for SimpleBuffer {
     }
 
     fn set_path(&mut self, path: std::path::PathBuf) {
+        if let Some(name) = path.file_name() {
+            self.name = name.to_string_lossy().into_owned();
+        }
         self.path = Some(path);
     }
 
     fn delete_range(&mut self, range: Range<usize>) -> String {
+        let range = self.floor_char_boundary(range.start)..self.ceil_char_boundary(range.end);
         let old = self.data[range.clone()].to_owned();
         self.data.replace_range(range, "");
         *self.outdated_lines.get_mut() = true;
@@ -133,7 +149,7 @@ impl super::BufCore for SimpleBuffer {
         if pos.x > max_x {
             None
         } else {
-            Some(line + pos.x)
+            Some(self.floor_char_boundary(line + pos.x))
         }
     }
 
@@ -142,6 +158,7 @@ impl super::BufCore for SimpleBuffer {
     }
 
     fn offset_to_pos(&self, off: usize) -> DocPos {
+        let off = self.floor_char_boundary(off);
         let lines = self.line_nums();
         let y = lines
             .iter()
@@ -156,11 +173,27 @@ impl super::BufCore for SimpleBuffer {
     }
 
     fn get_range(&self, rng: Range<usize>) -> String {
-        self.data[rng].to_owned()
+        self.data[self.floor_char_boundary(rng.start)..self.ceil_char_boundary(rng.end)].to_owned()
     }
 
     fn get_char(&self, pos: usize) -> char {
-        self.data[pos..].chars().next().expect("valid pos")
+        self.data[self.floor_char_boundary(pos)..].chars().next().expect("valid pos")
+    }
+
+    fn floor_char_boundary(&self, off: usize) -> usize {
+        let mut off = off.min(self.data.len());
+        while !self.data.is_char_boundary(off) {
+            off -= 1;
+        }
+        off
+    }
+
+    fn ceil_char_boundary(&self, off: usize) -> usize {
+        let mut off = off.min(self.data.len());
+        while !self.data.is_char_boundary(off) {
+            off += 1;
+        }
+        off
     }
 
 }
@@ -190,20 +223,22 @@ impl SimpleBuffer {
     }
 
     fn update_bufctx(&self, ctx: &mut Cursor, new_off: usize) {
-        let pos = self.offset_to_pos(new_off);
+        let pos = self.offset_to_end_pos(new_off);
         ctx.pos = pos;
-        ctx.virtcol = pos.y;
+        ctx.virtcol = None;
     }
 }
 
 
 impl SimpleBuffer {
-    pub fn chars_fwd(&self, pos: usize) -> impl Iterator<Item = char> + '_ {
-        self.data[pos..].chars()
+    /// the characters from the one `pos` is in to the end, with their offsets
+    pub fn chars_fwd(&self, pos: usize) -> impl Iterator<Item = (usize, char)> + '_ {
+        let start = self.floor_char_boundary(pos);
+        self.data[start..].char_indices().map(move |(i, c)| (start + i, c))
     }
 
-    pub fn chars_bck(&self, pos: usize) -> impl Iterator<Item = char> + '_ {
-        // we do this so as to not crash on empty buffer
-        self.data[..(pos + 1).min(self.data.len())].chars().rev()
+    /// the characters before `pos`, starting with the one it's in, with their offsets
+    pub fn chars_bck(&self, pos: usize) -> impl Iterator<Item = (usize, char)> + '_ {
+        self.data[..self.ceil_char_boundary(pos + 1)].char_indices().rev()
     }
 }
@@ -0,0 +1,132 @@
+//! Keeping the screen and Ctrl-c alive while the main loop is busy with one long command.
+//!
+//! Nothing is drawn and no keys are read while a command runs, which is a problem for a substitute
+//! over a huge file or a macro run ten thousand times. Every change to a buffer calls [`tick`],
+//! which is cheap until [`INTERVAL`] has passed since the main loop last got control or since the
+//! last time it did anything, and then paints "working…" on the bottom row and reads the keys typed
+//! in the meantime. A change can't stop halfway, so a Ctrl-c among them is only noted: loops that
+//! make many changes call [`check`] between them, which fails with [`EditorError::Aborted`] from
+//! then on until the main loop gets control again, so that the command stops where it is. The
+//! other keys are kept, [`crate::input::TermReader`] gives them out before anything newer.
+//!
+//! It's only done on the thread that [`enable`]s it, which is the main loop's, so not in tests.
+use std::cell::{Cell, RefCell};
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use crate::error::EditorError;
+
+/// how often a busy command lets the screen and keys be looked after
+pub const INTERVAL: Duration = Duration::from_millis(250);
+
+thread_local! {
+    static ENABLED: Cell<bool> = const { Cell::new(false) };
+    /// when the main loop last got control or [`check`] last looked after things
+    static LAST: Cell<Option<Instant>> = const { Cell::new(None) };
+    static INTERRUPTED: Cell<bool> = const { Cell::new(false) };
+    /// keys read while busy, for the main loop
+    static KEPT: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// paint and read keys from [`check`] on this thread
+pub fn enable() {
+    ENABLED.set(true);
+}
+
+/// the main loop has control again, a command run after this starts with a clean slate
+pub fn begin() {
+    LAST.set(Some(Instant::now()));
+    INTERRUPTED.set(false);
+}
+
+/// call every so often from a loop that can take long, and stop if it's an error
+pub fn check() -> Result<(), EditorError> {
+    tick();
+    if INTERRUPTED.get() {
+        return Err(EditorError::Aborted);
+    }
+    Ok(())
+}
+
+/// look after the screen and keys if it's time, noting a Ctrl-c for the next [`check`]
+pub fn tick() {
+    if INTERRUPTED.get() || !ENABLED.get() || LAST.get().is_some_and(|t| t.elapsed() < INTERVAL) {
+        return;
+    }
+    use nix::poll::{poll, PollFd, PollFlags};
+    paint();
+    let mut keys = [0; 256];
+    let mut ctrl_c = false;
+    // straight from the terminal, the reader of the main loop would give back the kept keys
+    while matches!(poll(&mut [PollFd::new(libc::STDIN_FILENO, PollFlags::POLLIN)], 0), Ok(n) if n > 0) {
+        match nix::unistd::read(libc::STDIN_FILENO, &mut keys) {
+            Ok(n) if n > 0 => ctrl_c |= sift(&keys[..n]),
+            _ => break,
+        }
+    }
+    LAST.set(Some(Instant::now()));
+    if ctrl_c {
+        INTERRUPTED.set(true);
+    }
+}
+
+/// keep `keys` except for Ctrl-c, returns whether there was one
+fn sift(keys: &[u8]) -> bool {
+    KEPT.with_borrow_mut(|kept| kept.extend(keys.iter().filter(|&&k| k != b'\x03')));
+    keys.contains(&b'\x03')
+}
+
+/// whether keys were kept by [`check`]
+pub fn has_keys() -> bool {
+    KEPT.with_borrow(|kept| !kept.is_empty())
+}
+
+/// move as many kept keys as fit into `buf`, returns how many
+pub fn take_keys(buf: &mut [u8]) -> usize {
+    KEPT.with_borrow_mut(|kept| {
+        let n = kept.len().min(buf.len());
+        buf[..n].copy_from_slice(&kept[..n]);
+        kept.drain(..n);
+        n
+    })
+}
+
+/// "working…" on the bottom row, until the next time the screen is drawn
+fn paint() {
+    let rows = terminal_size::terminal_size().map_or(40, |(_, h)| h.0);
+    let mut stdout = std::io::stdout().lock();
+    // saving and restoring the cursor around it
+    let _ = write!(stdout, "\x1b7\x1b[{rows};1H\x1b[0m\x1b[2Kworking… (Ctrl-c to stop)\x1b8");
+    let _ = stdout.flush();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keys() {
+        assert!(!sift(b"ab"));
+        assert!(sift(b"\x03c"));
+        assert!(has_keys());
+        let mut buf = [0; 2];
+        assert_eq!(take_keys(&mut buf), 2);
+        assert_eq!(&buf, b"ab");
+        assert_eq!(take_keys(&mut buf), 1);
+        assert_eq!(buf[0], b'c');
+        assert!(!has_keys());
+    }
+
+    #[test]
+    fn interrupted() {
+        begin();
+        assert!(check().is_ok());
+        INTERRUPTED.set(true);
+        assert!(matches!(check(), Err(EditorError::Aborted)));
+        // a change in between doesn't clear it
+        tick();
+        assert!(check().is_err());
+        begin();
+        assert!(check().is_ok());
+    }
+}
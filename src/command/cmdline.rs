@@ -2,7 +2,7 @@ use crate::buffer::{Buffer, BufferInner};
 use crate::debug::log;
 use crate::{guile, prelude::*};
 use std::fmt::Write;
-use std::sync::{mpsc, Arc, OnceLock};
+use std::sync::Arc;
 
 use crate::render::BufId;
 use crate::term::TermPos;
@@ -10,13 +10,71 @@ use crate::tui::{TermBox, TextSeverity};
 use crate::window::{Component, Window};
 use crate::{term, window::WindowInner};
 
+use super::complete::Completion;
 use super::{parser, Command};
 
-pub static CMD_TX: OnceLock<mpsc::Sender<CmdMsg>> = OnceLock::new();
-
+/// editing the command being typed, at the cursor
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CommandLineInput {
     Append(char),
+    Insert(String),
+    /// backspace
     Delete,
+    DeleteAfter,
+    /// `Ctrl-w`, delete the word before the cursor
+    DeleteWord,
+    /// `Ctrl-u`, delete everything before the cursor
+    DeleteLine,
+    Left,
+    Right,
+    Home,
+    End,
+}
+
+impl CommandLineInput {
+    /// apply to `text` with the cursor at byte offset `cursor`
+    fn apply(self, text: &mut String, cursor: &mut usize) {
+        let prev = |text: &str, off: usize| text[..off].char_indices().next_back().map_or(0, |(i, _)| i);
+        let next = |text: &str, off: usize| text[off..].chars().next().map_or(off, |c| off + c.len_utf8());
+        match self {
+            CommandLineInput::Append(c) => {
+                text.insert(*cursor, c);
+                *cursor += c.len_utf8();
+            }
+            CommandLineInput::Insert(s) => {
+                text.insert_str(*cursor, &s);
+                *cursor += s.len();
+            }
+            CommandLineInput::Delete => {
+                let start = prev(text, *cursor);
+                text.replace_range(start..*cursor, "");
+                *cursor = start;
+            }
+            CommandLineInput::DeleteAfter => {
+                let end = next(text, *cursor);
+                text.replace_range(*cursor..end, "");
+            }
+            CommandLineInput::DeleteWord => {
+                let before = text[..*cursor].trim_end();
+                let word = |c: char| c.is_alphanumeric() || c == '_';
+                let start = match before.chars().next_back() {
+                    Some(c) if word(c) => before.trim_end_matches(word).len(),
+                    Some(_) => before.trim_end_matches(|c: char| !word(c) && !c.is_whitespace()).len(),
+                    None => 0,
+                };
+                text.replace_range(start..*cursor, "");
+                *cursor = start;
+            }
+            CommandLineInput::DeleteLine => {
+                text.replace_range(..*cursor, "");
+                *cursor = 0;
+            }
+            CommandLineInput::Left => *cursor = prev(text, *cursor),
+            CommandLineInput::Right => *cursor = next(text, *cursor),
+            CommandLineInput::Home => *cursor = 0,
+            CommandLineInput::End => *cursor = text.len(),
+        }
+    }
 }
 
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
@@ -29,38 +87,40 @@ pub enum CommandLineMode {
 pub enum CommandType {
     Ex,
     Find,
+    FindBackward,
+    /// a question from Guile's `rs-read-string`, see [`CommandLine::start_prompt`]
+    Prompt,
+    /// a question answered with a single key, see [`crate::confirm`]
+    Confirm,
     None,
 }
 
-pub enum CmdMsg {
-    Str(String),
-    Gmsg(guile::Gmsg)
+struct TabState {
+    completion: Completion,
+    /// the candidate that was put in, `None` for the text that was typed
+    idx: Option<usize>,
+    typed: String,
 }
 
 pub struct CommandLine {
     mode: CommandLineMode,
     buf: Arc<Buffer>,
     typ: CommandType,
-    other_ctx: Cursor,
+    /// length of the prompt at the start of the buffer, which can't be edited
+    prompt: usize,
+    /// completion being cycled through with Tab, see [`CommandLine::tab_complete`]
+    completion: Option<TabState>,
+    /// the output shown and the cursor of the buffer being edited when the command line was
+    /// entered, put back by [`CommandLine::abort`]
+    before: Option<(String, TextSeverity, Cursor)>,
     window: Arc<Window>,
-    msg_rx: mpsc::Receiver<CmdMsg>,
+    /// ex commands that were run from here, oldest first
+    history: Vec<String>,
     pub output_severity: crate::tui::TextSeverity,
 }
 
 impl CommandLine {
     pub fn take_general_input(&mut self, tui: &TermGrid) {
-        while let Ok(msg) = self.msg_rx.try_recv() {
-            self.set_mode(CommandLineMode::Output);
-            let s: &str = match &msg {
-                CmdMsg::Str(s) => s,
-                CmdMsg::Gmsg(s) => s,
-            };
-            // log!("{s:?}");
-            self.output_severity = crate::tui::TextSeverity::Normal;
-            let mut buf = self.buf.get_mut();
-            buf.insert_str(s);
-        }
-
         // move this to rendering
         let buf = self.buf.get();
         if buf.linecnt() > 1 {
@@ -72,6 +132,24 @@ impl CommandLine {
         }
     }
 
+    /// add a message from [`crate::event::Event::Message`] to the output
+    pub fn show_message(&mut self, s: &str) {
+        self.set_mode(CommandLineMode::Output);
+        self.output_severity = crate::tui::TextSeverity::Normal;
+        self.buf.get_mut().insert_str(s);
+    }
+
+    /// take the output and its severity instead of displaying it, e.g. to print it when running
+    /// headless
+    pub fn take_output(&mut self) -> Option<(String, TextSeverity)> {
+        if self.mode != CommandLineMode::Output || self.buf.get().len() == 0 {
+            return None;
+        }
+        let s = self.buf.get().to_string();
+        self.buf.get_mut().clear();
+        Some((s, std::mem::take(&mut self.output_severity)))
+    }
+
     pub fn render(&self, ctx: &Ctx) -> std::fmt::Result {
         let window = self.window.get();
         let buf = self.buf.get();
@@ -83,10 +161,13 @@ impl CommandLine {
                 //     CommandType::Find => '/',
                 // };
                 window.draw(ctx);
+                self.draw_wildmenu(ctx);
                 let mut tui = ctx.tui.borrow_mut();
                 let (_, h) = tui.dim();
+                let text = buf.to_string();
+                let col = text[..buf.coff().min(text.len())].chars().count();
                 tui.set_cursorpos(TermPos {
-                    x: buf.len() as u32 + 1,
+                    x: col as u32 + 1,
                     y: h as u32 - 1,
                 });
             }
@@ -104,22 +185,114 @@ impl CommandLine {
         Ok(())
     }
 
+    /// the candidates of the completion being cycled through, over the status line
+    fn draw_wildmenu(&self, ctx: &Ctx) {
+        let Some(state) = self.completion.as_ref().filter(|s| s.completion.candidates.len() > 1) else {
+            return;
+        };
+        let candidates = &state.completion.candidates;
+        let y = self.window.get().outer_bounds().start.y;
+        let mut tui = ctx.tui.borrow_mut();
+        let w = tui.dim().0 as usize;
+        // room for the arrows at both ends
+        let page = wildmenu_page(candidates, state.idx, w.saturating_sub(4));
+        let base = Color { bg: BasicColor::Black, ..Color::new() };
+        let selected = Color { fg: BasicColor::Black, bg: BasicColor::Yellow, bold: true, italic: false };
+        let mut line = tui.refline(y, ..).colored(base);
+        let _ = line.write_str(if page.start > 0 { "< " } else { "  " });
+        for i in page.clone() {
+            line.set_color(if state.idx == Some(i) { selected } else { base });
+            let _ = line.write_str(&candidates[i]);
+            line.set_color(base);
+            let _ = line.write_str("  ");
+        }
+        if page.end < candidates.len() {
+            let _ = line.write_str(">");
+        }
+        let _ = write!(line, "{:w$}", "");
+    }
+
     pub fn draw_cursor(&self, tui: &mut TermGrid) {
         if self.mode == CommandLineMode::Input {
-            self.buf.get().cursor.draw(&self.window.get(), tui)
+            let buf = self.buf.get();
+            buf.cursor.draw(&self.window.get(), buf.line(buf.cursor.pos.y), tui)
         }
     }
 
     pub fn input(&mut self, input: CommandLineInput) {
+        // the arrows move through the candidates while they're shown
+        if self.completion.is_some() && matches!(input, CommandLineInput::Left | CommandLineInput::Right) {
+            self.next_candidate(input == CommandLineInput::Left);
+            return;
+        }
         self.set_mode(CommandLineMode::Input);
-        match input {
-            CommandLineInput::Append(c) => {
-                self.buf.get_mut().push(c);
-            }
-            CommandLineInput::Delete => {
-                self.buf.get_mut().pop();
-            }
+        self.completion = None;
+        let mut buf = self.buf.get_mut();
+        let mut prompt = buf.to_string();
+        let mut text = prompt.split_off(self.prompt.min(prompt.len()));
+        let mut cursor = buf.coff().clamp(prompt.len(), prompt.len() + text.len()) - prompt.len();
+        input.apply(&mut text, &mut cursor);
+        text.insert_str(0, &prompt);
+        let cursor = cursor + prompt.len();
+        buf.clear();
+        buf.insert_str(&text[..cursor]);
+        let pos = buf.cursor;
+        buf.insert_str(&text[cursor..]);
+        buf.cursor = pos;
+    }
+
+    /// complete the word before the cursor, putting in the next candidate every time it's called,
+    /// or the previous one if `backward`. After the last candidate the typed text comes back. Paths
+    /// are completed from `cwd`.
+    pub fn tab_complete(&mut self, backward: bool, options: &crate::options::Options, cwd: &std::path::Path) {
+        if self.typ != CommandType::Ex || self.mode != CommandLineMode::Input {
+            return;
+        }
+        if self.completion.is_none() {
+            let buf = self.buf.get();
+            let text = buf.to_string();
+            let cursor = buf.coff().min(text.len());
+            let Some(completion) = super::complete::complete(&text[..cursor], options, cwd) else {
+                return;
+            };
+            let typed = text[completion.start..cursor].to_owned();
+            drop(buf);
+            self.completion = Some(TabState { completion, idx: None, typed });
+        }
+        self.next_candidate(backward);
+    }
+
+    /// put in the candidate after the one of [`CommandLine::tab_complete`], or before it if
+    /// `backward`
+    fn next_candidate(&mut self, backward: bool) {
+        let Some(mut state) = self.completion.take() else {
+            return;
+        };
+        let mut buf = self.buf.get_mut();
+        let text = buf.to_string();
+        let cursor = buf.coff().min(text.len());
+        if state.completion.candidates.is_empty() {
+            return;
+        }
+        let len = state.completion.candidates.len();
+        state.idx = match (state.idx, backward) {
+            (None, false) => Some(0),
+            (None, true) => Some(len - 1),
+            (Some(i), false) if i + 1 < len => Some(i + 1),
+            (Some(i), true) if i > 0 => Some(i - 1),
+            (Some(_), _) => None,
+        };
+        let word = match state.idx {
+            Some(i) => &state.completion.candidates[i],
+            None => &state.typed,
         };
+        buf.clear();
+        buf.insert_str(&text[..state.completion.start]);
+        buf.insert_str(word);
+        let pos = buf.cursor;
+        buf.insert_str(&text[cursor..]);
+        buf.cursor = pos;
+        self.completion = Some(state);
     }
 
     fn set_mode(&mut self, mode: CommandLineMode) {
@@ -133,26 +306,123 @@ impl CommandLine {
 
     pub fn set_type(&mut self, typ: CommandType) {
         self.set_mode(match typ {
-            CommandType::Ex => CommandLineMode::Input,
-            CommandType::Find => CommandLineMode::Input,
+            CommandType::Ex | CommandType::Prompt | CommandType::Confirm => CommandLineMode::Input,
+            CommandType::Find | CommandType::FindBackward => CommandLineMode::Input,
             CommandType::None => CommandLineMode::Output,
         });
         self.typ = typ;
+        self.prompt = 0;
+    }
+
+    /// start an ex command with `text` typed and the candidates of `completion` shown, as if Tab
+    /// had been pressed but without putting any in yet
+    pub fn start_with_candidates(&mut self, text: &str, completion: Completion) {
+        self.set_type(CommandType::Ex);
+        self.buf.get_mut().insert_str(text);
+        let typed = text[completion.start..].to_owned();
+        self.completion = Some(TabState { completion, idx: None, typed });
+    }
+
+    /// ask for a line of text after `prompt`, which is taken back with [`Self::take_answer`]
+    pub fn start_prompt(&mut self, prompt: &str) {
+        self.set_mode(CommandLineMode::Output);
+        self.set_type(CommandType::Prompt);
+        self.buf.get_mut().insert_str(prompt);
+        self.prompt = prompt.len();
+    }
+
+    /// show `question`, which takes keys instead of the command line until it's cleared
+    pub fn start_confirm(&mut self, question: &str) {
+        self.set_type(CommandType::Confirm);
+        self.buf.get_mut().clear();
+        self.buf.get_mut().insert_str(question);
+        self.prompt = question.len();
+    }
+
+    /// the text typed after the prompt, ending the prompt
+    pub fn take_answer(&mut self) -> String {
+        let text = self.buf.get().to_string();
+        let answer = text[self.prompt.min(text.len())..].to_owned();
+        self.clear_command();
+        self.mode = CommandLineMode::Output;
+        answer
     }
 
     pub fn get_type(&self) -> CommandType {
         self.typ
     }
 
+    /// parse an ex command, reporting errors on the command line
+    pub fn parse(&mut self, s: &str) -> Option<Command> {
+        parser::parse_command(s, self)
+    }
+
+    /// keep the ex command being typed in the `:` register and the history
+    pub fn remember(&mut self, registers: &mut crate::register::Registers) {
+        if self.typ == CommandType::Ex && self.mode == CommandLineMode::Input {
+            let cmd = self.buf.get().to_string();
+            registers.set(':', cmd.clone());
+            self.add_history(cmd);
+        }
+    }
+
+    /// add `cmd` to the end of the history, moving it there if it was already in it
+    pub fn add_history(&mut self, cmd: String) {
+        if cmd.trim().is_empty() {
+            return;
+        }
+        self.history.retain(|c| *c != cmd);
+        self.history.push(cmd);
+    }
+
+    pub fn history(&self) -> &[String] {
+        &self.history
+    }
+
+    /// the ex command being typed, if it's one that can be previewed
+    pub fn preview_command(&self) -> Option<Command> {
+        if self.typ != CommandType::Ex || self.mode != CommandLineMode::Input {
+            return None;
+        }
+        parser::parse_preview(&self.buf.get().to_string())
+    }
+
     pub fn complete(&mut self) -> Option<Command> {
         assert_eq!(self.mode, CommandLineMode::Input);
         let s = self.buf.get().to_string();
-        let out = parser::parse_command(&s, self);
-        let mut buf = self.buf.get_mut();
-        self.typ = CommandType::None;
-        buf.clear();
+        let typ = std::mem::replace(&mut self.typ, CommandType::None);
+        self.completion = None;
+        // cleared first, so that parse errors written to the output are kept
+        self.buf.get_mut().clear();
         self.mode = CommandLineMode::Output;
-        out
+        match typ {
+            CommandType::Find | CommandType::FindBackward => {
+                parser::parse_search(&s, typ == CommandType::FindBackward, self)
+            }
+            _ => parser::parse_command(&s, self),
+        }
+    }
+
+    /// remember what to go back to if the command line is aborted, before it's used for input.
+    /// `cursor` is the cursor of the buffer being edited.
+    pub fn enter(&mut self, cursor: Cursor) {
+        let output = match self.mode {
+            CommandLineMode::Output => self.buf.get().to_string(),
+            CommandLineMode::Input => String::new(),
+        };
+        self.before = Some((output, self.output_severity, cursor));
+    }
+
+    /// give up on the command being typed, showing the output from before it again. Returns the
+    /// cursor the buffer being edited had then.
+    pub fn abort(&mut self) -> Option<Cursor> {
+        let before = self.before.take();
+        self.clear_command();
+        let (output, severity, cursor) = before?;
+        self.mode = CommandLineMode::Output;
+        self.buf.get_mut().insert_str(&output);
+        self.output_severity = severity;
+        Some(cursor)
     }
 
     pub fn clear_all(&mut self) {
@@ -161,28 +431,32 @@ impl CommandLine {
     }
 
     pub fn clear_command(&mut self) {
+        self.before = None;
         self.typ = CommandType::None;
+        self.completion = None;
+        self.prompt = 0;
         self.buf.get_mut().clear();
     }
 
     /// initialize command line - can only be done once in program execution
     pub fn new(tui: &TermGrid) -> Self {
-        let (w, h) = tui.dim();
+        // a screen too small for it isn't drawn until it grows, see `Ctx::draw`
+        let (w, h) = (tui.dim().0.max(2), tui.dim().1.max(2));
         let components = vec![
             Component::StatusLine,
             Component::CommandPrefix,
         ];
-        let (tx, rx) = mpsc::channel();
-        CMD_TX.set(tx).expect("Command line was initialized multiple times");
         let buf = Buffer::new();
         Self {
             mode: CommandLineMode::Output,
-            other_ctx: Cursor::new(),
+            before: None,
             typ: CommandType::None,
+            prompt: 0,
+            completion: None,
             window: Window::new_withdim(TermPos { x: 0, y: h - 2 }, w, 2, components, Arc::clone(&buf)),
+            history: Vec::new(),
             buf,
             output_severity: Default::default(),
-            msg_rx: rx,
         }
     }
 
@@ -192,12 +466,27 @@ impl CommandLine {
         let mut win = self.window.get_mut();
         win.set_bounds_outer(TermBox::from_ranges(0..w, (h-2)..h));
     }
+}
 
-    /// sends a message to the command line output, and will be displayed on next render call. This
-    /// function will never panic, since it's meant to be used for guile code.
-    pub fn send_msg(s: CmdMsg) -> Result<(), ()> {
-        let tx = CMD_TX.get().ok_or(())?;
-        tx.send(s).map_err(|_| ())
+/// the candidates shown in `width` columns, the page of them that has `selected`
+fn wildmenu_page(candidates: &[String], selected: Option<usize>, width: usize) -> std::ops::Range<usize> {
+    let selected = selected.unwrap_or(0);
+    let mut start = 0;
+    loop {
+        let mut end = start;
+        let mut used = 0;
+        // a page has at least one candidate even if it doesn't fit
+        while let Some(c) = candidates.get(end) {
+            used += c.chars().count() + 2;
+            if end > start && used > width {
+                break;
+            }
+            end += 1;
+        }
+        if selected < end || end == candidates.len() {
+            return start..end;
+        }
+        start = end;
     }
 }
 
@@ -208,3 +497,40 @@ impl std::fmt::Write for CommandLine {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn edit(text: &str, cursor: usize, inputs: impl IntoIterator<Item = CommandLineInput>) -> (String, usize) {
+        let mut text = text.to_string();
+        let mut cursor = cursor;
+        for input in inputs {
+            input.apply(&mut text, &mut cursor);
+        }
+        (text, cursor)
+    }
+
+    #[test]
+    fn wildmenu_pages() {
+        let candidates: Vec<String> = ["abc", "defg", "hi", "jklmnopq"].map(String::from).into();
+        assert_eq!(wildmenu_page(&candidates, None, 12), 0..2);
+        assert_eq!(wildmenu_page(&candidates, Some(1), 12), 0..2);
+        assert_eq!(wildmenu_page(&candidates, Some(2), 12), 2..3);
+        assert_eq!(wildmenu_page(&candidates, Some(3), 12), 3..4);
+        assert_eq!(wildmenu_page(&candidates, Some(3), 100), 0..4);
+    }
+
+    #[test]
+    fn editing() {
+        use CommandLineInput::*;
+        assert_eq!(edit("s/a/b", 5, [Left, Left, Append('x')]), ("s/ax/b".into(), 4));
+        assert_eq!(edit("é", 0, [Right, Right, Append('a')]), ("éa".into(), 3));
+        assert_eq!(edit("ab", 1, [Delete, Delete, DeleteAfter, DeleteAfter]), ("".into(), 0));
+        assert_eq!(edit("ab", 2, [Home, Insert("e ".into()), End]), ("e ab".into(), 4));
+        assert_eq!(edit("e foo_bar", 9, [DeleteWord]), ("e ".into(), 2));
+        assert_eq!(edit("e a/b  ", 7, [DeleteWord]), ("e a/".into(), 4));
+        assert_eq!(edit("e a/", 4, [DeleteWord]), ("e a".into(), 3));
+        assert_eq!(edit("set ro", 4, [DeleteLine]), ("ro".into(), 0));
+    }
+}
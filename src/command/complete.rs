@@ -0,0 +1,230 @@
+//! Tab completion of ex commands.
+//!
+//! Command names complete from the built-in commands and [`super::user`] commands. Arguments
+//! complete depending on the command: file names, option names, buffer names or the files under
+//! the working directory matched with [`fuzzy_score`], or for user commands whatever their
+//! completion procedure returns.
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::buffer::{registry, Buffer};
+use crate::options::Options;
+
+use super::parser::COMMANDS;
+use super::{range, user};
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Completion {
+    /// byte offset in the line where the completed word starts
+    pub start: usize,
+    pub candidates: Vec<String>,
+}
+
+/// complete the word that ends `line`, which is the text before the cursor. Relative paths are
+/// from `cwd`, the working directory of the window.
+pub fn complete(line: &str, options: &Options, cwd: &Path) -> Option<Completion> {
+    let (_, rest) = range::parse(line);
+    let name_start = line.len() - rest.len();
+    let name_len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+    let name = &rest[..name_len];
+    if name_len == rest.len() {
+        let mut candidates: Vec<String> = COMMANDS
+            .iter()
+            .map(|s| s.to_string())
+            .chain(user::names())
+            .filter(|c| c.starts_with(name))
+            .collect();
+        candidates.sort();
+        return Some(Completion { start: name_start, candidates });
+    }
+    let word_start = line.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+    let word = &line[word_start..];
+    let candidates = match name {
+        "w" | "write" | "e" | "edit" | "diffs" | "diffsplit" | "so" | "source" | "cd" | "lcd" | "screendump" | "TOhtml" => {
+            complete_path(word, cwd)
+        }
+        "b" | "buffer" => buffers(word).iter().map(|b| buffer_name(b)).collect(),
+        "fin" | "find" => files(cwd, word, &options.wildignore),
+        "se" | "set" | "setl" | "setlocal" => {
+            let names: Vec<_> = crate::options::Options::NAMES.iter().chain(crate::options::WinOptions::NAMES).collect();
+            let names = &names;
+            let negated = word.strip_prefix("no").into_iter().flat_map(|word| {
+                let is_bool = |n: &str| crate::options::Options::is_bool(n) || crate::options::WinOptions::is_bool(n);
+                names.iter().filter(move |n| n.starts_with(word) && is_bool(n)).map(|n| format!("no{n}"))
+            });
+            names.iter().filter(|n| n.starts_with(word)).map(|n| n.to_string()).chain(negated).collect()
+        }
+        name => {
+            let cmd = user::get(name)?;
+            crate::guile::complete_command(cmd.complete.as_ref()?, word)
+        }
+    };
+    Some(Completion { start: word_start, candidates })
+}
+
+/// how well `pattern` matches `name` ignoring case, lower is better. A match of the whole pattern
+/// in one piece comes first, earlier in the name the better, then the pattern's characters in order
+/// anywhere in it, closer together the better. `None` if they aren't all in it.
+pub fn fuzzy_score(pattern: &str, name: &str) -> Option<usize> {
+    let (pattern, name) = (pattern.to_lowercase(), name.to_lowercase());
+    if let Some(i) = name.find(&pattern) {
+        return Some(i);
+    }
+    let mut chars = name.char_indices();
+    let mut first = None;
+    let mut last = 0;
+    for p in pattern.chars() {
+        let (i, _) = chars.find(|&(_, c)| c == p)?;
+        first.get_or_insert(i);
+        last = i;
+    }
+    Some(name.len() + last - first.unwrap_or(0))
+}
+
+/// what a buffer is called for `:b`, its path as it was opened
+pub fn buffer_name(buf: &Buffer) -> String {
+    buf.get().path().map_or_else(String::new, |p| p.to_string_lossy().into_owned())
+}
+
+/// buffers with a file whose name `pattern` matches, the best match first. One whose name is
+/// `pattern` is the only one.
+pub fn buffers(pattern: &str) -> Vec<Arc<Buffer>> {
+    let mut matches: Vec<_> = registry::all()
+        .into_iter()
+        .filter(|b| b.get().path().is_some())
+        .filter_map(|b| Some((fuzzy_score(pattern, &buffer_name(&b))?, b)))
+        .collect();
+    if let Some(exact) = matches.iter().position(|(_, b)| buffer_name(b) == pattern) {
+        return vec![matches.swap_remove(exact).1];
+    }
+    // stable, so equal matches stay in the order of their ids
+    matches.sort_by_key(|(score, _)| *score);
+    matches.into_iter().map(|(_, b)| b).collect()
+}
+
+/// files under `root` that aren't ignored by `wildignore` or a `.gitignore`, see
+/// [`crate::ignore`], relative to it and with a name that `pattern` matches, the best match first.
+/// One whose name is `pattern` is the only one.
+pub fn files(root: &Path, pattern: &str, wildignore: &[String]) -> Vec<String> {
+    let names = crate::ignore::walk(root, wildignore)
+        .into_iter()
+        .filter_map(|p| Some(p.strip_prefix(root).ok()?.to_string_lossy().into_owned()));
+    let mut matches: Vec<_> = names.filter_map(|name| Some((fuzzy_score(pattern, &name)?, name))).collect();
+    if let Some(exact) = matches.iter().position(|(_, name)| name == pattern) {
+        return vec![matches.swap_remove(exact).1];
+    }
+    // stable, so equal matches stay in the order of their paths
+    matches.sort_by_key(|(score, _)| *score);
+    matches.into_iter().map(|(_, name)| name).collect()
+}
+
+/// files starting with `word`, with a `/` after directories
+fn complete_path(word: &str, cwd: &Path) -> Vec<String> {
+    let (dir, file) = match word.rfind('/') {
+        Some(i) => word.split_at(i + 1),
+        None => ("", word),
+    };
+    let Ok(entries) = cwd.join(dir).read_dir() else {
+        return Vec::new();
+    };
+    let mut out: Vec<String> = entries
+        .filter_map(|e| e.ok())
+        .filter_map(|e| {
+            let name = e.file_name().into_string().ok()?;
+            // hidden files only when asked for
+            if !name.starts_with(file) || (name.starts_with('.') && !file.starts_with('.')) {
+                return None;
+            }
+            let slash = if e.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{name}{slash}"))
+        })
+        .collect();
+    out.sort();
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn command_names() {
+        let c = complete("dif", &Options::default(), Path::new(".")).unwrap();
+        assert_eq!(c.start, 0);
+        assert_eq!(c.candidates, ["diffoff", "diffsplit"]);
+        let c = complete("%subs", &Options::default(), Path::new(".")).unwrap();
+        assert_eq!(c.start, 1);
+        assert_eq!(c.candidates, ["substitute"]);
+    }
+
+    #[test]
+    fn option_names() {
+        let c = complete("set ic nosmart", &Options::default(), Path::new(".")).unwrap();
+        assert_eq!(c.start, 7);
+        assert_eq!(c.candidates, ["nosmartcase"]);
+        assert_eq!(complete("set igno", &Options::default(), Path::new(".")).unwrap().candidates, ["ignorecase"]);
+        assert_eq!(complete("setl nonu", &Options::default(), Path::new(".")).unwrap().candidates, ["nonumber"]);
+        assert_eq!(complete("setl nu", &Options::default(), Path::new(".")).unwrap().candidates, ["number", "numberwidth"]);
+    }
+
+    #[test]
+    fn fuzzy() {
+        assert_eq!(fuzzy_score("main", "src/main.rs"), Some(4));
+        assert_eq!(fuzzy_score("MAIN", "src/main.rs"), Some(4));
+        assert_eq!(fuzzy_score("smr", "src/main.rs"), Some(11 + 9));
+        assert!(fuzzy_score("srm", "src/main.rs") < fuzzy_score("smr", "src/main.rs"));
+        assert_eq!(fuzzy_score("rsm", "src/main.rs"), None);
+        assert_eq!(fuzzy_score("", "x"), Some(0));
+    }
+
+    #[test]
+    fn buffer_names() {
+        let dir = std::env::temp_dir().join(format!("edit-buffer-names-{}", std::process::id()));
+        let [a, b] = ["alpha.rs", "beta.rs"].map(|name| {
+            let buf = Buffer::new();
+            buf.get_mut().set_path(dir.join(name));
+            buf
+        });
+        let unique = format!("{}/alp", dir.display());
+        assert!(Arc::ptr_eq(&buffers(&unique)[0], &a));
+        let both = buffers(&format!("{}/.rs", dir.display()));
+        assert!(both.iter().any(|x| Arc::ptr_eq(x, &a)) && both.iter().any(|x| Arc::ptr_eq(x, &b)));
+        assert_eq!(buffers(&buffer_name(&b)).len(), 1);
+        let c = complete(&format!("b {unique}"), &Options::default(), Path::new(".")).unwrap();
+        assert_eq!(c.candidates, [buffer_name(&a)]);
+    }
+
+    #[test]
+    fn paths() {
+        let dir = std::env::temp_dir().join(format!("edit-complete-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("file.txt"), "").unwrap();
+        std::fs::write(dir.join(".hidden"), "").unwrap();
+        let dir = dir.to_str().unwrap();
+        let c = complete(&format!("e {dir}/"), &Options::default(), Path::new(".")).unwrap();
+        assert_eq!(c.start, 2);
+        assert_eq!(c.candidates, [format!("{dir}/file.txt"), format!("{dir}/sub/")]);
+        let c = complete(&format!("w {dir}/.h"), &Options::default(), Path::new(".")).unwrap();
+        assert_eq!(c.candidates, [format!("{dir}/.hidden")]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn found_files() {
+        let dir = std::env::temp_dir().join(format!("edit-find-test-{}", std::process::id()));
+        for path in ["src/main.rs", "src/mod.rs", "target/main.rs"] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        let wildignore = Options::default().wildignore;
+        assert_eq!(files(&dir, "main", &wildignore), ["src/main.rs"]);
+        assert_eq!(files(&dir, "src", &wildignore), ["src/main.rs", "src/mod.rs"]);
+        assert_eq!(files(&dir, "main", &[]), ["src/main.rs", "target/main.rs"]);
+        // from the working directory of the window rather than that of the editor
+        let c = complete("fin mod", &Options::default(), &dir).unwrap();
+        assert_eq!(c.candidates, ["src/mod.rs"]);
+        assert_eq!(complete("e sr", &Options::default(), &dir).unwrap().candidates, ["src/"]);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
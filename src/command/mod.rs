@@ -1,57 +1,639 @@
-use crate::buffer::Buffer;
+use crate::buffer::{registry, Buffer};
+use crate::error::FileOp;
+use crate::confirm::Confirm;
 use crate::log;
-use crate::{guile, prelude::*};
-use crate::render::Ctx;
+use crate::{guile, keymap, prelude::*, Mode};
+use crate::options::WinOptions;
+use crate::qflist::ListKind;
+use crate::render::{BufId, Ctx};
+use crate::search::Offset;
+use range::LineRange;
 use std::fmt::Write;
-use std::{error::Error, fmt::Display, fs::OpenOptions, io::Read, path::PathBuf};
+use std::path::{Path, PathBuf};
+use std::{error::Error, fs::OpenOptions, io::Read, sync::Arc};
 pub mod cmdline;
+pub mod complete;
 mod parser;
+pub mod range;
+pub mod user;
 
 pub enum Command {
-    Write { path: Option<PathBuf> },
-    Edit { path: PathBuf },
+    /// `:[range]w[!][ >>] [path]`, the whole buffer to its own file by default. Asks before
+    /// writing over another file that exists unless there's a `!`.
+    Write { path: Option<PathBuf>, range: Option<LineRange>, append: bool, force: bool },
+    /// `:e[!] {path}`, where `!` throws away the changes to the focused buffer unless `hidden`
+    /// keeps it
+    Edit { path: PathBuf, force: bool },
+    /// `:fin[!] {name}`, edit the file under the working directory whose path `name` matches best,
+    /// see [`complete::files`]. When several match they're listed to pick from.
+    Find { name: String, force: bool },
+    DiffSplit { path: PathBuf },
+    DiffOff,
     Guile { cmd: String },
+    Source { path: PathBuf },
+    /// `:[range]normal[!] {keys}`, with a range the keys run from the start of each of its lines.
+    /// `:normal!` doesn't apply mappings.
+    Normal { range: Option<LineRange>, keys: String, remap: bool },
+    /// `:{line}`, move the cursor to the first non-blank of a line, the last one of a range
+    Goto { line: LineRange },
+    /// `:[line]@{register}`, run the lines of a register as ex commands from `line`. `:@:` repeats
+    /// the last command line and `:@@` the register run last.
+    ExecuteRegister { range: Option<LineRange>, register: char },
+    /// lists the mappings starting with `lhs` if `rhs` is empty. `<buffer>` or `<filetype=ft>`
+    /// before `lhs` limit the mapping to the focused buffer or to buffers of a filetype.
+    Map { mode: Mode, lhs: String, rhs: String, noremap: bool, scope: MapScope },
+    Unmap { mode: Mode, lhs: String, scope: MapScope },
+    Job { cmd: String },
+    Jobs,
+    JobKill { id: u64 },
+    JobLog,
     ListBuffers,
-    Substitute,
+    /// `:memory`, the memory each listed buffer takes up and that of the whole editor
+    Memory,
+    /// `:reg [names]`, the contents of every register or only the named ones
+    Registers { names: String },
+    /// `:screendump {path}`, write what's on screen to a file, as HTML if its name ends in `.html`
+    ScreenDump { path: PathBuf },
+    /// `:earlier {count}`, go back in the undo history by changes, or by file writes with `writes`
+    Earlier { count: usize, writes: bool },
+    /// `:later {count}`, the opposite of [`Command::Earlier`]
+    Later { count: usize, writes: bool },
+    /// `:b[!] {name}`, show a buffer in the focused window. The name is a [`BufId`] or part of the
+    /// name of a buffer, see [`complete::buffers`], and if more than one buffer matches they're
+    /// listed to pick from. `!` is as for [`Command::Edit`].
+    Buffer { name: String, force: bool },
+    /// `:bdelete[!] [id]` or `:bwipeout[!] [id]`, close the focused buffer or the one with that
+    /// [`BufId`], see [`Ctx::delete_buffer`]. The two are the same since nothing about a buffer
+    /// outlives it.
+    DeleteBuffer { id: Option<u64>, force: bool },
+    List(ListKind),
+    ListNext(ListKind),
+    ListPrev(ListKind),
+    Make { args: String },
+    Terminal { cmd: String },
+    /// `:set`, or `:setlocal` to only change the window-local options of the focused window
+    Set { args: Vec<String>, local: bool },
+    Search { pattern: String, backward: bool, offset: Offset },
+    /// `:s/pattern/replacement/flags`, on the cursor line unless there's a range
+    Substitute { range: LineRange, pattern: String, replacement: String, flags: String },
+    /// `:[range]sort[!] [flags] [/pattern/]`, all lines by default
+    Sort { range: Option<LineRange>, reverse: bool, flags: String, pattern: Option<String> },
+    /// `:[range]retab[!] [tabstop]`, redo the whitespace with tabs of all lines by default with
+    /// `expandtab` and the new `tabstop`. `!` also converts runs of spaces.
+    Retab { range: Option<LineRange>, all: bool, tabstop: Option<usize> },
     Global,
     Help,
-    Quit,
+    /// `:q[!]`, asks whether to write the changes to the focused buffer first unless there's a `!`
+    Quit { force: bool },
+    /// `:cd [dir]`, or `:lcd [dir]` for just the focused window. The home directory by default.
+    Cd { path: Option<PathBuf>, local: bool },
+    Pwd,
+    /// `:GitBlame`, toggle showing [`crate::blame`]
+    GitBlame,
+    /// `:[range]Align {delim}`, line up `delim` in the lines of the range, or in the lines around
+    /// the cursor that have it
+    Align { range: Option<LineRange>, delim: String },
+    /// `:TOhtml [path]`, write the focused buffer with its highlights to a file, see
+    /// [`crate::export`]. As text with escape sequences unless the name ends in `.html`, which is
+    /// the file name with `.html` added by default.
+    ToHtml { path: Option<PathBuf> },
+    /// `:Man {topic}`, show a manual page, see [`crate::man`]
+    Man { topic: String },
+    /// a command from [`user`]
+    User { name: String, range: Option<LineRange>, args: String },
+    /// `:com[!] [name replacement]`, define an alias in [`user`], or list them without a name
+    DefineUser { name: String, replacement: String, force: bool },
 }
 
-#[derive(Debug)]
-struct WriteCommandError;
-impl Display for WriteCommandError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str("Write command error")
+/// the scope of a mapping as it was typed, for [`Command::Map`] and [`Command::Unmap`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MapScope {
+    Global,
+    /// `<buffer>`, the focused buffer
+    Buffer,
+    /// `<filetype=ft>`
+    Filetype(String),
+}
+
+impl MapScope {
+    fn resolve(self, ctx: &Ctx) -> keymap::Scope {
+        match self {
+            MapScope::Global => keymap::Scope::Global,
+            MapScope::Buffer => keymap::Scope::Buffer(ctx.focused_buf_id()),
+            MapScope::Filetype(ft) => keymap::Scope::Filetype(ft),
+        }
     }
 }
-impl Error for WriteCommandError {}
 
 impl Command {
     pub fn exec(self, ctx: &mut Ctx) -> Result<(), Box<dyn Error>> {
+        crate::buffer::history::undo_break();
         match self {
             Command::Guile { cmd } => {
                 // log!("execing {cmd}");
                 guile::execute_guile_interpreted(&cmd).map_err(|_| "")?;
                 Ok(())
             },
-            Command::Write { path } => {
+            Command::Write { path, range, append, force } => {
+                if ctx.options.readonly {
+                    return Err(EditorError::ReadOnly.into());
+                }
+                let own = ctx.focused_buf().path().map(|p| p.to_path_buf());
                 let path = path
-                    .or_else(|| ctx.focused_buf().path().map(|p| p.to_path_buf()))
-                    .ok_or(Box::new(WriteCommandError))?;
-                let mut f = OpenOptions::new().write(true).create(true).open(&path)?;
-                let linecnt = ctx.focused_buf().linecnt();
-                let len = ctx.focused_buf().len();
-                ctx.focused_buf().serialize(&mut f)?;
-                write!(ctx.info(), "{path:?} {linecnt}L, {len}B written")?;
+                    .map(|p| expand(&p, ctx))
+                    .transpose()?
+                    .or_else(|| own.clone())
+                    .ok_or(EditorError::NoFileName)?;
+                // the rest of the file would be lost
+                if range.is_some() && !force && !append && own.as_ref() == Some(&path) {
+                    return Err("E140: Use ! to write partial buffer".into());
+                }
+                if !force && !append && own.as_ref() != Some(&path) && path.exists() {
+                    ctx.ask(Confirm::Overwrite { path, range });
+                    return Ok(());
+                }
+                let mut buf = ctx.focused_buf_mut();
+                // only writing all of it to its own file makes it unmodified
+                let saved = range.is_none() && !append && own.as_ref() == Some(&path);
+                let unformatted = match saved {
+                    true => crate::formatprg::on_save(&ctx.options, &mut buf, &path).err(),
+                    false => None,
+                };
+                let lines = match range {
+                    Some(range) => range.resolve(buf.cursor.pos.y, buf.linecnt())?,
+                    None => 0..buf.linecnt(),
+                };
+                let text = buf.to_string();
+                let text = &text[buf.line_offsets(lines.clone())];
+                let f = if append {
+                    OpenOptions::new().append(true).create(true).open(&path)
+                } else {
+                    OpenOptions::new().write(true).create(true).truncate(true).open(&path)
+                };
+                let mut f = f.map_err(|error| EditorError::Io { path: path.clone(), op: FileOp::Create, error })?;
+                std::io::Write::write_all(&mut f, text.as_bytes()).map_err(|error| EditorError::Io { path: path.clone(), op: FileOp::Write, error })?;
+                if saved {
+                    buf.mark_saved();
+                }
+                drop(buf);
+                let (linecnt, len) = (lines.len(), text.len());
+                let done = if append { "appended" } else { "written" };
+                match unformatted {
+                    Some(e) => write!(ctx.warning(), "{path:?} {linecnt}L, {len}B {done} unformatted: {e}")?,
+                    None => write!(ctx.info(), "{path:?} {linecnt}L, {len}B {done}")?,
+                }
+                if saved {
+                    if let Some(buf) = registry::get(ctx.focused_buf_id()) {
+                        ctx.lock_file(&buf);
+                    }
+                    ctx.start_lint(&path)?;
+                }
+                Ok(())
+            }
+            Command::Edit { path, force } => {
+                let path = expand(&path, ctx)?;
+                edit(ctx, &path, force)
+            }
+            Command::Find { name, force } => {
+                let root = ctx.cwd();
+                match &complete::files(&root, &name, &ctx.options.wildignore)[..] {
+                    [] => Err(format!("E345: Can't find file \"{name}\" in path").into()),
+                    [file] => edit(ctx, &root.join(file), force),
+                    matches => {
+                        let cmd = format!("fin{} {name}", if force { "!" } else { "" });
+                        let start = cmd.len() - name.len();
+                        ctx.pick(&cmd, complete::Completion { start, candidates: matches.to_vec() });
+                        Ok(())
+                    }
+                }
+            }
+            Command::DiffSplit { path } => {
+                let path = expand(&path, ctx)?;
+                let buf = Buffer::open(&path).map_err(|error| EditorError::Io { path, op: FileOp::Open, error })?;
+                ctx.diff_split(buf)
+            }
+            Command::DiffOff => {
+                ctx.diff_off();
+                Ok(())
+            }
+            Command::Source { path } => {
+                let path = expand(&path, ctx)?;
+                if !path.is_file() {
+                    return Err(format!("{path:?} is not a file").into());
+                }
+                guile::load_file(&path, ctx.options.secure).map_err(|_| format!("failed to load {path:?}"))?;
+                Ok(())
+            }
+            Command::Normal { range, keys, remap } => {
+                let Some(range) = range else {
+                    ctx.normal(keys.as_bytes(), remap);
+                    return Ok(());
+                };
+                let lines = {
+                    let buf = ctx.focused_buf();
+                    range.resolve(buf.cursor.pos.y, buf.linecnt())?
+                };
+                // otherwise the cursor is put back where the command line was opened
+                ctx.abort_command_line();
+                for y in lines {
+                    // the keys may have deleted lines
+                    if y >= ctx.focused_buf().linecnt() {
+                        break;
+                    }
+                    ctx.focused_win_mut().set_pos(DocPos { x: 0, y });
+                    ctx.normal(keys.as_bytes(), remap);
+                }
+                Ok(())
+            }
+            Command::Goto { line } => {
+                let buf = ctx.focused_buf();
+                let y = line.jump_target(buf.cursor.pos.y, buf.linecnt())?;
+                let text = buf.line(y);
+                let x = text.len() - text.trim_start().len();
+                drop(buf);
+                ctx.focused_win_mut().set_pos(DocPos { x, y });
+                Ok(())
+            }
+            Command::ExecuteRegister { range, register } => {
+                if let Some(range) = range {
+                    let buf = ctx.focused_buf();
+                    let lines = range.resolve(buf.cursor.pos.y, buf.linecnt())?;
+                    drop(buf);
+                    ctx.focused_win_mut().set_pos(DocPos { x: 0, y: lines.end - 1 });
+                }
+                ctx.execute_register_lines(register)
+            }
+            Command::Map { mode, lhs, rhs, noremap, scope } => {
+                let lhs = keymap::parse_keys(&lhs, &ctx.options.mapleader);
+                if !rhs.is_empty() {
+                    let rhs = keymap::parse_keys(&rhs, &ctx.options.mapleader);
+                    ctx.keymap.map(scope.resolve(ctx), mode, lhs, rhs, noremap);
+                    return Ok(());
+                }
+                // all that apply to the focused buffer, unless one scope was asked for
+                let scopes = match scope {
+                    MapScope::Global => ctx.keymap_scopes(),
+                    scope => vec![scope.resolve(ctx)],
+                };
+                let listing: Vec<_> = ctx
+                    .keymap
+                    .list(&scopes, mode, &lhs)
+                    .into_iter()
+                    .map(|(lhs, m, scope)| {
+                        let star = if m.noremap { '*' } else { ' ' };
+                        let local = if *scope == keymap::Scope::Global { ' ' } else { '@' };
+                        let lhs = keymap::key_notation(lhs);
+                        let rhs = keymap::key_notation(&m.rhs);
+                        format!("{}  {lhs:<12} {star}{local}{rhs}", keymap::mode_char(mode))
+                    })
+                    .collect();
+                if listing.is_empty() {
+                    write!(ctx.info(), "no mapping found")?;
+                }
+                write!(ctx.info(), "{}", listing.join("\n"))?;
+                Ok(())
+            }
+            Command::Unmap { mode, lhs, scope } => {
+                if lhs.is_empty() {
+                    return Err("argument required".into());
+                }
+                let lhs = keymap::parse_keys(&lhs, &ctx.options.mapleader);
+                if !ctx.keymap.unmap(&scope.resolve(ctx), mode, &lhs) {
+                    return Err("no such mapping".into());
+                }
+                Ok(())
+            }
+            Command::Job { cmd } => {
+                if cmd.trim().is_empty() {
+                    return Err("no command given".into());
+                }
+                let sink = crate::job::Sink::Buffer(Arc::clone(&ctx.job_log));
+                let id = crate::job::start(&cmd, sink)?;
+                write!(ctx.info(), "started job {id}")?;
+                Ok(())
+            }
+            Command::Jobs => {
+                let jobs = crate::job::list();
+                if jobs.is_empty() {
+                    write!(ctx.info(), "no running jobs")?;
+                }
+                for (i, (id, cmd)) in jobs.iter().enumerate() {
+                    if i != 0 {
+                        writeln!(ctx.info())?;
+                    }
+                    write!(ctx.info(), "{id:>3} {cmd}")?;
+                }
+                Ok(())
+            }
+            Command::JobKill { id } => {
+                crate::job::kill(id.into())?;
+                Ok(())
+            }
+            Command::JobLog => {
+                ctx.open_buffer(Arc::clone(&ctx.job_log));
+                Ok(())
+            }
+            Command::ScreenDump { path } => {
+                let path = expand(&path, ctx)?;
+                let html = path.extension().is_some_and(|e| e == "html" || e == "htm");
+                let tui = ctx.tui.borrow();
+                let dump = if html { tui.dump_html() } else { tui.dump_text() };
+                drop(tui);
+                std::fs::write(&path, dump).map_err(|error| EditorError::Io { path: path.clone(), op: FileOp::Create, error })?;
+                write!(ctx.info(), "{path:?} written")?;
+                Ok(())
+            }
+            // a count too large for `isize` still goes as far as there is history
+            Command::Earlier { count, writes } => travel(ctx, -isize::try_from(count).unwrap_or(isize::MAX), writes),
+            Command::Later { count, writes } => travel(ctx, isize::try_from(count).unwrap_or(isize::MAX), writes),
+            Command::Registers { names } => {
+                let names = (!names.is_empty()).then_some(&names[..]);
+                // in a buffer of its own so that a long list can be scrolled
+                let listing = ctx.registers.listing(names);
+                ctx.open_buffer(Buffer::from_string(listing));
+                Ok(())
+            }
+            Command::List(kind) => {
+                if ctx.list(kind).is_empty() {
+                    return Err("list is empty".into());
+                }
+                let list = std::mem::take(ctx.list(kind));
+                let res = list.write_listing(ctx.info());
+                *ctx.list(kind) = list;
+                res?;
+                Ok(())
+            }
+            Command::ListNext(kind) => {
+                ctx.list(kind).next().ok_or("no more items")?;
+                ctx.jump_list(kind)
+            }
+            Command::ListPrev(kind) => {
+                ctx.list(kind).prev().ok_or("no previous item")?;
+                ctx.jump_list(kind)
+            }
+            Command::Make { args } => ctx.start_make(&args),
+            Command::Terminal { cmd } => {
+                let cmd = match cmd.trim() {
+                    "" => std::env::var("SHELL").unwrap_or_else(|_| "sh".into()),
+                    cmd => cmd.to_owned(),
+                };
+                ctx.open_terminal(&cmd)?;
+                Ok(())
+            }
+            Command::Set { args, local } => {
+                for (i, arg) in args.iter().enumerate() {
+                    if let Some(name) = arg.strip_suffix('?') {
+                        let shown = match WinOptions::has(name) {
+                            true => ctx.focused_win().options.show(name)?,
+                            false => ctx.options.show(name)?,
+                        };
+                        if i != 0 {
+                            writeln!(ctx.info())?;
+                        }
+                        write!(ctx.info(), "{shown}")?;
+                        continue;
+                    }
+                    let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+                    if WinOptions::has(name) || name.strip_prefix("no").is_some_and(WinOptions::has) {
+                        if local {
+                            ctx.focused_win_mut().set_option(arg)?;
+                        } else {
+                            for win in ctx.windows() {
+                                win.get_mut().set_option(arg)?;
+                            }
+                        }
+                    } else {
+                        ctx.options.set(arg)?;
+                    }
+                }
+                Ok(())
+            }
+            Command::ListBuffers => {
+                let focused = ctx.focused_buf_id();
+                let alternate = ctx.alternate_buf().map(|b| b.id());
+                // internal buffers like popups and the command line have no file
+                let listed = registry::all()
+                    .into_iter()
+                    .filter(|b| b.id() == focused || b.get().path().is_some());
+                for (i, buf) in listed.enumerate() {
+                    let mark = match Some(buf.id()) {
+                        id if id == Some(focused) => '%',
+                        id if id == alternate => '#',
+                        _ => ' ',
+                    };
+                    let modified = if buf.get().is_modified() { '+' } else { ' ' };
+                    if i != 0 {
+                        writeln!(ctx.info())?;
+                    }
+                    write!(ctx.info(), "{:>3} {mark}{modified} {:?}", buf.id().id(), buf.get().name())?;
+                }
+                Ok(())
+            }
+            Command::Memory => {
+                let focused = ctx.focused_buf_id();
+                let listed = registry::all()
+                    .into_iter()
+                    .filter(|b| b.id() == focused || b.get().path().is_some());
+                for buf in listed {
+                    let usage = buf.get().mem_usage();
+                    write!(
+                        ctx.info(),
+                        "{:>3} {:?}: text {}, index {}, undo {}",
+                        buf.id().id(),
+                        buf.get().name(),
+                        size(usage.text),
+                        size(usage.index),
+                        size(usage.undo)
+                    )?;
+                    if usage.nodes > 0 {
+                        write!(ctx.info(), ", {} nodes", usage.nodes)?;
+                    }
+                    writeln!(ctx.info())?;
+                }
+                match resident_memory() {
+                    Some(rss) => write!(ctx.info(), "resident {}", size(rss))?,
+                    None => write!(ctx.info(), "resident unknown")?,
+                }
                 Ok(())
             }
-            Command::Edit { path } => {
-                ctx.open_buffer(Buffer::open(&path)?);
+            Command::Buffer { name, force } => {
+                let buf = match name.parse() {
+                    Ok(id) => registry::get(BufId::from_id(id)).ok_or_else(|| format!("buffer {id} does not exist"))?,
+                    Err(_) => match &complete::buffers(&name)[..] {
+                        [] => return Err(format!("E94: No matching buffer for {name}").into()),
+                        [buf] => Arc::clone(buf),
+                        matches => {
+                            let cmd = format!("b{} {name}", if force { "!" } else { "" });
+                            let candidates = matches.iter().map(|b| complete::buffer_name(b)).collect();
+                            let start = cmd.len() - name.len();
+                            ctx.pick(&cmd, complete::Completion { start, candidates });
+                            return Ok(());
+                        }
+                    },
+                };
+                let abandoned = ctx.abandon(force)?.filter(|b| !Arc::ptr_eq(b, &buf));
+                ctx.open_buffer(buf);
+                if let Some(abandoned) = abandoned {
+                    ctx.delete_buffer(&abandoned, true)?;
+                }
                 Ok(())
             }
-            Command::Quit => {
-                crate::exit();
+            Command::DeleteBuffer { id, force } => {
+                let id = id.map_or(ctx.focused_buf_id(), BufId::from_id);
+                let buf = registry::get(id).ok_or_else(|| format!("buffer {} does not exist", id.id()))?;
+                ctx.delete_buffer(&buf, force)
+            }
+            Command::Search { pattern, backward, offset } => ctx.search(&pattern, backward, offset),
+            Command::Substitute { range, pattern, replacement, flags } => {
+                ctx.substitute(range, &pattern, &replacement, &flags)
+            }
+            Command::Sort { range, reverse, flags, pattern } => {
+                ctx.check_modifiable()?;
+                let pattern = pattern.map(|p| crate::search::compile(&p, &ctx.options, None)).transpose()?;
+                let sort = crate::sort::Sort::new(reverse, &flags, pattern)?;
+                let mut buf = ctx.focused_buf_mut();
+                let lines = range.unwrap_or(LineRange::ALL).resolve(buf.cursor.pos.y, buf.linecnt())?;
+                let sorted = sort.sort(&buf.get_lines(lines.clone()));
+                buf.set_lines(lines, &sorted);
+                Ok(())
+            }
+            Command::Retab { range, all, tabstop } => {
+                ctx.check_modifiable()?;
+                let (old, expandtab) = (ctx.options.tabstop, ctx.options.expandtab);
+                let new = tabstop.unwrap_or(old);
+                let mut buf = ctx.focused_buf_mut();
+                let lines = range.unwrap_or(LineRange::ALL).resolve(buf.cursor.pos.y, buf.linecnt())?;
+                let retabbed: Vec<String> = buf
+                    .get_lines(lines.clone())
+                    .iter()
+                    .map(|line| crate::format::retab(line, old, new, expandtab, all))
+                    .collect();
+                let changed = buf.set_lines(lines, &retabbed);
+                drop(buf);
+                ctx.options.tabstop = new;
+                write!(ctx.info(), "{changed} lines changed")?;
+                Ok(())
+            }
+            Command::Quit { force } => {
+                if ctx.in_command_window() {
+                    ctx.close_command_window();
+                } else if force {
+                    crate::exit();
+                } else if ctx.focused_buf().is_modified() {
+                    ctx.ask(Confirm::Quit);
+                } else {
+                    ctx.check_hidden_changes()?;
+                    crate::exit();
+                }
+                Ok(())
+            }
+            Command::Cd { path, local } => {
+                let dir = match path {
+                    Some(path) => expand(&path, ctx)?,
+                    None => std::env::var_os("HOME").ok_or("HOME is not set")?.into(),
+                };
+                let dir = dir.canonicalize().map_err(|e| format!("{dir:?}: {e}"))?;
+                if !dir.is_dir() {
+                    return Err(format!("{dir:?} is not a directory").into());
+                }
+                ctx.set_cwd(dir.clone(), local)?;
+                write!(ctx.info(), "{}", dir.display())?;
+                Ok(())
+            }
+            Command::Pwd => {
+                let cwd = ctx.cwd();
+                write!(ctx.info(), "{}", cwd.display())?;
+                Ok(())
+            }
+            Command::GitBlame => ctx.toggle_blame(),
+            Command::Man { topic } => ctx.open_man(&topic),
+            Command::Align { range, delim } => {
+                ctx.check_modifiable()?;
+                if delim.is_empty() {
+                    return Err("argument required".into());
+                }
+                let mut buf = ctx.focused_buf_mut();
+                let y = buf.cursor.pos.y;
+                let lines = match range {
+                    Some(range) => range.resolve(y, buf.linecnt())?,
+                    None => {
+                        let has = |y: usize| buf.line(y).contains(&*delim);
+                        if !has(y) {
+                            return Err(EditorError::PatternNotFound(delim.clone()).into());
+                        }
+                        let start = (0..y).rev().take_while(|&y| has(y)).last().unwrap_or(y);
+                        let end = (y + 1..buf.linecnt()).take_while(|&y| has(y)).last().unwrap_or(y);
+                        start..end + 1
+                    }
+                };
+                let aligned = crate::format::align(&buf.get_lines(lines.clone()), &delim);
+                buf.set_lines(lines, &aligned);
+                Ok(())
+            }
+            Command::ToHtml { path } => {
+                let path = match path {
+                    Some(path) => expand(&path, ctx)?,
+                    None => {
+                        let own = ctx.focused_buf().path().ok_or(EditorError::NoFileName)?.as_os_str().to_owned();
+                        ctx.cwd().join(format!("{}.html", own.to_string_lossy()))
+                    }
+                };
+                let html = path.extension().is_some_and(|e| e == "html" || e == "htm");
+                let buf = ctx.focused_buf();
+                let out = if html { crate::export::html(&buf, buf.name()) } else { crate::export::ansi(&buf) };
+                drop(buf);
+                std::fs::write(&path, out).map_err(|error| EditorError::Io { path: path.clone(), op: FileOp::Create, error })?;
+                write!(ctx.info(), "{path:?} written")?;
+                Ok(())
+            }
+            Command::User { name, range, args } => {
+                let cmd = user::get(&name).ok_or_else(|| EditorError::UnknownCommand(name.clone()))?;
+                match &cmd.action {
+                    user::Action::Guile(f) => {
+                        if range.is_some() {
+                            return Err("No range allowed".into());
+                        }
+                        guile::run_command(f, &args).map_err(|_| format!("{name} failed"))?;
+                    }
+                    user::Action::Alias(replacement) => {
+                        let range = match range {
+                            Some(_) if !replacement.contains("<range>") => return Err("No range allowed".into()),
+                            Some(range) => range.to_string(),
+                            None => String::new(),
+                        };
+                        ctx.execute_alias(&user::expand(replacement, &range, &args))?;
+                    }
+                }
+                Ok(())
+            }
+            Command::DefineUser { name, replacement, force } => {
+                if name.is_empty() {
+                    let aliases: Vec<_> = user::names()
+                        .into_iter()
+                        .filter_map(|name| match &user::get(&name)?.action {
+                            user::Action::Alias(replacement) => Some((name, replacement.clone())),
+                            user::Action::Guile(_) => None,
+                        })
+                        .collect();
+                    if aliases.is_empty() {
+                        write!(ctx.info(), "no user commands")?;
+                    }
+                    for (i, (name, replacement)) in aliases.iter().enumerate() {
+                        if i != 0 {
+                            writeln!(ctx.info())?;
+                        }
+                        write!(ctx.info(), "{name:<12} {replacement}")?;
+                    }
+                    return Ok(());
+                }
+                if replacement.is_empty() {
+                    return Err("E471: Argument required".into());
+                }
+                if !force && user::get(&name).is_some() {
+                    return Err(format!("E174: Command already exists: add ! to replace it: {name}").into());
+                }
+                let cmd = user::UserCommand { action: user::Action::Alias(replacement), complete: None };
+                user::define(&name, cmd)?;
                 Ok(())
             }
             _ => {
@@ -61,3 +643,412 @@ impl Command {
         }
     }
 }
+
+/// `:e` of `path`, which is expanded already
+fn edit(ctx: &mut Ctx, path: &Path, force: bool) -> Result<(), Box<dyn Error>> {
+    let abandoned = ctx.abandon(force)?;
+    let buf = match ctx.listed_buffer(path) {
+        Some(buf) => buf,
+        None => Buffer::edit(path)?,
+    };
+    let abandoned = abandoned.filter(|b| !Arc::ptr_eq(b, &buf));
+    ctx.open_buffer(buf);
+    if let Some(abandoned) = abandoned {
+        ctx.delete_buffer(&abandoned, true)?;
+    }
+    if let Some(flag) = crate::buffer::file_flag(path) {
+        write!(ctx.info(), "{path:?} {flag}")?;
+    }
+    Ok(())
+}
+
+/// `:earlier` and `:later` in the focused buffer
+fn travel(ctx: &mut Ctx, count: isize, writes: bool) -> Result<(), Box<dyn Error>> {
+    ctx.check_modifiable()?;
+    if count == 0 {
+        return Ok(());
+    }
+    ctx.history_step(1, count > 0, |buf| buf.travel(count, writes));
+    Ok(())
+}
+
+/// `bytes` in the largest unit that leaves at least one of it, like `1.5K`
+fn size(bytes: usize) -> String {
+    let mut n = bytes as f64;
+    for unit in ["B", "K", "M"] {
+        if n < 1024.0 {
+            return if unit == "B" { format!("{bytes}B") } else { format!("{n:.1}{unit}") };
+        }
+        n /= 1024.0;
+    }
+    format!("{n:.1}G")
+}
+
+/// the memory of the editor that's in RAM, `None` where `/proc` doesn't have it
+fn resident_memory() -> Option<usize> {
+    let statm = std::fs::read_to_string("/proc/self/statm").ok()?;
+    let pages: usize = statm.split_whitespace().nth(1)?.parse().ok()?;
+    // SAFETY: sysconf has no preconditions
+    let page = unsafe { libc::sysconf(libc::_SC_PAGESIZE) };
+    Some(pages * usize::try_from(page).ok()?)
+}
+
+/// [`expand_path`] with the files of `ctx`, relative to its working directory
+fn expand(path: &Path, ctx: &Ctx) -> Result<PathBuf, Box<dyn Error>> {
+    let path = path.to_str().ok_or("path is not valid UTF-8")?;
+    let current = ctx.focused_buf().path().map(Path::to_path_buf);
+    let alternate = ctx.alternate_buf().and_then(|b| b.get().path().map(Path::to_path_buf));
+    let home = std::env::var_os("HOME").map(PathBuf::from);
+    let path = expand_path(path, current.as_deref(), alternate.as_deref(), home.as_deref())?;
+    Ok(ctx.cwd().join(path))
+}
+
+/// expand `%` to the `current` file, `#` to the `alternate` file and a leading `~` to the `home`
+/// directory. `%` and `#` can be followed by modifiers: `:h` for the directory, `:t` for the file
+/// name, `:r` to remove the extension and `:e` for just the extension. `\%` and `\#` are literal.
+fn expand_path(arg: &str, current: Option<&Path>, alternate: Option<&Path>, home: Option<&Path>) -> Result<PathBuf, Box<dyn Error>> {
+    let mut out = String::with_capacity(arg.len());
+    let mut rest = arg;
+    if let Some(after) = rest.strip_prefix('~').filter(|a| a.is_empty() || a.starts_with('/')) {
+        let home = home.ok_or("HOME is not set")?;
+        out.push_str(&home.to_string_lossy());
+        rest = after;
+    }
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        let file = match c {
+            '\\' if rest.starts_with(['%', '#']) => {
+                out.push_str(&rest[..1]);
+                rest = &rest[1..];
+                continue;
+            }
+            '%' => current.ok_or("No file name to substitute for '%'")?,
+            '#' => alternate.ok_or("No alternate file name to substitute for '#'")?,
+            c => {
+                out.push(c);
+                continue;
+            }
+        };
+        let mut file = file.to_path_buf();
+        while let Some(m) = rest.strip_prefix(':').and_then(|r| r.chars().next()).filter(|m| "htre".contains(*m)) {
+            file = match m {
+                'h' => file.parent().filter(|p| !p.as_os_str().is_empty()).unwrap_or(Path::new(".")).into(),
+                't' => file.file_name().map_or_else(PathBuf::new, PathBuf::from),
+                'r' => file.with_extension(""),
+                _ => file.extension().map_or_else(PathBuf::new, PathBuf::from),
+            };
+            rest = &rest[2..];
+        }
+        out.push_str(&file.to_string_lossy());
+    }
+    Ok(out.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expand_paths() {
+        let cur = Some(Path::new("src/command/mod.rs"));
+        let alt = Some(Path::new("notes.tar.gz"));
+        let home = Some(Path::new("/home/me"));
+        let exp = |s: &str| expand_path(s, cur, alt, home).map(|p| p.to_string_lossy().into_owned());
+        assert_eq!(exp("%.bak").unwrap(), "src/command/mod.rs.bak");
+        assert_eq!(exp("%:h/other.rs").unwrap(), "src/command/other.rs");
+        assert_eq!(exp("%:h:h").unwrap(), "src");
+        assert_eq!(exp("%:t").unwrap(), "mod.rs");
+        assert_eq!(exp("%:t:r").unwrap(), "mod");
+        assert_eq!(exp("%:e").unwrap(), "rs");
+        assert_eq!(exp("#:r").unwrap(), "notes.tar");
+        assert_eq!(exp("#:h").unwrap(), ".");
+        assert_eq!(exp("~/%:t").unwrap(), "/home/me/mod.rs");
+        assert_eq!(exp("a~b\\%").unwrap(), "a~b%");
+        assert_eq!(exp("%:x").unwrap(), "src/command/mod.rs:x");
+        assert!(expand_path("#", cur, None, home).is_err());
+        assert!(expand_path("~", cur, alt, None).is_err());
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(size(0), "0B");
+        assert_eq!(size(1023), "1023B");
+        assert_eq!(size(1536), "1.5K");
+        assert_eq!(size(3 << 20), "3.0M");
+        assert_eq!(size(5 << 30), "5.0G");
+        assert!(resident_memory().is_some_and(|rss| rss > 0));
+    }
+
+    #[test]
+    fn delete_buffer() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("text", 30, 8);
+        let first = ctx.focused_buf_id();
+        type_keys(&mut ctx, "x:bd\r");
+        assert_eq!(ctx.focused_buf_id(), first);
+        type_keys(&mut ctx, ":bd!\r");
+        assert_ne!(ctx.focused_buf_id(), first);
+        assert!(registry::get(first).is_none());
+        assert!(Arc::ptr_eq(&ctx.focused_win().buffer, &registry::get(ctx.focused_buf_id()).unwrap()));
+    }
+
+    #[test]
+    fn goto_line() {
+        use crate::testing::{editor, screen, type_keys};
+        let text: String = (1..=50).map(|i| format!("  line {i}\n")).collect();
+        let mut ctx = editor(&text, 30, 8);
+        type_keys(&mut ctx, ":42\r");
+        assert_eq!(ctx.focused_buf().cursor.pos, DocPos { x: 2, y: 41 });
+        assert!(screen(&mut ctx).contains("line 42"));
+        type_keys(&mut ctx, ":-5\r");
+        assert_eq!(ctx.focused_buf().cursor.pos.y, 36);
+        type_keys(&mut ctx, ":$\r");
+        assert_eq!(ctx.focused_buf().cursor.pos.y, 49);
+        assert!(screen(&mut ctx).contains("line 50"));
+        type_keys(&mut ctx, ":1\r:+100\r");
+        assert_eq!(ctx.focused_buf().cursor.pos.y, 0);
+        assert!(ctx.take_message().is_some_and(|(m, _)| m.contains("Invalid range")));
+    }
+
+    #[test]
+    fn jobkill() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("", 30, 8);
+        for id in ["abc", "12abc", "-1", "99999999999999999999"] {
+            type_keys(&mut ctx, &format!(":jobkill {id}\r"));
+            assert_eq!(ctx.take_message().unwrap().0, format!("Invalid job id: {id}"));
+        }
+        type_keys(&mut ctx, ":jobkill\r");
+        assert_eq!(ctx.take_message().unwrap().0, "E471: Argument required");
+    }
+
+    #[test]
+    fn execute_registers() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("x1\nx2\nx3\nx4\n", 30, 8);
+        type_keys(&mut ctx, ":s/x/y/\rj@:");
+        assert_eq!(ctx.focused_buf().to_string(), "y1\ny2\nx3\nx4\n");
+        // lines of ex commands, from the given line
+        ctx.registers.set('q', "s/x/a/\n+1s/x/b/\n");
+        type_keys(&mut ctx, ":3@q\r");
+        assert_eq!(ctx.focused_buf().to_string(), "y1\ny2\na3\nb4\n");
+        // keys, with ex commands among them
+        ctx.registers.set('k', "x:s/1/A/\r");
+        type_keys(&mut ctx, "gg@k");
+        assert_eq!(ctx.focused_buf().line(0), "A");
+        type_keys(&mut ctx, "@@");
+        assert_eq!(ctx.focused_buf().line(0), "");
+        type_keys(&mut ctx, "@z");
+        assert!(ctx.take_message().is_some_and(|(m, _)| m.contains("E353")));
+        ctx.registers.set('r', "@r");
+        type_keys(&mut ctx, "@r");
+        assert!(ctx.take_message().is_some_and(|(m, _)| m.contains("E169")));
+    }
+
+    #[test]
+    fn define_commands() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("ab\nab\nb\n", 30, 8);
+        type_keys(&mut ctx, ":command Sub <range>s/<args>/X/\r:2,$Sub b\r");
+        assert_eq!(ctx.focused_buf().to_string(), "ab\naX\nX\n");
+        type_keys(&mut ctx, ":Sub a\r");
+        assert_eq!(ctx.focused_buf().to_string(), "Xb\naX\nX\n");
+        type_keys(&mut ctx, ":command Sub e\r");
+        assert!(ctx.take_message().is_some_and(|(m, _)| m.contains("E174")));
+        type_keys(&mut ctx, ":command! Sub %s/X/Y/g\r:1Sub\r");
+        assert!(ctx.take_message().is_some_and(|(m, _)| m.contains("No range allowed")));
+        type_keys(&mut ctx, ":Sub\r");
+        assert_eq!(ctx.focused_buf().to_string(), "Yb\naY\nY\n");
+        type_keys(&mut ctx, ":command Loop Loop\r:Loop\r");
+        assert!(ctx.take_message().is_some_and(|(m, _)| m.contains("E169")));
+        type_keys(&mut ctx, ":command lower x\r");
+        assert!(ctx.take_message().is_some_and(|(m, _)| m.contains("uppercase")));
+        type_keys(&mut ctx, ":command\r");
+        assert_eq!(ctx.take_message().unwrap().0, "Loop         Loop\nSub          %s/X/Y/g");
+    }
+
+    #[test]
+    fn normal() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("a\nb\nc\n", 30, 8);
+        // from the start of every line of the range
+        type_keys(&mut ctx, "$:2,3normal ix\r");
+        assert_eq!(ctx.focused_buf().to_string(), "a\nxb\nxc\n");
+        // mappings apply without the !
+        type_keys(&mut ctx, ":nnoremap l x\r:2normal l\r");
+        assert_eq!(ctx.focused_buf().to_string(), "a\nb\nxc\n");
+        type_keys(&mut ctx, ":3normal! lx\r");
+        assert_eq!(ctx.focused_buf().to_string(), "a\nb\nx\n");
+        // an insert or an operator left incomplete ends in normal mode
+        type_keys(&mut ctx, ":1normal ihi\r");
+        assert_eq!(ctx.mode, Mode::Normal);
+        assert_eq!(ctx.focused_buf().to_string(), "hia\nb\nx\n");
+        type_keys(&mut ctx, ":normal d\rx");
+        assert_eq!(ctx.mode, Mode::Normal);
+        assert_eq!(ctx.focused_buf().to_string(), "hi\nb\nx\n");
+    }
+
+    #[test]
+    fn earlier_later() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("", 30, 8);
+        let path = std::env::temp_dir().join(format!("edit-earlier-test-{}", std::process::id()));
+        std::fs::write(&path, "abc\n").unwrap();
+        type_keys(&mut ctx, &format!(":e {}\rx:w\rx", path.display()));
+        type_keys(&mut ctx, &format!(":earlier {}\r", usize::MAX));
+        assert_eq!(ctx.focused_buf().to_string(), "abc\n");
+        type_keys(&mut ctx, ":later 0\r:later 0f\r");
+        assert_eq!(ctx.focused_buf().to_string(), "abc\n");
+        assert_eq!(ctx.take_message(), None);
+        type_keys(&mut ctx, ":later 1f\r");
+        assert_eq!(ctx.focused_buf().to_string(), "bc\n");
+        type_keys(&mut ctx, &format!(":later {}\r", usize::MAX));
+        assert_eq!(ctx.focused_buf().to_string(), "c\n");
+        type_keys(&mut ctx, ":earlier 0f\r");
+        assert_eq!(ctx.focused_buf().to_string(), "c\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn hidden() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("", 30, 8);
+        let dir = std::env::temp_dir().join(format!("edit-hidden-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (a, b) = (dir.join("a"), dir.join("b"));
+        type_keys(&mut ctx, &format!(":e {}\rix\x1b:e {}\r", a.display(), b.display()));
+        assert_eq!(ctx.focused_buf().path(), Some(a.as_path()));
+        type_keys(&mut ctx, &format!(":set hidden\r:e {}\r", b.display()));
+        assert_eq!(ctx.focused_buf().path(), Some(b.as_path()));
+        type_keys(&mut ctx, ":q\r");
+        assert!(ctx.take_message().unwrap().0.contains("E162"));
+        // also when the focused buffer has changes too and they're dropped or written
+        type_keys(&mut ctx, "iy\x1b:q\rn");
+        assert!(ctx.take_message().unwrap().0.contains("E162"));
+        assert!(ctx.focused_buf().is_modified());
+        type_keys(&mut ctx, ":q\ry");
+        assert!(ctx.take_message().unwrap().0.contains("E162"));
+        assert!(!ctx.focused_buf().is_modified());
+        // still has its changes
+        type_keys(&mut ctx, &format!(":e {}\r", a.display()));
+        assert!(ctx.focused_buf().is_modified());
+        type_keys(&mut ctx, &format!(":set nohidden\r:e! {}\r", b.display()));
+        assert!(ctx.listed_buffer(&a).is_none());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn buffer_by_name() {
+        use crate::command::cmdline::CommandType;
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("", 30, 8);
+        let dir = std::env::temp_dir().join(format!("edit-buffer-test-{}", std::process::id()));
+        let (a, b) = (dir.join("Alpha.rs"), dir.join("beta.rs"));
+        type_keys(&mut ctx, &format!(":e {}\r:e {}\r", a.display(), b.display()));
+        type_keys(&mut ctx, ":b alp\r");
+        assert_eq!(ctx.focused_buf().path(), Some(a.as_path()));
+        type_keys(&mut ctx, ":b nothing\r");
+        assert!(ctx.take_message().unwrap().0.contains("E94"));
+        // both match, so they're listed and Tab picks the closer match
+        type_keys(&mut ctx, &format!(":b {}/.rs\r", dir.display()));
+        assert_eq!(ctx.cmdtype(), CommandType::Ex);
+        type_keys(&mut ctx, "\t\r");
+        assert_eq!(ctx.focused_buf().path(), Some(b.as_path()));
+    }
+
+    #[test]
+    fn lock_new_file() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("", 30, 8);
+        let path = std::env::temp_dir().join(format!("edit-lock-new-test-{}", std::process::id()));
+        type_keys(&mut ctx, &format!(":set filelock\r:e {}\r", path.display()));
+        // nothing to lock and nothing to warn about until it's written
+        assert!(ctx.take_message().unwrap().0.ends_with("[New]"));
+        assert!(!ctx.focused_buf().is_locked());
+        type_keys(&mut ctx, "ix\x1b:w\r");
+        assert!(ctx.focused_buf().is_locked());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn format_on_save() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("", 30, 8);
+        let path = std::env::temp_dir().join(format!("edit-format-test-{}.txt", std::process::id()));
+        type_keys(&mut ctx, &format!(":e {}\riabc\x1b", path.display()));
+        type_keys(&mut ctx, ":set fos=text\r:set fp=text:tr\\ a-z\\ A-Z\r:w\r");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "ABC");
+        assert_eq!(ctx.focused_buf().to_string(), "ABC");
+        assert!(!ctx.focused_buf().is_modified());
+        // a formatter that fails leaves the text as it is
+        type_keys(&mut ctx, ":set fp=text:false\rxix\x1b:w\r");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "AxB");
+        let (msg, severity) = ctx.take_message().unwrap();
+        assert!(msg.contains("unformatted"), "{msg}");
+        assert_eq!(severity, crate::tui::TextSeverity::Warning);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn partial_write() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("", 30, 8);
+        let path = std::env::temp_dir().join(format!("edit-partial-test-{}.txt", std::process::id()));
+        std::fs::write(&path, "a\nb\nc\n").unwrap();
+        type_keys(&mut ctx, &format!(":e {}\r:2w\r", path.display()));
+        assert!(ctx.take_message().is_some_and(|(m, _)| m.contains("E140")));
+        type_keys(&mut ctx, &format!(":2w {}\r", path.display()));
+        assert!(ctx.take_message().is_some_and(|(m, _)| m.contains("E140")));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\nb\nc\n");
+        type_keys(&mut ctx, ":2w!\r");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "b\n");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn confirm() {
+        use crate::command::cmdline::CommandType;
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("new\n", 30, 8);
+        let path = std::env::temp_dir().join(format!("edit-confirm-test-{}", std::process::id()));
+        std::fs::write(&path, "old\n").unwrap();
+        type_keys(&mut ctx, &format!(":w {}\r", path.display()));
+        assert_eq!(ctx.cmdtype(), CommandType::Confirm);
+        type_keys(&mut ctx, "n");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old\n");
+        type_keys(&mut ctx, &format!(":w {}\ry", path.display()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\n");
+        type_keys(&mut ctx, &format!("0ix\x1b:w! {}\r", path.display()));
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "xnew\n");
+        // the changes are still only in the buffer, so quitting asks
+        type_keys(&mut ctx, ":q\r");
+        assert_eq!(ctx.cmdtype(), CommandType::Confirm);
+        type_keys(&mut ctx, "a\x1b");
+        assert_eq!(ctx.cmdtype(), CommandType::None);
+        std::fs::remove_file(path).unwrap();
+    }
+    #[test]
+    fn scoped_mappings() {
+        use crate::testing::{editor, type_keys};
+        let mut ctx = editor("abc\n", 30, 8);
+        type_keys(&mut ctx, ":nnoremap Q l\r:nnoremap <buffer> Q x\r:nmap <filetype=rust> Q h\rQ");
+        assert_eq!(ctx.focused_buf().to_string(), "bc\n");
+        ctx.take_message();
+        type_keys(&mut ctx, ":map Q\r");
+        assert_eq!(ctx.take_message().unwrap().0, "n  Q            *@x");
+        type_keys(&mut ctx, ":unmap <buffer> Q\rQ");
+        assert_eq!(ctx.focused_buf().cursor.pos.x, 1);
+        // another buffer only has the global mapping
+        ctx.open_buffer(Buffer::from_string("xyz\n"));
+        type_keys(&mut ctx, ":map <buffer> Q x\rQ");
+        assert_eq!(ctx.focused_buf().to_string(), "yz\n");
+        type_keys(&mut ctx, ":unmap <filetype=rust> Q\r:unmap <buffer> Q\r:unmap Q\r");
+        assert!(ctx.keymap.list(&ctx.keymap_scopes(), Mode::Normal, b"").is_empty());
+        // and a buffer's go away with it
+        type_keys(&mut ctx, ":map <buffer> Q x\r");
+        let buf = registry::get(ctx.focused_buf_id()).unwrap();
+        ctx.delete_buffer(&buf, true).unwrap();
+        let scopes = [crate::keymap::Scope::Buffer(buf.id())];
+        assert!(ctx.keymap.list(&scopes, Mode::Normal, b"").is_empty());
+    }
+}
@@ -2,9 +2,13 @@ use lazy_regex::regex;
 use std::fmt::Write;
 use std::ops::Range;
 
-use crate::{debug::log, prelude::*, tui::TextSeverity};
+use crate::{debug::log, prelude::*, tui::TextSeverity, Mode};
 
-use super::{cmdline::CommandLine, Command};
+use crate::qflist::ListKind;
+use crate::search::{self, Offset};
+
+use super::range::{self, LineRange};
+use super::{cmdline::CommandLine, user, Command, MapScope};
 
 struct Lexer<'a> {
     input: &'a str,
@@ -57,6 +61,14 @@ impl<'a> Lexer<'a> {
         })
     }
 
+    /// skip `s`, which must be next apart from whitespace
+    fn skip_str(&mut self, s: &str) {
+        let rest = self.remainder();
+        let skip = rest.len() - rest.trim_start().len() + s.len();
+        debug_assert!(rest.trim_start().starts_with(s));
+        self.idx += skip;
+    }
+
     fn remainder(&self) -> &str {
         &self.input[self.idx..]
     }
@@ -133,29 +145,368 @@ impl std::fmt::Display for TokenKind {
     }
 }
 
+/// parse `s` if it's a command that can be previewed while it's typed, without reporting errors
+pub fn parse_preview(s: &str) -> Option<Command> {
+    let (range, s) = range::parse(s);
+    let mut args = Lexer::new(s);
+    match args.try_next_expect(TokenKind::Ident).ok()?.data {
+        "s" | "su" | "substitute" => Some(substitute_command(&args, range.unwrap_or(LineRange::CURRENT))),
+        _ => None,
+    }
+}
+
+/// parse what was typed after `/` or `?`
+pub fn parse_search(s: &str, backward: bool, diag: &mut CommandLine) -> Option<Command> {
+    let (pattern, offset) = search::split_offset(s, if backward { '?' } else { '/' });
+    let offset = offset.unwrap_or_default();
+    let Some(offset) = Offset::parse(offset) else {
+        diag.output_severity = TextSeverity::Error;
+        write!(diag, "Invalid search offset: {offset}").unwrap();
+        return None;
+    };
+    Some(Command::Search { pattern: pattern.into(), backward, offset })
+}
+
+/// split `/pattern/replacement/flags`, where `/` can be any punctuation that doesn't appear
+/// unescaped in the pattern or replacement
+fn substitute_command(args: &Lexer, range: LineRange) -> Command {
+    let rest = args.remainder().trim_start();
+    let mut parts = ["", "", ""];
+    if let Some(delim) = rest.chars().next() {
+        let mut rest = &rest[delim.len_utf8()..];
+        for part in &mut parts[..2] {
+            let (before, after) = search::split_offset(rest, delim);
+            *part = before;
+            rest = after.unwrap_or_default();
+        }
+        parts[2] = rest.trim();
+    }
+    let [pattern, replacement, flags] = parts.map(String::from);
+    Command::Substitute { range, pattern, replacement, flags }
+}
+
+/// `:sort[!] [flags] [/pattern/]`, where the flags can also come after the pattern and `/` can be
+/// any punctuation
+fn sort_command(args: &Lexer, range: Option<LineRange>) -> Command {
+    let rest = args.remainder();
+    let (reverse, mut rest) = match rest.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, rest),
+    };
+    let mut flags = String::new();
+    let mut pattern = None;
+    while let Some(c) = rest.chars().next() {
+        rest = &rest[c.len_utf8()..];
+        if c.is_alphabetic() {
+            flags.push(c);
+        } else if !c.is_whitespace() && pattern.is_none() {
+            let (pat, after) = search::split_offset(rest, c);
+            pattern = Some(pat.to_string());
+            rest = after.unwrap_or_default();
+        } else if !c.is_whitespace() {
+            flags.push(c);
+        }
+    }
+    Command::Sort { range, reverse, flags, pattern }
+}
+
+/// `:earlier` and `:later` argument, a count of changes or of file writes with an `f` after it
+fn time_count(arg: &str, diag: &mut CommandLine) -> Option<(usize, bool)> {
+    let arg = arg.trim();
+    let (count, writes) = match arg.strip_suffix('f') {
+        Some(count) => (count, true),
+        None => (arg, false),
+    };
+    match count {
+        "" => Some((1, writes)),
+        count => match count.parse() {
+            Ok(count) => Some((count, writes)),
+            Err(_) => {
+                diag.output_severity = TextSeverity::Error;
+                write!(diag, "E475: Invalid argument: {arg}").unwrap();
+                None
+            }
+        },
+    }
+}
+
+/// the `<buffer>` or `<filetype=ft>` in front of the arguments of a mapping command, and what
+/// comes after it
+fn map_scope(args: &str) -> (MapScope, &str) {
+    let args = args.trim();
+    if let Some(rest) = args.strip_prefix("<buffer>") {
+        return (MapScope::Buffer, rest.trim_start());
+    }
+    let filetype = args.strip_prefix("<filetype=").and_then(|rest| rest.split_once('>'));
+    match filetype {
+        Some((ft, rest)) if !ft.is_empty() => (MapScope::Filetype(ft.into()), rest.trim_start()),
+        _ => (MapScope::Global, args),
+    }
+}
+
+/// `:map {lhs} {rhs}`, the right hand side is the rest of the line
+fn map_command(args: &Lexer, mode: Mode, noremap: bool) -> Command {
+    let (scope, rest) = map_scope(args.remainder());
+    let (lhs, rhs) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+    Command::Map {
+        mode,
+        lhs: lhs.into(),
+        rhs: rhs.trim_start().into(),
+        noremap,
+        scope,
+    }
+}
+
+fn unmap_command(args: &Lexer, mode: Mode) -> Command {
+    let (scope, lhs) = map_scope(args.remainder());
+    Command::Unmap { mode, lhs: lhs.into(), scope }
+}
+
+/// skip the `!` right after a command name, returning whether there was one
+fn bang(args: &mut Lexer) -> bool {
+    let bang = args.remainder().starts_with('!');
+    if bang {
+        args.skip_str("!");
+    }
+    bang
+}
+
+/// split on whitespace, except where escaped with a backslash
+fn split_args(s: &str) -> Vec<String> {
+    let mut out = Vec::new();
+    let mut curr = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.as_str().starts_with(char::is_whitespace) => {
+                curr.extend(chars.next());
+            }
+            c if c.is_whitespace() => {
+                if !curr.is_empty() {
+                    out.push(std::mem::take(&mut curr));
+                }
+            }
+            c => curr.push(c),
+        }
+    }
+    if !curr.is_empty() {
+        out.push(curr);
+    }
+    out
+}
+
+/// full names of the built-in commands, for completion
+pub const COMMANDS: &[&str] = &[
+    "write", "scm", "source", "normal", "map", "nmap", "imap", "cmap", "tmap", "noremap",
+    "nnoremap", "inoremap", "cnoremap", "tnoremap", "unmap", "nunmap", "iunmap", "cunmap", "tunmap",
+    "job", "jobs", "jobkill", "joblog", "quit", "edit", "find", "diffsplit", "diffoff", "buffers", "buffer", "memory",
+    "bdelete", "bwipeout",
+    "registers", "earlier", "later", "screendump", "sort", "retab",
+    "llist", "lnext", "lprevious", "clist", "cnext", "cprevious", "terminal", "make", "set", "setlocal",
+    "substitute", "global", "help", "cd", "lcd", "pwd", "command",
+    "GitBlame", "Align", "TOhtml", "Man",
+];
+
 pub fn parse_command(s: &str, diag: &mut CommandLine) -> Option<Command> {
+    let (mut range, s) = range::parse(s);
+    if let (Some(line), "") = (range, s.trim()) {
+        return Some(Command::Goto { line });
+    }
+    if let Some(rest) = s.trim_start().strip_prefix('@') {
+        let mut names = rest.trim().chars();
+        let register = names.next().unwrap_or('@');
+        if names.next().is_some() {
+            diag.output_severity = TextSeverity::Error;
+            write!(diag, "E488: Trailing characters: {}", rest.trim()).unwrap();
+            return None;
+        }
+        return Some(Command::ExecuteRegister { range, register });
+    }
     let mut args = Lexer::new(s);
     let res = match args.next_expects(diag, &[TokenKind::Ident])?.data {
-        "w" | "write" => Command::Write {
-            path: args
-                .try_next_expect(TokenKind::Path)
-                .ok()
-                .map(|p| p.data.into()),
-        },
+        "w" | "write" => {
+            let force = bang(&mut args);
+            let append = args.remainder().trim_start().starts_with(">>");
+            if append {
+                args.skip_str(">>");
+            }
+            Command::Write {
+                path: args
+                    .try_next_expect(TokenKind::Path)
+                    .ok()
+                    .map(|p| p.data.into()),
+                range: range.take(),
+                append,
+                force,
+            }
+        }
         "scm" => Command::Guile { cmd: args.remainder().into() },
-        "q" | "quit" => Command::Quit,
-        "e" | "edit" => Command::Edit {
+        "so" | "source" => Command::Source { path: args.remainder().trim().into() },
+        "norm" | "normal" => {
+            let keys = args.remainder();
+            let range = range.take();
+            match keys.strip_prefix('!') {
+                Some(keys) => Command::Normal { range, keys: keys.trim_start().into(), remap: false },
+                None => Command::Normal { range, keys: keys.trim_start().into(), remap: true },
+            }
+        }
+        "map" | "nm" | "nmap" => map_command(&args, Mode::Normal, false),
+        "im" | "imap" => map_command(&args, Mode::Insert, false),
+        "cm" | "cmap" => map_command(&args, Mode::Command, false),
+        "tma" | "tmap" => map_command(&args, Mode::Terminal, false),
+        "no" | "noremap" | "nn" | "nnoremap" => map_command(&args, Mode::Normal, true),
+        "ino" | "inoremap" => map_command(&args, Mode::Insert, true),
+        "cno" | "cnoremap" => map_command(&args, Mode::Command, true),
+        "tno" | "tnoremap" => map_command(&args, Mode::Terminal, true),
+        "unm" | "unmap" | "nun" | "nunmap" => unmap_command(&args, Mode::Normal),
+        "iu" | "iunmap" => unmap_command(&args, Mode::Insert),
+        "cu" | "cunmap" => unmap_command(&args, Mode::Command),
+        "tunma" | "tunmap" => unmap_command(&args, Mode::Terminal),
+        "job" => Command::Job { cmd: args.remainder().into() },
+        "jobs" => Command::Jobs,
+        "jobkill" => match args.remainder().trim() {
+            "" => {
+                diag.output_severity = TextSeverity::Error;
+                write!(diag, "E471: Argument required").unwrap();
+                return None;
+            }
+            id => match id.parse() {
+                Ok(id) => Command::JobKill { id },
+                Err(_) => {
+                    diag.output_severity = TextSeverity::Error;
+                    write!(diag, "Invalid job id: {id}").unwrap();
+                    return None;
+                }
+            },
+        },
+        "joblog" => Command::JobLog,
+        "q" | "quit" => Command::Quit { force: bang(&mut args) },
+        "e" | "edit" => {
+            let force = bang(&mut args);
+            Command::Edit { path: args.next_expects(diag, &[TokenKind::Path])?.data.into(), force }
+        }
+        "fin" | "find" => {
+            let force = bang(&mut args);
+            Command::Find { name: args.next_expects(diag, &[TokenKind::Path])?.data.into(), force }
+        }
+        "diffs" | "diffsplit" => Command::DiffSplit {
             path: args.next_expects(diag, &[TokenKind::Path])?.data.into(),
         },
+        "diffo" | "diffoff" => Command::DiffOff,
         "ls" | "buffers" => Command::ListBuffers,
-        "s" | "su" => Command::Substitute,
+        "mem" | "memory" => Command::Memory,
+        "reg" | "registers" => Command::Registers {
+            names: args.remainder().chars().filter(|c| !c.is_whitespace()).collect(),
+        },
+        "screendump" => Command::ScreenDump {
+            path: args.next_expects(diag, &[TokenKind::Path])?.data.into(),
+        },
+        "ea" | "earlier" => {
+            let (count, writes) = time_count(args.remainder(), diag)?;
+            Command::Earlier { count, writes }
+        }
+        "lat" | "later" => {
+            let (count, writes) = time_count(args.remainder(), diag)?;
+            Command::Later { count, writes }
+        }
+        "b" | "buffer" => {
+            let force = bang(&mut args);
+            let name = args.remainder().trim();
+            if name.is_empty() {
+                diag.output_severity = TextSeverity::Error;
+                write!(diag, "E471: Argument required").unwrap();
+                return None;
+            }
+            Command::Buffer { name: name.into(), force }
+        }
+        "bd" | "bdelete" | "bw" | "bwipeout" => {
+            let rest = args.remainder();
+            let (force, rest) = match rest.strip_prefix('!') {
+                Some(rest) => (true, rest.trim()),
+                None => (false, rest.trim()),
+            };
+            let id = match rest {
+                "" => None,
+                n => match n.parse() {
+                    Ok(n) => Some(n),
+                    Err(_) => {
+                        diag.output_severity = TextSeverity::Error;
+                        write!(diag, "E475: Invalid argument: {n}").unwrap();
+                        return None;
+                    }
+                },
+            };
+            Command::DeleteBuffer { id, force }
+        }
+        "ll" | "llist" => Command::List(ListKind::Location),
+        "lne" | "lnext" => Command::ListNext(ListKind::Location),
+        "lp" | "lprevious" => Command::ListPrev(ListKind::Location),
+        "cl" | "clist" => Command::List(ListKind::Quickfix),
+        "cn" | "cnext" => Command::ListNext(ListKind::Quickfix),
+        "cp" | "cprevious" => Command::ListPrev(ListKind::Quickfix),
+        "ter" | "terminal" => Command::Terminal { cmd: args.remainder().into() },
+        "mak" | "make" => Command::Make { args: args.remainder().into() },
+        "se" | "set" => Command::Set { args: split_args(args.remainder()), local: false },
+        "setl" | "setlocal" => Command::Set { args: split_args(args.remainder()), local: true },
+        "s" | "su" | "substitute" => substitute_command(&args, range.take().unwrap_or(LineRange::CURRENT)),
+        "sor" | "sort" => sort_command(&args, range.take()),
+        "ret" | "retab" => {
+            let rest = args.remainder();
+            let (all, rest) = match rest.strip_prefix('!') {
+                Some(rest) => (true, rest.trim()),
+                None => (false, rest.trim()),
+            };
+            let tabstop = match rest {
+                "" => None,
+                n => match n.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => {
+                        diag.output_severity = TextSeverity::Error;
+                        write!(diag, "E475: Invalid argument: {n}").unwrap();
+                        return None;
+                    }
+                },
+            };
+            Command::Retab { range: range.take(), all, tabstop }
+        }
         "g" | "global" => Command::Global,
         "h" | "help" => Command::Help,
+        "cd" | "chd" | "chdir" => Command::Cd {
+            path: args.try_next_expect(TokenKind::Path).ok().map(|p| p.data.into()),
+            local: false,
+        },
+        "lcd" | "lch" | "lchdir" => Command::Cd {
+            path: args.try_next_expect(TokenKind::Path).ok().map(|p| p.data.into()),
+            local: true,
+        },
+        "pw" | "pwd" => Command::Pwd,
+        "com" | "command" => {
+            let force = bang(&mut args);
+            let rest = args.remainder().trim();
+            let (name, replacement) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            Command::DefineUser { name: name.into(), replacement: replacement.trim().into(), force }
+        }
+        "GitBlame" => Command::GitBlame,
+        "Man" => Command::Man { topic: args.remainder().trim().into() },
+        "Align" => Command::Align { range: range.take(), delim: args.remainder().trim().into() },
+        "TOhtml" => Command::ToHtml {
+            path: args.try_next_expect(TokenKind::Path).ok().map(|p| p.data.into()),
+        },
+        name if user::get(name).is_some() => Command::User {
+            name: name.into(),
+            range: range.take(),
+            args: args.remainder().trim().into(),
+        },
         unknown => {
             diag.output_severity = TextSeverity::Error;
-            write!(diag, "Unknown command: {unknown:?}").unwrap();
+            write!(diag, "{}", EditorError::UnknownCommand(unknown.into())).unwrap();
             return None;
         }
     };
+    if range.is_some() {
+        diag.output_severity = TextSeverity::Error;
+        write!(diag, "No range allowed").unwrap();
+        return None;
+    }
     Some(res)
 }
@@ -0,0 +1,194 @@
+//! Line ranges in front of ex commands, like `:%s` or `:2,$-1s`.
+use std::ops::Range;
+
+use crate::utils::unit_err;
+
+unit_err!(InvalidRange: "Invalid range");
+unit_err!(BackwardsRange: "Backwards range given");
+
+/// a single line number, before it's known which buffer it applies to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Address {
+    /// line number as typed, counting from 1
+    Line(usize),
+    /// `.`, the cursor line, plus an offset
+    Current(isize),
+    /// `$`, the last line, plus an offset
+    Last(isize),
+}
+
+impl std::fmt::Display for Address {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (base, off) = match *self {
+            Address::Line(n) => return write!(f, "{n}"),
+            Address::Current(off) => ('.', off),
+            Address::Last(off) => ('$', off),
+        };
+        write!(f, "{base}")?;
+        if off != 0 {
+            write!(f, "{off:+}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Address {
+    /// resolve to a line index
+    fn resolve(self, cur: usize, linecnt: usize) -> Result<usize, InvalidRange> {
+        let y = match self {
+            Address::Line(n) => n.checked_sub(1),
+            Address::Current(off) => cur.checked_add_signed(off),
+            Address::Last(off) => linecnt.saturating_sub(1).checked_add_signed(off),
+        };
+        y.filter(|&y| y < linecnt.max(1)).ok_or(InvalidRange)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: Address,
+    pub end: Address,
+}
+
+/// the range as it could be typed
+impl std::fmt::Display for LineRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.start == self.end {
+            return write!(f, "{}", self.start);
+        }
+        write!(f, "{},{}", self.start, self.end)
+    }
+}
+
+impl LineRange {
+    pub const CURRENT: Self = Self {
+        start: Address::Current(0),
+        end: Address::Current(0),
+    };
+
+    pub const ALL: Self = Self {
+        start: Address::Line(1),
+        end: Address::Last(0),
+    };
+
+    /// resolve to a range of line indices, given the cursor line and the number of lines
+    pub fn resolve(&self, cur: usize, linecnt: usize) -> Result<Range<usize>, Box<dyn std::error::Error>> {
+        let start = self.start.resolve(cur, linecnt)?;
+        let end = self.end.resolve(cur, linecnt)?;
+        if start > end {
+            return Err(BackwardsRange.into());
+        }
+        Ok(start..end + 1)
+    }
+
+    /// the line a jump to the range goes to, its last one. A line number past the end of the
+    /// buffer is its last line, like `:0` is the first.
+    pub fn jump_target(&self, cur: usize, linecnt: usize) -> Result<usize, InvalidRange> {
+        let end = match self.end {
+            Address::Line(n) => Address::Line(n.clamp(1, linecnt.max(1))),
+            end => end,
+        };
+        end.resolve(cur, linecnt)
+    }
+}
+
+/// parse one address and its offsets, returning the rest of the string
+fn parse_address(s: &str) -> Option<(Address, &str)> {
+    let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (mut addr, mut rest) = match s.as_bytes().first()? {
+        b'.' => (Address::Current(0), &s[1..]),
+        b'$' => (Address::Last(0), &s[1..]),
+        b'0'..=b'9' => (Address::Line(s[..digits].parse().ok()?), &s[digits..]),
+        // a bare offset is relative to the cursor line
+        b'+' | b'-' => (Address::Current(0), s),
+        _ => return None,
+    };
+    while let Some(sign) = rest.chars().next().filter(|c| matches!(c, '+' | '-')) {
+        let rest_num = &rest[1..];
+        let digits = rest_num.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest_num.len());
+        let n: isize = if digits == 0 { 1 } else { rest_num[..digits].parse().ok()? };
+        let n = if sign == '-' { -n } else { n };
+        addr = match addr {
+            Address::Line(l) => Address::Line(l.checked_add_signed(n)?),
+            Address::Current(off) => Address::Current(off + n),
+            Address::Last(off) => Address::Last(off + n),
+        };
+        rest = &rest_num[digits..];
+    }
+    Some((addr, rest))
+}
+
+/// parse a range at the start of a command, returning it and the rest of the command
+pub fn parse(s: &str) -> (Option<LineRange>, &str) {
+    let s = s.trim_start();
+    if let Some(rest) = s.strip_prefix('%') {
+        return (Some(LineRange::ALL), rest);
+    }
+    let Some((start, rest)) = parse_address(s) else {
+        return (None, s);
+    };
+    if let Some((end, rest)) = rest.strip_prefix(',').and_then(parse_address) {
+        return (Some(LineRange { start, end }), rest);
+    }
+    (Some(LineRange { start, end: start }), rest)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_ranges() {
+        assert_eq!(parse("s/a/b/"), (None, "s/a/b/"));
+        assert_eq!(parse("%s"), (Some(LineRange::ALL), "s"));
+        assert_eq!(
+            parse("2,$-1s"),
+            (Some(LineRange { start: Address::Line(2), end: Address::Last(-1) }), "s")
+        );
+        assert_eq!(
+            parse(".+2w"),
+            (Some(LineRange { start: Address::Current(2), end: Address::Current(2) }), "w")
+        );
+        assert_eq!(
+            parse("-,+s"),
+            (Some(LineRange { start: Address::Current(-1), end: Address::Current(1) }), "s")
+        );
+        assert_eq!(
+            parse("3+1"),
+            (Some(LineRange { start: Address::Line(4), end: Address::Line(4) }), "")
+        );
+    }
+
+    #[test]
+    fn display() {
+        for typed in ["2,$-1", ".+2", ".-1,.+1", "1,$", "$"] {
+            assert_eq!(parse(typed).0.unwrap().to_string(), typed);
+        }
+        assert_eq!(parse("%").0.unwrap().to_string(), "1,$");
+    }
+
+    #[test]
+    fn resolve_ranges() {
+        assert_eq!(LineRange::ALL.resolve(3, 10).unwrap(), 0..10);
+        assert_eq!(LineRange::CURRENT.resolve(3, 10).unwrap(), 3..4);
+        assert_eq!(parse("-1,.+1").0.unwrap().resolve(3, 10).unwrap(), 2..5);
+        assert_eq!(LineRange::ALL.resolve(0, 0).unwrap(), 0..1);
+        assert!(parse("5,2").0.unwrap().resolve(0, 10).is_err());
+        assert!(parse("0").0.unwrap().resolve(0, 10).is_err());
+        assert!(parse("11").0.unwrap().resolve(0, 10).is_err());
+        assert!(parse("-1").0.unwrap().resolve(0, 10).is_err());
+    }
+
+    #[test]
+    fn jumps() {
+        let target = |s: &str| parse(s).0.unwrap().jump_target(3, 10).ok();
+        assert_eq!(target("5"), Some(4));
+        assert_eq!(target("0"), Some(0));
+        assert_eq!(target("99"), Some(9));
+        assert_eq!(target("$"), Some(9));
+        assert_eq!(target("+5"), Some(8));
+        assert_eq!(target("-2"), Some(1));
+        assert_eq!(target("1,4"), Some(3));
+        assert_eq!(target("+7"), None);
+    }
+}
@@ -0,0 +1,86 @@
+//! Ex commands defined at runtime, from Guile with `rs-define-command` or with `:command`.
+//!
+//! Their names have to start with an uppercase letter, which all but a few built-in commands don't,
+//! and can't be the name of one of those few, so that they can never shadow a built-in command. A
+//! command from `:command` is an alias: running it runs its replacement as an ex command,
+//! with `<args>` replaced by the arguments it was given and `<range>` by its range.
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::guile::ProtectedScm;
+use crate::utils::unit_err;
+
+unit_err!(InvalidName: "user command names must start with an uppercase letter and not be a built-in command");
+
+pub enum Action {
+    /// procedure called with the arguments as a string
+    Guile(ProtectedScm),
+    /// ex command run in its place, see [`expand`]
+    Alias(String),
+}
+
+pub struct UserCommand {
+    pub action: Action,
+    /// procedure called with the argument being typed, returning a list of candidates
+    pub complete: Option<ProtectedScm>,
+}
+
+thread_local! {
+    static COMMANDS: RefCell<BTreeMap<String, Rc<UserCommand>>> = Default::default();
+}
+
+pub fn valid_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_uppercase())
+        && name.chars().all(|c| c.is_ascii_alphanumeric())
+        && !super::parser::COMMANDS.contains(&name)
+}
+
+/// define or redefine a command
+pub fn define(name: &str, cmd: UserCommand) -> Result<(), InvalidName> {
+    if !valid_name(name) {
+        return Err(InvalidName);
+    }
+    // the previous definition is dropped outside of the borrow, since that may run Guile code
+    let prev = COMMANDS.with_borrow_mut(|c| c.insert(name.to_owned(), Rc::new(cmd)));
+    drop(prev);
+    Ok(())
+}
+
+pub fn get(name: &str) -> Option<Rc<UserCommand>> {
+    COMMANDS.with_borrow(|c| c.get(name).cloned())
+}
+
+/// names of all user commands, sorted
+pub fn names() -> Vec<String> {
+    COMMANDS.with_borrow(|c| c.keys().cloned().collect())
+}
+
+/// the command an alias runs, given the range and arguments it was run with
+pub fn expand(replacement: &str, range: &str, args: &str) -> String {
+    replacement.replace("<range>", range).replace("<args>", args)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names_are_uppercase() {
+        assert!(valid_name("Format"));
+        assert!(valid_name("W2"));
+        assert!(!valid_name("format"));
+        assert!(!valid_name("Fo-o"));
+        assert!(!valid_name(""));
+        assert!(!valid_name("Man"));
+        assert!(!valid_name("GitBlame"));
+    }
+
+    #[test]
+    fn expand_alias() {
+        assert_eq!(expand("<range>s/<args>/x/g", "2,$", "a b"), "2,$s/a b/x/g");
+        assert_eq!(expand("e <args>", "", ""), "e ");
+        // arguments aren't expanded again
+        assert_eq!(expand("echo <args>", "", "<range>"), "echo <range>");
+    }
+}
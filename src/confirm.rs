@@ -0,0 +1,225 @@
+//! Questions on the command line that are answered with a single key.
+//!
+//! While a question is open every key goes to it instead of to the mode the editor was in, see
+//! [`Ctx::ask`]. The keys are vim's: `y` for yes, `n` for no, `a` for yes to this and everything
+//! after it, `l` for yes to this and then stop, and `q` or escape to stop. Questions that only
+//! want some of them wait for one they do.
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use regex::Regex;
+
+use crate::buffer::extmark::Extmark;
+use crate::buffer::Buffer;
+use crate::command::range::LineRange;
+use crate::prelude::*;
+use crate::search::{self, Preview};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Answer {
+    Yes,
+    No,
+    All,
+    Last,
+    Quit,
+}
+
+impl Answer {
+    /// the answer `c` gives, `None` if it isn't one
+    pub fn from_key(c: char) -> Option<Self> {
+        Some(match c {
+            'y' | 'Y' => Answer::Yes,
+            'n' | 'N' => Answer::No,
+            'a' => Answer::All,
+            'l' => Answer::Last,
+            'q' | '\x1b' => Answer::Quit,
+            _ => return None,
+        })
+    }
+}
+
+pub enum Confirm {
+    /// `:s///c`, a question for every match
+    Substitute(Substitution),
+    /// `:w {path}` to a file that already exists, which is only written after `y`
+    Overwrite { path: PathBuf, range: Option<LineRange> },
+    /// `:q` with changes that weren't written. `y` writes them first, `n` throws them away.
+    Quit,
+}
+
+impl Confirm {
+    /// the question for the focused buffer `buf`
+    pub fn question(&self, buf: &BufferInner) -> String {
+        match self {
+            Confirm::Substitute(sub) => format!("replace with {} (y/n/a/q/l)?", sub.replacement),
+            Confirm::Overwrite { path, .. } => format!("Overwrite existing file {path:?} (y/n)?"),
+            Confirm::Quit => format!("Save changes to {:?} (y/n/q)?", buf.name()),
+        }
+    }
+
+    /// whether the question takes `answer`, only `:s///c` has more than yes and no
+    pub fn accepts(&self, answer: Answer) -> bool {
+        matches!(self, Confirm::Substitute(_)) || matches!(answer, Answer::Yes | Answer::No | Answer::Quit)
+    }
+}
+
+/// a `:s///c` going through its matches one at a time. Matches are found in the text as it was
+/// before the first replacement, which is kept, and moved by how much the replacements before them
+/// changed the length to get to the buffer.
+pub struct Substitution {
+    re: Regex,
+    template: String,
+    /// the replacement as it was typed, for the question
+    replacement: String,
+    global: bool,
+    text: String,
+    /// end of the lines being substituted
+    end: usize,
+    /// the match being asked about
+    matched: Range<usize>,
+    /// how far the replacements so far moved the text after them
+    delta: isize,
+    /// how many matches were replaced
+    pub count: usize,
+}
+
+impl Substitution {
+    /// a substitution of `lines` in `buf`, at its first match. `None` if there isn't any.
+    pub fn new(buf: &BufferInner, lines: Range<usize>, re: Regex, template: String, replacement: &str, global: bool) -> Option<Self> {
+        let range = buf.line_offsets(lines);
+        let mut sub = Substitution {
+            re,
+            template,
+            replacement: replacement.into(),
+            global,
+            text: buf.to_string(),
+            end: range.end,
+            matched: range.start..range.start,
+            delta: 0,
+            count: 0,
+        };
+        sub.find(range.start).then_some(sub)
+    }
+
+    /// the match being asked about, as offsets in the buffer
+    pub fn current(&self) -> Range<usize> {
+        let shift = |off: usize| off.saturating_add_signed(self.delta);
+        shift(self.matched.start)..shift(self.matched.end)
+    }
+
+    /// move to the first match at or after `off`, returning whether there is one
+    fn find(&mut self, off: usize) -> bool {
+        let Some(m) = self.re.find_at(&self.text[..self.end], off.min(self.end)) else {
+            return false;
+        };
+        self.matched = m.range();
+        true
+    }
+
+    /// replace the current match
+    fn replace(&mut self, buf: &mut BufferInner) {
+        let caps = self.re.captures_at(&self.text[..self.end], self.matched.start).expect("the current match is still there");
+        let mut rep = String::new();
+        caps.expand(&self.template, &mut rep);
+        buf.replace_range(self.current(), &rep);
+        self.delta += rep.len() as isize - self.matched.len() as isize;
+        self.count += 1;
+    }
+
+    /// go on to the match after the current one
+    fn next(&mut self) -> bool {
+        let text = &self.text;
+        let mut off = self.matched.end;
+        // an empty match would be found again, as regex does it goes on after the next character
+        if self.matched.is_empty() {
+            off += text[off..].chars().next().map_or(1, char::len_utf8);
+        }
+        if !self.global {
+            let line_end = text[self.matched.start..].find('\n').map_or(text.len(), |i| self.matched.start + i + 1);
+            off = off.max(line_end);
+        }
+        off <= self.end && self.find(off)
+    }
+
+    /// take `answer` for the current match, returning whether there's another one to ask about
+    pub fn answer(&mut self, buf: &mut BufferInner, answer: Answer) -> bool {
+        match answer {
+            Answer::Quit => false,
+            Answer::No => self.next(),
+            Answer::Yes => {
+                self.replace(buf);
+                self.next()
+            }
+            Answer::Last => {
+                self.replace(buf);
+                false
+            }
+            Answer::All => {
+                loop {
+                    self.replace(buf);
+                    if !self.next() {
+                        break;
+                    }
+                }
+                false
+            }
+        }
+    }
+
+    /// `target` with the current match highlighted
+    pub fn preview(&self, target: Arc<Buffer>) -> Preview {
+        let orig = target.get();
+        let mut buf = BufferInner::from_string(orig.to_string());
+        buf.cursor = orig.cursor;
+        drop(orig);
+        buf.extmarks_mut().add(Extmark::highlight(search::NAMESPACE, self.current(), search::MATCH_COLOR));
+        Preview { target, buf }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// `text` after a substitution of all its lines that's given `answers`, and whether it wanted
+    /// more of them
+    fn run(text: &str, pattern: &str, replacement: &str, global: bool, answers: &[Answer]) -> (String, bool) {
+        let mut buf = BufferInner::from_str(text);
+        let re = Regex::new(pattern).unwrap();
+        let template = crate::search::replacement_template(replacement);
+        let lines = 0..buf.linecnt();
+        let Some(mut sub) = Substitution::new(&buf, lines, re, template, replacement, global) else {
+            return (buf.to_string(), false);
+        };
+        let mut more = true;
+        for &answer in answers {
+            assert!(more, "asked too few times");
+            more = sub.answer(&mut buf, answer);
+        }
+        (buf.to_string(), more)
+    }
+
+    #[test]
+    fn substitute() {
+        use Answer::*;
+        let text = "a a\na\na\n";
+        assert_eq!(run(text, "a", "bb", false, &[Yes, No, Yes]), ("bb a\na\nbb\n".into(), false));
+        assert_eq!(run(text, "a", "bb", true, &[Yes, Yes, No]), ("bb bb\na\na\n".into(), true));
+        assert_eq!(run(text, "a", "b", true, &[No, All]), ("a b\nb\nb\n".into(), false));
+        assert_eq!(run(text, "a", "b", true, &[Yes, Last]), ("b b\na\na\n".into(), false));
+        assert_eq!(run(text, "a", "b", true, &[Quit]), (text.into(), false));
+        assert_eq!(run("é", "x*", "-", true, &[All]), ("-é-".into(), false));
+        // the line ends where it did before the replacement
+        assert_eq!(run("a a\nb\n", "a", "x\ny", false, &[Yes]), ("x\ny a\nb\n".into(), false));
+    }
+
+    #[test]
+    fn answers() {
+        assert_eq!(Answer::from_key('\x1b'), Some(Answer::Quit));
+        assert_eq!(Answer::from_key('x'), None);
+        assert!(!Confirm::Quit.accepts(Answer::All));
+        let overwrite = Confirm::Overwrite { path: "/x".into(), range: None };
+        assert_eq!(overwrite.question(&BufferInner::new()), r#"Overwrite existing file "/x" (y/n)?"#);
+    }
+}
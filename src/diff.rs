@@ -0,0 +1,356 @@
+//! Line diffs between two buffers, shown side by side with `:diffsplit`.
+//!
+//! Lines are compared with Myers' algorithm. The result is a list of [`Hunk`]s, from which each
+//! side gets a list of [`Row`]s to display: lines that only exist on the other side are shown as
+//! filler so both windows stay aligned.
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::buffer::Buffer;
+use crate::prelude::*;
+
+/// a region that differs. Either range may be empty, but not both.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hunk {
+    pub a: Range<usize>,
+    pub b: Range<usize>,
+}
+
+impl Hunk {
+    fn side(&self, side: Side) -> &Range<usize> {
+        match side {
+            Side::A => &self.a,
+            Side::B => &self.b,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    A,
+    B,
+}
+
+impl Side {
+    pub fn other(self) -> Self {
+        match self {
+            Side::A => Side::B,
+            Side::B => Side::A,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RowKind {
+    Same,
+    /// the line only exists on this side
+    Added,
+    /// the line exists on both sides, but differs
+    Changed,
+    /// the line only exists on the other side
+    Filler,
+}
+
+impl RowKind {
+    /// highlight for the row, `None` if it isn't part of a hunk
+    pub fn color(self) -> Option<Color> {
+        let bg = match self {
+            RowKind::Same => return None,
+            RowKind::Added => BasicColor::Green,
+            RowKind::Changed => BasicColor::Blue,
+            RowKind::Filler => {
+                return Some(Color { fg: BasicColor::Red, ..Color::new() });
+            }
+        };
+        Some(Color { fg: BasicColor::Black, bg, ..Color::new() })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Row {
+    /// line of the buffer, `None` for filler and rows past the end
+    pub line: Option<usize>,
+    pub kind: RowKind,
+}
+
+/// the hunks that turn `a` into `b`, in order
+pub fn diff<T: PartialEq>(a: &[T], b: &[T]) -> Vec<Hunk> {
+    let pre = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let suf = a[pre..]
+        .iter()
+        .rev()
+        .zip(b[pre..].iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a_mid, b_mid) = (&a[pre..(a.len() - suf)], &b[pre..(b.len() - suf)]);
+
+    let mut out = Vec::new();
+    let (mut pa, mut pb) = (0, 0);
+    let matches = myers(a_mid, b_mid);
+    for (i, j) in matches.into_iter().chain([(a_mid.len(), b_mid.len())]) {
+        if i > pa || j > pb {
+            out.push(Hunk {
+                a: (pre + pa)..(pre + i),
+                b: (pre + pb)..(pre + j),
+            });
+        }
+        (pa, pb) = (i + 1, j + 1);
+    }
+    out
+}
+
+/// pairs of indices of equal elements in a shortest edit script, in order
+fn myers<T: PartialEq>(a: &[T], b: &[T]) -> Vec<(usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let off = max;
+    let mut v = vec![0isize; 2 * max as usize + 2];
+    // v for diagonals -d..=d before each round
+    let mut trace = Vec::new();
+    'outer: for d in 0..=max {
+        trace.push(v[((off - d) as usize)..=((off + d) as usize)].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let i = (off + k) as usize;
+            let mut x = if k == -d || (k != d && v[i - 1] < v[i + 1]) {
+                v[i + 1]
+            } else {
+                v[i - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[i] = x;
+            if x >= n && y >= m {
+                break 'outer;
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let at = |k: isize| v[(k + d) as usize];
+        let prev_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = if d == 0 { 0 } else { at(prev_k) };
+        let prev_y = if d == 0 { 0 } else { prev_x - prev_k };
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            out.push((x as usize, y as usize));
+        }
+        (x, y) = (prev_x, prev_y);
+    }
+    out.reverse();
+    out
+}
+
+/// what to show on each line of one side. `len` is the number of lines on that side.
+pub fn rows(hunks: &[Hunk], side: Side, len: usize) -> Vec<Row> {
+    let mut out = Vec::with_capacity(len);
+    let mut line = 0;
+    let same = |line| Row { line: Some(line), kind: RowKind::Same };
+    for hunk in hunks {
+        let (this, other) = (hunk.side(side), hunk.side(side.other()));
+        out.extend((line..this.start).map(same));
+        for i in 0..this.len().max(other.len()) {
+            out.push(match (i < this.len(), i < other.len()) {
+                (true, true) => Row { line: Some(this.start + i), kind: RowKind::Changed },
+                (true, false) => Row { line: Some(this.start + i), kind: RowKind::Added },
+                _ => Row { line: None, kind: RowKind::Filler },
+            });
+        }
+        line = this.end;
+    }
+    out.extend((line..len).map(same));
+    out
+}
+
+/// two buffers being compared
+pub struct DiffView {
+    bufs: [Arc<Buffer>; 2],
+    hunks: Vec<Hunk>,
+    /// versions of the buffers the hunks were computed from
+    versions: [usize; 2],
+    /// first row shown, shared by both windows so they scroll together
+    pub top: usize,
+}
+
+impl DiffView {
+    pub fn new(a: Arc<Buffer>, b: Arc<Buffer>) -> Self {
+        let mut out = Self {
+            bufs: [a, b],
+            hunks: Vec::new(),
+            versions: [usize::MAX; 2],
+            top: 0,
+        };
+        out.update();
+        out
+    }
+
+    /// recompute the hunks if either buffer changed
+    pub fn update(&mut self) {
+        let (a, b) = (self.bufs[0].get(), self.bufs[1].get());
+        let versions = [a.version(), b.version()];
+        if versions == self.versions {
+            return;
+        }
+        let lines_a = a.get_lines(0..a.linecnt());
+        let lines_b = b.get_lines(0..b.linecnt());
+        self.hunks = diff(&lines_a, &lines_b);
+        self.versions = versions;
+    }
+
+    pub fn buffer(&self, side: Side) -> &Arc<Buffer> {
+        match side {
+            Side::A => &self.bufs[0],
+            Side::B => &self.bufs[1],
+        }
+    }
+
+    pub fn side_of(&self, buf: &Arc<Buffer>) -> Option<Side> {
+        [Side::A, Side::B]
+            .into_iter()
+            .find(|&s| Arc::ptr_eq(self.buffer(s), buf))
+    }
+
+    pub fn hunks(&self) -> &[Hunk] {
+        &self.hunks
+    }
+
+    pub fn rows(&self, side: Side) -> Vec<Row> {
+        let len = self.buffer(side).get().linecnt();
+        rows(&self.hunks, side, len)
+    }
+
+    /// row that `line` of `side` is shown on
+    pub fn row_of(&self, side: Side, line: usize) -> usize {
+        self.rows(side)
+            .iter()
+            .position(|r| r.line == Some(line))
+            .unwrap_or(line)
+    }
+
+    /// scroll so that `line` of `side` is within `height` rows of the top
+    pub fn scroll_to(&mut self, side: Side, line: usize, height: usize) {
+        let row = self.row_of(side, line);
+        self.top = self.top.clamp(row.saturating_sub(height.saturating_sub(1)), row);
+    }
+
+    /// the hunk at `line`. A hunk that is empty on this side belongs to the line above it.
+    pub fn hunk_at(&self, side: Side, line: usize) -> Option<&Hunk> {
+        self.hunks.iter().find(|h| {
+            let rng = h.side(side);
+            if rng.is_empty() {
+                rng.start.saturating_sub(1) == line
+            } else {
+                rng.contains(&line)
+            }
+        })
+    }
+
+    /// first line of the next hunk after `line`
+    pub fn next_hunk(&self, side: Side, line: usize) -> Option<usize> {
+        self.hunks
+            .iter()
+            .map(|h| h.side(side).start)
+            .find(|&start| start > line)
+    }
+
+    /// first line of the closest hunk before `line`
+    pub fn prev_hunk(&self, side: Side, line: usize) -> Option<usize> {
+        self.hunks
+            .iter()
+            .rev()
+            .map(|h| h.side(side).start)
+            .find(|&start| start < line)
+    }
+
+    /// make the lines of `hunk` on the side opposite `from` the same as on `from`
+    pub fn copy_hunk(&self, hunk: &Hunk, from: Side) {
+        let src = self.buffer(from).get();
+        let mut text = String::new();
+        for line in src.get_lines(hunk.side(from).clone()) {
+            text.push_str(line);
+            text.push('\n');
+        }
+        drop(src);
+        let to = from.other();
+        self.buffer(to)
+            .get_mut()
+            .replace_lines(hunk.side(to).clone(), &text);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn hunks() {
+        let a = ["a", "b", "c", "d", "e"];
+        let b = ["a", "x", "c", "e", "f"];
+        assert_eq!(
+            diff(&a, &b),
+            vec![
+                Hunk { a: 1..2, b: 1..2 },
+                Hunk { a: 3..4, b: 3..3 },
+                Hunk { a: 5..5, b: 4..5 },
+            ]
+        );
+        assert_eq!(diff(&a, &a), vec![]);
+        assert_eq!(diff(&[], &a), vec![Hunk { a: 0..0, b: 0..5 }]);
+        assert_eq!(diff(&a, &[]), vec![Hunk { a: 0..5, b: 0..0 }]);
+    }
+
+    #[test]
+    fn minimal() {
+        let a: Vec<_> = "abcabba".chars().collect();
+        let b: Vec<_> = "cbabac".chars().collect();
+        let changed: usize = diff(&a, &b).iter().map(|h| h.a.len() + h.b.len()).sum();
+        assert_eq!(changed, 5);
+    }
+
+    #[test]
+    fn aligned_rows() {
+        let a = ["a", "b", "c", "d"];
+        let b = ["a", "B", "B2", "d", "e"];
+        let hunks = diff(&a, &b);
+        let ra = rows(&hunks, Side::A, a.len());
+        let rb = rows(&hunks, Side::B, b.len());
+        assert_eq!(ra.len(), rb.len());
+        let kinds = |rows: &[Row]| rows.iter().map(|r| r.kind).collect::<Vec<_>>();
+        use RowKind::*;
+        assert_eq!(kinds(&ra), [Same, Changed, Changed, Same, Filler]);
+        assert_eq!(kinds(&rb), [Same, Changed, Changed, Same, Added]);
+        assert_eq!(ra[3].line, Some(3));
+        assert_eq!(rb[3].line, Some(3));
+    }
+
+    #[test]
+    fn copy_between_buffers() {
+        let a = Buffer::new();
+        a.get_mut().insert_str("one\ntwo\nthree\n");
+        let b = Buffer::new();
+        b.get_mut().insert_str("one\n2\nthree\nfour\n");
+        let view = DiffView::new(Arc::clone(&a), Arc::clone(&b));
+        let hunk = view.hunk_at(Side::B, 1).unwrap().clone();
+        assert_eq!(view.hunk_at(Side::A, 1), Some(&hunk));
+        view.copy_hunk(&hunk, Side::B);
+        assert_eq!(a.get().to_string(), "one\n2\nthree\n");
+
+        let mut view = view;
+        view.update();
+        // "four" doesn't exist in a, so its hunk belongs to the line above it there
+        let hunk = view.hunk_at(Side::A, 2).unwrap().clone();
+        view.copy_hunk(&hunk, Side::A);
+        assert_eq!(b.get().to_string(), "one\n2\nthree\n");
+    }
+}
@@ -0,0 +1,111 @@
+//! Errors that commands show on the command line.
+//!
+//! Commands return `Box<dyn Error>`, so anything can be an error, but the common failures are
+//! spelled out here so that they read the same wherever they come from. They're shown with vim's
+//! error number in front, which makes them easy to look up.
+use std::fmt::Display;
+use std::path::PathBuf;
+
+/// what was being done with a file when it failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileOp {
+    /// opening it to edit or read
+    Open,
+    /// opening it to write, like for `:w`
+    Create,
+    /// writing to it once it was opened
+    Write,
+}
+
+#[derive(Debug)]
+pub enum EditorError {
+    /// opening, reading or writing `path` failed
+    Io { path: PathBuf, op: FileOp, error: std::io::Error },
+    /// a change while the `readonly` option is set
+    ReadOnly,
+    /// a command that needs the file name of a buffer that has none
+    NoFileName,
+    /// a change to a buffer that can't be changed, like a page from `:Man`
+    NotModifiable,
+    /// a search or substitute without a match
+    PatternNotFound(String),
+    /// an ex command that doesn't exist
+    UnknownCommand(String),
+    /// the user gave up on the command, like with `<C-c>`
+    Aborted,
+}
+
+impl EditorError {
+    /// vim's number for the error, `None` if vim doesn't have one
+    pub fn code(&self) -> Option<u32> {
+        match self {
+            EditorError::Io { error, .. } if error.kind() == std::io::ErrorKind::IsADirectory => Some(17),
+            EditorError::Io { op: FileOp::Create, error, .. } if error.kind() == std::io::ErrorKind::PermissionDenied => Some(212),
+            EditorError::Io { op: FileOp::Write, .. } => Some(514),
+            EditorError::Io { .. } => None,
+            EditorError::ReadOnly => Some(45),
+            EditorError::NotModifiable => Some(21),
+            EditorError::NoFileName => Some(32),
+            EditorError::PatternNotFound(_) => Some(486),
+            EditorError::UnknownCommand(_) => Some(492),
+            EditorError::Aborted => None,
+        }
+    }
+}
+
+impl Display for EditorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(code) = self.code() {
+            write!(f, "E{code}: ")?;
+        }
+        match self {
+            EditorError::Io { path, error, .. } if error.kind() == std::io::ErrorKind::IsADirectory => {
+                write!(f, "{path:?} is a directory")
+            }
+            EditorError::Io { path, op, error } => match (op, error.kind()) {
+                (FileOp::Open, std::io::ErrorKind::PermissionDenied) => write!(f, "{path:?} [Permission Denied]"),
+                (FileOp::Create, std::io::ErrorKind::PermissionDenied) => write!(f, "Can't open {path:?} for writing"),
+                (FileOp::Open | FileOp::Create, _) => write!(f, "Can't open {path:?}: {error}"),
+                (FileOp::Write, _) => write!(f, "Write error in {path:?}: {error}"),
+            },
+            EditorError::ReadOnly => f.write_str("'readonly' option is set"),
+            EditorError::NotModifiable => f.write_str("Cannot make changes, 'modifiable' is off"),
+            EditorError::NoFileName => f.write_str("No file name"),
+            EditorError::PatternNotFound(pattern) => write!(f, "Pattern not found: {pattern}"),
+            EditorError::UnknownCommand(name) => write!(f, "Not an editor command: {name}"),
+            EditorError::Aborted => f.write_str("Interrupted"),
+        }
+    }
+}
+
+impl std::error::Error for EditorError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EditorError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn messages() {
+        assert_eq!(EditorError::ReadOnly.to_string(), "E45: 'readonly' option is set");
+        assert_eq!(EditorError::NotModifiable.to_string(), "E21: Cannot make changes, 'modifiable' is off");
+        assert_eq!(EditorError::PatternNotFound("x+".into()).to_string(), "E486: Pattern not found: x+");
+        assert_eq!(EditorError::UnknownCommand("foo".into()).to_string(), "E492: Not an editor command: foo");
+        assert_eq!(EditorError::Aborted.to_string(), "Interrupted");
+        let error = std::io::Error::from(std::io::ErrorKind::StorageFull);
+        let io = EditorError::Io { path: "/x".into(), op: FileOp::Write, error };
+        assert!(io.to_string().starts_with(r#"E514: Write error in "/x": "#));
+        let error = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let io = EditorError::Io { path: "/etc/x".into(), op: FileOp::Create, error };
+        assert_eq!(io.to_string(), r#"E212: Can't open "/etc/x" for writing"#);
+        let error = std::io::Error::from(std::io::ErrorKind::PermissionDenied);
+        let io = EditorError::Io { path: "/etc/x".into(), op: FileOp::Open, error };
+        assert_eq!(io.to_string(), r#""/etc/x" [Permission Denied]"#);
+    }
+}
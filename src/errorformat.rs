@@ -0,0 +1,234 @@
+//! Parsing compiler output into quickfix items, in the style of vim's `errorformat`.
+//!
+//! Each format is a line pattern where these are special:
+//!
+//! | item   | matches                                             |
+//! |--------|-----------------------------------------------------|
+//! | `%f`   | file name                                           |
+//! | `%l`   | line number                                         |
+//! | `%c`   | column number                                       |
+//! | `%m`   | message                                             |
+//! | `%t`   | type character (`e`rror, `w`arning, `i`nfo, `n`ote)  |
+//! | `%.`   | any character                                       |
+//! | `%#`   | as few as possible of the previous item (`*?`)      |
+//! | `%%`   | a literal `%`                                       |
+//!
+//! A format may start with a prefix to describe multi-line messages: `%E`, `%W`, and `%I` start
+//! an error, warning, or info message, and `%C` continues it. A multi-line message ends when the
+//! next one starts. Lines matching no format are ignored, as are messages without a location.
+use std::path::PathBuf;
+
+use regex::Regex;
+
+use crate::qflist::QfItem;
+use crate::tui::TextSeverity;
+use crate::utils::unit_err;
+
+unit_err!(InvalidFormat: "invalid errorformat");
+
+/// handles rustc's multi-line diagnostics and the `file:line:col: message` style used by gcc,
+/// clang, and `cargo --message-format=short`
+pub const DEFAULT: &[&str] = &[
+    "%Eerror%.%#: %m",
+    "%Wwarning%.%#: %m",
+    "%C%.%#--> %f:%l:%c",
+    "%f:%l:%c: %t%.%#: %m",
+    "%f:%l:%c: %m",
+    "%f:%l: %m",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Single,
+    Start(TextSeverity),
+    Continue,
+}
+
+struct Format {
+    kind: Kind,
+    re: Regex,
+}
+
+/// a message being built from multiple lines
+#[derive(Default)]
+struct Partial {
+    path: Option<PathBuf>,
+    line: Option<usize>,
+    col: Option<usize>,
+    text: String,
+    severity: TextSeverity,
+}
+
+impl Partial {
+    fn finish(self) -> Option<QfItem> {
+        Some(QfItem {
+            path: self.path?,
+            line: self.line?.saturating_sub(1),
+            col: self.col.unwrap_or(1).saturating_sub(1),
+            text: self.text,
+            severity: self.severity,
+        })
+    }
+
+    /// fill in whatever is still missing from a matched line
+    fn fill(&mut self, caps: &regex::Captures) {
+        if self.path.is_none() {
+            self.path = caps.name("f").map(|m| m.as_str().into());
+        }
+        if self.line.is_none() {
+            self.line = caps.name("l").and_then(|m| m.as_str().parse().ok());
+        }
+        if self.col.is_none() {
+            self.col = caps.name("c").and_then(|m| m.as_str().parse().ok());
+        }
+        if self.text.is_empty() {
+            if let Some(m) = caps.name("m") {
+                self.text = m.as_str().to_owned();
+            }
+        }
+        if let Some(t) = caps.name("t") {
+            self.severity = severity_of(t.as_str());
+        }
+    }
+}
+
+fn severity_of(t: &str) -> TextSeverity {
+    match t.chars().next().map(|c| c.to_ascii_lowercase()) {
+        Some('w') => TextSeverity::Warning,
+        Some('i' | 'n') => TextSeverity::Normal,
+        _ => TextSeverity::Error,
+    }
+}
+
+fn compile(fmt: &str) -> Result<Format, InvalidFormat> {
+    let (kind, body) = match fmt.get(..2) {
+        Some("%E") => (Kind::Start(TextSeverity::Error), &fmt[2..]),
+        Some("%W") => (Kind::Start(TextSeverity::Warning), &fmt[2..]),
+        Some("%I") => (Kind::Start(TextSeverity::Normal), &fmt[2..]),
+        Some("%C") => (Kind::Continue, &fmt[2..]),
+        _ => (Kind::Single, fmt),
+    };
+    let mut re = String::from("^");
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            re.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+            continue;
+        }
+        let item = match chars.next().ok_or(InvalidFormat)? {
+            'f' => r"(?P<f>.+?)",
+            'l' => r"(?P<l>\d+)",
+            'c' => r"(?P<c>\d+)",
+            'm' => r"(?P<m>.*)",
+            't' => r"(?P<t>.)",
+            '.' => ".",
+            '#' => "*?",
+            '%' => "%",
+            _ => return Err(InvalidFormat),
+        };
+        re.push_str(item);
+    }
+    re.push('$');
+    let re = Regex::new(&re).map_err(|_| InvalidFormat)?;
+    Ok(Format { kind, re })
+}
+
+/// parse `lines` of output with `formats`, tried in order
+pub fn parse<'a>(
+    formats: &[impl AsRef<str>],
+    lines: impl IntoIterator<Item = &'a str>,
+) -> Result<Vec<QfItem>, InvalidFormat> {
+    let formats = formats
+        .iter()
+        .map(|f| compile(f.as_ref()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut out = Vec::new();
+    let mut partial: Option<Partial> = None;
+    for line in lines {
+        let Some((fmt, caps)) = formats
+            .iter()
+            .find_map(|f| f.re.captures(line).map(|c| (f, c)))
+        else {
+            continue;
+        };
+        match fmt.kind {
+            Kind::Single => {
+                out.extend(partial.take().and_then(Partial::finish));
+                let mut p = Partial::default();
+                p.fill(&caps);
+                out.extend(p.finish());
+            }
+            Kind::Start(severity) => {
+                out.extend(partial.take().and_then(Partial::finish));
+                let mut p = Partial {
+                    severity,
+                    ..Default::default()
+                };
+                p.fill(&caps);
+                partial = Some(p);
+            }
+            Kind::Continue => {
+                if let Some(p) = &mut partial {
+                    p.fill(&caps);
+                }
+            }
+        }
+    }
+    out.extend(partial.take().and_then(Partial::finish));
+    Ok(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rustc_output() {
+        let output = "\
+   Compiling edit v0.1.0 (/src/rvim)
+error[E0308]: mismatched types
+  --> src/main.rs:4:18
+   |
+4  |     let x: u8 = \"a\";
+   |                 ^^^ expected `u8`, found `&str`
+   |
+warning: unused variable: `y`
+ --> src/lib.rs:10:9
+  |
+  = note: `#[warn(unused_variables)]` on by default
+warning: `edit` (bin \"edit\") generated 1 warning
+error: could not compile `edit` (bin \"edit\") due to 1 previous error";
+        let items = parse(DEFAULT, output.lines()).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].path, PathBuf::from("src/main.rs"));
+        assert_eq!((items[0].line, items[0].col), (3, 17));
+        assert_eq!(items[0].text, "mismatched types");
+        assert_eq!(items[0].severity, TextSeverity::Error);
+        assert_eq!(items[1].path, PathBuf::from("src/lib.rs"));
+        assert_eq!(items[1].text, "unused variable: `y`");
+        assert_eq!(items[1].severity, TextSeverity::Warning);
+    }
+
+    #[test]
+    fn gcc_style() {
+        let output = "\
+main.c:3:5: warning: implicit declaration of function 'foo'
+main.c:7: error: expected ';'
+make: *** [all] Error 1";
+        let items = parse(DEFAULT, output.lines()).unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].severity, TextSeverity::Warning);
+        assert_eq!(items[0].text, "implicit declaration of function 'foo'");
+        assert_eq!((items[1].line, items[1].col), (6, 0));
+        assert_eq!(items[1].text, "error: expected ';'");
+    }
+
+    #[test]
+    fn custom_format() {
+        let items = parse(&["%f(%l) %%%m"], ["a b.txt(12) %oops", "nope"]).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].path, PathBuf::from("a b.txt"));
+        assert_eq!(items[0].text, "oops");
+        assert!(parse(&["%q"], ["x"]).is_err());
+    }
+}
@@ -0,0 +1,91 @@
+//! The editor's event bus.
+//!
+//! Anything that has to reach the editor from outside of [`crate::render::Ctx`], whether from
+//! Guile, another thread or code that can't see the context, [`post`]s an [`Event`]. The main loop
+//! handles them in [`crate::render::Ctx::process_events`] every iteration, in the order they were
+//! posted.
+use std::sync::mpsc;
+use std::sync::{Mutex, OnceLock};
+
+use crate::guile::{Gmsg, ProtectedScm};
+use crate::render::BufId;
+use crate::window::org::Layout;
+
+/// text for the command line output
+pub enum Message {
+    Str(String),
+    Gmsg(Gmsg),
+}
+
+impl std::ops::Deref for Message {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        match self {
+            Message::Str(s) => s,
+            Message::Gmsg(s) => s,
+        }
+    }
+}
+
+pub enum Event {
+    Message(Message),
+    /// keys run in normal mode, as if they were typed
+    Keys(Vec<u8>),
+    /// insert `text` at the byte offset `off` of a buffer, if it's still open
+    Insert { buf: BufId, off: usize, text: String },
+    /// leave the editor
+    Quit,
+    /// Ctrl-c with no command waiting for keys, see [`crate::render::Ctx::interrupt`]
+    Interrupt,
+    /// call a Guile procedure without arguments
+    Hook(ProtectedScm),
+    /// run an ex command, as if it was typed after `:`
+    Command(String),
+    /// arrange the windows like this, see [`crate::render::Ctx::set_layout`]
+    Layout(Layout),
+}
+
+struct Bus {
+    tx: mpsc::Sender<Event>,
+    rx: Mutex<mpsc::Receiver<Event>>,
+}
+
+fn bus() -> &'static Bus {
+    static BUS: OnceLock<Bus> = OnceLock::new();
+    BUS.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        Bus { tx, rx: Mutex::new(rx) }
+    })
+}
+
+/// queue `event` for the main loop, from any thread
+pub fn post(event: Event) {
+    // the receiver lives as long as the bus, so this can't fail
+    let _ = bus().tx.send(event);
+}
+
+/// every event posted since the last call
+pub fn drain() -> Vec<Event> {
+    bus().rx.lock().unwrap().try_iter().collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn in_order() {
+        post(Event::Keys(b"ix".to_vec()));
+        std::thread::spawn(|| post(Event::Message(Message::Str("hello".into())))).join().unwrap();
+        // other tests may post too
+        let events = drain();
+        let mut events = events.iter().filter(|e| match e {
+            Event::Keys(k) => k == b"ix",
+            Event::Message(m) => &**m == "hello",
+            _ => false,
+        });
+        assert!(matches!(events.next(), Some(Event::Keys(_))));
+        assert!(matches!(events.next(), Some(Event::Message(_))));
+    }
+}
@@ -0,0 +1,116 @@
+//! Exporting a buffer with its colors for `:TOhtml`.
+//!
+//! The colors are those of [`crate::syntax`] with the highlights of the buffer's extmarks over them,
+//! where a later mark is drawn over an earlier one like it is on screen. The buffer becomes an HTML page or text with the escape
+//! sequences of a terminal, which can be shown with `cat` or `less -R`.
+use std::fmt::Write;
+
+use crate::prelude::*;
+use crate::tui::escape_html;
+
+/// the text of `buf` as runs of the same color, split at the ends of lines
+fn runs(buf: &BufferInner) -> Vec<(String, Color)> {
+    let text = buf.to_string();
+    let mut colors = vec![Color::new(); text.len()];
+    let filetype = buf.path().and_then(crate::syntax::filetype).filter(|_| !buf.has_long_lines());
+    if let Some(filetype) = filetype {
+        let mut start = 0;
+        for line in text.split_inclusive('\n') {
+            for (range, color) in crate::syntax::highlight(&filetype, line.trim_end_matches('\n')) {
+                colors[start + range.start..start + range.end].fill(color);
+            }
+            start += line.len();
+        }
+    }
+    for (_, mark) in buf.extmarks().iter() {
+        if let Some(color) = mark.highlight {
+            let end = mark.range.end.min(text.len());
+            colors[mark.range.start.min(end)..end].fill(color);
+        }
+    }
+    let mut out: Vec<(String, Color)> = Vec::new();
+    for (off, c) in text.char_indices() {
+        let color = if c == '\n' { Color::new() } else { colors[off] };
+        match out.last_mut() {
+            Some((run, last)) if *last == color && !run.ends_with('\n') && c != '\n' => run.push(c),
+            _ => out.push((c.to_string(), color)),
+        }
+    }
+    out
+}
+
+/// `buf` as an HTML page titled `title`
+pub fn html(buf: &BufferInner, title: &str) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{}</title></head>\n\
+         <body style=\"background:#000000;color:#e5e5e5\">\n<pre>\n",
+        escape_html(title)
+    );
+    for (text, color) in runs(buf) {
+        let text = escape_html(&text);
+        match color.style() {
+            style if style.is_empty() => out.push_str(&text),
+            style => {
+                let _ = write!(out, "<span style=\"{style}\">{text}</span>");
+            }
+        }
+    }
+    out.push_str("</pre>\n</body>\n</html>\n");
+    out
+}
+
+/// `buf` as text with escape sequences for its colors, which are reset at the end of every line
+pub fn ansi(buf: &BufferInner) -> String {
+    let mut out = String::new();
+    let mut prev = Color::new();
+    for (text, color) in runs(buf) {
+        out.push_str(&color.sgr(&prev));
+        out.push_str(&text);
+        prev = color;
+    }
+    out.push_str(&Color::new().sgr(&prev));
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::extmark::Extmark;
+
+    fn buffer() -> BufferInner {
+        let mut buf = BufferInner::from_str("a <b>\nc\n");
+        let red = Color { fg: BasicColor::Red, bold: true, ..Color::new() };
+        buf.extmarks_mut().add(Extmark::highlight("test", 2..7, red));
+        buf
+    }
+
+    #[test]
+    fn to_html() {
+        let html = html(&buffer(), "x & y");
+        assert!(html.contains("<title>x &amp; y</title>"));
+        assert!(html.contains(
+            "<pre>\na <span style=\"color:#cd0000;font-weight:bold;\">&lt;b&gt;</span>\n\
+             <span style=\"color:#cd0000;font-weight:bold;\">c</span>\n</pre>"
+        ));
+    }
+
+    #[test]
+    fn syntax_under_extmarks() {
+        crate::syntax::define("exporttest", r"\bfn\b", "Keyword").unwrap();
+        let keyword = crate::syntax::group_color("Keyword").unwrap();
+        let red = Color { fg: BasicColor::Red, ..Color::new() };
+        let mut buf = BufferInner::from_str("fn a\nfn b\n");
+        buf.set_path("x.exporttest".into());
+        buf.extmarks_mut().add(Extmark::highlight("test", 5..7, red));
+        let runs = runs(&buf);
+        assert_eq!(runs[0], ("fn".into(), keyword));
+        assert_eq!(runs[1], (" a".into(), Color::new()));
+        assert_eq!(runs[3], ("fn".into(), red));
+    }
+
+    #[test]
+    fn to_ansi() {
+        assert_eq!(ansi(&buffer()), "a \x1b[1;31m<b>\x1b[22;39m\n\x1b[1;31mc\x1b[22;39m\n");
+        assert_eq!(ansi(&BufferInner::from_str("plain\n")), "plain\n");
+    }
+}
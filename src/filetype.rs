@@ -0,0 +1,39 @@
+//! Filetype detection. For now this is purely extension based.
+use std::path::Path;
+
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cc", "cpp"),
+    ("cpp", "cpp"),
+    ("hpp", "cpp"),
+    ("py", "python"),
+    ("scm", "scheme"),
+    ("sh", "sh"),
+    ("toml", "toml"),
+    ("md", "markdown"),
+    ("txt", "text"),
+];
+
+/// guess the filetype of a path, `None` if unknown
+pub fn detect(path: &Path) -> Option<&'static str> {
+    let ext = path.extension()?.to_str()?;
+    EXTENSIONS
+        .iter()
+        .find(|(e, _)| e.eq_ignore_ascii_case(ext))
+        .map(|(_, ft)| *ft)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detect_by_extension() {
+        assert_eq!(detect(Path::new("src/main.rs")), Some("rust"));
+        assert_eq!(detect(Path::new("a/b/README.MD")), Some("markdown"));
+        assert_eq!(detect(Path::new("Makefile")), None);
+        assert_eq!(detect(Path::new("archive.tar.gz")), None);
+    }
+}
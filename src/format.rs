@@ -0,0 +1,311 @@
+//! Reflowing text to `textwidth`, for `gq`.
+//!
+//! Lines are joined into paragraphs and wrapped again. Indentation and comment markers at the start
+//! of a line are kept on every line they wrap to, and list items keep their continuation lines
+//! lined up after the marker. Blank lines, lines with a different leader and new list items start
+//! new paragraphs.
+//!
+//! [`break_at`] does the same for a single line while it's typed, for the `t` and `c` flags of
+//! `formatoptions`, and [`align`] lines up the columns of lines split by a delimiter, for `:Align`.
+//! [`retab`] converts whitespace between tabs and spaces.
+use std::ops::Range;
+
+use unicode_width::UnicodeWidthStr;
+
+/// comment markers kept at the start of wrapped lines, longest first where they share a start
+const COMMENTS: &[&str] = &["///", "//!", "//", "#", "--", ";", ">"];
+
+/// the part of `line` that's repeated on the lines it wraps to: indentation, a comment marker and
+/// the space after it
+pub fn leader(line: &str) -> &str {
+    let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+    let rest = &line[indent..];
+    let Some(marker) = COMMENTS.iter().find(|c| rest.starts_with(**c)) else {
+        return &line[..indent];
+    };
+    let after = &rest[marker.len()..];
+    let space = after.len() - after.trim_start_matches([' ', '\t']).len();
+    &line[..indent + marker.len() + space]
+}
+
+/// length of the list item marker that `text` starts with, like `- `, `* ` or `12. `
+fn list_marker(text: &str) -> Option<usize> {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let marker = match text[digits..].chars().next()? {
+        '.' | ')' if digits > 0 => digits + 1,
+        '-' | '*' | '+' if digits == 0 => 1,
+        _ => return None,
+    };
+    let space = text[marker..].len() - text[marker..].trim_start().len();
+    (space > 0).then_some(marker + space)
+}
+
+/// the leader of `line` with a list marker replaced by spaces, what lines it wraps to start with
+fn continuation(line: &str) -> String {
+    let lead = leader(line);
+    let marker = list_marker(&line[lead.len()..]).unwrap_or(0);
+    format!("{lead}{}", " ".repeat(marker))
+}
+
+/// whether `line` starts with a comment marker
+pub fn is_comment(line: &str) -> bool {
+    !leader(line).trim().is_empty()
+}
+
+/// where to break `line`, the text before the cursor, once it's wider than `width`: the whitespace
+/// before the last word that starts within the width, or the first one if no word does. Also
+/// returns what the new line starts with.
+pub fn break_at(line: &str, width: usize) -> Option<(Range<usize>, String)> {
+    if line.width() <= width {
+        return None;
+    }
+    let cont = continuation(line);
+    let lead = leader(line).len();
+    let lead = lead + list_marker(&line[lead..]).unwrap_or(0);
+    let mut breaks = Vec::new();
+    let mut start = None;
+    for (i, c) in line.char_indices().skip_while(|(i, _)| *i < lead) {
+        match (c.is_whitespace(), start) {
+            (true, None) => start = Some(i),
+            (false, Some(s)) => {
+                breaks.push(s..i);
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    let fits = breaks.iter().rev().find(|b| line[..b.start].width() <= width);
+    Some((fits.or(breaks.first())?.clone(), cont))
+}
+
+struct Paragraph {
+    /// leader of the first line, including a list marker
+    first: String,
+    /// leader of the lines after it
+    rest: String,
+    words: Vec<String>,
+}
+
+impl Paragraph {
+    fn wrap(&self, width: usize, out: &mut String) {
+        let mut line = self.first.clone();
+        let mut empty = true;
+        for word in &self.words {
+            if !empty && line.width() + 1 + word.width() > width {
+                out.push_str(line.trim_end());
+                out.push('\n');
+                line = self.rest.clone();
+                empty = true;
+            }
+            if !empty {
+                line.push(' ');
+            }
+            line.push_str(word);
+            empty = false;
+        }
+        out.push_str(line.trim_end());
+        out.push('\n');
+    }
+}
+
+/// `text`, made of whole lines, reflowed to lines of at most `width` columns where the words allow
+pub fn reflow(text: &str, width: usize) -> String {
+    let mut out = String::new();
+    let mut para: Option<Paragraph> = None;
+    for line in text.lines() {
+        let lead = leader(line);
+        let body = line[lead.len()..].trim_end();
+        let marker = list_marker(body);
+        let continues = para.as_ref().is_some_and(|p| p.rest == lead && marker.is_none());
+        if body.is_empty() || !continues {
+            if let Some(p) = para.take() {
+                p.wrap(width, &mut out);
+            }
+        }
+        if body.is_empty() {
+            out.push_str(line.trim_end());
+            out.push('\n');
+            continue;
+        }
+        let para = para.get_or_insert_with(|| {
+            let marker = marker.unwrap_or(0);
+            Paragraph { first: format!("{lead}{}", &body[..marker]), rest: continuation(line), words: Vec::new() }
+        });
+        let body = if continues { body } else { &body[marker.unwrap_or(0)..] };
+        para.words.extend(body.split_whitespace().map(String::from));
+    }
+    if let Some(p) = para {
+        p.wrap(width, &mut out);
+    }
+    out
+}
+
+/// `lines` with every `delim` in them lined up, padding the text before each one with spaces. Text
+/// between delimiters is trimmed and has a space on both sides. Lines without `delim` are left as
+/// they are.
+pub fn align(lines: &[&str], delim: &str) -> Vec<String> {
+    let split: Vec<Option<Vec<&str>>> = lines
+        .iter()
+        .map(|line| {
+            let mut fields: Vec<&str> = line.split(delim).collect();
+            if fields.len() < 2 {
+                return None;
+            }
+            // the first field keeps its indentation
+            fields[0] = fields[0].trim_end();
+            for field in &mut fields[1..] {
+                *field = field.trim();
+            }
+            Some(fields)
+        })
+        .collect();
+    // the width of every field that's followed by a delimiter
+    let mut widths: Vec<usize> = Vec::new();
+    for fields in split.iter().flatten() {
+        for (i, field) in fields[..fields.len() - 1].iter().enumerate() {
+            match widths.get_mut(i) {
+                Some(w) => *w = (*w).max(field.width()),
+                None => widths.push(field.width()),
+            }
+        }
+    }
+    lines
+        .iter()
+        .zip(split)
+        .map(|(line, fields)| {
+            let Some(fields) = fields else {
+                return line.to_string();
+            };
+            let mut out = String::new();
+            for (field, width) in fields[..fields.len() - 1].iter().zip(&widths) {
+                if !out.is_empty() {
+                    out.push(' ');
+                }
+                out.push_str(field);
+                out.push_str(&" ".repeat(width - field.width()));
+                out.push(' ');
+                out.push_str(delim);
+            }
+            let last = fields[fields.len() - 1];
+            if !last.is_empty() {
+                out.push(' ');
+                out.push_str(last);
+            }
+            out
+        })
+        .collect()
+}
+
+/// columns that `text` at the start of a line takes up, with tabs going to the next multiple of
+/// `tabstop`
+pub fn columns(text: &str, tabstop: usize) -> usize {
+    advance(0, text, tabstop)
+}
+
+/// the column after `text` that starts at column `col`
+fn advance(col: usize, text: &str, tabstop: usize) -> usize {
+    let tabstop = tabstop.max(1);
+    text.chars().fold(col, |col, c| match c {
+        '\t' => (col / tabstop + 1) * tabstop,
+        c => col + c.to_string().width(),
+    })
+}
+
+/// `line` with each run of whitespace that has a tab in it, where tabs are `tabstop` columns wide,
+/// redone with tabs of `new_tabstop` columns or with only spaces for `expandtab`, so that the text
+/// after it stays in the same column. With `all`, runs of more than one space are redone too.
+pub fn retab(line: &str, tabstop: usize, new_tabstop: usize, expandtab: bool, all: bool) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut col = 0;
+    let mut rest = line;
+    while !rest.is_empty() {
+        let text = rest.trim_start_matches([' ', '\t']);
+        let run = &rest[..rest.len() - text.len()];
+        let end = advance(col, run, tabstop);
+        if run.contains('\t') || (all && run.len() > 1) {
+            out.push_str(&whitespace(col..end, new_tabstop, expandtab));
+        } else {
+            out.push_str(run);
+        }
+        let word = &text[..text.find([' ', '\t']).unwrap_or(text.len())];
+        out.push_str(word);
+        col = advance(end, word, tabstop);
+        rest = &text[word.len()..];
+    }
+    out
+}
+
+/// whitespace that fills `cols`, with tabs of `tabstop` columns unless `expandtab` is set
+fn whitespace(cols: Range<usize>, tabstop: usize, expandtab: bool) -> String {
+    if expandtab || tabstop == 0 {
+        return " ".repeat(cols.len());
+    }
+    let mut out = String::new();
+    let mut col = cols.start;
+    while (col / tabstop + 1) * tabstop <= cols.end {
+        out.push('\t');
+        col = (col / tabstop + 1) * tabstop;
+    }
+    out.push_str(&" ".repeat(cols.end - col));
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn prose() {
+        let text = "one two three\nfour five six seven eight\n\nnine\n";
+        assert_eq!(reflow(text, 14), "one two three\nfour five six\nseven eight\n\nnine\n");
+        assert_eq!(reflow("a\nb\n", 80), "a b\n");
+    }
+
+    #[test]
+    fn comments() {
+        assert_eq!(leader("    /// doc"), "    /// ");
+        let text = "    // the quick brown fox\n    // jumps\n";
+        assert_eq!(reflow(text, 20), "    // the quick\n    // brown fox\n    // jumps\n");
+    }
+
+    #[test]
+    fn lists() {
+        let text = "- one two three four\n- five\n  six\n1. seven eight\n";
+        assert_eq!(reflow(text, 12), "- one two\n  three four\n- five six\n1. seven\n   eight\n");
+        let text = "// * a b c\n";
+        assert_eq!(reflow(text, 8), "// * a b\n//   c\n");
+    }
+
+    #[test]
+    fn typing() {
+        assert_eq!(break_at("one two", 7), None);
+        assert_eq!(break_at("one two three", 10), Some((7..8, String::new())));
+        assert_eq!(break_at("  // one  two", 10), Some((8..10, "  // ".into())));
+        assert_eq!(break_at("- abcdefghij", 5), None);
+        assert_eq!(break_at("1. ab cdefgh", 5), Some((5..6, "   ".into())));
+        assert!(is_comment("  # x") && !is_comment("  x"));
+    }
+
+    #[test]
+    fn aligning() {
+        let lines = ["    a = 1,", "    bcd= 22,", "    // comment", "    ef =3,"];
+        assert_eq!(
+            align(&lines, "="),
+            ["    a   = 1,", "    bcd = 22,", "    // comment", "    ef  = 3,"]
+        );
+        let table = ["a|b|c", "aaa | bb |", "x"];
+        assert_eq!(align(&table, "|"), ["a   | b  | c", "aaa | bb |", "x"]);
+    }
+
+    #[test]
+    fn retabbing() {
+        assert_eq!(retab("\t  a\tb", 8, 8, true, false), "          a     b");
+        assert_eq!(retab("  \ta", 4, 4, true, false), "    a");
+        assert_eq!(retab("          a", 8, 4, false, true), "\t\t  a");
+        assert_eq!(retab("    a", 8, 8, false, false), "    a");
+        assert_eq!(retab("\t\ta", 4, 8, false, false), "\ta");
+        // after the indentation too, from the column the run starts in
+        assert_eq!(retab("a\tb", 4, 8, false, false), "a   b");
+        assert_eq!(retab("ab      c d", 4, 4, false, true), "ab\t\tc d");
+    }
+}
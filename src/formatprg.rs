@@ -0,0 +1,135 @@
+//! Running a formatter on a buffer before it's written, see the `formatonsave` option.
+//!
+//! The formatter for a filetype comes from `formatprg`, whose items are `filetype:command`. The
+//! command is run with `sh -c`, where `%` is replaced by the file, gets the text of the buffer on
+//! its stdin and prints the formatted text. The buffer only changes if the formatter exits
+//! successfully within `formattimeout` milliseconds. Otherwise the text is written as it is and the
+//! write warns about it.
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::options::Options;
+use crate::prelude::*;
+
+/// the command `formatprg` has for `filetype`, the last one if there's more than one
+pub fn program<'a>(formatprg: &'a [String], filetype: &str) -> Option<&'a str> {
+    formatprg.iter().rev().find_map(|f| f.strip_prefix(filetype)?.strip_prefix(':'))
+}
+
+/// the output of the shell command `cmd` given `input`, if it exits successfully within `timeout`
+pub fn run(cmd: &str, input: &str, timeout: Duration) -> Result<String, Box<dyn std::error::Error>> {
+    use std::os::unix::process::CommandExt;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .process_group(0)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    // each on its own thread, so that a formatter that writes before it has read everything can't
+    // fill a pipe and wait on us
+    let mut stdin = child.stdin.take().expect("stdin is piped");
+    let empty = input.is_empty();
+    let input = input.to_owned();
+    std::thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let read = |mut pipe: Box<dyn Read + Send>| {
+        std::thread::spawn(move || {
+            let mut out = String::new();
+            let _ = pipe.read_to_string(&mut out);
+            out
+        })
+    };
+    let stdout = read(Box::new(child.stdout.take().expect("stdout is piped")));
+    let stderr = read(Box::new(child.stderr.take().expect("stderr is piped")));
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if start.elapsed() > timeout {
+            // the whole group, in case the shell started it in the background
+            let _ = nix::sys::signal::killpg(nix::unistd::Pid::from_raw(child.id() as i32), nix::sys::signal::SIGKILL);
+            let _ = child.wait();
+            return Err(format!("{cmd:?} timed out").into());
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    };
+    if !status.success() {
+        let stderr = stderr.join().unwrap_or_default();
+        return Err(match stderr.lines().find(|l| !l.trim().is_empty()) {
+            Some(line) => format!("{cmd:?} failed: {}", line.trim()),
+            None => format!("{cmd:?} failed: {status}"),
+        }
+        .into());
+    }
+    let out = stdout.join().map_err(|_| "formatter output can't be read")?;
+    // more likely a formatter that wants a file than one that deletes everything
+    if out.is_empty() && !empty {
+        return Err(format!("{cmd:?} printed nothing").into());
+    }
+    Ok(out)
+}
+
+/// `prg` with `%` replaced by the file, quoted for the shell
+fn command(prg: &str, path: &Path) -> String {
+    prg.replace('%', &crate::job::quote(path))
+}
+
+/// format `buf`, which is about to be written to `path`, if `formatonsave` has its filetype. When
+/// formatting fails the buffer stays as it is and the error is returned.
+pub fn on_save(options: &Options, buf: &mut BufferInner, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(filetype) = buf.filetype() else {
+        return Ok(());
+    };
+    if !options.formatonsave.iter().any(|f| f == filetype) {
+        return Ok(());
+    }
+    let prg = program(&options.formatprg, filetype).ok_or_else(|| format!("no formatprg for {filetype}"))?;
+    let text = buf.to_string();
+    let out = run(&command(prg, path), &text, Duration::from_millis(options.formattimeout as u64))?;
+    if out != text {
+        buf.replace_lines(0..buf.linecnt(), &out);
+        // on a character, as it's written from normal mode
+        let DocPos { x, y } = buf.cursor.pos;
+        let len = buf.line(y).len();
+        buf.cursor.set_pos(DocPos { x: x.min(len.saturating_sub(1)), y });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn programs() {
+        let formatprg = ["rust:rustfmt".to_string(), "c:indent".into(), "rust:rustfmt --edition 2021".into()];
+        assert_eq!(program(&formatprg, "rust"), Some("rustfmt --edition 2021"));
+        assert_eq!(program(&formatprg, "c"), Some("indent"));
+        assert_eq!(program(&formatprg, "cpp"), None);
+    }
+
+    #[test]
+    fn quoted_file() {
+        let path = Path::new("$(touch x).rs");
+        assert_eq!(command("fmt --stdin-filepath %", path), "fmt --stdin-filepath '$(touch x).rs'");
+        assert_eq!(run(&command("echo %", path), "", SECOND).unwrap(), "$(touch x).rs\n");
+    }
+
+    #[test]
+    fn running() {
+        assert_eq!(run("tr a-z A-Z", "abc\n", SECOND).unwrap(), "ABC\n");
+        assert_eq!(run("cat", "", SECOND).unwrap(), "");
+        let e = run("echo oops >&2; exit 1", "x", SECOND).unwrap_err();
+        assert_eq!(e.to_string(), r#""echo oops >&2; exit 1" failed: oops"#);
+        assert!(run("true", "x", SECOND).unwrap_err().to_string().contains("printed nothing"));
+        let start = Instant::now();
+        assert!(run("sleep 5", "x", Duration::from_millis(50)).unwrap_err().to_string().contains("timed out"));
+        assert!(start.elapsed() < SECOND);
+    }
+}
@@ -10,9 +10,10 @@ use libc::c_void;
 
 mod utils;
 mod convert;
-use convert::ToScm;
+use convert::{alist, arg, FromScm, ToScm};
 
-use crate::{buffer::Buffer, debug::log};
+use crate::event::{self, Event, Message};
+use crate::{buffer::Buffer, debug::log, job::JobId, prelude::DocPos};
 
 mod sealed {
     pub(super) struct Sealed;
@@ -22,6 +23,7 @@ type ScmFn0 = unsafe extern "C" fn() -> SCM;
 type ScmFn1 = unsafe extern "C" fn(SCM) -> SCM;
 type ScmFn2 = unsafe extern "C" fn(SCM, SCM) -> SCM;
 type ScmFn3 = unsafe extern "C" fn(SCM, SCM, SCM) -> SCM;
+type ScmFn4 = unsafe extern "C" fn(SCM, SCM, SCM, SCM) -> SCM;
 
 fn rvim_init() {
     unsafe {
@@ -33,14 +35,86 @@ fn rvim_init() {
         let f: ScmFn0 = rscm_current_buffer;
         scm_c_define_gsubr(c"rs-curr-buf".as_ptr(), 0, 0, 0, f as *mut _);
 
+        let f: ScmFn1 = rscm_buffer_id;
+        scm_c_define_gsubr(c"rs-buf-id".as_ptr(), 1, 0, 0, f as *mut _);
+
+        let f: ScmFn1 = rscm_buffer_by_id;
+        scm_c_define_gsubr(c"rs-buf-by-id".as_ptr(), 1, 0, 0, f as *mut _);
+
         let f: ScmFn2 = rscm_char_after;
         scm_c_define_gsubr(c"rs-char-after".as_ptr(), 2, 0, 0, f as *mut _);
 
         let f: ScmFn1 = rscm_curr_pos;
         scm_c_define_gsubr(c"rs-curr-pos".as_ptr(), 1, 0, 0, f as *mut _);
 
+        let f: ScmFn0 = rscm_cursor;
+        scm_c_define_gsubr(c"rs-cursor".as_ptr(), 0, 0, 0, f as *mut _);
+
+        let f: ScmFn2 = rscm_set_cursor;
+        scm_c_define_gsubr(c"rs-set-cursor".as_ptr(), 2, 0, 0, f as *mut _);
+
+        let f: ScmFn0 = rscm_topline;
+        scm_c_define_gsubr(c"rs-topline".as_ptr(), 0, 0, 0, f as *mut _);
+
+        let f: ScmFn0 = rscm_win_height;
+        scm_c_define_gsubr(c"rs-win-height".as_ptr(), 0, 0, 0, f as *mut _);
+
+        let f: ScmFn1 = rscm_scroll;
+        scm_c_define_gsubr(c"rs-scroll".as_ptr(), 1, 0, 0, f as *mut _);
+
+        let f: ScmFn0 = rscm_list_buffers;
+        scm_c_define_gsubr(c"rs-list-buffers".as_ptr(), 0, 0, 0, f as *mut _);
+
+        let f: ScmFn0 = rscm_window_geometry;
+        scm_c_define_gsubr(c"rs-window-geometry".as_ptr(), 0, 0, 0, f as *mut _);
+
         let f: ScmFn3 = rscm_insert_str;
         scm_c_define_gsubr(c"rs-insert-str".as_ptr(), 3, 0, 0, f as *mut _);
+
+        let f: ScmFn3 = rscm_start_job;
+        scm_c_define_gsubr(c"rs-start-job".as_ptr(), 3, 0, 0, f as *mut _);
+
+        let f: ScmFn1 = rscm_kill_job;
+        scm_c_define_gsubr(c"rs-kill-job".as_ptr(), 1, 0, 0, f as *mut _);
+
+        let f: ScmFn1 = rscm_normal;
+        scm_c_define_gsubr(c"rs-normal".as_ptr(), 1, 0, 0, f as *mut _);
+
+        let f: ScmFn2 = rscm_read_string;
+        scm_c_define_gsubr(c"rs-read-string".as_ptr(), 2, 0, 0, f as *mut _);
+
+        let f: ScmFn4 = rscm_set_extmark;
+        scm_c_define_gsubr(c"rs-set-extmark".as_ptr(), 4, 0, 0, f as *mut _);
+
+        let f: ScmFn3 = rscm_set_virt_text;
+        scm_c_define_gsubr(c"rs-set-virt-text".as_ptr(), 3, 0, 0, f as *mut _);
+
+        let f: ScmFn2 = rscm_del_extmark;
+        scm_c_define_gsubr(c"rs-del-extmark".as_ptr(), 2, 0, 0, f as *mut _);
+
+        let f: ScmFn3 = rscm_define_command;
+        scm_c_define_gsubr(c"rs-define-command".as_ptr(), 3, 0, 0, f as *mut _);
+
+        let f: ScmFn0 = rscm_window_layout;
+        scm_c_define_gsubr(c"rs-window-layout".as_ptr(), 0, 0, 0, f as *mut _);
+
+        let f: ScmFn1 = rscm_set_layout;
+        scm_c_define_gsubr(c"rs-set-layout!".as_ptr(), 1, 0, 0, f as *mut _);
+
+        let f: ScmFn3 = rscm_define_syntax;
+        scm_c_define_gsubr(c"rs-define-syntax".as_ptr(), 3, 0, 0, f as *mut _);
+
+        let f: ScmFn3 = rscm_post_insert;
+        scm_c_define_gsubr(c"rs-post-insert".as_ptr(), 3, 0, 0, f as *mut _);
+
+        let f: ScmFn0 = rscm_post_quit;
+        scm_c_define_gsubr(c"rs-post-quit".as_ptr(), 0, 0, 0, f as *mut _);
+
+        let f: ScmFn1 = rscm_post_call;
+        scm_c_define_gsubr(c"rs-post-call".as_ptr(), 1, 0, 0, f as *mut _);
+
+        let f: ScmFn0 = rscm_undo_break;
+        scm_c_define_gsubr(c"rs-undo-break".as_ptr(), 0, 0, 0, f as *mut _);
     }
 }
 
@@ -173,7 +247,9 @@ pub fn execute_guile_interpreted(s: &str) -> Result<(), ()> {
             let inport = scm_open_input_string(s_str);
             let read = scm_read(inport);
             let interaction_env = scm_interaction_environment();
-            let ret = scm_eval(read, interaction_env);
+            // defined in base.scm, so that the command can wait for `prompt-input`
+            let eval = scm_variable_ref(scm_c_lookup(c"eval-suspendable".as_ptr()));
+            let ret = scm_call_2(eval, read, interaction_env);
             let port = scm_open_output_string();
             scm_display(ret, port);
             let s_out_str = scm_get_output_string(port);
@@ -185,10 +261,34 @@ pub fn execute_guile_interpreted(s: &str) -> Result<(), ()> {
             }
         })
     };
-    use crate::command::cmdline;
     let msg = ret.ok_or(())?;
-    let msg = cmdline::CmdMsg::Gmsg(msg);
-    cmdline::CommandLine::send_msg(msg)
+    event::post(Event::Message(Message::Gmsg(msg)));
+    Ok(())
+}
+
+/// evaluate every form in the Scheme file at `path`. A `sandboxed` file runs in a module of its
+/// own that only has pure procedures and the editor's procedures that can't reach outside of it,
+/// see `load-sandboxed` in base.scm.
+pub fn load_file(path: &std::path::Path, sandboxed: bool) -> Result<(), ()> {
+    use std::os::unix::ffi::OsStrExt;
+    // the sandboxed load takes a Scheme string, the other one any bytes
+    let name = match sandboxed {
+        true => Some(path.to_str().ok_or(())?),
+        false => None,
+    };
+    let path = std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|_| ())?;
+    let ret = unsafe {
+        with_guile(|| match name {
+            Some(name) => {
+                let load = scm_variable_ref(scm_c_lookup(c"load-sandboxed".as_ptr()));
+                scm_call_1(load, name.to_scm());
+            }
+            None => {
+                scm_c_primitive_load(path.as_ptr());
+            }
+        })
+    };
+    ret.ok_or(())
 }
 
 /// string from Guile that uses C malloc and free, must be valid utf8
@@ -200,17 +300,6 @@ pub struct Gmsg {
 unsafe impl Send for Gmsg {}
 unsafe impl Sync for Gmsg {}
 
-impl Gmsg {
-    unsafe fn from_scm(obj: SCM) -> Self {
-        let mut len = 0;
-        let msg = scm_to_utf8_stringn(obj, &mut len);
-        Gmsg {
-            len,
-            msg,
-        }
-    }
-}
-
 impl std::borrow::Borrow<str> for Gmsg {
     fn borrow(&self) -> &str {
         &*self
@@ -262,18 +351,17 @@ unsafe fn rscm_unwrap_soft<T: ToScm>(opt: Option<T>) -> SCM {
 
 /// calls thunk which prints to current output port, and displays it in the command line.
 pub unsafe extern "C" fn rscm_print_msg(thunk: SCM) -> SCM {
-    use crate::command::cmdline;
     let s_out_str = scm_call_with_output_string(thunk);
-    let msg = Gmsg::from_scm(s_out_str);
-    let msg = cmdline::CmdMsg::Gmsg(msg);
-    result_bool(cmdline::CommandLine::send_msg(msg))
+    let msg: Gmsg = arg(c"rs-print-msg", 1, s_out_str);
+    event::post(Event::Message(Message::Gmsg(msg)));
+    SCM_BOOL_T
 }
 
+/// show a string in the command line output, from any thread
 pub unsafe extern "C" fn rscm_msg_chr(ch: SCM) -> SCM {
-    use crate::command::cmdline;
-    let msg = Gmsg::from_scm(ch);
-    let msg = cmdline::CmdMsg::Gmsg(msg);
-    result_bool(cmdline::CommandLine::send_msg(msg))
+    let msg: Gmsg = arg(c"rs-send-str", 1, ch);
+    event::post(Event::Message(Message::Gmsg(msg)));
+    SCM_BOOL_T
 }
 
 unsafe fn rscm_from_str_symbol(s: &str) -> SCM {
@@ -327,7 +415,7 @@ unsafe impl ScmRef for Buffer {
 }
 
 pub unsafe extern "C" fn rscm_current_buffer() -> SCM {
-    let curr = reentry(|| crate::render::CURRENT_BUF.get());
+    let curr = reentry(crate::buffer::focus::get);
     let Some(curr) = curr else {
         return SCM_BOOL_F
     };
@@ -336,9 +424,25 @@ pub unsafe extern "C" fn rscm_current_buffer() -> SCM {
     scm_make_foreign_object_1(BUF_REF_TY, raw as *mut _)
 }
 
+pub unsafe extern "C" fn rscm_buffer_id(buf: SCM) -> SCM {
+    let p: *const Buffer = rscm_as_ty(buf);
+    (*p).id().id().to_scm()
+}
+
+/// the buffer with the id, or `#f` if it was closed
+pub unsafe extern "C" fn rscm_buffer_by_id(id: SCM) -> SCM {
+    let id = crate::render::BufId::from_id(arg(c"rs-buf-by-id", 1, id));
+    let Some(buf) = reentry(|| crate::buffer::registry::get(id)) else {
+        return SCM_BOOL_F
+    };
+
+    let raw: *const Buffer = Arc::into_raw(buf);
+    scm_make_foreign_object_1(BUF_REF_TY, raw as *mut _)
+}
+
 pub unsafe extern "C" fn rscm_char_after(buf: SCM, pos: SCM) -> SCM {
     let p: *const Buffer = rscm_as_ty(buf);
-    let pos = scm_to_uint64(pos) as usize;
+    let pos: usize = arg(c"rs-char-after", 2, pos);
     let ch = reentry(|| {
         let guard = (*p).get();
         if pos < guard.len() {
@@ -359,10 +463,92 @@ pub unsafe extern "C" fn rscm_curr_pos(buf: SCM) -> SCM {
     scm_from_uint64(pos as u64)
 }
 
+/// line and column of the cursor in the focused window as a pair, counting from 0. The column is
+/// a byte offset in the line.
+pub unsafe extern "C" fn rscm_cursor() -> SCM {
+    let pos = reentry(|| Some(crate::buffer::focus::window()?.get().buffer.get().cursor.pos));
+    match pos {
+        Some(pos) => (pos.y, pos.x).to_scm(),
+        None => SCM_BOOL_F,
+    }
+}
+
+/// move the cursor of the focused window, as far as the buffer goes, scrolling it into view
+pub unsafe extern "C" fn rscm_set_cursor(line: SCM, col: SCM) -> SCM {
+    let pos = DocPos { x: arg(c"rs-set-cursor", 2, col), y: arg(c"rs-set-cursor", 1, line) };
+    let win = reentry(crate::buffer::focus::window);
+    let Some(win) = win else {
+        return SCM_BOOL_F;
+    };
+    reentry(|| win.get_mut().set_pos(pos));
+    SCM_BOOL_T
+}
+
+/// first line shown in the focused window
+pub unsafe extern "C" fn rscm_topline() -> SCM {
+    let top = reentry(|| Some(crate::buffer::focus::window()?.get().buffer.get().cursor.topline));
+    rscm_unwrap_soft(top)
+}
+
+/// number of lines the focused window shows
+pub unsafe extern "C" fn rscm_win_height() -> SCM {
+    let h = reentry(|| Some(crate::buffer::focus::window()?.get().height()));
+    rscm_unwrap_soft(h)
+}
+
+/// scroll the focused window down by some lines, or up if negative, like `Ctrl-e` and `Ctrl-y`
+pub unsafe extern "C" fn rscm_scroll(lines: SCM) -> SCM {
+    let lines: isize = arg(c"rs-scroll", 1, lines);
+    let win = reentry(crate::buffer::focus::window);
+    let Some(win) = win else {
+        return SCM_BOOL_F;
+    };
+    reentry(|| win.get_mut().scroll_lines(lines));
+    SCM_BOOL_T
+}
+
+/// every open buffer as an alist of id to name, in the order they were opened
+pub unsafe extern "C" fn rscm_list_buffers() -> SCM {
+    let bufs = reentry(|| {
+        let mut bufs: Vec<_> = crate::buffer::registry::all()
+            .iter()
+            .map(|b| (b.id().id(), b.get().name().to_owned()))
+            .collect();
+        bufs.sort_by_key(|(id, _)| *id);
+        bufs
+    });
+    bufs.to_scm()
+}
+
+/// position and size of the focused window on the screen, as an alist of `x`, `y`, `width` and
+/// `height`
+pub unsafe extern "C" fn rscm_window_geometry() -> SCM {
+    let bounds = reentry(|| Some(crate::buffer::focus::window()?.get().outer_bounds()));
+    let Some(b) = bounds else {
+        return SCM_BOOL_F;
+    };
+    alist([("x", b.start.x), ("y", b.start.y), ("width", b.xlen()), ("height", b.ylen())])
+}
+
+/// the layout of the windows, see [`crate::window::org::Layout`] for its shape
+pub unsafe extern "C" fn rscm_window_layout() -> SCM {
+    rscm_unwrap_soft(reentry(crate::buffer::focus::layout))
+}
+
+/// arrange the windows like a layout from `rs-window-layout` once the main loop gets to it.
+/// Returns `#f` if the layout isn't one.
+pub unsafe extern "C" fn rscm_set_layout(layout: SCM) -> SCM {
+    let Some(layout) = crate::window::org::Layout::from_scm(layout) else {
+        return SCM_BOOL_F;
+    };
+    event::post(Event::Layout(layout));
+    SCM_BOOL_T
+}
+
 pub unsafe extern "C" fn rscm_insert_str(buf: SCM, pos: SCM, string: SCM) -> SCM {
     let p: *const Buffer = rscm_as_ty(buf);
-    let pos = scm_to_uint64(pos) as usize;
-    let s = Gmsg::from_scm(string);
+    let pos: usize = arg(c"rs-insert-str", 2, pos);
+    let s: Gmsg = arg(c"rs-insert-str", 3, string);
     reentry(|| {
         let mut guard = (*p).get_mut();
         if pos < guard.len() {
@@ -375,6 +561,250 @@ pub unsafe extern "C" fn rscm_insert_str(buf: SCM, pos: SCM, string: SCM) -> SCM
     SCM_UNSPECIFIED
 }
 
+thread_local! {
+    /// line and exit procedures of jobs started from Guile
+    static JOB_CALLBACKS: std::cell::RefCell<std::collections::HashMap<JobId, (ProtectedScm, ProtectedScm)>>
+        = Default::default();
+}
+
+/// start a shell command as a job, calling `on_line` with every line of output and `on_exit` with
+/// the exit code (or `#f` if killed). Returns the job id or `#f` if it could not be started.
+pub unsafe extern "C" fn rscm_start_job(cmd: SCM, on_line: SCM, on_exit: SCM) -> SCM {
+    let cmd: Gmsg = arg(c"rs-start-job", 1, cmd);
+    let res = reentry(|| crate::job::start(&cmd, crate::job::Sink::Guile));
+    let Ok(id) = res else {
+        return SCM_BOOL_F;
+    };
+    let callbacks = (protect(on_line), protect(on_exit));
+    JOB_CALLBACKS.with(|c| c.borrow_mut().insert(id, callbacks));
+    id.id().to_scm()
+}
+
+pub unsafe extern "C" fn rscm_kill_job(id: SCM) -> SCM {
+    let id = JobId::from(arg::<u64>(c"rs-kill-job", 1, id));
+    result_bool(reentry(|| crate::job::kill(id).map_err(|_| ())))
+}
+
+/// run keys in normal mode once the current command is done
+pub unsafe extern "C" fn rscm_normal(keys: SCM) -> SCM {
+    let keys: Gmsg = arg(c"rs-normal", 1, keys);
+    event::post(Event::Keys(keys.as_bytes().to_vec()));
+    SCM_BOOL_T
+}
+
+/// what is waiting for the answer to the prompt, see [`read_string`]
+type PromptCallback = Box<dyn FnOnce(Option<&str>)>;
+
+thread_local! {
+    /// prompt from `rs-read-string` that hasn't been shown yet
+    static PENDING_PROMPT: std::cell::RefCell<Option<String>> = Default::default();
+    static PROMPT_CALLBACK: std::cell::RefCell<Option<PromptCallback>> = Default::default();
+}
+
+/// ask for a line of text in the command line, calling `on_done` with it once Enter is pressed, or
+/// with `#f` if the prompt is cancelled. Returns `#f` if another prompt is already open.
+pub unsafe extern "C" fn rscm_read_string(prompt: SCM, on_done: SCM) -> SCM {
+    let prompt: Gmsg = arg(c"rs-read-string", 1, prompt);
+    let on_done = protect(on_done);
+    let asked = read_string(&prompt, move |answer| {
+        with_guile(|| {
+            let answer = match answer {
+                Some(s) => scm_from_utf8_stringn(s.as_ptr().cast(), s.len()),
+                None => SCM_BOOL_F,
+            };
+            scm_call_1(on_done.0, answer);
+        });
+    });
+    to_scm_bool(asked)
+}
+
+/// ask for a line of text in the command line, `on_done` gets it once Enter is pressed, or `None`
+/// if the prompt is cancelled. Returns false if another prompt is already open.
+pub fn read_string(prompt: &str, on_done: impl FnOnce(Option<&str>) + 'static) -> bool {
+    if PROMPT_CALLBACK.with(|c| c.borrow().is_some()) {
+        return false;
+    }
+    PENDING_PROMPT.with(|p| *p.borrow_mut() = Some(prompt.to_string()));
+    PROMPT_CALLBACK.with(|c| *c.borrow_mut() = Some(Box::new(on_done)));
+    true
+}
+
+/// the prompt Guile is waiting to show
+pub fn take_prompt() -> Option<String> {
+    PENDING_PROMPT.with(|p| p.borrow_mut().take())
+}
+
+/// answer the open prompt, `None` if it was cancelled
+pub fn prompt_answer(answer: Option<&str>) {
+    if let Some(on_done) = PROMPT_CALLBACK.with(|c| c.borrow_mut().take()) {
+        on_done(answer);
+    }
+}
+
+/// highlight the text between two offsets with a background color, by name. Returns the id of the
+/// extmark, or `#f` if the offsets or the color are invalid.
+pub unsafe extern "C" fn rscm_set_extmark(buf: SCM, start: SCM, end: SCM, color: SCM) -> SCM {
+    use crate::buffer::extmark::Extmark;
+    use crate::tui::{BasicColor, Color};
+    let p: *const Buffer = rscm_as_ty(buf);
+    let range = arg(c"rs-set-extmark", 2, start)..arg(c"rs-set-extmark", 3, end);
+    let color: Gmsg = arg(c"rs-set-extmark", 4, color);
+    let id = reentry(|| {
+        let bg = BasicColor::from_name(&color)?;
+        let mut guard = (*p).get_mut();
+        if range.start > range.end || range.end > guard.len() {
+            return None;
+        }
+        let mark = Extmark::highlight("guile", range, Color { bg, ..Color::new() });
+        Some(guard.extmarks_mut().add(mark).id())
+    });
+    rscm_unwrap_soft(id)
+}
+
+/// show text after the end of the line with the offset, returns the id of the extmark or `#f` if
+/// the offset is past the end
+pub unsafe extern "C" fn rscm_set_virt_text(buf: SCM, off: SCM, text: SCM) -> SCM {
+    use crate::buffer::extmark::{Extmark, VIRT_TEXT_COLOR};
+    let p: *const Buffer = rscm_as_ty(buf);
+    let off: usize = arg(c"rs-set-virt-text", 2, off);
+    let text: Gmsg = arg(c"rs-set-virt-text", 3, text);
+    let id = reentry(|| {
+        let mut guard = (*p).get_mut();
+        if off > guard.len() {
+            return None;
+        }
+        let mark = Extmark::virt_text("guile", off, text.to_string(), VIRT_TEXT_COLOR);
+        Some(guard.extmarks_mut().add(mark).id())
+    });
+    rscm_unwrap_soft(id)
+}
+
+pub unsafe extern "C" fn rscm_del_extmark(buf: SCM, id: SCM) -> SCM {
+    let p: *const Buffer = rscm_as_ty(buf);
+    let id: u64 = arg(c"rs-del-extmark", 2, id);
+    let removed = reentry(|| (*p).get_mut().extmarks_mut().remove(id.into()).is_some());
+    to_scm_bool(removed)
+}
+
+/// define the ex command `name`, which calls `run` with its arguments as a string. `complete` is
+/// `#f` or called with the argument being typed and returns a list of candidates.
+pub unsafe extern "C" fn rscm_define_command(name: SCM, run: SCM, complete: SCM) -> SCM {
+    use crate::command::user;
+    let name: Gmsg = arg(c"rs-define-command", 1, name);
+    let cmd = user::UserCommand {
+        action: user::Action::Guile(protect(run)),
+        complete: (complete != SCM_BOOL_F).then(|| protect(complete)),
+    };
+    result_bool(reentry(|| user::define(&name, cmd).map_err(|_| ())))
+}
+
+/// highlight the matches of a regex in the buffers of a filetype with a highlight group, like
+/// `"Comment"`. Returns `#f` if the regex or the group are invalid.
+pub unsafe extern "C" fn rscm_define_syntax(filetype: SCM, pattern: SCM, group: SCM) -> SCM {
+    let filetype: Gmsg = arg(c"rs-define-syntax", 1, filetype);
+    let pattern: Gmsg = arg(c"rs-define-syntax", 2, pattern);
+    let group: Gmsg = arg(c"rs-define-syntax", 3, group);
+    let res = reentry(|| crate::syntax::define(&filetype, &pattern, &group).map_err(|e| log!("rs-define-syntax: {e}")));
+    result_bool(res)
+}
+
+/// insert a string at an offset of the buffer with the id once the main loop gets to it. Unlike
+/// `rs-insert-str` this is safe from any thread.
+pub unsafe extern "C" fn rscm_post_insert(id: SCM, off: SCM, text: SCM) -> SCM {
+    let buf = crate::render::BufId::from_id(arg(c"rs-post-insert", 1, id));
+    let off = arg(c"rs-post-insert", 2, off);
+    let text = arg(c"rs-post-insert", 3, text);
+    event::post(Event::Insert { buf, off, text });
+    SCM_UNSPECIFIED
+}
+
+/// leave the editor once the main loop gets to it, from any thread
+pub unsafe extern "C" fn rscm_post_quit() -> SCM {
+    event::post(Event::Quit);
+    SCM_UNSPECIFIED
+}
+
+/// end the current undo step, so the changes after it are undone separately
+pub unsafe extern "C" fn rscm_undo_break() -> SCM {
+    crate::buffer::history::undo_break();
+    SCM_UNSPECIFIED
+}
+
+/// call a procedure without arguments from the main loop, from any thread
+pub unsafe extern "C" fn rscm_post_call(thunk: SCM) -> SCM {
+    event::post(Event::Hook(protect(thunk)));
+    SCM_UNSPECIFIED
+}
+
+/// call a procedure from [`Event::Hook`], which is unprotected once it returns or throws
+pub fn call_hook(f: ProtectedScm) {
+    unsafe {
+        with_guile(|| {
+            scm_call_0(f.0);
+        });
+    }
+}
+
+/// call the procedure of a user command
+pub fn run_command(f: &ProtectedScm, args: &str) -> Result<(), ()> {
+    unsafe {
+        with_guile(|| {
+            let args = scm_from_utf8_stringn(args.as_ptr().cast(), args.len());
+            scm_call_1(f.0, args);
+        })
+    }
+    .ok_or(())
+}
+
+/// candidates from the completion procedure of a user command, ignoring anything but strings
+pub fn complete_command(f: &ProtectedScm, word: &str) -> Vec<String> {
+    unsafe {
+        with_guile(|| {
+            let word = scm_from_utf8_stringn(word.as_ptr().cast(), word.len());
+            let mut list = scm_call_1(f.0, word);
+            let mut out = Vec::new();
+            while scm_pair_p(list) != SCM_BOOL_F {
+                out.extend(String::from_scm(scm_car(list)));
+                list = scm_cdr(list);
+            }
+            out
+        })
+    }
+    .unwrap_or_default()
+}
+
+/// pass a line of output to the job's Guile procedure
+pub fn job_line(id: JobId, line: &str) {
+    let Some(f) = JOB_CALLBACKS.with(|c| c.borrow().get(&id).map(|(f, _)| f.0)) else {
+        return;
+    };
+    unsafe {
+        with_guile(|| {
+            let s = scm_from_utf8_stringn(line.as_ptr().cast(), line.len());
+            scm_call_1(f, s);
+        });
+    }
+}
+
+/// call the job's exit procedure and forget about it
+pub fn job_exit(id: JobId, code: Option<i32>) {
+    let Some((on_line, on_exit)) = JOB_CALLBACKS.with(|c| c.borrow_mut().remove(&id)) else {
+        return;
+    };
+    unsafe {
+        with_guile(|| {
+            let code = match code {
+                Some(c) => c.to_scm(),
+                None => SCM_BOOL_F,
+            };
+            scm_call_1(on_exit.0, code);
+            // unprotecting has to happen in guile mode
+            drop(on_line);
+            drop(on_exit);
+        });
+    }
+}
+
 pub fn initialize() {
     static ONCE: std::sync::Once = std::sync::Once::new();
 
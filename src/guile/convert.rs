@@ -1,5 +1,8 @@
 use guile_sys::*;
 
+use crate::render::BufId;
+use crate::window::org::{Arrange, Layout, LayoutBuf};
+
 pub(super) unsafe trait ToScm: Send + Sync {
     unsafe fn to_scm(self) -> SCM;
 }
@@ -37,3 +40,226 @@ unsafe impl ToScm for char {
         scm_integer_to_char(s)
     }
 }
+
+unsafe impl ToScm for bool {
+    unsafe fn to_scm(self) -> SCM {
+        if self {
+            SCM_BOOL_T
+        } else {
+            SCM_BOOL_F
+        }
+    }
+}
+
+unsafe impl ToScm for &str {
+    unsafe fn to_scm(self) -> SCM {
+        scm_from_utf8_stringn(self.as_ptr().cast(), self.len())
+    }
+}
+
+unsafe impl ToScm for String {
+    unsafe fn to_scm(self) -> SCM {
+        self.as_str().to_scm()
+    }
+}
+
+/// a pair
+unsafe impl<A: ToScm, B: ToScm> ToScm for (A, B) {
+    unsafe fn to_scm(self) -> SCM {
+        let car = self.0.to_scm();
+        scm_cons(car, self.1.to_scm())
+    }
+}
+
+unsafe impl<T: ToScm> ToScm for Vec<T> {
+    unsafe fn to_scm(self) -> SCM {
+        list(self)
+    }
+}
+
+/// a list of `items`. It's built on the stack where the garbage collector can see it, rather than
+/// by collecting the converted items in a `Vec`.
+pub(super) unsafe fn list<T: ToScm>(items: impl IntoIterator<Item = T>) -> SCM {
+    let mut out = SCM_EOL;
+    for item in items {
+        out = scm_cons(item.to_scm(), out);
+    }
+    scm_reverse_x(out, SCM_EOL)
+}
+
+/// an association list with symbols as keys, like `((line . 3) (col . 0))`
+pub(super) unsafe fn alist<T: ToScm>(items: impl IntoIterator<Item = (&'static str, T)>) -> SCM {
+    list(items.into_iter().map(|(key, v)| (Symbol(key.to_owned()), v)))
+}
+
+/// Checked conversion of Scheme values, `None` if the value has the wrong type or doesn't fit.
+pub(super) trait FromScm: Sized {
+    unsafe fn from_scm(obj: SCM) -> Option<Self>;
+}
+
+/// converts argument `pos` (counting from 1) of the procedure `subr`, throwing a `wrong-type-arg`
+/// error if it has the wrong type. Throwing skips the destructors of the Rust frames in between,
+/// so anything owned by the procedure at that point is leaked.
+pub(super) unsafe fn arg<T: FromScm>(subr: &std::ffi::CStr, pos: i32, obj: SCM) -> T {
+    match T::from_scm(obj) {
+        Some(v) => v,
+        None => {
+            scm_wrong_type_arg(subr.as_ptr(), pos, obj);
+            std::process::abort()
+        }
+    }
+}
+
+macro_rules! unsigned {
+    ($($t:ty),*) => {$(
+        impl FromScm for $t {
+            unsafe fn from_scm(obj: SCM) -> Option<Self> {
+                (scm_is_unsigned_integer(obj, 0, <$t>::MAX as u64) != 0).then(|| scm_to_uint64(obj) as $t)
+            }
+        }
+    )*};
+}
+unsigned!(u64, usize, u32);
+
+macro_rules! signed {
+    ($($t:ty),*) => {$(
+        impl FromScm for $t {
+            unsafe fn from_scm(obj: SCM) -> Option<Self> {
+                (scm_is_signed_integer(obj, <$t>::MIN as i64, <$t>::MAX as i64) != 0)
+                    .then(|| scm_to_int64(obj) as $t)
+            }
+        }
+    )*};
+}
+signed!(i64, isize, i32);
+
+impl FromScm for bool {
+    unsafe fn from_scm(obj: SCM) -> Option<Self> {
+        (scm_boolean_p(obj) != SCM_BOOL_F).then_some(obj != SCM_BOOL_F)
+    }
+}
+
+impl FromScm for super::Gmsg {
+    unsafe fn from_scm(obj: SCM) -> Option<Self> {
+        if scm_string_p(obj) == SCM_BOOL_F {
+            return None;
+        }
+        let mut len = 0;
+        let msg = scm_to_utf8_stringn(obj, &mut len);
+        Some(super::Gmsg { len, msg })
+    }
+}
+
+impl FromScm for String {
+    unsafe fn from_scm(obj: SCM) -> Option<Self> {
+        super::Gmsg::from_scm(obj).map(|s| s.to_string())
+    }
+}
+
+/// name of a Scheme symbol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Symbol(pub String);
+
+unsafe impl ToScm for Symbol {
+    unsafe fn to_scm(self) -> SCM {
+        scm_from_utf8_symboln(self.0.as_ptr().cast(), self.0.len())
+    }
+}
+
+impl FromScm for Symbol {
+    unsafe fn from_scm(obj: SCM) -> Option<Self> {
+        if scm_symbol_p(obj) == SCM_BOOL_F {
+            return None;
+        }
+        String::from_scm(scm_symbol_to_string(obj)).map(Symbol)
+    }
+}
+
+/// a proper list with every item of the type
+impl<T: FromScm> FromScm for Vec<T> {
+    unsafe fn from_scm(mut obj: SCM) -> Option<Self> {
+        if scm_list_p(obj) == SCM_BOOL_F {
+            return None;
+        }
+        let mut out = Vec::new();
+        while scm_pair_p(obj) != SCM_BOOL_F {
+            out.push(T::from_scm(scm_car(obj))?);
+            obj = scm_cdr(obj);
+        }
+        Some(out)
+    }
+}
+
+impl<A: FromScm, B: FromScm> FromScm for (A, B) {
+    unsafe fn from_scm(obj: SCM) -> Option<Self> {
+        if scm_pair_p(obj) == SCM_BOOL_F {
+            return None;
+        }
+        Some((A::from_scm(scm_car(obj))?, B::from_scm(scm_cdr(obj))?))
+    }
+}
+
+/// a window layout as `(row first second)` or `(col first second)` for windows side by side or
+/// above each other, with `(leaf "path")` for a window showing a file and `(leaf id)` for one
+/// showing a buffer without one. Reading it takes any number of windows in a row or column.
+unsafe impl ToScm for Layout {
+    unsafe fn to_scm(self) -> SCM {
+        match self {
+            Layout::Leaf(buf) => {
+                let buf = match buf {
+                    LayoutBuf::Path(path) => (&*path.to_string_lossy()).to_scm(),
+                    LayoutBuf::Id(id) => id.id().to_scm(),
+                };
+                scm_cons(Symbol("leaf".into()).to_scm(), scm_cons(buf, SCM_EOL))
+            }
+            Layout::Split { first, second, arrange } => {
+                let name = match arrange {
+                    Arrange::Horizontal => "row",
+                    Arrange::Vertical => "col",
+                };
+                let rest = scm_cons(second.to_scm(), SCM_EOL);
+                let rest = scm_cons(first.to_scm(), rest);
+                scm_cons(Symbol(name.into()).to_scm(), rest)
+            }
+        }
+    }
+}
+
+impl FromScm for Layout {
+    unsafe fn from_scm(obj: SCM) -> Option<Self> {
+        if scm_list_p(obj) == SCM_BOOL_F || scm_pair_p(obj) == SCM_BOOL_F {
+            return None;
+        }
+        let Symbol(name) = Symbol::from_scm(scm_car(obj))?;
+        let mut items = Vec::new();
+        let mut rest = scm_cdr(obj);
+        while scm_pair_p(rest) != SCM_BOOL_F {
+            items.push(scm_car(rest));
+            rest = scm_cdr(rest);
+        }
+        let arrange = match name.as_str() {
+            "leaf" => {
+                let &[buf] = items.as_slice() else {
+                    return None;
+                };
+                return match String::from_scm(buf) {
+                    Some(path) => Some(Layout::Leaf(LayoutBuf::Path(path.into()))),
+                    None => Some(Layout::Leaf(LayoutBuf::Id(BufId::from_id(u64::from_scm(buf)?)))),
+                };
+            }
+            "row" => Arrange::Horizontal,
+            "col" => Arrange::Vertical,
+            _ => return None,
+        };
+        if items.len() < 2 {
+            return None;
+        }
+        let children = items.into_iter().map(|item| Layout::from_scm(item)).collect::<Option<Vec<_>>>()?;
+        // more than two are split off one after the other, each from the rest of the row
+        children.into_iter().rev().reduce(|second, first| Layout::Split {
+            first: Box::new(first),
+            second: Box::new(second),
+            arrange,
+        })
+    }
+}
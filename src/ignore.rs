@@ -0,0 +1,244 @@
+//! Files to skip when walking a directory, with the rules of `.gitignore`.
+//!
+//! Every line of a `.gitignore` is a pattern, apart from blank lines and comments starting with
+//! `#`. In a pattern `*` matches anything but `/`, `?` one character that isn't `/`, and `[...]` one
+//! of a set of characters. `**/` at the start matches any directories, `/**` at the end everything
+//! inside and `/**/` in the middle zero or more directories. A pattern with a `/` before its end is
+//! relative to the directory of the `.gitignore`, one without matches a name at any depth. A
+//! trailing `/` only matches directories and a leading `!` includes again what an earlier pattern
+//! excluded. The last pattern that matches a path decides, and the rules of a `.gitignore` deeper
+//! down come after those above it.
+//!
+//! [`walk`] reads the `.gitignore` of every directory it goes into, after the patterns of the
+//! `wildignore` option, which skip [`DEFAULT`] unless it's changed. `:find` looks for files with
+//! it, see [`crate::command::complete::files`].
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+/// git's own directory and the build output of cargo and npm
+pub const DEFAULT: &[&str] = &[".git/", "target/", "node_modules/"];
+
+struct Rule {
+    re: Regex,
+    negate: bool,
+    dir_only: bool,
+}
+
+/// the patterns of a `.gitignore`, for paths relative to its directory
+#[derive(Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+/// the regex for the glob `glob`, without anchors
+fn glob_regex(glob: &str) -> String {
+    let mut out = String::new();
+    let mut rest = glob;
+    while let Some(c) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("**/").filter(|_| out.is_empty()) {
+            out.push_str("(?:.*/)?");
+            rest = after;
+            continue;
+        }
+        if let Some(after) = rest.strip_prefix("/**/") {
+            out.push_str("/(?:.*/)?");
+            rest = after;
+            continue;
+        }
+        if rest == "/**" {
+            out.push_str("/.*");
+            break;
+        }
+        rest = &rest[c.len_utf8()..];
+        match c {
+            '*' if rest.starts_with('*') => {
+                rest = &rest[1..];
+                out.push_str(".*");
+            }
+            '*' => out.push_str("[^/]*"),
+            '?' => out.push_str("[^/]"),
+            '[' if rest.contains(']') => {
+                // a `]` right after the `[` is in the set
+                let first = rest.chars().next().map_or(0, char::len_utf8);
+                let end = rest[first..].find(']').map_or(0, |i| i + first);
+                let set = &rest[..end];
+                out.push('[');
+                let set = match set.strip_prefix(['!', '^']) {
+                    Some(set) => {
+                        out.push('^');
+                        set
+                    }
+                    None => set,
+                };
+                // only ranges are special inside the brackets
+                for c in set.chars() {
+                    if c == '-' {
+                        out.push('-');
+                    } else {
+                        out.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+                    }
+                }
+                out.push(']');
+                rest = &rest[end + 1..];
+            }
+            '\\' => {
+                let Some(c) = rest.chars().next() else {
+                    break;
+                };
+                rest = &rest[c.len_utf8()..];
+                out.push_str(&regex::escape(c.encode_utf8(&mut [0; 4])));
+            }
+            c => out.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    out
+}
+
+impl Rules {
+    /// rules from the lines of a `.gitignore`, skipping patterns that don't make a valid regex
+    pub fn new<'a>(lines: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut rules = Vec::new();
+        for line in lines {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (negate, line) = match line.strip_prefix('!') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let (dir_only, line) = match line.strip_suffix('/') {
+                Some(line) => (true, line),
+                None => (false, line),
+            };
+            let re = match line.strip_prefix('/') {
+                Some(line) => format!("^{}$", glob_regex(line)),
+                None if line.contains('/') => format!("^{}$", glob_regex(line)),
+                None => format!("^(?:.*/)?{}$", glob_regex(line)),
+            };
+            if let Ok(re) = Regex::new(&re) {
+                rules.push(Rule { re, negate, dir_only });
+            }
+        }
+        Rules { rules }
+    }
+
+    /// the rules of the `.gitignore` in `dir`, `None` if there isn't one
+    pub fn read(dir: &Path) -> Option<Self> {
+        let text = std::fs::read_to_string(dir.join(".gitignore")).ok()?;
+        Some(Self::new(text.lines()))
+    }
+
+    /// whether `path`, relative to the directory of the rules and with `/` between its
+    /// components, is ignored. `None` if no rule matches it.
+    pub fn ignored(&self, path: &str, is_dir: bool) -> Option<bool> {
+        let rule = self.rules.iter().rev().find(|r| (is_dir || !r.dir_only) && r.re.is_match(path))?;
+        Some(!rule.negate)
+    }
+}
+
+/// the files under `root` that aren't ignored by `patterns` or the `.gitignore` files in it, in
+/// the order of their paths. Directories that can't be read are skipped.
+pub fn walk(root: &Path, patterns: &[String]) -> Vec<PathBuf> {
+    let base = Rules::new(patterns.iter().map(String::as_str));
+    let mut out = Vec::new();
+    // every directory whose rules apply, by its path relative to `root`
+    let mut scopes = vec![(String::new(), base)];
+    walk_dir(root, "", &mut scopes, &mut out);
+    out
+}
+
+fn walk_dir(dir: &Path, rel: &str, scopes: &mut Vec<(String, Rules)>, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = dir.read_dir() else {
+        return;
+    };
+    let own = Rules::read(dir);
+    let pushed = own.is_some();
+    if let Some(rules) = own {
+        scopes.push((rel.to_owned(), rules));
+    }
+    let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).collect();
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = format!("{rel}{name}");
+        let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+        let ignored = scopes
+            .iter()
+            .rev()
+            .find_map(|(scope, rules)| rules.ignored(&path[scope.len()..], is_dir))
+            .unwrap_or(false);
+        if ignored {
+            continue;
+        }
+        if is_dir {
+            walk_dir(&entry.path(), &format!("{path}/"), scopes, out);
+        } else {
+            out.push(entry.path());
+        }
+    }
+    if pushed {
+        scopes.pop();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn ignored(patterns: &str, path: &str, is_dir: bool) -> bool {
+        Rules::new(patterns.lines()).ignored(path, is_dir).unwrap_or(false)
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(ignored("*.o", "a/b/c.o", false));
+        assert!(!ignored("*.o", "a/b/c.oo", false));
+        assert!(ignored("target/", "sub/target", true));
+        assert!(!ignored("target/", "sub/target", false));
+        assert!(ignored("/build", "build", true));
+        assert!(!ignored("/build", "sub/build", true));
+        assert!(ignored("doc/*.html", "doc/a.html", false));
+        assert!(!ignored("doc/*.html", "doc/sub/a.html", false));
+        assert!(ignored("**/logs", "a/b/logs", true));
+        assert!(ignored("a/**/z", "a/z", false));
+        assert!(ignored("a/**/z", "a/b/c/z", false));
+        assert!(ignored("a/**", "a/b/c", false));
+        assert!(ignored("file[0-9].txt", "file3.txt", false));
+        assert!(!ignored("file[!0-9].txt", "file3.txt", false));
+        assert!(ignored("[ä]", "ä", false));
+        assert!(ignored("[é-ü]x", "öx", false));
+        assert!(ignored("?.rs", "x.rs", false));
+        assert!(!ignored("?.rs", "xy.rs", false));
+        assert!(ignored("\\#hash", "#hash", false));
+        assert!(!ignored("# comment\n\n", "# comment", false));
+    }
+
+    #[test]
+    fn last_rule_wins() {
+        let rules = "*.log\n!keep.log";
+        assert!(ignored(rules, "a.log", false));
+        assert!(!ignored(rules, "keep.log", false));
+        assert_eq!(Rules::new(rules.lines()).ignored("keep.log", false), Some(false));
+        assert_eq!(Rules::new(rules.lines()).ignored("a.rs", false), None);
+    }
+
+    #[test]
+    fn walking() {
+        let dir = std::env::temp_dir().join(format!("edit-ignore-test-{}", std::process::id()));
+        for path in ["src/main.rs", "src/gen.rs", "target/debug/x", "sub/a.tmp", "sub/keep.tmp", "sub/b.rs"] {
+            let path = dir.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, "").unwrap();
+        }
+        std::fs::write(dir.join(".gitignore"), "gen.rs\n*.tmp\n").unwrap();
+        std::fs::write(dir.join("sub/.gitignore"), "!keep.tmp\n").unwrap();
+        let defaults: Vec<String> = DEFAULT.iter().map(|s| s.to_string()).collect();
+        let files: Vec<_> = walk(&dir, &defaults).into_iter().map(|p| p.strip_prefix(&dir).unwrap().to_owned()).collect();
+        let expected = [".gitignore", "src/main.rs", "sub/.gitignore", "sub/b.rs", "sub/keep.tmp"];
+        assert_eq!(files, expected.map(PathBuf::from));
+        assert!(walk(&dir, &[]).contains(&dir.join("target/debug/x")));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,7 +1,9 @@
 use crate::log;
 use crate::prelude::*;
+use crate::command::cmdline::CommandLineInput;
 use crate::textobj::Motion;
 use std::io::stdin;
+use std::cell::RefCell;
 use std::io::Read;
 
 use crate::Ctx;
@@ -17,7 +19,67 @@ pub enum Operation {
     DeleteAfter,
     SwitchMode(Mode),
     RecenterView,
-    Debug,
+    GotoDefinition,
+    /// `gf`, edit the file whose name is under the cursor
+    GotoFile,
+    /// `Ctrl-^`, edit the alternate buffer
+    EditAlternate,
+    /// `gx`, open the URL or file name under the cursor outside of the editor
+    OpenExternal,
+    Hover,
+    NextWindow,
+    StartSearch { backward: bool },
+    /// repeat the last search, in the opposite direction if `reverse` is set
+    SearchNext { reverse: bool },
+    PageDown,
+    PageUp,
+    NextHunk,
+    PrevHunk,
+    /// copy the diff hunk under the cursor to the other buffer
+    DiffPut,
+    /// replace the diff hunk under the cursor with the other buffer's version
+    DiffGet,
+    /// raw bytes for the program in a terminal buffer
+    TerminalInput(Vec<u8>),
+    /// editing the command line
+    CommandLine(CommandLineInput),
+    /// `Shift-Tab` on the command line, the previous completion
+    CompletePrev,
+    /// `Ctrl-r`, insert a register into the command line or the buffer. On the command line
+    /// `Ctrl-w` is the word under the cursor.
+    InsertRegister(char),
+    /// `Ctrl-w` in insert mode, delete the word before the cursor
+    DeleteWordBefore,
+    /// `Ctrl-u` in insert mode, delete the text before the cursor on its line
+    DeleteLineBefore,
+    /// `Ctrl-o` in insert mode, run one normal mode command and go back to insert mode
+    NormalOnce,
+    /// `p` and `P`, put text from a register after or before the cursor. Text that ends in a
+    /// newline is put below or above the cursor line, with `indent` reindented like the cursor line
+    /// for `]p` and `[p`.
+    Put { before: bool, indent: bool },
+    /// `u`, revert the last change
+    Undo,
+    /// `Ctrl-r`, make an undone change again
+    Redo,
+    /// `g Ctrl-d`, write a dump of the screen to the debug log
+    DumpScreen,
+    /// `gq`, reflow the lines of the motion, or the cursor line, to `textwidth`
+    Format,
+    /// `ys`, surround the text of the motion, or the cursor line, with the delimiters of a character
+    Surround(char),
+    /// `cs`, change the delimiters around the cursor
+    ChangeSurround { old: char, new: char },
+    /// `ds`, delete the delimiters around the cursor
+    DeleteSurround(char),
+    /// `q:`, open the command-line window with the history of ex commands
+    CommandWindow,
+    /// `@`, run the keys in a register, or the ex command in it for `@:`
+    ExecuteRegister(char),
+    /// `Enter`, run the cursor line in the command-line window, elsewhere move down a line
+    Enter,
+    /// `.`, make the last change again, see [`Repeat`]
+    RepeatChange,
     None,
 }
 
@@ -27,6 +89,36 @@ pub struct Action {
     pub operation: Operation,
     pub repeat: Option<u32>,
     pub post_motion: Option<Motion>,
+    /// `"x` before the command, the register it uses instead of the unnamed one
+    pub register: Option<char>,
+}
+
+impl Operation {
+    /// whether the operation changes the text of a buffer
+    pub fn is_edit(&self) -> bool {
+        matches!(
+            self,
+            Operation::Change
+                | Operation::Delete
+                | Operation::Replace(_)
+                | Operation::Insert(_)
+                | Operation::DeleteBefore
+                | Operation::DeleteAfter
+                | Operation::DeleteWordBefore
+                | Operation::DeleteLineBefore
+                | Operation::InsertRegister(_)
+                | Operation::SwitchMode(Mode::Insert)
+                | Operation::DiffPut
+                | Operation::DiffGet
+                | Operation::Put { .. }
+                | Operation::Format
+                | Operation::Surround(_)
+                | Operation::ChangeSurround { .. }
+                | Operation::DeleteSurround(_)
+                | Operation::Undo
+                | Operation::Redo
+        )
+    }
 }
 
 impl Action {
@@ -36,6 +128,7 @@ impl Action {
             operation: Operation::None,
             repeat: None,
             post_motion: None,
+            register: None,
         }
     }
 }
@@ -58,30 +151,258 @@ impl From<Operation> for Action {
     }
 }
 
+/// Unbuffered reader for the terminal. We can't use [`std::io::Stdin`] since its buffering hides
+/// pending input from [`TermReader::poll`]. Keys that [`crate::busy::check`] read come first.
+pub struct TermReader {
+    fd: std::os::unix::io::RawFd,
+}
+
+impl TermReader {
+    pub fn new(fd: std::os::unix::io::RawFd) -> Self {
+        Self { fd }
+    }
+
+    /// wait up to `timeout_ms` for input to be available, returns true if it is
+    pub fn poll(&self, timeout_ms: i32) -> bool {
+        use nix::poll::{poll, PollFd, PollFlags};
+        if crate::busy::has_keys() {
+            return true;
+        }
+        let mut fds = [PollFd::new(self.fd, PollFlags::POLLIN)];
+        matches!(poll(&mut fds, timeout_ms), Ok(n) if n > 0)
+    }
+}
+
+impl Read for TermReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let kept = crate::busy::take_keys(buf);
+        if kept > 0 {
+            return Ok(kept);
+        }
+        loop {
+            match nix::unistd::read(self.fd, buf) {
+                Err(nix::errno::Errno::EINTR) => continue,
+                res => return res.map_err(std::io::Error::from),
+            }
+        }
+    }
+}
+
+/// keys of a normal mode command that isn't complete yet. They're kept between calls to
+/// [`handle_input`] so that the editor can be redrawn while waiting for the rest.
+#[derive(Debug, Default)]
+pub struct PendingKeys(RefCell<Vec<char>>);
+
+impl PendingKeys {
+    /// whether an operator is waiting for its motion
+    pub fn operator(&self) -> bool {
+        let keys = self.0.borrow();
+        matches!(syn::parse_keys(&keys), syn::Parse::Pending { operator: true })
+    }
+
+    /// drop the keys, the command is never completed
+    pub fn cancel(&self) {
+        self.0.borrow_mut().clear();
+    }
+
+    pub fn keys(&self) -> Vec<char> {
+        self.0.borrow().clone()
+    }
+}
+
+/// the keys of the last change, for `.`. [`handle_input`] records the keys it reads, and
+/// [`Repeat::action`] keeps those of a normal mode command that changes text along with the keys
+/// typed in the insert mode it may start, up to the key that leaves it. Making the change again is
+/// typing them again, like with `:normal`.
+#[derive(Debug, Default)]
+pub struct Repeat {
+    /// read since the last action
+    typed: RefCell<Vec<u8>>,
+    last: Vec<u8>,
+    /// whether the last change goes on in insert mode
+    open: bool,
+}
+
+impl Repeat {
+    /// keep the keys of `action` if it's part of a change, given in `mode`
+    pub fn action(&mut self, mode: Mode, action: &Action) {
+        let typed = std::mem::take(self.typed.get_mut());
+        match mode {
+            Mode::Normal if action.operation.is_edit() && !matches!(action.operation, Operation::Undo | Operation::Redo) => {
+                self.last = typed;
+                self.open = true;
+            }
+            Mode::Insert if self.open => self.last.extend(typed),
+            _ => (),
+        }
+    }
+
+    /// the action was done and left the editor in `mode`, the change ends unless that's insert
+    /// mode
+    pub fn done(&mut self, mode: Mode) {
+        self.open &= mode == Mode::Insert;
+    }
+
+    pub fn keys(&self) -> &[u8] {
+        &self.last
+    }
+}
+
+/// a reader that records the keys read from it in [`Repeat`]
+struct Recorder<'a, R> {
+    inner: &'a mut R,
+    typed: &'a RefCell<Vec<u8>>,
+}
+
+impl<R: Read> Read for Recorder<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.typed.borrow_mut().extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: KeyReader> KeyReader for Recorder<'_, R> {
+    fn sequence_pending(&mut self) -> bool {
+        self.inner.sequence_pending()
+    }
+}
+
+/// what can be typed after `keys` in normal mode, as the next key and a description of what it
+/// does. The description starts with `+` if more keys are needed after it.
+pub fn continuations(keys: &[char]) -> Vec<(String, String)> {
+    syn::continuations(keys)
+}
+
+/// keys for [`handle_input`]
+pub trait KeyReader: Read {
+    /// whether the rest of an escape sequence can be read without waiting, to tell the escape key
+    /// from keys like the arrows
+    fn sequence_pending(&mut self) -> bool;
+}
+
 fn read_char(reader: &mut impl Read) -> Option<char> {
     let mut buf = [0u8];
     reader.read_exact(&mut buf).ok()?;
     let c = char::try_from(buf[0]).ok()?;
-    if c == '\x03' {
-        crate::exit();
-        return None;
-    }
     // log!("read: {c:?}");
     Some(c)
 }
 
-pub fn handle_input(ctx: &Ctx, reader: &mut impl Read) -> Option<Action> {
+/// keys that only mean something in insert mode
+fn insert_mode_key(c: char, reader: &mut impl KeyReader) -> Option<Operation> {
+    Some(match c {
+        '\x17' => Operation::DeleteWordBefore,
+        '\x15' => Operation::DeleteLineBefore,
+        '\x0f' => Operation::NormalOnce,
+        '\x12' => Operation::InsertRegister(read_char(reader)?),
+        _ => return None,
+    })
+}
+
+/// keys that only mean something on the command line
+fn command_line_key(c: char, reader: &mut impl KeyReader) -> Option<Operation> {
+    let input = match c {
+        '\x17' => CommandLineInput::DeleteWord,
+        '\x15' => CommandLineInput::DeleteLine,
+        '\x02' => CommandLineInput::Home,
+        '\x05' => CommandLineInput::End,
+        '\x12' => return Some(Operation::InsertRegister(read_char(reader)?)),
+        '\x1b' if reader.sequence_pending() => {
+            let mut seq = String::new();
+            loop {
+                let c = read_char(reader)?;
+                seq.push(c);
+                // the sequence ends with a letter or `~`, after the leading `[` or `O`
+                if seq.len() > 1 && (c.is_ascii_alphabetic() || c == '~') {
+                    break;
+                }
+            }
+            match &seq[..] {
+                "[D" | "OD" => CommandLineInput::Left,
+                "[C" | "OC" => CommandLineInput::Right,
+                "[H" | "OH" | "[1~" => CommandLineInput::Home,
+                "[F" | "OF" | "[4~" => CommandLineInput::End,
+                "[3~" => CommandLineInput::DeleteAfter,
+                "[Z" => return Some(Operation::CompletePrev),
+                _ => return Some(Operation::None),
+            }
+        }
+        _ => return None,
+    };
+    Some(Operation::CommandLine(input))
+}
+
+pub fn handle_input(ctx: &Ctx, reader: &mut impl KeyReader) -> Option<Action> {
+    let start = ctx.repeat.typed.borrow().len();
+    let action = read_action(ctx, &mut Recorder { inner: reader, typed: &ctx.repeat.typed });
+    // only a Ctrl-c right after another one leaves the editor
+    if ctx.repeat.typed.borrow()[start..] != [b'\x03'] {
+        ctx.last_interrupt.set(None);
+    }
+    // keys that didn't make a command, like an escape that cancelled one, aren't part of a change
+    if action.is_none() && ctx.mode == Mode::Normal && ctx.pending.0.borrow().is_empty() {
+        ctx.repeat.typed.borrow_mut().clear();
+    }
+    action
+}
+
+fn read_action(ctx: &Ctx, reader: &mut impl KeyReader) -> Option<Action> {
     match ctx.mode {
-        Mode::Normal => syn::parse_normal_command(reader),
+        Mode::Normal => {
+            let c = read_char(reader)?;
+            let mut keys = ctx.pending.0.borrow_mut();
+            if ctx.pager && keys.is_empty() {
+                // keys of `less`, everything else is a normal command
+                match c {
+                    'q' => {
+                        crate::exit();
+                        return None;
+                    }
+                    ' ' => return Some(Operation::PageDown.into()),
+                    'b' => return Some(Operation::PageUp.into()),
+                    _ => (),
+                }
+            }
+            // Ctrl-c cancels a command that is waiting for more keys, without one it interrupts
+            // whatever else is going on. Ctrl-\ leaves right away, for when nothing else works.
+            if c == '\x03' && keys.is_empty() {
+                crate::event::post(crate::event::Event::Interrupt);
+                return None;
+            }
+            if c == '\x1c' && keys.is_empty() {
+                crate::exit();
+                return None;
+            }
+            if c == '\x1b' || c == '\x03' {
+                keys.clear();
+                return None;
+            }
+            keys.push(c);
+            match syn::parse_keys(&keys) {
+                syn::Parse::Done(action) => {
+                    keys.clear();
+                    Some(action)
+                }
+                syn::Parse::Pending { .. } => None,
+                syn::Parse::Invalid => {
+                    keys.clear();
+                    None
+                }
+            }
+        }
         Mode::Insert | Mode::Command => Some({
             let c = read_char(reader)?;
             // log!("{:x}", c as u32);
+            let special = match ctx.mode {
+                Mode::Command => command_line_key(c, reader),
+                _ => insert_mode_key(c, reader),
+            };
+            if let Some(op) = special {
+                return Some(op.into());
+            }
             match c {
-                '\x03' => {
-                    crate::exit();
-                    return None;
-                }
-                '\x1b' => Action {
+                '\x03' | '\x1b' => Action {
                     // escape key, this needs to be more sophisticated for pasting
                     operation: Operation::SwitchMode(Mode::Normal),
                     ..Action::new()
@@ -99,12 +420,36 @@ pub fn handle_input(ctx: &Ctx, reader: &mut impl Read) -> Option<Action> {
                 },
             }
         }),
+        Mode::Terminal => {
+            let mut bytes = [0; 64];
+            let n = reader.read(&mut bytes).ok()?;
+            let bytes = &bytes[..n];
+            // Ctrl-\ Ctrl-n leaves terminal mode, like in vim
+            if bytes.first() == Some(&0x1c) {
+                let next = match bytes.get(1) {
+                    Some(&b) => b,
+                    None => {
+                        let mut b = [0];
+                        reader.read_exact(&mut b).ok()?;
+                        b[0]
+                    }
+                };
+                if next == 0x0e {
+                    return Some(Operation::SwitchMode(Mode::Normal).into());
+                }
+                let mut out = bytes.to_vec();
+                if bytes.len() == 1 {
+                    out.push(next);
+                }
+                return Some(Operation::TerminalInput(out).into());
+            }
+            Some(Operation::TerminalInput(bytes.to_vec()).into())
+        }
     }
 }
 
 /// syntax and structure of commands
 mod syn {
-    use super::read_char;
     use crate::textobj;
     use textobj::motions;
 
@@ -112,22 +457,13 @@ mod syn {
     use super::Mode;
     use super::Motion;
     use super::Operation;
-    use super::Read;
-
-    fn is_motion_start(c: char) -> bool {
-        let mots = load_motions();
-        for def in mots {
-            if def.comps[0] == CommComp::Char(c) {
-                return true;
-            }
-        }
-        false
-    }
 
-    #[derive(PartialEq, Eq, Debug)]
+    #[derive(PartialEq, Eq, Debug, Clone, Copy)]
     enum CommComp {
         Char(char),
         Motion,
+        /// any key, given to the operation by [`with_chars`]
+        Any,
     }
 
     #[derive(Debug)]
@@ -145,102 +481,197 @@ mod syn {
         action: Action,
     }
 
-    fn parse_motion(first: char, reader: &mut impl Read) -> Option<Motion> {
-        let mut defs: Vec<_> = load_motions()
-            .into_iter()
-            .filter(|d| d.comps[0] == CommComp::Char(first))
-            .collect();
-        let mut idx = 0;
-        let mut rem = vec![];
-        while !defs.is_empty() {
-            let c = if idx != 0 { read_char(reader)? } else { first };
-            for (i, CommDef { comps, .. }) in defs.iter().enumerate() {
-                match &comps[idx] {
-                    CommComp::Char(xc) if c == *xc => {
-                        if idx == comps.len() - 1 {
-                            return Some(
-                                defs.swap_remove(i)
-                                    .action
-                                    .motion
-                                    .expect("motion has motion"),
-                            );
-                        }
-                    }
-                    CommComp::Motion => {
-                        panic!("motion token in motion")
-                    }
-                    _ => rem.push(i),
-                };
-            }
-            if rem.len() == defs.len() {
-                return None;
+    /// what the keys typed so far make
+    #[derive(PartialEq, Eq, Debug)]
+    pub(super) enum Parse<T> {
+        Done(T),
+        /// more keys are needed, `operator` if an operator is waiting for its motion
+        Pending { operator: bool },
+        Invalid,
+    }
+
+    /// `keys` against a definition of only keys
+    fn match_chars(comps: &[CommComp], keys: &[char]) -> Parse<()> {
+        if keys.len() > comps.len() {
+            return Parse::Invalid;
+        }
+        for (comp, c) in comps.iter().zip(keys) {
+            match comp {
+                CommComp::Char(xc) if xc == c => (),
+                CommComp::Char(_) => return Parse::Invalid,
+                CommComp::Any => (),
+                CommComp::Motion => panic!("motion token in motion"),
             }
-            for i in rem.iter().rev() {
-                defs.swap_remove(*i);
+        }
+        if keys.len() == comps.len() {
+            Parse::Done(())
+        } else {
+            Parse::Pending { operator: false }
+        }
+    }
+
+    /// the first definition that `keys` complete, or whether any of them could still be completed
+    fn first_done<T>(parses: impl Iterator<Item = Parse<T>>) -> Parse<T> {
+        let mut res = Parse::Invalid;
+        for parse in parses {
+            match parse {
+                Parse::Done(_) => return parse,
+                Parse::Pending { operator } => {
+                    let operator = operator || matches!(res, Parse::Pending { operator: true });
+                    res = Parse::Pending { operator };
+                }
+                Parse::Invalid => (),
             }
-            rem.clear();
-            idx += 1;
         }
-        None
+        res
     }
 
-    pub(super) fn parse_normal_command(reader: &mut impl Read) -> Option<super::Action> {
-        let mut idx = 0;
-        let mut defs: Vec<_> = load_comps()
-            .into_iter()
-            .filter(|d| !matches!(d.ctype, CommType::TextObject))
-            .collect();
-        let mut rem = vec![];
-        loop {
-            let c = read_char(reader)?;
-            let maybe_motion = is_motion_start(c);
-            for (i, CommDef { comps, .. }) in defs.iter().enumerate() {
-                // if comps.len() == idx && !matches!(comps.last(), Some(CommComp::Motion)) {
-                //     assert_ne!(comps.last(), Some(&CommComp::Motion));
-                //     return Some(defs.swap_remove(i).action);
-                // }
-                match &comps[idx] {
-                    CommComp::Char(xc) if c == *xc => {
-                        if comps.len() == idx + 1 {
-                            return Some(defs.swap_remove(i).action);
-                        }
-                    }
-                    CommComp::Motion if maybe_motion => {
-                        assert_eq!(
-                            defs.len() - rem.len(),
-                            1,
-                            "motion command should imply only possibility"
-                        );
-                        let base = defs.swap_remove(i);
-                        assert!(
-                            base.action.motion.is_none(),
-                            "commands with motion should not include motion"
-                        );
-                        return Some(Action {
-                            motion: Some(parse_motion(c, reader)?),
-                            ..base.action
-                        });
-                    }
-                    _ => rem.push(i),
-                };
+    fn parse_motion(keys: &[char]) -> Parse<Motion> {
+        first_done(load_motions().into_iter().map(|def| match match_chars(&def.comps, keys) {
+            Parse::Done(()) => Parse::Done(def.action.motion.expect("motion has motion")),
+            Parse::Pending { operator } => Parse::Pending { operator },
+            Parse::Invalid => Parse::Invalid,
+        }))
+    }
+
+    /// `action` with the keys typed for the `{char}`s of its command
+    fn with_chars(action: Action, chars: &[char]) -> Action {
+        let operation = match (action.operation, chars) {
+            (Operation::Surround(_), &[c]) => Operation::Surround(c),
+            (Operation::ChangeSurround { .. }, &[old, new]) => Operation::ChangeSurround { old, new },
+            (Operation::DeleteSurround(_), &[c]) => Operation::DeleteSurround(c),
+            (Operation::ExecuteRegister(_), &[c]) => Operation::ExecuteRegister(c),
+            (operation, _) => operation,
+        };
+        Action { operation, ..action }
+    }
+
+    /// the keys in `keys` that are typed for `{char}`s
+    fn any_chars(comps: &[CommComp], keys: &[char]) -> Vec<char> {
+        comps.iter().zip(keys).filter(|(comp, _)| **comp == CommComp::Any).map(|(_, c)| *c).collect()
+    }
+
+    /// `keys` against one normal mode command
+    fn parse_def(def: CommDef, keys: &[char]) -> Parse<Action> {
+        let Some(motion_idx) = def.comps.iter().position(|c| *c == CommComp::Motion) else {
+            return match match_chars(&def.comps, keys) {
+                Parse::Done(()) => Parse::Done(with_chars(def.action, &any_chars(&def.comps, keys))),
+                Parse::Pending { operator } => Parse::Pending { operator },
+                Parse::Invalid => Parse::Invalid,
+            };
+        };
+        let (lead, motion) = keys.split_at(motion_idx.min(keys.len()));
+        match match_chars(&def.comps[..motion_idx], lead) {
+            Parse::Done(()) => (),
+            Parse::Pending { operator } => return Parse::Pending { operator },
+            Parse::Invalid => return Parse::Invalid,
+        }
+        if motion.is_empty() {
+            return Parse::Pending { operator: true };
+        }
+        assert!(def.action.motion.is_none(), "commands with motion should not include motion");
+        // the motion ends at the first key that completes it, the keys after it are for `{char}`s
+        let trailing = &def.comps[motion_idx + 1..];
+        for end in 1..=motion.len() {
+            match parse_motion(&motion[..end]) {
+                Parse::Done(parsed) => {
+                    let rest = &motion[end..];
+                    return match match_chars(trailing, rest) {
+                        Parse::Done(()) => Parse::Done(Action {
+                            motion: Some(parsed),
+                            ..with_chars(def.action, &any_chars(trailing, rest))
+                        }),
+                        Parse::Pending { .. } => Parse::Pending { operator: false },
+                        Parse::Invalid => Parse::Invalid,
+                    };
+                }
+                Parse::Pending { .. } => (),
+                Parse::Invalid => return Parse::Invalid,
             }
-            if rem.len() == defs.len() {
-                return None;
+        }
+        Parse::Pending { operator: true }
+    }
+
+    /// the normal mode command that `keys` make so far
+    pub(super) fn parse_keys(keys: &[char]) -> Parse<Action> {
+        if let ['"', rest @ ..] = keys {
+            return match rest {
+                [] => Parse::Pending { operator: false },
+                [register, rest @ ..] => match parse_keys(rest) {
+                    Parse::Done(action) => Parse::Done(Action { register: Some(*register), ..action }),
+                    Parse::Pending { operator } => Parse::Pending { operator },
+                    Parse::Invalid => Parse::Invalid,
+                },
+            };
+        }
+        let defs = load_comps().into_iter().filter(|d| !matches!(d.ctype, CommType::TextObject));
+        first_done(defs.map(|def| parse_def(def, keys)))
+    }
+
+    /// the next keys after `keys` in `comps`, as the key (`None` for a `{char}`), the name of the
+    /// command and whether it's complete with that key. `{motion}` can be any motion or text
+    /// object, which names the command from there on.
+    fn next_keys(
+        comps: &[CommComp],
+        keys: &[char],
+        name: &'static str,
+        motions: &[CommDef],
+        out: &mut Vec<(Option<char>, &'static str, bool)>,
+    ) {
+        let Some((first, rest)) = comps.split_first() else {
+            return;
+        };
+        match (first, keys.split_first()) {
+            (CommComp::Motion, _) => {
+                for motion in motions {
+                    let comps: Vec<_> = motion.comps.iter().chain(rest).copied().collect();
+                    next_keys(&comps, keys, motion.name, motions, out);
+                }
             }
-            for i in rem.iter().rev() {
-                defs.swap_remove(*i);
+            (CommComp::Char(c), None) => out.push((Some(*c), name, rest.is_empty())),
+            (CommComp::Any, None) => out.push((None, name, rest.is_empty())),
+            (CommComp::Char(c), Some((k, keys))) if c == k => next_keys(rest, keys, name, motions, out),
+            (CommComp::Char(_), Some(_)) => (),
+            (CommComp::Any, Some((_, keys))) => next_keys(rest, keys, name, motions, out),
+        }
+    }
+
+    /// see [`super::continuations`]
+    pub(super) fn continuations(keys: &[char]) -> Vec<(String, String)> {
+        if let ['"', rest @ ..] = keys {
+            return match rest {
+                [] => vec![("{char}".into(), "+register".into())],
+                [_, rest @ ..] => continuations(rest),
+            };
+        }
+        let motions = load_motions();
+        let mut next = Vec::new();
+        for def in load_comps().into_iter().filter(|d| !matches!(d.ctype, CommType::TextObject)) {
+            next_keys(&def.comps, keys, def.name, &motions, &mut next);
+        }
+        let mut by_key: std::collections::BTreeMap<Option<char>, Vec<(&str, bool)>> = Default::default();
+        for (key, name, done) in next {
+            let names = by_key.entry(key).or_default();
+            if !names.contains(&(name, done)) {
+                names.push((name, done));
             }
-            rem.clear();
-            idx += 1;
         }
-        // match defs.len() {
-        //     0 => None,
-        //     1 => {
-        //         assert!(!matches!(defs[0].comps.last().unwrap(), CommComp::Motion) || defs[0].action.motion.is_some());
-        //         Some(defs.swap_remove(0).action)
-        //     },
-        //     _ => unreachable!()
-        // }
+        by_key
+            .into_iter()
+            .map(|(key, names)| {
+                let key = match key {
+                    Some(c) => crate::keymap::key_notation(c.to_string().as_bytes()),
+                    None => "{char}".into(),
+                };
+                let desc = match &names[..] {
+                    [(name, true)] => name.replace('_', " "),
+                    [(name, false)] => format!("+{}", name.replace('_', " ")),
+                    [(name, _), more @ ..] => format!("+{} and {} more", name.replace('_', " "), more.len()),
+                    [] => unreachable!("every key has a name"),
+                };
+                (key, desc)
+            })
+            .collect()
     }
 
     macro_rules! commdef {
@@ -277,8 +708,13 @@ mod syn {
             $v.push(CommComp::Char($next));
             commdef!(@pseq $v @ $($rem)*);
         };
-        (@pseq $v:ident @ {motion}) => {
+        (@pseq $v:ident @ {motion} $($rem:tt)*) => {
             $v.push(CommComp::Motion);
+            commdef!(@pseq $v @ $($rem)*);
+        };
+        (@pseq $v:ident @ {char} $($rem:tt)*) => {
+            $v.push(CommComp::Any);
+            commdef!(@pseq $v @ $($rem)*);
         };
         (@pseq $v:ident @ ) => { };
     }
@@ -296,10 +732,24 @@ mod syn {
             ..Action::new()
         },
         ex: Normal = (':') => Operation::SwitchMode(Mode::Command),
-        debug: Normal = ('p') => Operation::Debug,
+        put: Normal = ('p') => Operation::Put { before: false, indent: false },
+        put_before: Normal = ('P') => Operation::Put { before: true, indent: false },
+        undo: Normal = ('u') => Operation::Undo,
+        redo: Normal = ('\x12') => Operation::Redo,
 
         change: Normal = ('c' {motion}) => Operation::Change,
         delete: Normal = ('d' {motion}) => Operation::Delete,
+        format: Normal = ('g' 'q' {motion}) => Operation::Format,
+        dump_screen: Normal = ('g' '\x04') => Operation::DumpScreen,
+        format_line: Normal = ('g' 'q' 'q') => Operation::Format,
+        surround: Normal = ('y' 's' {motion} {char}) => Operation::Surround(' '),
+        surround_line: Normal = ('y' 's' 's' {char}) => Operation::Surround(' '),
+        change_surround: Normal = ('c' 's' {char} {char}) => Operation::ChangeSurround { old: ' ', new: ' ' },
+        delete_surround: Normal = ('d' 's' {char}) => Operation::DeleteSurround(' '),
+        command_window: Normal = ('q' ':') => Operation::CommandWindow,
+        execute_register: Normal = ('@' {char}) => Operation::ExecuteRegister(' '),
+        enter: Normal = ('\r') => Operation::Enter,
+        repeat_change: Normal = ('.') => Operation::RepeatChange,
 
 
         left: Motion = ('h') => Motion::ScreenSpace { dy: 0, dx: -1 },
@@ -308,6 +758,27 @@ mod syn {
         right: Motion = ('l') => Motion::ScreenSpace { dy: 0, dx: 1 },
 
         recenter: Normal = ('z' 'z') => Operation::RecenterView,
+        goto_definition: Normal = ('g' 'd') => Operation::GotoDefinition,
+        goto_file: Normal = ('g' 'f') => Operation::GotoFile,
+        open_external: Normal = ('g' 'x') => Operation::OpenExternal,
+        hover: Normal = ('K') => Operation::Hover,
+        edit_alternate: Normal = ('\x1e') => Operation::EditAlternate,
+        next_window: Normal = ('\x17' 'w') => Operation::NextWindow,
+        next_window: Normal = ('\x17' '\x17') => Operation::NextWindow,
+        search:          Normal = ('/') => Operation::StartSearch { backward: false },
+        search_backward: Normal = ('?') => Operation::StartSearch { backward: true },
+        search_next:     Normal = ('n') => Operation::SearchNext { reverse: false },
+        search_prev:     Normal = ('N') => Operation::SearchNext { reverse: true },
+        page_down:       Normal = ('\x06') => Operation::PageDown,
+        page_up:         Normal = ('\x02') => Operation::PageUp,
+        next_hunk: Normal = (']' 'c') => Operation::NextHunk,
+        prev_hunk: Normal = ('[' 'c') => Operation::PrevHunk,
+        put_indented:        Normal = (']' 'p') => Operation::Put { before: false, indent: true },
+        put_indented_before: Normal = ('[' 'p') => Operation::Put { before: true, indent: true },
+        put_indented_before: Normal = ('[' 'P') => Operation::Put { before: true, indent: true },
+        put_indented_before: Normal = (']' 'P') => Operation::Put { before: true, indent: true },
+        diff_put: Normal = ('d' 'p') => Operation::DiffPut,
+        diff_get: Normal = ('d' 'o') => Operation::DiffGet,
 
         inner_word:      TextObject = ('i' 'w') => Motion::TextObj(textobj::inner_word),
         a_word:          TextObject = ('a' 'w') => Motion::TextObj(textobj::a_word),
@@ -337,6 +808,12 @@ mod syn {
         a_tick:          TextObject = ('a' '\'') => Motion::TextObj(textobj::a_tick),
         inner_backtick:  TextObject = ('i' '`') => Motion::TextObj(textobj::inner_backtick),
         a_backtick:      TextObject = ('a' '`') => Motion::TextObj(textobj::a_backtick),
+        a_function:      TextObject = ('a' 'f') => Motion::TextObj(textobj::a_function),
+        inner_function:  TextObject = ('i' 'f') => Motion::TextObj(textobj::inner_function),
+        a_class:         TextObject = ('a' 'c') => Motion::TextObj(textobj::a_class),
+        inner_class:     TextObject = ('i' 'c') => Motion::TextObj(textobj::inner_class),
+        inner_indent:    TextObject = ('i' 'i') => Motion::TextObj(textobj::inner_indent),
+        an_indent:       TextObject = ('a' 'i') => Motion::TextObj(textobj::an_indent),
 
         word_subset_backward:    Motion = ('b') => Motion::TextMotion(motions::word_subset_backward),
         word_backward:           Motion = ('B') => Motion::TextMotion(motions::word_backward),
@@ -354,6 +831,21 @@ mod syn {
     #[cfg(test)]
     mod test {
         use super::*;
+        use crate::input::read_char;
+        use std::io::Read;
+
+        /// read a whole normal mode command from `reader`
+        fn parse_normal_command(reader: &mut impl Read) -> Option<Action> {
+            let mut keys = Vec::new();
+            loop {
+                keys.push(read_char(reader)?);
+                match parse_keys(&keys) {
+                    Parse::Done(action) => return Some(action),
+                    Parse::Pending { .. } => (),
+                    Parse::Invalid => return None,
+                }
+            }
+        }
 
         macro_rules! input_test {
             ($name:ident, $input:literal => match $expected:pat) => {
@@ -385,6 +877,30 @@ mod syn {
             };
         }
 
+        #[test]
+        fn continuations_of_prefix() {
+            let next = continuations(&['g']);
+            assert!(next.contains(&("d".into(), "goto definition".into())));
+            assert!(next.contains(&("q".into(), "+format and 1 more".into())));
+            assert!(next.contains(&("<C-d>".into(), "dump screen".into())));
+            let next = continuations(&['c', 's']);
+            assert_eq!(next, [("{char}".into(), "+change surround".into())]);
+            let next = continuations(&['d']);
+            assert!(next.contains(&("w".into(), "word subset forward".into())));
+            assert!(next.contains(&("p".into(), "diff put".into())));
+            assert!(continuations(&['x']).is_empty());
+            assert_eq!(continuations(&['"']), [("{char}".into(), "+register".into())]);
+        }
+
+        #[test]
+        fn operator_pending() {
+            assert_eq!(parse_keys(&['d']), Parse::Pending { operator: true });
+            assert_eq!(parse_keys(&['c', 'i']), Parse::Pending { operator: true });
+            assert_eq!(parse_keys(&['g']), Parse::Pending { operator: false });
+            assert_eq!(parse_keys(&['c', 'Q']), Parse::Invalid);
+            assert_eq!(parse_keys(&['y', 's', 'w']), Parse::Pending { operator: false });
+        }
+
         input_test!(single_normal, "i" => Operation::SwitchMode(Mode::Insert));
         input_test!(single_normal_extra, "iXXXX" => Operation::SwitchMode(Mode::Insert));
         input_test!(single_motion, "h" => Motion::ScreenSpace{ dy: 0, dx: -1 });
@@ -394,5 +910,25 @@ mod syn {
             match Action { motion: Some(Motion::TextObj(_)), operation: Operation::Change, ..});
         input_test!(single_with_motion, "ch" => 
             match Action { motion: Some(Motion::ScreenSpace{..}), operation: Operation::Change, ..});
+        input_test!(goto_definition, "gd" => Operation::GotoDefinition);
+        input_test!(diff_put_shares_operator, "dp" => Operation::DiffPut);
+        input_test!(delete_with_diff_commands, "dw" =>
+            match Action { motion: Some(Motion::TextMotion(_)), operation: Operation::Delete, ..});
+        input_test!(next_hunk, "]c" => Operation::NextHunk);
+        input_test!(command_window, "q:" => Operation::CommandWindow);
+        input_test!(repeat_command_line, "@:" => Operation::ExecuteRegister(':'));
+        input_test!(execute_last_register, "@@" => Operation::ExecuteRegister('@'));
+        input_test!(put_from_register, "\"2p" =>
+            match Action { operation: Operation::Put { before: false, indent: false }, register: Some('2'), ..});
+        input_test!(put_indented, "[p" => Operation::Put { before: true, indent: true });
+        input_test!(delete_into_register, "\"adw" =>
+            match Action { operation: Operation::Delete, register: Some('a'), ..});
+        input_test!(surround_text_object, "ysiw)" =>
+            match Action { motion: Some(Motion::TextObj(_)), operation: Operation::Surround(')'), ..});
+        input_test!(surround_line, "yss\"" => Operation::Surround('"'));
+        input_test!(change_surround, "cs'\"" => Operation::ChangeSurround { old: '\'', new: '"' });
+        input_test!(delete_surround, "dsb" => Operation::DeleteSurround('b'));
+        input_test!(start_of_buffer_shares_prefix, "gg" => 
+            match Action { motion: Some(Motion::TextMotion(_)), operation: Operation::None, ..});
     }
 }
@@ -0,0 +1,298 @@
+//! Asynchronous external commands.
+//!
+//! Every job is run through `sh -c` with its stdout and stderr read line by line on their own
+//! threads. Lines are sent back over a channel that the main loop drains with [`poll`], so a job
+//! never blocks the editor. What happens to the output is decided by the job's [`Sink`].
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+
+use crate::buffer::Buffer;
+use crate::progress::TaskId;
+use crate::utils::unit_err;
+
+unit_err!(NoSuchJob: "no such job");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct JobId(u64);
+
+impl JobId {
+    fn new() -> Self {
+        static JOB_ID_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(1);
+        JobId(JOB_ID_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed))
+    }
+
+    pub fn id(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for JobId {
+    fn from(value: u64) -> Self {
+        JobId(value)
+    }
+}
+
+impl std::fmt::Display for JobId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// where the output of a job goes
+#[derive(Clone)]
+pub enum Sink {
+    /// append every line to a buffer
+    Buffer(Arc<Buffer>),
+    /// call the Guile procedures registered for the job
+    Guile,
+    /// collect the output for the quickfix list, used by `:make`
+    Quickfix,
+    /// collect the output for [`crate::lint`]
+    Lint,
+    /// collect the output for [`crate::blame`]
+    Blame,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JobEventKind {
+    /// a line of output, without the trailing newline
+    Line(Stream, String),
+    /// the job exited. The code is `None` if it was killed by a signal.
+    Exited(Option<i32>),
+}
+
+pub struct JobEvent {
+    pub id: JobId,
+    pub sink: Sink,
+    pub kind: JobEventKind,
+}
+
+enum Incoming {
+    Line(JobId, Stream, String),
+    Closed(JobId),
+}
+
+struct Job {
+    cmd: String,
+    child: Child,
+    sink: Sink,
+    /// number of output streams still being read
+    open: u8,
+    /// shown in the status line while it runs
+    task: TaskId,
+}
+
+struct Table {
+    jobs: BTreeMap<JobId, Job>,
+    tx: mpsc::Sender<Incoming>,
+    rx: mpsc::Receiver<Incoming>,
+}
+
+fn table() -> &'static Mutex<Table> {
+    static TABLE: OnceLock<Mutex<Table>> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let (tx, rx) = mpsc::channel();
+        Mutex::new(Table {
+            jobs: BTreeMap::new(),
+            tx,
+            rx,
+        })
+    })
+}
+
+fn spawn_reader(id: JobId, stream: Stream, r: impl Read + Send + 'static, tx: mpsc::Sender<Incoming>) {
+    std::thread::spawn(move || {
+        let mut r = BufReader::new(r);
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            match r.read_until(b'\n', &mut line) {
+                Ok(0) | Err(_) => break,
+                Ok(_) => {
+                    if line.last() == Some(&b'\n') {
+                        line.pop();
+                    }
+                    let s = String::from_utf8_lossy(&line).into_owned();
+                    if tx.send(Incoming::Line(id, stream, s)).is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+        let _ = tx.send(Incoming::Closed(id));
+    });
+}
+
+/// start `cmd` as a shell command. The job gets its own process group so that killing it also
+/// kills anything the shell started.
+pub fn start(cmd: &str, sink: Sink) -> std::io::Result<JobId> {
+    use std::os::unix::process::CommandExt;
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .process_group(0)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let id = JobId::new();
+    let mut table = table().lock().unwrap();
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    spawn_reader(id, Stream::Stdout, stdout, table.tx.clone());
+    spawn_reader(id, Stream::Stderr, stderr, table.tx.clone());
+    table.jobs.insert(
+        id,
+        Job {
+            cmd: cmd.to_owned(),
+            child,
+            sink,
+            open: 2,
+            task: crate::progress::start(cmd),
+        },
+    );
+    Ok(id)
+}
+
+/// `path` as a single word of a shell command
+pub fn quote(path: &std::path::Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// kill a running job. Its exit is still reported through [`poll`].
+pub fn kill(id: JobId) -> Result<(), NoSuchJob> {
+    let table = table().lock().unwrap();
+    let job = table.jobs.get(&id).ok_or(NoSuchJob)?;
+    let pgid = nix::unistd::Pid::from_raw(job.child.id() as i32);
+    let _ = nix::sys::signal::killpg(pgid, nix::sys::signal::Signal::SIGKILL);
+    Ok(())
+}
+
+/// ids and commands of every running job
+pub fn list() -> Vec<(JobId, String)> {
+    let table = table().lock().unwrap();
+    table
+        .jobs
+        .iter()
+        .map(|(id, job)| (*id, job.cmd.clone()))
+        .collect()
+}
+
+/// everything that happened since the last call. Exited jobs are reaped here.
+pub fn poll() -> Vec<JobEvent> {
+    let mut table = table().lock().unwrap();
+    let mut events = Vec::new();
+    while let Ok(msg) = table.rx.try_recv() {
+        match msg {
+            Incoming::Line(id, stream, line) => {
+                let Some(job) = table.jobs.get(&id) else {
+                    continue;
+                };
+                events.push(JobEvent {
+                    id,
+                    sink: job.sink.clone(),
+                    kind: JobEventKind::Line(stream, line),
+                });
+            }
+            Incoming::Closed(id) => {
+                let Some(job) = table.jobs.get_mut(&id) else {
+                    continue;
+                };
+                job.open -= 1;
+                if job.open > 0 {
+                    continue;
+                }
+                // both pipes are closed so the process is exiting, this won't block for long
+                let mut job = table.jobs.remove(&id).expect("checked above");
+                let code = job.child.wait().ok().and_then(|s| s.code());
+                crate::progress::finish(job.task);
+                events.push(JobEvent {
+                    id,
+                    sink: job.sink,
+                    kind: JobEventKind::Exited(code),
+                });
+            }
+        }
+    }
+    events
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// jobs share one channel, so tests polling concurrently would steal each other's events
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    /// poll until `id` exits, returning all of its events
+    fn run(id: JobId) -> Vec<JobEventKind> {
+        let mut out = Vec::new();
+        for _ in 0..500 {
+            for ev in poll() {
+                if ev.id == id {
+                    let done = matches!(ev.kind, JobEventKind::Exited(_));
+                    out.push(ev.kind);
+                    if done {
+                        return out;
+                    }
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("job did not exit");
+    }
+
+    #[test]
+    fn output_and_exit_code() {
+        let _guard = SERIAL.lock().unwrap();
+        let id = start("echo out; echo err >&2; exit 3", Sink::Guile).unwrap();
+        let events = run(id);
+        assert!(events.contains(&JobEventKind::Line(Stream::Stdout, "out".into())));
+        assert!(events.contains(&JobEventKind::Line(Stream::Stderr, "err".into())));
+        assert_eq!(events.last(), Some(&JobEventKind::Exited(Some(3))));
+        assert!(list().iter().all(|(j, _)| *j != id));
+    }
+
+    #[test]
+    fn kill_job() {
+        let _guard = SERIAL.lock().unwrap();
+        let id = start("sleep 10; echo done", Sink::Guile).unwrap();
+        assert!(list().iter().any(|(j, _)| *j == id));
+        kill(id).unwrap();
+        assert_eq!(run(id), vec![JobEventKind::Exited(None)]);
+        assert!(kill(id).is_err());
+    }
+
+    #[test]
+    fn interrupt() {
+        use crate::testing::{editor, type_keys};
+        let _guard = SERIAL.lock().unwrap();
+        let mut ctx = editor("", 30, 8);
+        let leave = "Type :qa and press Enter to leave, or Ctrl-c again to leave without saving";
+        // running jobs are killed, which doesn't count towards leaving
+        let id = start("sleep 10", Sink::Guile).unwrap();
+        ctx.interrupt();
+        assert_eq!(ctx.take_message().unwrap().0, "interrupted 1 job");
+        assert_eq!(run(id), vec![JobEventKind::Exited(None)]);
+        ctx.interrupt();
+        assert_eq!(ctx.take_message().unwrap().0, leave);
+        // the next Ctrl-c leaves, with no message
+        type_keys(&mut ctx, "\x03");
+        ctx.interrupt();
+        assert_eq!(ctx.take_message(), None);
+        // but not after another key
+        ctx.interrupt();
+        assert_eq!(ctx.take_message().unwrap().0, leave);
+        type_keys(&mut ctx, "l");
+        ctx.interrupt();
+        assert_eq!(ctx.take_message().unwrap().0, leave);
+    }
+}
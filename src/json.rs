@@ -0,0 +1,360 @@
+//! Minimal JSON reading and writing. This is just enough to speak JSON-RPC with language servers
+//! and read the machine-readable output of tools - I'd rather not pull in serde for that.
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::utils::unit_err;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(BTreeMap<String, Value>),
+}
+
+unit_err!(ParseError: "invalid json");
+
+impl Value {
+    /// build an object from key-value pairs
+    pub fn object<'a>(pairs: impl IntoIterator<Item = (&'a str, Value)>) -> Self {
+        Value::Object(pairs.into_iter().map(|(k, v)| (k.to_owned(), v)).collect())
+    }
+
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let mut p = Parser { s: s.as_bytes(), idx: 0 };
+        let v = p.value()?;
+        p.ws();
+        if p.idx != p.s.len() {
+            return Err(ParseError);
+        }
+        Ok(v)
+    }
+
+    /// index into an object, `None` if this is not an object or the key is missing
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(m) => m.get(key),
+            _ => None,
+        }
+    }
+
+    /// walk a path of object keys
+    pub fn path(&self, keys: &[&str]) -> Option<&Value> {
+        keys.iter().try_fold(self, |v, k| v.get(k))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        self.as_u64().map(|n| n as usize)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(a) => Some(a),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::String(value.to_owned())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::String(value)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(value: bool) -> Self {
+        Value::Bool(value)
+    }
+}
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<i64> for Value {
+    fn from(value: i64) -> Self {
+        Value::Number(value as f64)
+    }
+}
+
+impl From<Vec<Value>> for Value {
+    fn from(value: Vec<Value>) -> Self {
+        Value::Array(value)
+    }
+}
+
+fn write_escaped(f: &mut std::fmt::Formatter<'_>, s: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => f.write_str("null"),
+            Value::Bool(b) => write!(f, "{b}"),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Number(n) => write!(f, "{n}"),
+            Value::String(s) => write_escaped(f, s),
+            Value::Array(a) => {
+                f.write_char('[')?;
+                for (i, v) in a.iter().enumerate() {
+                    if i != 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{v}")?;
+                }
+                f.write_char(']')
+            }
+            Value::Object(m) => {
+                f.write_char('{')?;
+                for (i, (k, v)) in m.iter().enumerate() {
+                    if i != 0 {
+                        f.write_char(',')?;
+                    }
+                    write_escaped(f, k)?;
+                    write!(f, ":{v}")?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    s: &'a [u8],
+    idx: usize,
+}
+
+impl Parser<'_> {
+    fn ws(&mut self) {
+        while self.s.get(self.idx).is_some_and(|c| c.is_ascii_whitespace()) {
+            self.idx += 1;
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.idx).copied()
+    }
+
+    fn expect(&mut self, lit: &str) -> Result<(), ParseError> {
+        if self.s[self.idx..].starts_with(lit.as_bytes()) {
+            self.idx += lit.len();
+            Ok(())
+        } else {
+            Err(ParseError)
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, ParseError> {
+        self.ws();
+        match self.peek().ok_or(ParseError)? {
+            b'n' => self.expect("null").map(|_| Value::Null),
+            b't' => self.expect("true").map(|_| Value::Bool(true)),
+            b'f' => self.expect("false").map(|_| Value::Bool(false)),
+            b'"' => self.string().map(Value::String),
+            b'[' => {
+                self.idx += 1;
+                let mut out = Vec::new();
+                self.ws();
+                if self.peek() == Some(b']') {
+                    self.idx += 1;
+                    return Ok(Value::Array(out));
+                }
+                loop {
+                    out.push(self.value()?);
+                    self.ws();
+                    match self.peek() {
+                        Some(b',') => self.idx += 1,
+                        Some(b']') => {
+                            self.idx += 1;
+                            return Ok(Value::Array(out));
+                        }
+                        _ => return Err(ParseError),
+                    }
+                }
+            }
+            b'{' => {
+                self.idx += 1;
+                let mut out = BTreeMap::new();
+                self.ws();
+                if self.peek() == Some(b'}') {
+                    self.idx += 1;
+                    return Ok(Value::Object(out));
+                }
+                loop {
+                    self.ws();
+                    let k = self.string()?;
+                    self.ws();
+                    self.expect(":")?;
+                    let v = self.value()?;
+                    out.insert(k, v);
+                    self.ws();
+                    match self.peek() {
+                        Some(b',') => self.idx += 1,
+                        Some(b'}') => {
+                            self.idx += 1;
+                            return Ok(Value::Object(out));
+                        }
+                        _ => return Err(ParseError),
+                    }
+                }
+            }
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Result<Value, ParseError> {
+        let start = self.idx;
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_digit() || matches!(c, b'-' | b'+' | b'.' | b'e' | b'E'))
+        {
+            self.idx += 1;
+        }
+        let s = std::str::from_utf8(&self.s[start..self.idx]).map_err(|_| ParseError)?;
+        s.parse().map(Value::Number).map_err(|_| ParseError)
+    }
+
+    fn hex4(&mut self) -> Result<u32, ParseError> {
+        let h = self.s.get(self.idx..(self.idx + 4)).ok_or(ParseError)?;
+        let h = std::str::from_utf8(h).map_err(|_| ParseError)?;
+        self.idx += 4;
+        u32::from_str_radix(h, 16).map_err(|_| ParseError)
+    }
+
+    fn string(&mut self) -> Result<String, ParseError> {
+        self.expect("\"")?;
+        let mut out = Vec::new();
+        loop {
+            let c = self.peek().ok_or(ParseError)?;
+            self.idx += 1;
+            match c {
+                b'"' => break,
+                b'\\' => {
+                    let e = self.peek().ok_or(ParseError)?;
+                    self.idx += 1;
+                    let c = match e {
+                        b'"' => '"',
+                        b'\\' => '\\',
+                        b'/' => '/',
+                        b'b' => '\x08',
+                        b'f' => '\x0c',
+                        b'n' => '\n',
+                        b'r' => '\r',
+                        b't' => '\t',
+                        b'u' => {
+                            let mut n = self.hex4()?;
+                            // surrogate pair
+                            if (0xd800..0xdc00).contains(&n) {
+                                self.expect("\\u")?;
+                                let lo = self.hex4()?;
+                                n = 0x10000 + ((n - 0xd800) << 10) + (lo.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(n).unwrap_or(char::REPLACEMENT_CHARACTER)
+                        }
+                        _ => return Err(ParseError),
+                    };
+                    out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                c => out.push(c),
+            }
+        }
+        String::from_utf8(out).map_err(|_| ParseError)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_scalars() {
+        assert_eq!(Value::parse("null").unwrap(), Value::Null);
+        assert_eq!(Value::parse(" true ").unwrap(), Value::Bool(true));
+        assert_eq!(Value::parse("-12.5e1").unwrap(), Value::Number(-125.0));
+        assert_eq!(Value::parse(r#""a\nbé""#).unwrap(), Value::from("a\nbé"));
+        assert_eq!(Value::parse(r#""😀""#).unwrap(), Value::from("😀"));
+    }
+
+    #[test]
+    fn parse_nested() {
+        let v = Value::parse(r#"{"a": [1, {"b": "c"}], "d": {}}"#).unwrap();
+        assert_eq!(v.get("a").and_then(Value::as_array).map(<[_]>::len), Some(2));
+        assert_eq!(v.get("a").unwrap().as_array().unwrap()[1].path(&["b"]).and_then(Value::as_str), Some("c"));
+        assert_eq!(v.get("d"), Some(&Value::Object(BTreeMap::new())));
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(Value::parse("{").is_err());
+        assert!(Value::parse("[1,]").is_err());
+        assert!(Value::parse("1 2").is_err());
+        assert!(Value::parse("").is_err());
+    }
+
+    #[test]
+    fn roundtrip() {
+        let v = Value::object([
+            ("id", Value::from(3usize)),
+            ("text", Value::from("quote \" and \\ and \t")),
+            ("list", Value::from(vec![Value::Null, Value::from(false)])),
+        ]);
+        let s = v.to_string();
+        assert_eq!(s, r#"{"id":3,"list":[null,false],"text":"quote \" and \\ and \t"}"#);
+        assert_eq!(Value::parse(&s).unwrap(), v);
+    }
+}
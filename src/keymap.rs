@@ -0,0 +1,585 @@
+//! Key mappings, defined with `:map` and friends.
+//!
+//! Mappings rewrite keys before they reach the command parser. Keys are collected in a
+//! [`Typeahead`] buffer, and when they match the left hand side of a mapping they are replaced
+//! with its right hand side. Unless the mapping was made with `:noremap`, the replacement is
+//! checked for mappings again. When the keys so far could still become a longer mapping, we wait
+//! up to `timeoutlen` milliseconds for more before deciding. If they aren't a mapping on their own,
+//! like `<leader>` usually isn't, typed keys are instead held in the typeahead once the time is up,
+//! and the main loop shows what can follow them until the next key arrives.
+//!
+//! A mapping can be limited to one buffer, with `:map <buffer>`, or to buffers of a filetype, with
+//! `:map <filetype=rust>`. The mappings of the buffer come first, then those of its filetype and
+//! then the global ones, so a buffer can change what a key does without it changing anywhere
+//! else. Those of a buffer go away with it.
+//!
+//! The built-in commands aren't mappings, they're still the `commdef!` table in [`crate::input`]
+//! that parses the keys once they're mapped. An operator like `d` takes any motion after it, which
+//! a mapping from fixed keys can only express by listing every pair, and the key hints are made
+//! from the table too. So mappings are a layer on top of the table rather than a replacement.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::io::Read;
+
+use crate::event::{self, Event, Message};
+use crate::input::{KeyReader, TermReader};
+use crate::render::{BufId, Ctx};
+use crate::Mode;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mapping {
+    pub rhs: Vec<u8>,
+    /// don't map the keys of `rhs` again
+    pub noremap: bool,
+}
+
+/// where a mapping applies
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Scope {
+    Global,
+    Buffer(BufId),
+    Filetype(String),
+}
+
+impl Scope {
+    /// the scopes that apply in buffer `id` of `filetype`, in the order they're looked up
+    pub fn of(id: BufId, filetype: Option<&str>) -> Vec<Scope> {
+        let mut scopes = vec![Scope::Buffer(id)];
+        scopes.extend(filetype.map(|ft| Scope::Filetype(ft.into())));
+        scopes.push(Scope::Global);
+        scopes
+    }
+}
+
+#[derive(Default)]
+pub struct KeyMap {
+    maps: HashMap<Scope, HashMap<Mode, BTreeMap<Vec<u8>, Mapping>>>,
+}
+
+enum Lookup<'a> {
+    /// nothing starts with the keys
+    None,
+    /// a mapping of the first `len` keys. `longer` is set if more keys could match a longer one.
+    Found { len: usize, mapping: &'a Mapping, longer: bool },
+    /// the keys are the start of a mapping, but don't match one yet
+    Pending,
+}
+
+impl KeyMap {
+    pub fn map(&mut self, scope: Scope, mode: Mode, lhs: Vec<u8>, rhs: Vec<u8>, noremap: bool) {
+        self.maps
+            .entry(scope)
+            .or_default()
+            .entry(mode)
+            .or_default()
+            .insert(lhs, Mapping { rhs, noremap });
+    }
+
+    /// remove a mapping, returns false if there was none
+    pub fn unmap(&mut self, scope: &Scope, mode: Mode, lhs: &[u8]) -> bool {
+        self.maps
+            .get_mut(scope)
+            .and_then(|m| m.get_mut(&mode))
+            .is_some_and(|m| m.remove(lhs).is_some())
+    }
+
+    /// remove the mappings of buffer `id`, once it's deleted
+    pub fn forget_buffer(&mut self, id: BufId) {
+        self.maps.remove(&Scope::Buffer(id));
+    }
+
+    fn table(&self, scope: &Scope, mode: Mode) -> Option<&BTreeMap<Vec<u8>, Mapping>> {
+        self.maps.get(scope)?.get(&mode)
+    }
+
+    /// mappings in `scopes` whose left hand side starts with `prefix`, in order, with the scope
+    /// they're from. A mapping hides those with the same left hand side in later scopes.
+    pub fn list<'a>(&'a self, scopes: &'a [Scope], mode: Mode, prefix: &[u8]) -> Vec<(&'a [u8], &'a Mapping, &'a Scope)> {
+        let mut found: BTreeMap<&[u8], (&Mapping, &Scope)> = BTreeMap::new();
+        for scope in scopes {
+            let Some(table) = self.table(scope, mode) else {
+                continue;
+            };
+            let matching = table.range(prefix.to_vec()..).take_while(|(lhs, _)| lhs.starts_with(prefix));
+            for (lhs, mapping) in matching {
+                found.entry(lhs).or_insert((mapping, scope));
+            }
+        }
+        found.into_iter().map(|(lhs, (mapping, scope))| (lhs, mapping, scope)).collect()
+    }
+
+    fn lookup(&self, scopes: &[Scope], mode: Mode, keys: &[u8]) -> Lookup<'_> {
+        let longer = self.list(scopes, mode, keys).iter().any(|(lhs, ..)| lhs.len() > keys.len());
+        let get = |lhs: &[u8]| scopes.iter().find_map(|scope| self.table(scope, mode)?.get(lhs));
+        let found = (1..=keys.len()).rev().find_map(|len| Some((len, get(&keys[..len])?)));
+        match found {
+            Some((len, mapping)) => Lookup::Found { len, mapping, longer },
+            None if longer => Lookup::Pending,
+            None => Lookup::None,
+        }
+    }
+}
+
+/// `mapleader` as used for `<leader>` by default
+pub const DEFAULT_LEADER: &str = "\\";
+
+/// keys written in `<...>` notation, e.g. `<leader>w<CR>` or `<C-w>`
+pub fn parse_keys(s: &str, leader: &str) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('<') {
+        out.extend_from_slice(&rest.as_bytes()[..start]);
+        rest = &rest[start..];
+        let special = rest.find('>').and_then(|end| {
+            let name = &rest[1..end];
+            Some((special_key(name, leader)?, end))
+        });
+        match special {
+            Some((keys, end)) => {
+                out.extend(keys);
+                rest = &rest[(end + 1)..];
+            }
+            None => {
+                out.push(b'<');
+                rest = &rest[1..];
+            }
+        }
+    }
+    out.extend_from_slice(rest.as_bytes());
+    out
+}
+
+fn special_key(name: &str, leader: &str) -> Option<Vec<u8>> {
+    let key = match name.to_ascii_lowercase().as_str() {
+        "leader" => return Some(leader.as_bytes().to_vec()),
+        "cr" | "enter" | "return" => b'\r',
+        "esc" => 0x1b,
+        "space" => b' ',
+        "tab" => b'\t',
+        "bs" => 0x7f,
+        "lt" => b'<',
+        "bar" => b'|',
+        "bslash" => b'\\',
+        lower => {
+            let c = lower.strip_prefix("c-")?;
+            let &[c] = c.as_bytes() else {
+                return None;
+            };
+            if !c.is_ascii_alphabetic() && !b"@[\\]^_".contains(&c) {
+                return None;
+            }
+            c.to_ascii_uppercase() & 0x1f
+        }
+    };
+    Some(vec![key])
+}
+
+/// keys in the notation accepted by [`parse_keys`]
+pub fn key_notation(keys: &[u8]) -> String {
+    let mut out = String::new();
+    for &k in keys {
+        match k {
+            b'\r' => out.push_str("<CR>"),
+            0x1b => out.push_str("<Esc>"),
+            b' ' => out.push_str("<Space>"),
+            b'\t' => out.push_str("<Tab>"),
+            0x7f => out.push_str("<BS>"),
+            k if k < 0x20 => out.push_str(&format!("<C-{}>", (k | 0x40).to_ascii_lowercase() as char)),
+            k => out.push(k as char),
+        }
+    }
+    out
+}
+
+/// letter used for `mode` when listing mappings
+pub fn mode_char(mode: Mode) -> char {
+    match mode {
+        Mode::Normal => 'n',
+        Mode::Insert => 'i',
+        Mode::Command => 'c',
+        Mode::Terminal => 't',
+    }
+}
+
+/// where typed keys come from
+pub trait KeySource: Read {
+    /// wait up to `timeout_ms` for input, returns true if there is some
+    fn poll(&self, timeout_ms: i32) -> bool;
+
+    /// whether keys can come in escape sequences, like the arrow keys do from a terminal
+    fn has_sequences(&self) -> bool {
+        false
+    }
+
+    /// whether someone is typing the keys, who can be shown what to type next
+    fn is_typed(&self) -> bool {
+        false
+    }
+}
+
+impl KeySource for TermReader {
+    fn poll(&self, timeout_ms: i32) -> bool {
+        TermReader::poll(self, timeout_ms)
+    }
+
+    fn has_sequences(&self) -> bool {
+        true
+    }
+
+    fn is_typed(&self) -> bool {
+        true
+    }
+}
+
+/// fixed keys, e.g. for `:normal`
+impl KeySource for &[u8] {
+    fn poll(&self, _timeout_ms: i32) -> bool {
+        !self.is_empty()
+    }
+}
+
+/// keys that have been typed but not yet used
+pub struct Typeahead<R> {
+    inner: R,
+    /// keys and whether they may still be mapped
+    keys: VecDeque<(u8, bool)>,
+    /// whether keys from `inner` are mapped
+    remap: bool,
+    /// mappings applied since the typeahead was last empty, to stop recursive mappings
+    depth: usize,
+    /// how many keys at the end of `keys` came straight from `inner`, rather than from a mapping
+    typed: usize,
+    /// whether the key given out last was one of those, so that it can start an escape sequence
+    last_typed: bool,
+    /// the keys are the start of a mapping that waits for the next typed key
+    held: bool,
+}
+
+impl<R: KeySource> Typeahead<R> {
+    pub fn new(inner: R, remap: bool) -> Self {
+        Self {
+            inner,
+            keys: VecDeque::new(),
+            remap,
+            depth: 0,
+            typed: 0,
+            last_typed: false,
+            held: false,
+        }
+    }
+
+    /// wait up to `timeout_ms` for keys, returns true if there are some. Held keys don't count,
+    /// they need another one first.
+    pub fn poll(&self, timeout_ms: i32) -> bool {
+        !self.keys.is_empty() && !self.held || self.inner.poll(timeout_ms)
+    }
+
+    /// the keys that wait for the next one to become a mapping, see [`Ctx::key_hints`]
+    pub fn held(&self) -> Option<Vec<u8>> {
+        self.held.then(|| self.keys.iter().map(|(k, _)| *k).collect())
+    }
+
+    /// read everything that is available without waiting, so that fast typing or a paste is
+    /// handled as a whole
+    pub fn fill(&mut self) -> std::io::Result<()> {
+        let mut buf = [0; 4096];
+        while self.inner.poll(0) {
+            let n = self.inner.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            self.keys.extend(buf[..n].iter().map(|&b| (b, self.remap)));
+            self.typed += n;
+            self.held = false;
+        }
+        Ok(())
+    }
+
+    /// reader of the keys with the mappings of the current mode applied
+    pub fn reader<'a>(&'a mut self, ctx: &'a Ctx) -> Mapped<'a, R> {
+        let timeout_ms = ctx.options.timeoutlen.try_into().unwrap_or(i32::MAX);
+        Mapped {
+            hints: Some(ctx),
+            ..self.mapped(&ctx.keymap, ctx.keymap_scopes(), ctx.mode, timeout_ms, ctx.options.maxmapdepth)
+        }
+    }
+
+    pub fn mapped<'a>(
+        &'a mut self,
+        map: &'a KeyMap,
+        scopes: Vec<Scope>,
+        mode: Mode,
+        timeout_ms: i32,
+        max_depth: usize,
+    ) -> Mapped<'a, R> {
+        Mapped {
+            typeahead: self,
+            map,
+            scopes,
+            mode,
+            timeout_ms,
+            max_depth,
+            hints: None,
+        }
+    }
+
+    /// read a key into the typeahead, returns false at the end of input
+    fn read_more(&mut self) -> std::io::Result<bool> {
+        let mut b = [0];
+        if self.inner.read(&mut b)? == 0 {
+            return Ok(false);
+        }
+        self.keys.push_back((b[0], self.remap));
+        self.typed += 1;
+        self.held = false;
+        Ok(true)
+    }
+}
+
+/// reader of keys after mapping them, see [`Typeahead::reader`]
+pub struct Mapped<'a, R> {
+    typeahead: &'a mut Typeahead<R>,
+    map: &'a KeyMap,
+    /// the scopes of the mappings, in the order they're looked up
+    scopes: Vec<Scope>,
+    mode: Mode,
+    timeout_ms: i32,
+    max_depth: usize,
+    /// where to show the key hints
+    hints: Option<&'a Ctx>,
+}
+
+impl<R: KeySource> Mapped<'_, R> {
+    fn next_key(&mut self) -> std::io::Result<Option<u8>> {
+        let ta = &mut *self.typeahead;
+        loop {
+            if ta.keys.is_empty() {
+                ta.depth = 0;
+                if !ta.read_more()? {
+                    return Ok(None);
+                }
+            }
+            let keys: Vec<u8> = ta.keys.iter().take_while(|(_, remap)| *remap).map(|(k, _)| *k).collect();
+            // keys that can't be mapped can't be part of a longer mapping either
+            let can_extend = keys.len() == ta.keys.len();
+            let lookup = self.map.lookup(&self.scopes, self.mode, &keys);
+            let (len, mapping) = match lookup {
+                Lookup::Found { longer: true, .. } | Lookup::Pending
+                    if can_extend && ta.inner.poll(self.timeout_ms) =>
+                {
+                    if !ta.read_more()? {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+                // the main loop shows what can follow while it waits for the next key
+                Lookup::Pending
+                    if can_extend
+                        && ta.inner.is_typed()
+                        && self.hints.is_some_and(|ctx| ctx.key_hints(&keys).is_some()) =>
+                {
+                    ta.held = true;
+                    return Err(std::io::ErrorKind::WouldBlock.into());
+                }
+                Lookup::Found { len, mapping, .. } => (len, mapping),
+                Lookup::Pending | Lookup::None => {
+                    ta.last_typed = ta.typed == ta.keys.len();
+                    let key = ta.keys.pop_front().map(|(k, _)| k);
+                    ta.typed = ta.typed.min(ta.keys.len());
+                    return Ok(key);
+                }
+            };
+
+            ta.depth += 1;
+            if ta.depth > self.max_depth {
+                ta.keys.clear();
+                ta.typed = 0;
+                event::post(Event::Message(Message::Str("recursive mapping".into())));
+                return Err(std::io::Error::other("recursive mapping"));
+            }
+            ta.keys.drain(..len);
+            ta.typed = ta.typed.min(ta.keys.len());
+            // like in vim, a mapping that starts with its own keys doesn't map them again, so
+            // `:map j jzz` works
+            let own = !mapping.noremap && mapping.rhs.starts_with(&keys[..len]);
+            for (i, &k) in mapping.rhs.iter().enumerate().rev() {
+                ta.keys.push_front((k, !(mapping.noremap || own && i < len)));
+            }
+        }
+    }
+}
+
+impl<R: KeySource> Read for Mapped<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        match self.next_key()? {
+            Some(k) => {
+                buf[0] = k;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl<R: KeySource> KeyReader for Mapped<'_, R> {
+    fn sequence_pending(&mut self) -> bool {
+        // a terminal writes a sequence all at once, so the rest of it is there already, either
+        // still to be read or read into the typeahead with everything else that was typed. Keys
+        // from mappings and `:normal` are never sequences, `<Esc>O` there is escape and `O`.
+        let ta = &self.typeahead;
+        if !ta.inner.has_sequences() || !ta.last_typed {
+            return false;
+        }
+        if ta.keys.is_empty() {
+            ta.inner.poll(0)
+        } else {
+            ta.typed == ta.keys.len()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn expand(map: &KeyMap, mode: Mode, input: &str) -> std::io::Result<String> {
+        let mut ta = Typeahead::new(input.as_bytes(), true);
+        let mut out = Vec::new();
+        ta.mapped(map, vec![Scope::Global], mode, 0, 100).read_to_end(&mut out)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    fn map(map: &mut KeyMap, lhs: &str, rhs: &str, noremap: bool) {
+        map.map(Scope::Global, Mode::Normal, lhs.into(), rhs.into(), noremap);
+    }
+
+    #[test]
+    fn recursive_and_not() {
+        let mut m = KeyMap::default();
+        map(&mut m, "a", "b", false);
+        map(&mut m, "b", "c", false);
+        map(&mut m, "x", "b", true);
+        assert_eq!(expand(&m, Mode::Normal, "abxd").unwrap(), "ccbd");
+        assert_eq!(expand(&m, Mode::Insert, "abx").unwrap(), "abx");
+
+        map(&mut m, "j", "jzz", false);
+        assert_eq!(expand(&m, Mode::Normal, "j").unwrap(), "jzz");
+
+        map(&mut m, "c", "a", false);
+        assert!(expand(&m, Mode::Normal, "a").is_err());
+        assert!(m.unmap(&Scope::Global, Mode::Normal, b"c"));
+        assert!(!m.unmap(&Scope::Global, Mode::Normal, b"c"));
+        assert_eq!(expand(&m, Mode::Normal, "a").unwrap(), "c");
+    }
+
+    #[test]
+    fn longest_match() {
+        let mut m = KeyMap::default();
+        map(&mut m, "g", "1", true);
+        map(&mut m, "gh", "2", true);
+        map(&mut m, "ijk", "3", true);
+        assert_eq!(expand(&m, Mode::Normal, "gx").unwrap(), "1x");
+        assert_eq!(expand(&m, Mode::Normal, "ghg").unwrap(), "21");
+        // the input ends before the mapping is complete, like a timeout
+        assert_eq!(expand(&m, Mode::Normal, "ij").unwrap(), "ij");
+        assert_eq!(expand(&m, Mode::Normal, "iijk").unwrap(), "i3");
+        let listed: Vec<_> = m.list(&[Scope::Global], Mode::Normal, b"g").into_iter().map(|(lhs, ..)| lhs).collect();
+        assert_eq!(listed, [b"g".as_slice(), b"gh"]);
+    }
+
+    #[test]
+    fn scopes() {
+        let mut m = KeyMap::default();
+        let (buf, other) = (BufId::new(), BufId::new());
+        let rust = Scope::Filetype("rust".into());
+        map(&mut m, "q", "global", true);
+        m.map(rust.clone(), Mode::Normal, "q".into(), "rust".into(), true);
+        m.map(Scope::Buffer(buf), Mode::Normal, "q".into(), "buffer".into(), true);
+        m.map(rust.clone(), Mode::Normal, "qq".into(), "long".into(), true);
+        let expand = |scopes: Vec<Scope>, input: &str| {
+            let mut ta = Typeahead::new(input.as_bytes(), true);
+            let mut out = String::new();
+            ta.mapped(&m, scopes, Mode::Normal, 0, 100).read_to_string(&mut out).unwrap();
+            out
+        };
+        assert_eq!(expand(Scope::of(buf, Some("rust")), "q"), "buffer");
+        assert_eq!(expand(Scope::of(other, Some("rust")), "q"), "rust");
+        assert_eq!(expand(Scope::of(other, Some("c")), "q"), "global");
+        // the longest match still wins, whatever its scope
+        assert_eq!(expand(Scope::of(buf, Some("rust")), "qq"), "long");
+        let scopes = Scope::of(buf, Some("rust"));
+        let listed: Vec<_> = m.list(&scopes, Mode::Normal, b"q").into_iter().map(|(.., s)| s.clone()).collect();
+        assert_eq!(listed, [Scope::Buffer(buf), rust.clone()]);
+        assert!(!m.unmap(&Scope::Global, Mode::Normal, b"qq"));
+        assert!(m.unmap(&rust, Mode::Normal, b"qq"));
+    }
+
+    /// keys from a terminal, all there at once
+    struct Typed<'a>(&'a [u8]);
+
+    impl Read for Typed<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.0.read(buf)
+        }
+    }
+
+    impl KeySource for Typed<'_> {
+        fn poll(&self, _timeout_ms: i32) -> bool {
+            !self.0.is_empty()
+        }
+
+        fn has_sequences(&self) -> bool {
+            true
+        }
+
+        fn is_typed(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn queued_sequences() {
+        let mut ctx = crate::testing::editor("abc\n", 30, 8);
+        // three times left to get to the start, then end
+        let mut keys = Typeahead::new(Typed(b":/X/\x1b[D\x1b[D\x1b[Ds/b\x1b[F\r"), true);
+        keys.fill().unwrap();
+        while keys.poll(0) {
+            if let Some(action) = crate::input::handle_input(&ctx, &mut keys.reader(&ctx)) {
+                ctx.process_action(action);
+            }
+        }
+        assert_eq!(ctx.focused_buf().to_string(), "aXc\n");
+        assert_eq!(ctx.mode, Mode::Normal);
+    }
+
+    #[test]
+    fn held_prefix() {
+        let mut ctx = crate::testing::editor("abc\n", 30, 8);
+        ctx.keymap.map(Scope::Global, Mode::Normal, ",w".into(), "x".into(), true);
+        let mut keys = Typeahead::new(Typed(b","), true);
+        keys.fill().unwrap();
+        assert!(crate::input::handle_input(&ctx, &mut keys.reader(&ctx)).is_none());
+        // back in the main loop without a key to handle
+        assert_eq!(keys.held().as_deref(), Some(b",".as_slice()));
+        assert!(!keys.poll(0));
+        assert!(ctx.pending.keys().is_empty());
+    }
+
+    #[test]
+    fn fill_queue() {
+        let mut keys = Typeahead::new(&b"ihello"[..], true);
+        keys.fill().unwrap();
+        assert_eq!(keys.keys.len(), 6);
+        assert!(keys.poll(0));
+    }
+
+    #[test]
+    fn notation() {
+        assert_eq!(parse_keys("<leader>w<CR>", ","), b",w\r");
+        assert_eq!(parse_keys("<C-w>j<Esc>", DEFAULT_LEADER), b"\x17j\x1b");
+        assert_eq!(parse_keys("a<b><lt>", DEFAULT_LEADER), b"a<b><");
+        assert_eq!(key_notation(b"\x17 :w\r"), "<C-w><Space>:w<CR>");
+    }
+}
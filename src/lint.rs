@@ -0,0 +1,115 @@
+//! Running a linter when a file is written, see the `lintprg` option.
+//!
+//! The output is either cargo's `--message-format json`, one object per line, or plain text that
+//! is parsed with `errorformat`. Problems are shown as extmarks in the `lint` namespace, with a
+//! sign and the message after the line, and are replaced by the next run.
+use std::path::Path;
+
+use crate::buffer::extmark::Extmark;
+use crate::buffer::registry;
+use crate::errorformat::InvalidFormat;
+use crate::json::Value;
+use crate::qflist::QfItem;
+use crate::tui::TextSeverity;
+
+pub const NAMESPACE: &str = "lint";
+
+/// the problems in the linter output `lines`
+pub fn parse(errorformat: &[String], lines: &[String]) -> Result<Vec<QfItem>, InvalidFormat> {
+    let (json, text): (Vec<_>, Vec<_>) = lines.iter().map(String::as_str).partition(|l| l.starts_with('{'));
+    let mut items: Vec<_> = json.into_iter().filter_map(|l| cargo_message(&Value::parse(l).ok()?)).collect();
+    items.extend(crate::errorformat::parse(errorformat, text)?);
+    Ok(items)
+}
+
+/// a `compiler-message` from cargo, located at its primary span
+fn cargo_message(v: &Value) -> Option<QfItem> {
+    if v.get("reason")?.as_str()? != "compiler-message" {
+        return None;
+    }
+    let msg = v.get("message")?;
+    let severity = match msg.get("level")?.as_str()? {
+        "error" | "error: internal compiler error" => TextSeverity::Error,
+        "warning" => TextSeverity::Warning,
+        _ => TextSeverity::Normal,
+    };
+    let span = msg
+        .get("spans")?
+        .as_array()?
+        .iter()
+        .find(|s| s.get("is_primary").and_then(Value::as_bool) == Some(true))?;
+    Some(QfItem {
+        path: span.get("file_name")?.as_str()?.into(),
+        line: span.get("line_start")?.as_usize()?.checked_sub(1)?,
+        col: span.get("column_start")?.as_usize()?.saturating_sub(1),
+        text: msg.get("message")?.as_str()?.to_owned(),
+        severity,
+    })
+}
+
+/// replace the lint marks of every open buffer with `items`
+pub fn show(items: &[QfItem]) {
+    for buf in registry::all() {
+        let mut buf = buf.get_mut();
+        buf.extmarks_mut().clear(NAMESPACE);
+        let Some(path) = buf.path().map(crate::lsp::abs_path) else {
+            continue;
+        };
+        for item in items.iter().filter(|i| crate::lsp::abs_path(&i.path) == path) {
+            if item.line >= buf.linecnt() {
+                continue;
+            }
+            let off = buf.line_offsets(item.line..item.line).start;
+            let color = item.severity.color();
+            let text = item.text.lines().next().unwrap_or_default().to_owned();
+            let mark = Extmark {
+                sign: Some((item.severity.sign(), color)),
+                ..Extmark::virt_text(NAMESPACE, off, text, color)
+            };
+            buf.extmarks_mut().add(mark);
+        }
+    }
+}
+
+/// `lintprg` with `%` replaced by the file, quoted for the shell
+pub fn command(lintprg: &str, file: &Path) -> String {
+    lintprg.replace('%', &crate::job::quote(file))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn quoted_file() {
+        assert_eq!(command("lint %", Path::new("a b.rs")), "lint 'a b.rs'");
+        assert_eq!(command("lint %", Path::new("it's;rm")), r"lint 'it'\''s;rm'");
+    }
+
+    #[test]
+    fn cargo_json() {
+        let line = r#"{"reason":"compiler-message","message":{"level":"warning","message":"unused variable: `x`",
+            "spans":[{"file_name":"src/other.rs","line_start":1,"column_start":1,"is_primary":false},
+            {"file_name":"src/main.rs","line_start":3,"column_start":9,"is_primary":true}]}}"#;
+        let lines = [line.replace('\n', ""), r#"{"reason":"build-finished","success":true}"#.into()];
+        let items = parse(&[], &lines).unwrap();
+        assert_eq!(
+            items,
+            [QfItem {
+                path: "src/main.rs".into(),
+                line: 2,
+                col: 8,
+                text: "unused variable: `x`".into(),
+                severity: TextSeverity::Warning,
+            }]
+        );
+    }
+
+    #[test]
+    fn plain_text() {
+        let efm = ["%f:%l:%c: %m".to_owned()];
+        let items = parse(&efm, &["a.py:4:2: E225 missing whitespace".into()]).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!((items[0].line, &items[0].text[..]), (3, "E225 missing whitespace"));
+    }
+}
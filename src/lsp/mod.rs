@@ -0,0 +1,846 @@
+//! Minimal language server client.
+//!
+//! Every filetype with a configured server gets one child process that we speak JSON-RPC to over
+//! stdio. Writes happen on the main thread, but responses and notifications are read on a
+//! separate thread per server and handed back through a channel, so the editor never blocks
+//! waiting for a server. The main loop drains that channel with [`LspManager::poll`].
+//!
+//! Documents are always synced in full - incremental sync is a future improvement.
+use std::collections::HashMap;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::buffer::{Buffer, BufferInner};
+use crate::debug::log;
+use crate::json::Value;
+use crate::progress::TaskId;
+use crate::tui::TextSeverity;
+use crate::utils::unit_err;
+
+mod rpc;
+
+/// language servers by filetype
+const SERVERS: &[(&str, &[&str])] = &[
+    ("rust", &["rust-analyzer"]),
+    ("c", &["clangd"]),
+    ("cpp", &["clangd"]),
+    ("python", &["pylsp"]),
+];
+
+unit_err!(NoServer: "no language server for this buffer");
+
+/// how long the servers get to answer `shutdown` when the editor exits
+const SHUTDOWN_WAIT: Duration = Duration::from_millis(500);
+
+/// something that happened on a server that the editor needs to act on
+pub enum LspEvent {
+    /// jump to a location. Column is in UTF-16 code units, as the protocol specifies.
+    Jump { path: PathBuf, line: usize, character: usize },
+    /// diagnostics for a file were replaced
+    Diagnostics(PathBuf),
+    /// documentation for the symbol under the cursor, as plain text
+    Hover(String),
+    Message(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub line: usize,
+    /// UTF-16 code units
+    pub character: usize,
+    pub severity: TextSeverity,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn sign(&self) -> char {
+        self.severity.sign()
+    }
+}
+
+/// a `$/progress` notification of work done
+#[derive(Debug, PartialEq, Eq)]
+enum Progress {
+    Begin { title: String, percent: Option<u8> },
+    Report(Option<u8>),
+    End,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Request {
+    Initialize,
+    Definition,
+    Hover,
+    Shutdown,
+}
+
+enum Incoming {
+    Msg(usize, Value),
+    Exited(usize),
+}
+
+struct Client {
+    filetype: &'static str,
+    child: Child,
+    stdin: ChildStdin,
+    next_id: u64,
+    pending: HashMap<u64, Request>,
+    /// messages held until the server has answered `initialize`
+    queued: Option<Vec<Value>>,
+    /// open documents and the buffer version last sent
+    docs: HashMap<PathBuf, usize>,
+    /// shown until the server has answered `initialize`
+    starting: TaskId,
+    /// the tasks of the server's progress tokens
+    progress: HashMap<String, TaskId>,
+}
+
+impl Client {
+    fn start(idx: usize, filetype: &'static str, cmd: &[&str], tx: mpsc::Sender<Incoming>) -> std::io::Result<Self> {
+        let mut child = Command::new(cmd[0])
+            .args(&cmd[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        std::thread::spawn(move || {
+            let mut r = BufReader::new(stdout);
+            loop {
+                match rpc::read_msg(&mut r) {
+                    Ok(Some(msg)) => {
+                        if tx.send(Incoming::Msg(idx, msg)).is_err() {
+                            return;
+                        }
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::InvalidData => continue,
+                    Ok(None) | Err(_) => break,
+                }
+            }
+            let _ = tx.send(Incoming::Exited(idx));
+        });
+        let mut client = Client {
+            filetype,
+            child,
+            stdin,
+            next_id: 0,
+            pending: HashMap::new(),
+            queued: None,
+            docs: HashMap::new(),
+            starting: crate::progress::start(&format!("starting {filetype} server")),
+            progress: HashMap::new(),
+        };
+        let root = std::env::current_dir().unwrap_or_default();
+        let params = Value::object([
+            ("processId", Value::from(std::process::id() as u64)),
+            ("rootUri", path_to_uri(&root).into()),
+            ("clientInfo", Value::object([("name", "rvim".into())])),
+            (
+                "capabilities",
+                Value::object([
+                    (
+                        "textDocument",
+                        Value::object([
+                            ("publishDiagnostics", Value::object([])),
+                            ("definition", Value::object([])),
+                            (
+                                "hover",
+                                Value::object([(
+                                    "contentFormat",
+                                    Value::from(vec!["plaintext".into(), "markdown".into()]),
+                                )]),
+                            ),
+                            ("synchronization", Value::object([])),
+                        ]),
+                    ),
+                    ("window", Value::object([("workDoneProgress", true.into())])),
+                ]),
+            ),
+        ]);
+        client.request(Request::Initialize, "initialize", params);
+        client.queued = Some(Vec::new());
+        Ok(client)
+    }
+
+    fn send(&mut self, msg: Value) {
+        if let Some(q) = &mut self.queued {
+            q.push(msg);
+            return;
+        }
+        if let Err(e) = rpc::write_msg(&mut self.stdin, &msg) {
+            log!("failed to write to {} language server: {e}", self.filetype);
+        }
+    }
+
+    fn request(&mut self, kind: Request, method: &str, params: Value) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pending.insert(id, kind);
+        self.send(Value::object([
+            ("jsonrpc", "2.0".into()),
+            ("id", id.into()),
+            ("method", method.into()),
+            ("params", params),
+        ]));
+    }
+
+    fn notify(&mut self, method: &str, params: Value) {
+        self.send(Value::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", method.into()),
+            ("params", params),
+        ]));
+    }
+
+    /// ask the server to shut down, returning the id of the request to wait for. A server that
+    /// hasn't answered `initialize` yet is only told to exit.
+    fn shutdown(&mut self) -> Option<u64> {
+        if self.queued.is_some() {
+            return None;
+        }
+        let id = self.next_id;
+        self.request(Request::Shutdown, "shutdown", Value::Null);
+        Some(id)
+    }
+
+    /// called once `initialize` is answered, sends everything that was held back
+    fn ready(&mut self) {
+        crate::progress::finish(self.starting);
+        let queued = self.queued.take().unwrap_or_default();
+        self.notify("initialized", Value::object([]));
+        for msg in queued {
+            self.send(msg);
+        }
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.queued = None;
+        crate::progress::finish(self.starting);
+        for (_, task) in self.progress.drain() {
+            crate::progress::finish(task);
+        }
+        self.notify("exit", Value::Null);
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub struct LspManager {
+    clients: Vec<Option<Client>>,
+    tx: mpsc::Sender<Incoming>,
+    rx: mpsc::Receiver<Incoming>,
+    diagnostics: HashMap<PathBuf, Vec<Diagnostic>>,
+    /// the absolute path of each buffer path, so that syncing after every action doesn't
+    /// resolve it again
+    paths: HashMap<PathBuf, PathBuf>,
+}
+
+impl LspManager {
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::channel();
+        Self {
+            clients: Vec::new(),
+            tx,
+            rx,
+            diagnostics: HashMap::new(),
+            paths: HashMap::new(),
+        }
+    }
+
+    /// the absolute path the servers know the buffer at `path` by
+    fn doc_path(&mut self, path: &Path) -> PathBuf {
+        self.paths
+            .entry(path.to_owned())
+            .or_insert_with(|| abs_path(path))
+            .clone()
+    }
+
+    fn client_for(&mut self, filetype: &'static str) -> Option<&mut Client> {
+        let idx = self
+            .clients
+            .iter()
+            .position(|c| c.as_ref().is_some_and(|c| c.filetype == filetype));
+        let idx = match idx {
+            Some(idx) => idx,
+            None => {
+                let (_, cmd) = SERVERS.iter().find(|(ft, _)| *ft == filetype)?;
+                let idx = self.clients.len();
+                match Client::start(idx, filetype, cmd, self.tx.clone()) {
+                    Ok(c) => self.clients.push(Some(c)),
+                    Err(e) => {
+                        log!("could not start language server {:?}: {e}", cmd);
+                        return None;
+                    }
+                }
+                idx
+            }
+        };
+        self.clients[idx].as_mut()
+    }
+
+    /// start tracking a buffer, launching its language server if needed
+    pub fn attach(&mut self, buf: &Arc<Buffer>) {
+        let buf = buf.get();
+        let (Some(ft), Some(path)) = (buf.filetype(), buf.path()) else {
+            return;
+        };
+        let path = self.doc_path(path);
+        let Some(client) = self.client_for(ft) else {
+            return;
+        };
+        if client.docs.contains_key(&path) {
+            return;
+        }
+        client.docs.insert(path.clone(), buf.version());
+        let params = Value::object([(
+            "textDocument",
+            Value::object([
+                ("uri", path_to_uri(&path).into()),
+                ("languageId", ft.into()),
+                ("version", buf.version().into()),
+                ("text", buf.to_string().into()),
+            ]),
+        )]);
+        client.notify("textDocument/didOpen", params);
+    }
+
+    /// stop tracking a buffer that's being closed, so that opening it again starts afresh
+    pub fn detach(&mut self, buf: &BufferInner) {
+        let (Some(ft), Some(path)) = (buf.filetype(), buf.path()) else {
+            return;
+        };
+        let path = self.paths.remove(path).unwrap_or_else(|| abs_path(path));
+        let Some(client) = self.running_client(ft) else {
+            return;
+        };
+        if client.docs.remove(&path).is_none() {
+            return;
+        }
+        let params = Value::object([("textDocument", Value::object([("uri", path_to_uri(&path).into())]))]);
+        client.notify("textDocument/didClose", params);
+    }
+
+    /// send the buffer's text to its server if it changed since last time
+    pub fn sync(&mut self, buf: &BufferInner) {
+        let (Some(ft), Some(path)) = (buf.filetype(), buf.path()) else {
+            return;
+        };
+        let path = self.doc_path(path);
+        let Some(client) = self.running_client(ft) else {
+            return;
+        };
+        match client.docs.get_mut(&path) {
+            Some(v) if *v != buf.version() => *v = buf.version(),
+            _ => return,
+        }
+        let params = Value::object([
+            (
+                "textDocument",
+                Value::object([
+                    ("uri", path_to_uri(&path).into()),
+                    ("version", buf.version().into()),
+                ]),
+            ),
+            (
+                "contentChanges",
+                Value::from(vec![Value::object([("text", buf.to_string().into())])]),
+            ),
+        ]);
+        client.notify("textDocument/didChange", params);
+    }
+
+    fn running_client(&mut self, filetype: &str) -> Option<&mut Client> {
+        self.clients
+            .iter_mut()
+            .flatten()
+            .find(|c| c.filetype == filetype)
+    }
+
+    /// ask for the definition of the symbol under the cursor. The answer arrives later as a
+    /// [`LspEvent::Jump`].
+    pub fn goto_definition(&mut self, buf: &BufferInner) -> Result<(), NoServer> {
+        self.position_request(buf, Request::Definition, "textDocument/definition")
+    }
+
+    /// ask for documentation of the symbol under the cursor. The answer arrives later as a
+    /// [`LspEvent::Hover`].
+    pub fn hover(&mut self, buf: &BufferInner) -> Result<(), NoServer> {
+        self.position_request(buf, Request::Hover, "textDocument/hover")
+    }
+
+    /// send a request whose parameters are the document and cursor position
+    fn position_request(
+        &mut self,
+        buf: &BufferInner,
+        kind: Request,
+        method: &str,
+    ) -> Result<(), NoServer> {
+        let ft = buf.filetype().ok_or(NoServer)?;
+        let path = self.doc_path(buf.path().ok_or(NoServer)?);
+        self.sync(buf);
+        let client = self.running_client(ft).ok_or(NoServer)?;
+        let pos = buf.cursor.pos;
+        let character = byte_to_utf16(buf.line(pos.y), pos.x);
+        let params = Value::object([
+            ("textDocument", Value::object([("uri", path_to_uri(&path).into())])),
+            (
+                "position",
+                Value::object([("line", pos.y.into()), ("character", character.into())]),
+            ),
+        ]);
+        client.request(kind, method, params);
+        Ok(())
+    }
+
+    pub fn diagnostics(&self, path: &Path) -> &[Diagnostic] {
+        self.diagnostics
+            .get(&abs_path(path))
+            .map_or(&[], Vec::as_slice)
+    }
+
+    /// handle everything the servers have sent since the last call
+    pub fn poll(&mut self) -> Vec<LspEvent> {
+        let mut events = Vec::new();
+        while let Ok(msg) = self.rx.try_recv() {
+            match msg {
+                Incoming::Msg(idx, msg) => self.handle(idx, msg, &mut events),
+                Incoming::Exited(idx) => {
+                    if let Some(c) = self.clients.get_mut(idx).and_then(Option::take) {
+                        events.push(LspEvent::Message(format!(
+                            "{} language server exited",
+                            c.filetype
+                        )));
+                    }
+                }
+            }
+        }
+        events
+    }
+
+    fn handle(&mut self, idx: usize, msg: Value, events: &mut Vec<LspEvent>) {
+        let Some(client) = self.clients.get_mut(idx).and_then(Option::as_mut) else {
+            return;
+        };
+        let method = msg.get("method").and_then(Value::as_str);
+        let id = msg.get("id");
+        match (method, id) {
+            (Some(_), Some(id)) => {
+                // a request from the server, we don't support any so just acknowledge it
+                let reply = Value::object([
+                    ("jsonrpc", "2.0".into()),
+                    ("id", id.clone()),
+                    ("result", Value::Null),
+                ]);
+                client.send(reply);
+            }
+            (Some("textDocument/publishDiagnostics"), None) => {
+                let Some(params) = msg.get("params") else { return };
+                let Some(path) = params.get("uri").and_then(Value::as_str).and_then(uri_to_path) else {
+                    return;
+                };
+                let diags = params
+                    .get("diagnostics")
+                    .and_then(Value::as_array)
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(parse_diagnostic)
+                    .collect();
+                self.diagnostics.insert(path.clone(), diags);
+                events.push(LspEvent::Diagnostics(path));
+            }
+            (Some("window/showMessage"), None) => {
+                if let Some(m) = msg.path(&["params", "message"]).and_then(Value::as_str) {
+                    events.push(LspEvent::Message(m.to_owned()));
+                }
+            }
+            (Some("$/progress"), None) => {
+                let Some((token, progress)) = msg.get("params").and_then(parse_progress) else {
+                    return;
+                };
+                match progress {
+                    Progress::Begin { title, percent } => {
+                        let task = crate::progress::start(&title);
+                        crate::progress::report(task, percent);
+                        if let Some(old) = client.progress.insert(token, task) {
+                            crate::progress::finish(old);
+                        }
+                    }
+                    Progress::Report(percent) => {
+                        if let Some(&task) = client.progress.get(&token) {
+                            crate::progress::report(task, percent);
+                        }
+                    }
+                    Progress::End => {
+                        if let Some(task) = client.progress.remove(&token) {
+                            crate::progress::finish(task);
+                        }
+                    }
+                }
+            }
+            (Some(_), None) => (),
+            (None, Some(id)) => {
+                let Some(kind) = id.as_u64().and_then(|id| client.pending.remove(&id)) else {
+                    return;
+                };
+                if let Some(err) = msg.path(&["error", "message"]).and_then(Value::as_str) {
+                    events.push(LspEvent::Message(err.to_owned()));
+                    return;
+                }
+                let result = msg.get("result").unwrap_or(&Value::Null);
+                match kind {
+                    Request::Initialize => client.ready(),
+                    Request::Definition => match parse_location(result) {
+                        Some(jump) => events.push(jump),
+                        None => events.push(LspEvent::Message("definition not found".into())),
+                    },
+                    Request::Hover => match parse_hover(result) {
+                        Some(text) => events.push(LspEvent::Hover(text)),
+                        None => events.push(LspEvent::Message("no documentation found".into())),
+                    },
+                    Request::Shutdown => (),
+                }
+            }
+            (None, None) => (),
+        }
+    }
+}
+
+impl Drop for LspManager {
+    /// shut the servers down as the protocol asks, `shutdown` and then `exit`, which dropping the
+    /// clients sends
+    fn drop(&mut self) {
+        let mut waiting: Vec<(usize, u64)> = self
+            .clients
+            .iter_mut()
+            .enumerate()
+            .filter_map(|(idx, c)| Some((idx, c.as_mut()?.shutdown()?)))
+            .collect();
+        let deadline = Instant::now() + SHUTDOWN_WAIT;
+        while !waiting.is_empty() {
+            let Ok(msg) = self.rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) else {
+                break;
+            };
+            match msg {
+                Incoming::Msg(idx, msg) if msg.get("method").is_none() => {
+                    let id = msg.get("id").and_then(Value::as_u64);
+                    waiting.retain(|&w| Some(w) != id.map(|id| (idx, id)));
+                }
+                Incoming::Msg(..) => (),
+                Incoming::Exited(idx) => waiting.retain(|&(i, _)| i != idx),
+            }
+        }
+    }
+}
+
+impl Default for LspManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn parse_position(pos: &Value) -> Option<(usize, usize)> {
+    Some((pos.get("line")?.as_usize()?, pos.get("character")?.as_usize()?))
+}
+
+fn parse_diagnostic(d: &Value) -> Option<Diagnostic> {
+    let (line, character) = parse_position(d.path(&["range", "start"])?)?;
+    let severity = match d.get("severity").and_then(Value::as_u64) {
+        Some(1) | None => TextSeverity::Error,
+        Some(2) => TextSeverity::Warning,
+        Some(_) => TextSeverity::Normal,
+    };
+    let message = d.get("message")?.as_str()?.lines().next().unwrap_or("").to_owned();
+    Some(Diagnostic {
+        line,
+        character,
+        severity,
+        message,
+    })
+}
+
+/// parse the params of `$/progress` into its token and what happened. The token may be a string
+/// or a number, so it's kept as its json.
+fn parse_progress(params: &Value) -> Option<(String, Progress)> {
+    let token = params.get("token")?.to_string();
+    let value = params.get("value")?;
+    let percent = value.get("percentage").and_then(Value::as_u64).map(|p| p.min(100) as u8);
+    let progress = match value.get("kind")?.as_str()? {
+        "begin" => Progress::Begin {
+            title: value.get("title")?.as_str()?.to_owned(),
+            percent,
+        },
+        "report" => Progress::Report(percent),
+        "end" => Progress::End,
+        _ => return None,
+    };
+    Some((token, progress))
+}
+
+/// parse the result of a definition request, which may be a `Location`, `Location[]`, or
+/// `LocationLink[]`. Only the first location is used.
+fn parse_location(result: &Value) -> Option<LspEvent> {
+    let loc = match result {
+        Value::Array(a) => a.first()?,
+        v => v,
+    };
+    let (uri, range) = match loc.get("targetUri") {
+        Some(uri) => (uri, loc.get("targetSelectionRange")?),
+        None => (loc.get("uri")?, loc.get("range")?),
+    };
+    let path = uri_to_path(uri.as_str()?)?;
+    let (line, character) = parse_position(range.get("start")?)?;
+    Some(LspEvent::Jump {
+        path,
+        line,
+        character,
+    })
+}
+
+/// parse the result of a hover request into plain text. `contents` may be a `MarkupContent`, a
+/// `MarkedString`, or an array of `MarkedString`. Markdown is shown as-is, except that code fences
+/// are dropped.
+fn parse_hover(result: &Value) -> Option<String> {
+    fn marked(v: &Value) -> Option<&str> {
+        match v {
+            Value::String(s) => Some(s),
+            v => v.get("value")?.as_str(),
+        }
+    }
+    let contents = result.get("contents")?;
+    let parts: Vec<&str> = match contents {
+        Value::Array(a) => a.iter().filter_map(marked).collect(),
+        v => vec![marked(v)?],
+    };
+    let text = parts
+        .join("\n\n")
+        .lines()
+        .filter(|l| !l.trim_start().starts_with("```"))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let text = text.trim();
+    if text.is_empty() {
+        return None;
+    }
+    Some(text.to_owned())
+}
+
+/// make a path absolute without requiring it to exist
+pub fn abs_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| {
+        std::env::current_dir()
+            .map(|d| d.join(path))
+            .unwrap_or_else(|_| path.to_owned())
+    })
+}
+
+pub fn path_to_uri(path: &Path) -> String {
+    let mut out = String::from("file://");
+    for b in path.to_string_lossy().bytes() {
+        if b.is_ascii_alphanumeric() || b"/-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{b:02X}"));
+        }
+    }
+    out
+}
+
+pub fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let s = uri.strip_prefix("file://")?.as_bytes();
+    let mut out = Vec::with_capacity(s.len());
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == b'%' {
+            let hex = std::str::from_utf8(s.get((i + 1)..(i + 3))?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(s[i]);
+            i += 1;
+        }
+    }
+    Some(PathBuf::from(String::from_utf8(out).ok()?))
+}
+
+/// convert a byte column in `line` to UTF-16 code units
+pub fn byte_to_utf16(line: &str, byte: usize) -> usize {
+    line.char_indices()
+        .take_while(|(i, _)| *i < byte)
+        .map(|(_, c)| c.len_utf16())
+        .sum()
+}
+
+/// convert a UTF-16 column in `line` to a byte column, clamped to the line
+pub fn utf16_to_byte(line: &str, character: usize) -> usize {
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        if units >= character {
+            return i;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn uri_roundtrip() {
+        let p = Path::new("/tmp/some dir/ß.rs");
+        let uri = path_to_uri(p);
+        assert_eq!(uri, "file:///tmp/some%20dir/%C3%9F.rs");
+        assert_eq!(uri_to_path(&uri).as_deref(), Some(p));
+        assert_eq!(uri_to_path("http://example.com"), None);
+    }
+
+    #[test]
+    fn utf16_columns() {
+        let line = "a😀b";
+        assert_eq!(byte_to_utf16(line, 0), 0);
+        assert_eq!(byte_to_utf16(line, 1), 1);
+        assert_eq!(byte_to_utf16(line, 5), 3);
+        assert_eq!(utf16_to_byte(line, 3), 5);
+        assert_eq!(utf16_to_byte(line, 100), line.len());
+    }
+
+    #[test]
+    fn definition_result_shapes() {
+        let loc = Value::parse(
+            r#"{"uri":"file:///a.rs","range":{"start":{"line":3,"character":4},"end":{"line":3,"character":5}}}"#,
+        )
+        .unwrap();
+        let link = Value::parse(
+            r#"[{"targetUri":"file:///b.rs","targetRange":{},"targetSelectionRange":{"start":{"line":1,"character":2}}}]"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            parse_location(&loc),
+            Some(LspEvent::Jump { line: 3, character: 4, .. })
+        ));
+        assert!(matches!(
+            parse_location(&Value::Array(vec![loc])),
+            Some(LspEvent::Jump { line: 3, .. })
+        ));
+        assert!(matches!(
+            parse_location(&link),
+            Some(LspEvent::Jump { line: 1, character: 2, path }) if path == Path::new("/b.rs")
+        ));
+        assert!(parse_location(&Value::Null).is_none());
+    }
+
+    #[test]
+    fn hover_result_shapes() {
+        let markup = Value::parse(
+            r#"{"contents":{"kind":"markdown","value":"```rust\nfn main()\n```\n---\ndocs"}}"#,
+        )
+        .unwrap();
+        assert_eq!(parse_hover(&markup).as_deref(), Some("fn main()\n---\ndocs"));
+        let marked = Value::parse(
+            r#"{"contents":[{"language":"c","value":"int x"},"a variable"]}"#,
+        )
+        .unwrap();
+        assert_eq!(parse_hover(&marked).as_deref(), Some("int x\n\na variable"));
+        let empty = Value::parse(r#"{"contents":[]}"#).unwrap();
+        assert_eq!(parse_hover(&empty), None);
+        assert_eq!(parse_hover(&Value::Null), None);
+    }
+
+    #[test]
+    fn diagnostic_parsing() {
+        let d = Value::parse(
+            r#"{"range":{"start":{"line":7,"character":1}},"severity":2,"message":"unused\nmore"}"#,
+        )
+        .unwrap();
+        let d = parse_diagnostic(&d).unwrap();
+        assert_eq!(d.line, 7);
+        assert_eq!(d.severity, TextSeverity::Warning);
+        assert_eq!(d.message, "unused");
+        assert_eq!(d.sign(), 'W');
+    }
+
+    #[test]
+    fn progress_parsing() {
+        let parse = |s: &str| parse_progress(&Value::parse(s).unwrap());
+        let begin = parse(r#"{"token":"idx","value":{"kind":"begin","title":"Indexing","percentage":0}}"#);
+        let title = "Indexing".to_owned();
+        assert_eq!(begin, Some((r#""idx""#.into(), Progress::Begin { title, percent: Some(0) })));
+        let report = parse(r#"{"token":3,"value":{"kind":"report","message":"a.rs"}}"#);
+        assert_eq!(report, Some(("3".into(), Progress::Report(None))));
+        assert_eq!(parse(r#"{"token":3,"value":{"kind":"end"}}"#), Some(("3".into(), Progress::End)));
+        assert_eq!(parse(r#"{"token":3,"value":{"kind":"other"}}"#), None);
+    }
+
+    /// a server for `filetype` that answers `initialize` and writes everything it's sent to `log`
+    fn fake_server(lsp: &mut LspManager, filetype: &'static str, log: &Path) {
+        let reply = r#"{"jsonrpc":"2.0","id":0,"result":{"capabilities":{}}}"#;
+        let script = format!("printf 'Content-Length: {}\\r\\n\\r\\n%s' '{reply}'; cat > '{}'", reply.len(), log.display());
+        let idx = lsp.clients.len();
+        let client = Client::start(idx, filetype, &["sh", "-c", &script], lsp.tx.clone()).unwrap();
+        lsp.clients.push(Some(client));
+        let start = std::time::Instant::now();
+        while lsp.clients[idx].as_ref().unwrap().queued.is_some() {
+            assert!(start.elapsed() < Duration::from_secs(5), "fake server did not answer");
+            lsp.poll();
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn close_document() {
+        let dir = std::env::temp_dir().join(format!("edit-lsp-close-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (file, log) = (dir.join("main.rs"), dir.join("log"));
+        std::fs::write(&file, "fn main() {}\n").unwrap();
+        let mut lsp = LspManager::new();
+        fake_server(&mut lsp, "rust", &log);
+        let buf = Buffer::edit(&file).unwrap();
+        lsp.attach(&buf);
+        lsp.detach(&buf.get());
+        assert!(lsp.clients[0].as_ref().unwrap().docs.is_empty());
+        // closed twice is still one didClose
+        lsp.detach(&buf.get());
+        lsp.attach(&buf);
+        drop(lsp);
+        let sent = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(sent.matches("textDocument/didOpen").count(), 2);
+        assert_eq!(sent.matches("textDocument/didClose").count(), 1);
+        assert!(sent.find("didClose") < sent.rfind("didOpen"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn path_resolved_once() {
+        let dir = std::env::temp_dir().join(format!("edit-lsp-path-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let (file, link, log) = (dir.join("main.rs"), dir.join("link.rs"), dir.join("log"));
+        std::fs::write(&file, "fn main() {}\n").unwrap();
+        std::os::unix::fs::symlink(&file, &link).unwrap();
+        let mut lsp = LspManager::new();
+        fake_server(&mut lsp, "rust", &log);
+        let buf = Buffer::edit(&file).unwrap();
+        buf.get_mut().set_path(link.clone());
+        lsp.attach(&buf);
+        // still the file the link led to after the link is gone
+        std::fs::remove_file(&link).unwrap();
+        buf.get_mut().insert_at(0, "x");
+        lsp.sync(&buf.get());
+        let docs = &lsp.clients[0].as_ref().unwrap().docs;
+        assert_eq!(docs.keys().collect::<Vec<_>>(), [&abs_path(&file)]);
+        lsp.detach(&buf.get());
+        assert!(lsp.paths.is_empty());
+        drop(lsp);
+        let sent = std::fs::read_to_string(&log).unwrap();
+        assert_eq!(sent.matches(&path_to_uri(&abs_path(&file))).count(), 3);
+        assert_eq!(sent.matches("textDocument/didChange").count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
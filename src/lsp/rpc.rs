@@ -0,0 +1,75 @@
+//! JSON-RPC base protocol framing: a `Content-Length` header, a blank line, then the body.
+use std::io::{BufRead, ErrorKind, Write};
+
+use crate::json::Value;
+
+pub fn write_msg(w: &mut impl Write, msg: &Value) -> std::io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+/// read one message. Returns `Ok(None)` on a clean EOF and an `InvalidData` error if the body is
+/// not valid json (the stream is still usable afterwards in that case).
+pub fn read_msg(r: &mut impl BufRead) -> std::io::Result<Option<Value>> {
+    let mut len = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            if len.is_some() {
+                break;
+            }
+            // stray blank line between messages
+            continue;
+        }
+        if let Some((k, v)) = header.split_once(':') {
+            if k.eq_ignore_ascii_case("content-length") {
+                len = v.trim().parse::<usize>().ok();
+            }
+        }
+    }
+    let mut body = vec![0; len.expect("checked above")];
+    r.read_exact(&mut body)?;
+    let body = String::from_utf8(body).map_err(|_| std::io::Error::from(ErrorKind::InvalidData))?;
+    Value::parse(&body)
+        .map(Some)
+        .map_err(|_| std::io::Error::from(ErrorKind::InvalidData))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn framing_roundtrip() {
+        let a = Value::object([("id", 1usize.into()), ("method", "initialize".into())]);
+        let b = Value::object([("text", "ünïcode\r\n".into())]);
+        let mut out = Vec::new();
+        write_msg(&mut out, &a).unwrap();
+        write_msg(&mut out, &b).unwrap();
+        let mut r = std::io::Cursor::new(out);
+        assert_eq!(read_msg(&mut r).unwrap(), Some(a));
+        assert_eq!(read_msg(&mut r).unwrap(), Some(b));
+        assert_eq!(read_msg(&mut r).unwrap(), None);
+    }
+
+    #[test]
+    fn extra_headers() {
+        let raw = "Content-Type: application/vscode-jsonrpc\r\nContent-Length: 4\r\n\r\nnull";
+        let mut r = std::io::Cursor::new(raw.as_bytes());
+        assert_eq!(read_msg(&mut r).unwrap(), Some(Value::Null));
+    }
+
+    #[test]
+    fn invalid_body_recoverable() {
+        let raw = "Content-Length: 1\r\n\r\n{Content-Length: 2\r\n\r\n[]";
+        let mut r = std::io::Cursor::new(raw.as_bytes());
+        assert_eq!(read_msg(&mut r).unwrap_err().kind(), ErrorKind::InvalidData);
+        assert_eq!(read_msg(&mut r).unwrap(), Some(Value::Array(vec![])));
+    }
+}
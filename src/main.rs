@@ -11,6 +11,39 @@ mod tui;
 mod window;
 mod guile;
 mod utils;
+mod json;
+mod lsp;
+mod filetype;
+mod qflist;
+mod recent;
+mod job;
+mod options;
+mod errorformat;
+mod lint;
+mod terminal;
+mod diff;
+mod args;
+mod blame;
+mod search;
+mod startuptime;
+mod keymap;
+mod register;
+mod event;
+mod format;
+mod surround;
+mod sort;
+mod export;
+mod error;
+mod confirm;
+mod ignore;
+mod formatprg;
+mod progress;
+mod busy;
+mod syntax;
+mod server;
+mod man;
+#[cfg(test)]
+mod testing;
 use prelude::*;
 
 use libc::STDIN_FILENO;
@@ -21,7 +54,7 @@ use nix::sys::{
 };
 use render::Ctx;
 use std::path::PathBuf;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
 use std::{
     panic::{self, PanicInfo},
     path::Path,
@@ -30,11 +63,13 @@ use std::{
 
 use crate::debug::log;
 
-#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
 pub enum Mode {
     Normal,
     Insert,
     Command,
+    /// keys go to the program running in a terminal buffer
+    Terminal,
 }
 
 static EXIT_PENDING: AtomicBool = AtomicBool::new(false);
@@ -45,32 +80,179 @@ static DEFAULT_PANIC: std::sync::Mutex<
 // holds the original termios state to restore to when exiting
 static ORIGINAL_TERMIOS: Mutex<Option<Termios>> = Mutex::new(None);
 
+/// how long the main loop waits for input before checking for background events
+const EVENT_POLL_MS: i32 = 50;
+
+/// shortest time between two renders, changes within it are drawn together
+const FRAME: std::time::Duration = std::time::Duration::from_millis(16);
+
+/// most commands handled from the input queue before drawing, so that a huge paste still shows
+/// progress
+const MAX_QUEUED_COMMANDS: usize = 4096;
+
 fn exit() {
     EXIT_PENDING.store(true, std::sync::atomic::Ordering::Relaxed);
 }
 
-fn main_loop() {
-    let path = if let Some(file) = std::env::args().nth(1) {
-        PathBuf::from(file)
+fn read_stdin() -> std::io::Result<String> {
+    use std::io::Read;
+    let mut content = Vec::new();
+    std::io::stdin().lock().read_to_end(&mut content)?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+/// read all of stdin, then make the terminal our stdin so keys can still be read
+fn take_stdin() -> std::io::Result<String> {
+    use std::os::unix::io::AsRawFd;
+    let content = read_stdin()?;
+    let tty = std::fs::File::options().read(true).write(true).open("/dev/tty")?;
+    nix::unistd::dup2(tty.as_raw_fd(), STDIN_FILENO)?;
+    Ok(content)
+}
+
+/// run the commands from the arguments without taking over the terminal. Messages are printed,
+/// errors to stderr. Fails if any command reported an error.
+fn run_headless(args: args::Args, stdin_content: Option<String>) -> Result<(), ()> {
+    let buf = match (args.file, stdin_content) {
+        (_, Some(content)) => buffer::Buffer::from_string(content),
+        (Some(args::FileArg::Path(path)), None) => match buffer::Buffer::open(&path) {
+            Ok(buf) => buf,
+            Err(e) => {
+                eprintln!("could not open {path:?}: {e}");
+                return Err(());
+            }
+        },
+        _ => buffer::Buffer::new(),
+    };
+    let mut ctx = Ctx::headless(buf);
+    ctx.options.readonly = args.readonly;
+    guile::initialize();
+
+    let mut failed = false;
+    for cmd in &args.commands {
+        ctx.run_command(cmd);
+        ctx.process_events();
+        if let Some((msg, severity)) = ctx.take_message() {
+            let msg = msg.trim_end();
+            if severity == tui::TextSeverity::Error {
+                eprintln!("{msg}");
+                failed = true;
+            } else {
+                println!("{msg}");
+            }
+        }
+        if EXIT_PENDING.load(std::sync::atomic::Ordering::Acquire) {
+            break;
+        }
+    }
+    if failed {
+        Err(())
     } else {
-        Path::new("./assets/test/passage_wrapped.txt").into()
+        Ok(())
+    }
+}
+
+fn main_loop(args: args::Args, stdin_content: Option<String>) {
+    let (readonly, pager) = (args.readonly, args.pager);
+    // shown once the screen is up
+    let mut open_error = None;
+    let buf = match (args.file, stdin_content) {
+        (_, Some(content)) => buffer::Buffer::from_string(content),
+        (Some(args::FileArg::Path(path)), None) => buffer::Buffer::edit(&path).unwrap_or_else(|e| {
+            open_error = Some(e);
+            buffer::Buffer::new()
+        }),
+        _ => buffer::Buffer::new(),
     };
+    startuptime::mark("load buffer");
+    if let Some(path) = buf.get().path() {
+        recent::add(path);
+    }
 
-    let mut ctx: Ctx = Ctx::from_file(
-        libc::STDIN_FILENO,
-        &path
-    )
-    .unwrap();
+    let mut ctx: Ctx = Ctx::from_buffer(libc::STDIN_FILENO, Arc::clone(&buf));
+    if let Some(e) = open_error {
+        ctx.err(&e);
+    } else if let Some((path, flag)) = buf.get().path().and_then(|p| Some((p, buffer::file_flag(p)?))) {
+        use std::fmt::Write;
+        let _ = write!(ctx.info(), "{path:?} {flag}");
+    } else if buf.get().len() == 0 {
+        ctx.show_welcome();
+    }
+    ctx.options.readonly = readonly;
+    ctx.pager = pager;
+    startuptime::mark("create windows");
+    // removes the socket once the loop is done
+    let _server = match args.listen.as_deref().map(server::listen).transpose() {
+        Ok(server) => server,
+        Err(e) => {
+            ctx.err(&e);
+            None
+        }
+    };
 
     guile::initialize();
+    startuptime::mark("guile init");
+    // after initialization, which may have set 'filelock'
+    ctx.lock_file(&buf);
 
     ctx.render();
-    let mut stdin = std::io::stdin().lock();
+    startuptime::mark("first render");
+    if let Err(e) = startuptime::finish() {
+        use std::fmt::Write;
+        let _ = write!(ctx.warning(), "could not write startup times: {e}");
+        ctx.render();
+    }
+    let mut keys = keymap::Typeahead::new(input::TermReader::new(libc::STDIN_FILENO), true);
+    busy::enable();
+    let mut last_render = std::time::Instant::now();
+    let mut last_key = std::time::Instant::now();
     loop {
-        if let Some(token) = input::handle_input(&ctx, &mut stdin) {
-            ctx.process_action(token);
-            ctx.render();
+        // wake up periodically so background events (e.g. language servers) are handled even
+        // while no keys are pressed, and in time for the next frame if there are changes to draw
+        let mut timeout = if ctx.dirty {
+            FRAME.saturating_sub(last_render.elapsed()).as_millis() as i32
+        } else {
+            EVENT_POLL_MS
         };
+        // and in time to show what can follow the keys of an unfinished command
+        let hints_after = std::time::Duration::from_millis(ctx.options.timeoutlen as u64);
+        let hints_due = !ctx.pending.keys().is_empty() && !ctx.showing_key_hints();
+        if hints_due {
+            timeout = timeout.min(hints_after.saturating_sub(last_key.elapsed()).as_millis() as i32);
+        }
+        let input_ready = keys.poll(timeout);
+        busy::begin();
+        ctx.process_events();
+        if hints_due && !input_ready && last_key.elapsed() >= hints_after {
+            ctx.show_key_hints(&keys.held().unwrap_or_default());
+        }
+        if input_ready {
+            last_key = std::time::Instant::now();
+            ctx.hide_key_hints();
+            // everything that has arrived, e.g. a paste, is handled before drawing once
+            if let Err(e) = keys.fill() {
+                log!("could not read input: {e}");
+            }
+            for _ in 0..MAX_QUEUED_COMMANDS {
+                if !keys.poll(0) || EXIT_PENDING.load(std::sync::atomic::Ordering::Acquire) {
+                    break;
+                }
+                if let Some(token) = input::handle_input(&ctx, &mut keys.reader(&ctx)) {
+                    ctx.process_action(token);
+                }
+            }
+            // the start of a mapping waited `timeoutlen` for more already
+            if let Some(held) = keys.held() {
+                ctx.show_key_hints(&held);
+            }
+            // even a key that didn't complete a command changes what's pending
+            ctx.dirty = true;
+        }
+        // with key repeat, the changes of several keys are drawn at once
+        if ctx.dirty && last_render.elapsed() >= FRAME {
+            ctx.render();
+            last_render = std::time::Instant::now();
+        }
         if EXIT_PENDING.load(std::sync::atomic::Ordering::Acquire) {
             return;
         }
@@ -78,6 +260,48 @@ fn main_loop() {
 }
 
 fn main() -> Result<(), ()> {
+    let start = std::time::Instant::now();
+    let args = match args::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("{e}");
+            return Err(());
+        }
+    };
+    if let Some(socket) = &args.server {
+        return server::send(socket, &args.remote_send).map_err(|e| eprintln!("could not send to {socket:?}: {e}"));
+    }
+    if let Some(report) = &args.startuptime {
+        startuptime::enable(report, start);
+        startuptime::mark("parse arguments");
+    }
+    // this has to happen before we touch the terminal settings, since stdin isn't a terminal yet
+    // the pager reads a pipe even without `-`
+    let piped = args.pager && args.file.is_none() && !nix::unistd::isatty(STDIN_FILENO).unwrap_or(true);
+    let stdin_content = match args.file {
+        // there may not be a terminal at all when running headless
+        Some(args::FileArg::Stdin) if args.headless => Some(read_stdin()),
+        Some(args::FileArg::Stdin) => Some(take_stdin()),
+        None if piped => Some(take_stdin()),
+        _ => None,
+    };
+    let stdin_content = match stdin_content.transpose() {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("could not read stdin: {e}");
+            return Err(());
+        }
+    };
+    if stdin_content.is_some() {
+        startuptime::mark("read stdin");
+    }
+    if args.headless {
+        let res = run_headless(args, stdin_content);
+        let _ = recent::save();
+        debug::cleanup();
+        return res;
+    }
+
     // panic handler is needed because we need to restore the terminal
     let mut guard = ORIGINAL_TERMIOS.lock().unwrap();
     *guard = Some(termios::tcgetattr(STDIN_FILENO).unwrap());
@@ -92,7 +316,8 @@ fn main() -> Result<(), ()> {
     // let mut ctx = Ctx::from_buffer(libc::STDIN_FILENO, buf);
 
 
-    main_loop();
+    main_loop(args, stdin_content);
+    let _ = recent::save();
 
     term::flush();
     term::altbuf_disable();
@@ -0,0 +1,143 @@
+//! `:Man`, reading manual pages in a buffer.
+//!
+//! `man -P cat` formats the page for the width of the window. Its bold and underlined text comes
+//! out as overstrikes, a character typed over itself or over `_` with a backspace between, which
+//! are taken out of the text and put back as highlights, with underlines shown in italics. The
+//! buffer can't be changed, and `K` in it opens the page of the word under the cursor, so that
+//! `SEE ALSO` can be followed.
+use std::ops::Range;
+use std::process::Command;
+
+use crate::tui::Color;
+
+pub const NAMESPACE: &str = "man";
+
+const BOLD: Color = Color { bold: true, ..Color::new() };
+const ITALIC: Color = Color { italic: true, ..Color::new() };
+
+/// the text of a page and where its bold and underlined parts are
+pub type Page = (String, Vec<(Range<usize>, Color)>);
+
+/// the arguments to `man` for `topic`, like `printf`, `3 printf` or `printf(3)`
+pub fn args(topic: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let topic = topic.trim();
+    if topic.is_empty() {
+        return Err("E471: Argument required".into());
+    }
+    if let Some((name, section)) = topic.strip_suffix(')').and_then(|t| t.split_once('(')) {
+        return Ok(vec![section.into(), name.into()]);
+    }
+    Ok(topic.split_whitespace().map(String::from).collect())
+}
+
+/// the text of a page from `man` and the highlights for its bold and underlined parts
+pub fn render(raw: &str) -> Page {
+    let mut text = String::with_capacity(raw.len());
+    let mut highlights: Vec<(Range<usize>, Color)> = Vec::new();
+    let mut chars = raw.chars().peekable();
+    while let Some(c) = chars.next() {
+        let mut style = None;
+        let mut shown = c;
+        // there can be several overstrikes on one character, like `_\bx\bx` for bold underline,
+        // which is shown bold
+        while chars.peek() == Some(&'\x08') {
+            chars.next();
+            let Some(over) = chars.next() else {
+                break;
+            };
+            style = Some(if shown == '_' && over != '_' { ITALIC } else { BOLD });
+            shown = over;
+        }
+        let start = text.len();
+        text.push(shown);
+        let Some(color) = style else {
+            continue;
+        };
+        match highlights.last_mut() {
+            Some((range, last)) if range.end == start && *last == color => range.end = text.len(),
+            _ => highlights.push((start..text.len(), color)),
+        }
+    }
+    (text, highlights)
+}
+
+/// the page for `topic` formatted `width` columns wide
+pub fn page(topic: &str, width: u32) -> Result<Page, Box<dyn std::error::Error>> {
+    let output = Command::new("man")
+        .arg("-P")
+        .arg("cat")
+        .args(args(topic)?)
+        .env("MANWIDTH", width.to_string())
+        // overstrikes rather than escape sequences, even though it isn't writing to a terminal
+        .env("MAN_KEEP_FORMATTING", "1")
+        .env("GROFF_NO_SGR", "1")
+        .output()
+        .map_err(|e| format!("could not run man: {e}"))?;
+    if !output.status.success() || output.stdout.is_empty() {
+        let err = String::from_utf8_lossy(&output.stderr);
+        let err = err.lines().next().unwrap_or("").trim();
+        if err.is_empty() {
+            return Err(format!("no manual entry for {}", topic.trim()).into());
+        }
+        return Err(err.into());
+    }
+    Ok(render(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// the topic under byte `col` of `line`, with its section if it's followed by one like `ls(1)`
+pub fn topic_at(line: &str, col: usize) -> Option<String> {
+    let is_name = |c: char| c.is_alphanumeric() || "_-.:+".contains(c);
+    if !line.get(col..)?.starts_with(is_name) {
+        return None;
+    }
+    let start = line[..col].rfind(|c| !is_name(c)).map_or(0, |i| i + 1);
+    let end = line[col..].find(|c| !is_name(c)).map_or(line.len(), |i| col + i);
+    // a word at the end of a sentence
+    let name = line[start..end].trim_end_matches(['.', ':']);
+    if name.is_empty() {
+        return None;
+    }
+    let section = line[start + name.len()..]
+        .strip_prefix('(')
+        .and_then(|s| s.split_once(')'))
+        .map(|(s, _)| s)
+        .filter(|s| !s.is_empty() && s.starts_with(|c: char| c.is_ascii_digit()));
+    Some(match section {
+        Some(section) => format!("{name}({section})"),
+        None => name.into(),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn topics() {
+        assert_eq!(args("printf").unwrap(), ["printf"]);
+        assert_eq!(args(" 3 printf ").unwrap(), ["3", "printf"]);
+        assert_eq!(args("printf(3)").unwrap(), ["3", "printf"]);
+        assert!(args("  ").is_err());
+    }
+
+    #[test]
+    fn overstrike() {
+        let (text, highlights) = render("N\x08NA\x08AM\x08ME\x08E\n  _\x08f_\x08o  x_\x08_\n");
+        assert_eq!(text, "NAME\n  fo  x_\n");
+        assert_eq!(highlights, [(0..4, BOLD), (7..9, ITALIC), (12..13, BOLD)]);
+        let (text, highlights) = render("_\x08a\x08a é\x08é");
+        assert_eq!(text, "a é");
+        assert_eq!(highlights, [(0..1, BOLD), (2..4, BOLD)]);
+    }
+
+    #[test]
+    fn topic_under_cursor() {
+        let line = "SEE ALSO: fprintf(3), sh(1p) and ls.";
+        assert_eq!(topic_at(line, 12).as_deref(), Some("fprintf(3)"));
+        assert_eq!(topic_at(line, 22).as_deref(), Some("sh(1p)"));
+        assert_eq!(topic_at(line, 33).as_deref(), Some("ls"));
+        assert_eq!(topic_at(line, 3), None);
+        assert_eq!(topic_at(line, 1).as_deref(), Some("SEE"));
+        assert_eq!(topic_at(line, 99), None);
+    }
+}
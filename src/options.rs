@@ -0,0 +1,335 @@
+//! Editor options, set with `:set`.
+//!
+//! Options are declared with the [`options!`] macro, which generates the [`Options`] struct along
+//! with lookup by name. Boolean options are turned on with `:set name` and off with
+//! `:set noname`, everything else takes `:set name=value`. `:set name?` shows the current value.
+//! Numbers can be limited to a range with `#[range(..)]`.
+//!
+//! The [`WinOptions`] are local to a window. `:set` changes them in every window and `:setlocal` only
+//! in the focused one. A window split off from another starts with its values.
+use std::fmt::Write;
+
+use crate::utils::unit_err;
+
+unit_err!(UnknownOption: "unknown option");
+unit_err!(InvalidValue: "invalid value for option");
+
+/// a type that can be the value of an option
+pub trait OptionValue: Sized {
+    fn parse_value(s: &str) -> Option<Self>;
+
+    fn write_value(&self, out: &mut String);
+
+    /// only booleans can be set without a value
+    fn from_bool(_b: bool) -> Option<Self> {
+        None
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        None
+    }
+}
+
+impl OptionValue for bool {
+    fn parse_value(_s: &str) -> Option<Self> {
+        None
+    }
+
+    fn write_value(&self, _out: &mut String) {}
+
+    fn from_bool(b: bool) -> Option<Self> {
+        Some(b)
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        Some(*self)
+    }
+}
+
+impl OptionValue for usize {
+    fn parse_value(s: &str) -> Option<Self> {
+        s.parse().ok()
+    }
+
+    fn write_value(&self, out: &mut String) {
+        let _ = write!(out, "{self}");
+    }
+}
+
+impl OptionValue for String {
+    fn parse_value(s: &str) -> Option<Self> {
+        Some(s.to_owned())
+    }
+
+    fn write_value(&self, out: &mut String) {
+        out.push_str(self);
+    }
+}
+
+/// comma separated list. A literal comma is written as `\,`.
+impl OptionValue for Vec<String> {
+    fn parse_value(s: &str) -> Option<Self> {
+        let mut out = vec![String::new()];
+        let mut chars = s.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' if chars.as_str().starts_with(',') => {
+                    chars.next();
+                    out.last_mut().expect("never empty").push(',');
+                }
+                ',' => out.push(String::new()),
+                c => out.last_mut().expect("never empty").push(c),
+            }
+        }
+        out.retain(|s| !s.is_empty());
+        Some(out)
+    }
+
+    fn write_value(&self, out: &mut String) {
+        for (i, item) in self.iter().enumerate() {
+            if i != 0 {
+                out.push(',');
+            }
+            out.push_str(&item.replace(',', "\\,"));
+        }
+    }
+}
+
+macro_rules! options {
+    ($(#[$attr:meta])* struct $struct:ident {
+        $($(#[doc = $doc:literal])* $(#[range($range:expr)])? $name:ident $(| $short:ident)?: $ty:ty = $default:expr),* $(,)?
+    }) => {
+        $(#[$attr])*
+        pub struct $struct {
+            $($(#[doc = $doc])* pub $name: $ty,)*
+        }
+
+        impl Default for $struct {
+            fn default() -> Self {
+                Self {
+                    $($name: $default,)*
+                }
+            }
+        }
+
+        impl $struct {
+            /// full names of all options
+            pub const NAMES: &'static [&'static str] = &[$(stringify!($name)),*];
+
+            /// set an option from its textual value. `None` is for booleans.
+            fn set_value(&mut self, name: &str, value: Option<&str>) -> Result<(), Box<dyn std::error::Error>> {
+                match name {
+                    $(stringify!($name) $(| stringify!($short))? => {
+                        let v = match value {
+                            Some(v) => <$ty as OptionValue>::parse_value(v),
+                            None => <$ty as OptionValue>::from_bool(true),
+                        };
+                        let v = v.ok_or(InvalidValue)?;
+                        $(if !($range).contains(&v) {
+                            return Err(InvalidValue.into());
+                        })?
+                        self.$name = v;
+                    })*
+                    _ => return Err(UnknownOption.into()),
+                }
+                Ok(())
+            }
+
+            /// whether `name` is a boolean option, which `:set noname` turns off
+            pub fn is_bool(name: &str) -> bool {
+                match name {
+                    $(stringify!($name) $(| stringify!($short))? => <$ty as OptionValue>::from_bool(true).is_some(),)*
+                    _ => false,
+                }
+            }
+
+            fn set_bool(&mut self, name: &str, value: bool) -> Result<(), Box<dyn std::error::Error>> {
+                match name {
+                    $(stringify!($name) $(| stringify!($short))? => {
+                        self.$name = <$ty as OptionValue>::from_bool(value).ok_or(InvalidValue)?;
+                    })*
+                    _ => return Err(UnknownOption.into()),
+                }
+                Ok(())
+            }
+
+            /// the current value of an option, formatted as `:set` shows it
+            pub fn show(&self, name: &str) -> Result<String, UnknownOption> {
+                let mut out = String::new();
+                match name {
+                    $(stringify!($name) $(| stringify!($short))? => {
+                        if let Some(b) = self.$name.as_bool() {
+                            if !b {
+                                out.push_str("no");
+                            }
+                            out.push_str(stringify!($name));
+                        } else {
+                            out.push_str(concat!(stringify!($name), "="));
+                            self.$name.write_value(&mut out);
+                        }
+                    })*
+                    _ => return Err(UnknownOption),
+                }
+                Ok(out)
+            }
+
+            /// apply a single `:set` argument
+            pub fn set(&mut self, arg: &str) -> Result<(), Box<dyn std::error::Error>> {
+                if let Some((name, value)) = arg.split_once('=') {
+                    return self.set_value(name, Some(value));
+                }
+                match self.set_value(arg, None) {
+                    Err(e) if e.is::<UnknownOption>() => {
+                        let name = arg.strip_prefix("no").ok_or(UnknownOption)?;
+                        self.set_bool(name, false)
+                    }
+                    res => res,
+                }
+            }
+        }
+    };
+}
+
+options! {
+    struct Options {
+        /// program run by `:make`
+        makeprg | mp: String = "cargo build".into(),
+        /// patterns used to parse the output of `:make`, see [`crate::errorformat`]
+        errorformat | efm: Vec<String> = crate::errorformat::DEFAULT.iter().map(|s| s.to_string()).collect(),
+        /// refuse to change or write buffers
+        readonly | ro: bool = false,
+        /// what `<leader>` stands for in mappings
+        mapleader: String = crate::keymap::DEFAULT_LEADER.into(),
+        /// milliseconds to wait for the rest of a mapping
+        timeoutlen | tm: usize = 1000,
+        /// how many mappings can expand before giving up on a recursive mapping
+        maxmapdepth | mmd: usize = 1000,
+        /// ignore case in search patterns
+        ignorecase | ic: bool = false,
+        /// with `ignorecase`, don't ignore case in patterns that contain uppercase letters
+        smartcase | scs: bool = false,
+        /// show what `:s` will do while it's being typed
+        inccommand | icm: bool = true,
+        /// program run on every write to check the file, with `%` replaced by its name. Its output
+        /// is parsed with `errorformat` unless it's cargo's JSON messages. Nothing is run when empty.
+        lintprg: String = String::new(),
+        /// lock files while they're open and warn about files locked by another editor
+        filelock | fl: bool = false,
+        /// characters to fill rows with: `eob` after the end of the buffer, `diff` for deleted lines in
+        /// diff mode
+        fillchars | fcs: Vec<String> = vec!["eob:~".into(), "diff:-".into()],
+        /// let the view scroll past the end of the buffer, until the last line is at the top
+        scrollpastend | spe: bool = true,
+        /// run Scheme files loaded by `:source` in a sandbox, without files, processes, jobs or loading
+        /// other modules
+        secure: bool = false,
+        /// width `gq` reflows text to, 79 when it's 0. Text typed past it wraps as set by
+        /// `formatoptions`.
+        textwidth | tw: usize = 0,
+        /// flags for formatting while typing in insert mode with `textwidth` set: `t` wraps text and
+        /// `c` wraps comments
+        formatoptions | fo: String = "tc".into(),
+        /// columns a tab counts for, which `:retab` converts indentation by
+        tabstop | ts: usize = 8,
+        /// type spaces instead of a tab in insert mode, and have `:retab` indent with spaces
+        expandtab | et: bool = false,
+        /// keep buffers with changes in memory when switching away from them, instead of asking to
+        /// write them first or throwing the changes away with `!`
+        hidden | hid: bool = false,
+        /// where the cursor can go past the end of a line: `onemore` for just after the last
+        /// character, `all` for any column
+        virtualedit | ve: String = String::new(),
+        /// formatters for `formatonsave` as `filetype:command` items, see [`crate::formatprg`]
+        formatprg | fp: Vec<String> = Vec::new(),
+        /// filetypes that are run through their `formatprg` before they're written
+        formatonsave | fos: Vec<String> = Vec::new(),
+        /// milliseconds to wait for a `formatprg` before writing without it
+        formattimeout: usize = 3000,
+        /// patterns of files to skip when walking directories, on top of `.gitignore` files, see
+        /// [`crate::ignore`]
+        wildignore | wig: Vec<String> = crate::ignore::DEFAULT.iter().map(|s| s.to_string()).collect(),
+    }
+}
+
+options! {
+    #[derive(Clone)]
+    struct WinOptions {
+        /// show the number of each line
+        number | nu: bool = true,
+        /// show how far lines are from the cursor line. With `number` the cursor line has its own
+        /// number.
+        relativenumber | rnu: bool = true,
+        /// fewest columns the line numbers take, including the space after them. They take more
+        /// when the buffer has lines with more digits.
+        #[range(1..=20)]
+        numberwidth | nuw: usize = 4,
+        /// lines kept in view above and below the cursor
+        scrolloff | so: usize = 0,
+    }
+}
+
+impl WinOptions {
+    /// whether `name` is a window-local option
+    pub fn has(name: &str) -> bool {
+        Self::default().show(name).is_ok()
+    }
+}
+
+impl Options {
+    /// the character `fillchars` has for `item`, or its default
+    pub fn fillchar(&self, item: &str) -> char {
+        let set = self.fillchars.iter().rev().find_map(|f| f.strip_prefix(item)?.strip_prefix(':')?.chars().next());
+        set.unwrap_or(match item {
+            "eob" => '~',
+            "diff" => '-',
+            _ => ' ',
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn set_and_show() {
+        let mut o = Options::default();
+        o.set("mp=make -j4").unwrap();
+        assert_eq!(o.makeprg, "make -j4");
+        assert_eq!(o.show("makeprg").unwrap(), "makeprg=make -j4");
+        assert!(o.set("makeprg").is_err());
+        assert!(o.set("nomakeprg").is_err());
+        assert!(o.set("bogus=1").is_err());
+    }
+
+    #[test]
+    fn window_local() {
+        assert!(WinOptions::has("nu") && WinOptions::has("scrolloff"));
+        assert!(!WinOptions::has("makeprg"));
+        let mut o = WinOptions::default();
+        o.set("nornu").unwrap();
+        assert_eq!(o.show("relativenumber").unwrap(), "norelativenumber");
+        assert!(WinOptions::is_bool("rnu") && !WinOptions::is_bool("numberwidth"));
+        assert!(o.set("nuw=0").is_err() && o.set("nuw=21").is_err());
+        o.set("nuw=20").unwrap();
+        assert_eq!(o.numberwidth, 20);
+    }
+
+    #[test]
+    fn fillchars() {
+        let mut o = Options::default();
+        assert_eq!(o.fillchar("eob"), '~');
+        o.set("fcs=eob: ").unwrap();
+        assert_eq!((o.fillchar("eob"), o.fillchar("diff")), (' ', '-'));
+        o.set("fcs=diff:·").unwrap();
+        assert_eq!(o.fillchar("diff"), '·');
+    }
+
+    #[test]
+    fn list_values() {
+        let mut o = Options::default();
+        o.set(r"efm=%f:%l: %m,%f\,%l").unwrap();
+        assert_eq!(o.errorformat, vec!["%f:%l: %m", "%f,%l"]);
+        assert_eq!(o.show("efm").unwrap(), r"errorformat=%f:%l: %m,%f\,%l");
+    }
+}
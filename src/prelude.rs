@@ -1,7 +1,9 @@
 pub use crate::buffer::BufferInner;
 pub use crate::buffer::Cursor;
 pub use crate::buffer::DocPos;
+pub use crate::buffer::DocRange;
 pub use crate::buffer::FileOff;
 pub use crate::buffer::LinesInclusive;
+pub use crate::error::EditorError;
 pub use crate::render::Ctx;
 pub use crate::tui::{BasicColor, Color, TermGrid};
@@ -0,0 +1,135 @@
+//! Work going on in the background, shown at the right of the status line.
+//!
+//! Anything that runs outside of the main loop, like jobs and language servers, registers a task
+//! with [`start`] and removes it with [`finish`] once it's done. A task without a percentage is
+//! shown with a spinner. When there's more than one the oldest is shown with the number of others.
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use unicode_truncate::UnicodeTruncateStr;
+use unicode_width::UnicodeWidthStr;
+
+/// the spinner, one frame every [`TICK_MS`]
+pub const FRAMES: [char; 10] = ['⠋', '⠙', '⠹', '⠸', '⠼', '⠴', '⠦', '⠧', '⠇', '⠏'];
+pub const TICK_MS: u128 = 100;
+/// the widest a label is shown
+const LABEL_WIDTH: usize = 24;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(usize);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Task {
+    pub label: String,
+    /// how far along it is, `None` if that isn't known
+    pub percent: Option<u8>,
+}
+
+fn tasks_lock() -> &'static Mutex<BTreeMap<TaskId, Task>> {
+    static TASKS: OnceLock<Mutex<BTreeMap<TaskId, Task>>> = OnceLock::new();
+    TASKS.get_or_init(Default::default)
+}
+
+/// set whenever the tasks change, so the status line can be redrawn
+static CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// register a task, which is shown until it's given to [`finish`]
+pub fn start(label: &str) -> TaskId {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let id = TaskId(NEXT.fetch_add(1, Ordering::Relaxed));
+    let task = Task {
+        label: label.to_owned(),
+        percent: None,
+    };
+    tasks_lock().lock().unwrap().insert(id, task);
+    CHANGED.store(true, Ordering::Relaxed);
+    id
+}
+
+/// set how far along task `id` is, clamped to 100. Does nothing if it has finished.
+pub fn report(id: TaskId, percent: Option<u8>) {
+    if let Some(task) = tasks_lock().lock().unwrap().get_mut(&id) {
+        task.percent = percent.map(|p| p.min(100));
+        CHANGED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// remove task `id`, finishing it twice is fine
+pub fn finish(id: TaskId) {
+    if tasks_lock().lock().unwrap().remove(&id).is_some() {
+        CHANGED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// the running tasks, oldest first
+pub fn tasks() -> Vec<Task> {
+    tasks_lock().lock().unwrap().values().cloned().collect()
+}
+
+/// whether the tasks changed since the last call
+pub fn take_changed() -> bool {
+    CHANGED.swap(false, Ordering::Relaxed)
+}
+
+/// the frame of the spinner for now
+pub fn frame() -> usize {
+    static EPOCH: OnceLock<Instant> = OnceLock::new();
+    (EPOCH.get_or_init(Instant::now).elapsed().as_millis() / TICK_MS) as usize
+}
+
+/// the status line segment for `tasks` with the spinner at `frame`, `None` if there aren't any
+pub fn segment(tasks: &[Task], frame: usize) -> Option<String> {
+    let first = tasks.first()?;
+    let label = if first.label.width() > LABEL_WIDTH {
+        format!("{}…", first.label.unicode_truncate(LABEL_WIDTH - 1).0)
+    } else {
+        first.label.clone()
+    };
+    let mut out = match first.percent {
+        Some(p) => format!("{p}% {label}"),
+        None => format!("{} {label}", FRAMES[frame % FRAMES.len()]),
+    };
+    if tasks.len() > 1 {
+        out.push_str(&format!(" (+{})", tasks.len() - 1));
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn task(label: &str, percent: Option<u8>) -> Task {
+        Task {
+            label: label.into(),
+            percent,
+        }
+    }
+
+    #[test]
+    fn segments() {
+        assert_eq!(segment(&[], 0), None);
+        assert_eq!(segment(&[task("cargo build", None)], 1).as_deref(), Some("⠙ cargo build"));
+        assert_eq!(segment(&[task("cargo build", None)], 11).as_deref(), Some("⠙ cargo build"));
+        let both = [task("indexing", Some(42)), task("make", None)];
+        assert_eq!(segment(&both, 0).as_deref(), Some("42% indexing (+1)"));
+        let long = segment(&[task(&"x".repeat(40), Some(5))], 0).unwrap();
+        assert_eq!(long, format!("5% {}…", "x".repeat(LABEL_WIDTH - 1)));
+    }
+
+    #[test]
+    fn lifetime() {
+        let id = start("progress test");
+        let find = || tasks().into_iter().find(|t| t.label == "progress test");
+        assert_eq!(find(), Some(task("progress test", None)));
+        report(id, Some(150));
+        assert_eq!(find().unwrap().percent, Some(100));
+        finish(id);
+        assert_eq!(find(), None);
+        finish(id);
+        report(id, Some(1));
+        assert_eq!(find(), None);
+    }
+}
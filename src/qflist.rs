@@ -0,0 +1,139 @@
+//! Lists of file locations, as used by the location list. Items are kept in the order they were
+//! given and the list remembers which one was last visited.
+use std::fmt::Write;
+use std::path::PathBuf;
+
+use crate::tui::TextSeverity;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QfItem {
+    pub path: PathBuf,
+    /// zero-indexed line
+    pub line: usize,
+    /// zero-indexed byte column
+    pub col: usize,
+    pub text: String,
+    pub severity: TextSeverity,
+}
+
+/// which list a command operates on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ListKind {
+    /// global, filled by `:make`
+    Quickfix,
+    /// diagnostics of the focused buffer
+    Location,
+}
+
+#[derive(Debug, Default)]
+pub struct QfList {
+    items: Vec<QfItem>,
+    idx: usize,
+}
+
+impl QfList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&mut self, items: Vec<QfItem>) {
+        self.items = items;
+        self.idx = 0;
+    }
+
+    pub fn clear(&mut self) {
+        self.set(Vec::new())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn items(&self) -> &[QfItem] {
+        &self.items
+    }
+
+    pub fn current(&self) -> Option<&QfItem> {
+        self.items.get(self.idx)
+    }
+
+    /// index of the current item
+    pub fn idx(&self) -> usize {
+        self.idx
+    }
+
+    pub fn next(&mut self) -> Option<&QfItem> {
+        if self.idx + 1 >= self.items.len() {
+            return None;
+        }
+        self.idx += 1;
+        self.current()
+    }
+
+    pub fn prev(&mut self) -> Option<&QfItem> {
+        self.idx = self.idx.checked_sub(1)?;
+        self.current()
+    }
+
+    /// write a listing of every item, marking the current one
+    pub fn write_listing(&self, out: &mut impl Write) -> std::fmt::Result {
+        for (i, item) in self.items.iter().enumerate() {
+            let mark = if i == self.idx { '>' } else { ' ' };
+            write!(
+                out,
+                "{mark}{:>3} {}:{}:{}: {}",
+                i + 1,
+                item.path.display(),
+                item.line + 1,
+                item.col + 1,
+                item.text
+            )?;
+            if i + 1 != self.items.len() {
+                out.write_char('\n')?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn item(line: usize) -> QfItem {
+        QfItem {
+            path: "a.rs".into(),
+            line,
+            col: 0,
+            text: format!("item {line}"),
+            severity: TextSeverity::Error,
+        }
+    }
+
+    #[test]
+    fn navigation_is_bounded() {
+        let mut l = QfList::new();
+        assert!(l.current().is_none());
+        assert!(l.next().is_none());
+        l.set(vec![item(0), item(1)]);
+        assert_eq!(l.current().map(|i| i.line), Some(0));
+        assert!(l.prev().is_none());
+        assert_eq!(l.next().map(|i| i.line), Some(1));
+        assert!(l.next().is_none());
+        assert_eq!(l.current().map(|i| i.line), Some(1));
+        assert_eq!(l.prev().map(|i| i.line), Some(0));
+    }
+
+    #[test]
+    fn listing() {
+        let mut l = QfList::new();
+        l.set(vec![item(0), item(4)]);
+        let mut s = String::new();
+        l.write_listing(&mut s).unwrap();
+        assert_eq!(s, ">  1 a.rs:1:1: item 0\n   2 a.rs:5:1: item 4");
+    }
+}
@@ -0,0 +1,96 @@
+//! Files that were opened recently, for the welcome screen.
+//!
+//! The list is kept in `$XDG_STATE_HOME/edit/recent`, one path per line with the most recent
+//! first. It's read once at startup, so the welcome screen shows the files from before this
+//! session, and the files opened since are only added when the editor exits, see [`save`].
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// how many files are remembered
+const MAX: usize = 20;
+
+fn list_path() -> Option<PathBuf> {
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&std::env::var_os("HOME")?).join(".local/state"),
+    };
+    Some(state.join("edit/recent"))
+}
+
+fn read() -> Vec<PathBuf> {
+    let Some(content) = list_path().and_then(|p| std::fs::read_to_string(p).ok()) else {
+        return Vec::new();
+    };
+    content.lines().filter(|l| !l.is_empty()).map(PathBuf::from).collect()
+}
+
+/// files opened in this session, the most recent first
+static OPENED: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// the files that were recent when the editor started and still existed then
+pub fn at_startup() -> &'static [PathBuf] {
+    static LIST: OnceLock<Vec<PathBuf>> = OnceLock::new();
+    LIST.get_or_init(|| read().into_iter().filter(|p| p.is_file()).collect())
+}
+
+/// move `path` to the front of `list`
+fn push(list: &mut Vec<PathBuf>, path: &Path) {
+    list.retain(|p| p != path);
+    list.insert(0, path.to_owned());
+    list.truncate(MAX);
+}
+
+/// `path` as it's remembered, so that the list still works from another directory. A file that
+/// doesn't exist yet can't be canonicalized, it's only made absolute.
+fn full_path(path: &Path) -> PathBuf {
+    path.canonicalize().or_else(|_| std::path::absolute(path)).unwrap_or_else(|_| path.to_owned())
+}
+
+/// remember that `path` was opened
+pub fn add(path: &Path) {
+    push(&mut OPENED.lock().unwrap(), &full_path(path));
+}
+
+/// add the files opened in this session to the list on disk. Another editor may have written it in
+/// the meantime, so it's read again first.
+pub fn save() -> std::io::Result<()> {
+    let opened = std::mem::take(&mut *OPENED.lock().unwrap());
+    if opened.is_empty() {
+        return Ok(());
+    }
+    let file = list_path().ok_or(std::io::ErrorKind::NotFound)?;
+    let mut list = read();
+    for path in opened.iter().rev() {
+        push(&mut list, path);
+    }
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let content: String = list.iter().map(|p| format!("{}\n", p.display())).collect();
+    std::fs::write(file, content)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn most_recent_first() {
+        let mut list = vec![PathBuf::from("/a"), PathBuf::from("/b")];
+        push(&mut list, Path::new("/b"));
+        assert_eq!(list, [PathBuf::from("/b"), PathBuf::from("/a")]);
+        for i in 0..MAX {
+            push(&mut list, Path::new(&format!("/{i}")));
+        }
+        assert_eq!(list.len(), MAX);
+        assert_eq!(list[0], Path::new(&format!("/{}", MAX - 1)));
+    }
+
+    #[test]
+    fn absolute_paths() {
+        let recent = Path::new("src/recent.rs").canonicalize().unwrap();
+        assert_eq!(full_path(Path::new("src/../src/recent.rs")), recent);
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(full_path(Path::new("no/such/file")), cwd.join("no/such/file"));
+    }
+}
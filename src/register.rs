@@ -0,0 +1,167 @@
+//! Registers, named places to keep text.
+//!
+//! `"` is the unnamed register, which holds the last deleted or changed text. Deletes of more than
+//! one line also go to `1`, after shifting `1`–`8` into `2`–`9` like a kill ring, and smaller
+//! deletes go to `-`. A delete into a named register doesn't touch the numbered ones. `/` holds the
+//! last search pattern and `:` the last ex command.
+use std::collections::HashMap;
+
+#[derive(Debug, Clone)]
+pub struct EmptyRegister(pub char);
+
+impl std::fmt::Display for EmptyRegister {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "E353: Nothing in register {}", self.0)
+    }
+}
+
+impl std::error::Error for EmptyRegister {}
+
+#[derive(Debug, Default)]
+pub struct Registers {
+    regs: HashMap<char, String>,
+}
+
+impl Registers {
+    pub fn get(&self, name: char) -> Option<&str> {
+        self.regs.get(&name).map(String::as_str)
+    }
+
+    /// set a register, an uppercase name appends to the lowercase one
+    pub fn set(&mut self, name: char, text: impl Into<String>) {
+        let text = text.into();
+        if name.is_ascii_uppercase() {
+            self.regs.entry(name.to_ascii_lowercase()).or_default().push_str(&text);
+        } else if name != '_' {
+            self.regs.insert(name, text);
+        }
+    }
+
+    /// record text that was deleted or changed, into `register` if one was given
+    pub fn deleted(&mut self, register: Option<char>, text: String) {
+        if text.is_empty() || register == Some('_') {
+            return;
+        }
+        match register {
+            Some(name) if name != '"' => self.set(name, text.clone()),
+            _ if text.contains('\n') => {
+                for i in (1..9).rev() {
+                    if let Some(older) = self.regs.remove(&digit(i)) {
+                        self.regs.insert(digit(i + 1), older);
+                    }
+                }
+                self.set('1', text.clone());
+            }
+            _ => self.set('-', text.clone()),
+        }
+        self.set('"', text);
+    }
+}
+
+/// order of the registers in `:registers`
+const ORDER: &str = "\"0123456789abcdefghijklmnopqrstuvwxyz-:/";
+
+/// longest contents shown in `:registers`, in characters
+const MAX_SHOWN: usize = 70;
+
+impl Registers {
+    /// the table of `:registers`, with only the registers in `names` if it's given
+    pub fn listing(&self, names: Option<&str>) -> String {
+        let mut out = String::from("Type Name Content\n");
+        let shown = ORDER.chars().filter(|c| names.is_none_or(|n| n.contains(*c)));
+        for name in shown {
+            let Some(text) = self.get(name).filter(|t| !t.is_empty()) else {
+                continue;
+            };
+            let kind = if text.ends_with('\n') { 'l' } else { 'c' };
+            out.push_str(&format!("  {kind}  \"{name}   {}\n", visible(text, MAX_SHOWN)));
+        }
+        out
+    }
+}
+
+/// `text` with control characters as `^X`, cut to `max` characters
+fn visible(text: &str, max: usize) -> String {
+    let mut out = String::new();
+    for c in text.chars() {
+        if c.is_ascii_control() {
+            out.push('^');
+            out.push(char::from(c as u8 ^ 0x40));
+        } else {
+            out.push(c);
+        }
+    }
+    match out.char_indices().nth(max) {
+        Some((i, _)) => out[..i].to_owned(),
+        None => out,
+    }
+}
+
+/// `lines` with their indentation changed so that the first line that isn't blank has `indent`,
+/// keeping the indentation of the others relative to it. Lines indented less than the first are
+/// put at `indent`.
+pub fn reindent(lines: &str, indent: &str) -> String {
+    let leading = |l: &str| l.len() - l.trim_start().len();
+    let Some(first) = lines.lines().find(|l| !l.trim().is_empty()) else {
+        return lines.to_owned();
+    };
+    let from = &first[..leading(first)];
+    lines
+        .split_inclusive('\n')
+        .map(|l| {
+            if l.trim().is_empty() {
+                return l.trim_start_matches([' ', '\t']).to_owned();
+            }
+            let rest = l.strip_prefix(from).unwrap_or(&l[leading(l)..]);
+            format!("{indent}{rest}")
+        })
+        .collect()
+}
+
+fn digit(i: u32) -> char {
+    char::from_digit(i, 10).expect("register numbers are single digits")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn numbered_deletes() {
+        let mut regs = Registers::default();
+        for i in 0..10 {
+            regs.deleted(None, format!("line {i}\n"));
+        }
+        regs.deleted(None, "word".into());
+        assert_eq!(regs.get('1'), Some("line 9\n"));
+        assert_eq!(regs.get('9'), Some("line 1\n"));
+        assert_eq!(regs.get('-'), Some("word"));
+        assert_eq!(regs.get('"'), Some("word"));
+        regs.deleted(Some('a'), "named\n".into());
+        regs.deleted(Some('A'), "more\n".into());
+        assert_eq!(regs.get('a'), Some("named\nmore\n"));
+        assert_eq!(regs.get('1'), Some("line 9\n"));
+        regs.deleted(Some('_'), "gone\n".into());
+        assert_eq!(regs.get('"'), Some("more\n"));
+    }
+
+    #[test]
+    fn listing() {
+        let mut regs = Registers::default();
+        regs.deleted(None, "one\ttwo\n".into());
+        regs.set('/', "x".repeat(100));
+        assert_eq!(
+            regs.listing(None),
+            format!("Type Name Content\n  l  \"\"   one^Itwo^J\n  l  \"1   one^Itwo^J\n  c  \"/   {}\n", "x".repeat(70))
+        );
+        assert_eq!(regs.listing(Some("/a")), format!("Type Name Content\n  c  \"/   {}\n", "x".repeat(70)));
+    }
+
+    #[test]
+    fn reindented() {
+        let text = "        if x {\n            y();\n\n        }\n";
+        assert_eq!(reindent(text, "    "), "    if x {\n        y();\n\n    }\n");
+        assert_eq!(reindent("  a\nb\n", "\t"), "\ta\n\tb\n");
+        assert_eq!(reindent("\n", "  "), "\n");
+    }
+}
@@ -1,33 +1,66 @@
 use crate::command::cmdline::CommandLine;
-use crate::command::cmdline::CommandLineInput;
+use crate::command::cmdline::{CommandLineInput, CommandType};
+use crate::blame::Blame;
 use crate::debug::log;
+use crate::buffer::extmark::Extmark;
+use crate::buffer::lock::FileLock;
+use crate::diff::{DiffView, Side};
 use crate::input::Action;
 use crate::input::Operation;
+use crate::input::PendingKeys;
+use crate::input::Repeat;
+use crate::event::Event;
+use crate::register::EmptyRegister;
+use crate::confirm::{Answer, Confirm, Substitution};
+use crate::error::EditorError;
+use crate::job::{JobEventKind, Sink};
+use crate::lsp::{LspEvent, LspManager};
+use crate::job::JobId;
+use crate::keymap::{KeyMap, Scope, Typeahead};
+use crate::options::Options;
+use crate::qflist::{ListKind, QfItem, QfList};
+use crate::register::Registers;
+use crate::command::range::LineRange;
+use crate::command::Command;
+use crate::search::{self, Offset, Preview, Search};
+use crate::terminal::{Status, Terminal};
 use crate::textobj::Motion;
 
 use crate::term;
-use crate::tui::TermBox;
+use crate::tui::{TermBox, TermSz};
 use crate::tui::TermGrid;
-use crate::tui::TextSeverity;
-use crate::utils::AtomicArc;
+use crate::tui::{Owner, TextSeverity};
 use crate::window::*;
+use crate::window::org::{Layout, LayoutBuf};
 use crate::Color;
 use crate::{buffer::*, Mode};
 
 use nix::sys::termios;
 use nix::sys::termios::{LocalFlags, Termios};
-use std::cell::RefCell;
-use std::collections::BTreeMap;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashSet};
 use std::fmt::Display;
 use std::fmt::Write;
 use std::ops::Range;
 use std::os::unix::io::RawFd;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::sync::RwLockReadGuard;
 use std::sync::RwLockWriteGuard;
+use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+/// the smallest screen the first window and the command line are made for, with room for the sign
+/// column, line numbers and a cell of text. Smaller screens show a note until they grow.
+const MIN_SCREEN: TermSz = TermSz::new(8, 3);
+
+/// how deep registers run with `@` can run each other
+const MAX_EXECUTE_DEPTH: usize = 100;
+/// how deep `:command` aliases can run each other, every level is a whole ex command on the stack
+const MAX_ALIAS_DEPTH: usize = 20;
+/// lines of text in the window opened by `q:`, like Vim's default 'cmdwinheight'
+const COMMAND_WINDOW_HEIGHT: u32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct BufId {
     id: u64,
 }
@@ -42,20 +75,80 @@ impl BufId {
         let id = ANON_ID_COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
         BufId { id }
     }
+
+    /// id from [`BufId::id`], which may not belong to any buffer
+    pub fn from_id(id: u64) -> Self {
+        BufId { id }
+    }
 }
 
-pub static CURRENT_BUF: AtomicArc<Buffer> = AtomicArc::new();
+/// the window opened by `q:`, see [`Ctx::open_command_window`]
+struct CommandWindow {
+    win: Arc<Window>,
+    /// the window that was focused before, where the command runs
+    prev: Arc<Window>,
+    alternate: Option<Arc<Buffer>>,
+}
 
 pub struct Ctx {
-    first_buffer: Arc<Buffer>,
-    last_buffer: Arc<Buffer>,
     termios: Termios,
     orig_termios: Termios,
     command_line: CommandLine,
     focused_buf: Arc<Buffer>,
+    /// buffers opened for a file, kept in memory until `:bdelete`
+    listed: Vec<Arc<Buffer>>,
+    /// the buffer that was focused before, `#` in ex commands
+    alternate_buf: Option<Arc<Buffer>>,
     focused_win: Arc<Window>,
     root: crate::window::org::Node,
+    lsp: LspManager,
+    popup: Option<Popup>,
+    /// what can follow the keys of an unfinished command, shown after waiting `timeoutlen`
+    key_hints: Option<Popup>,
+    command_window: Option<CommandWindow>,
+    pub loclist: QfList,
+    pub qflist: QfList,
+    pub options: Options,
+    /// the running `:make` job and its output so far
+    make: Option<(JobId, Vec<String>)>,
+    /// the running `lintprg` and its output so far
+    lint: Option<(JobId, Vec<String>)>,
+    blame: Option<Blame>,
+    /// buffers showing a page from `:Man`, which can't be changed
+    man_pages: HashSet<BufId>,
+    /// output of jobs started from the command line
+    pub job_log: Arc<Buffer>,
+    terminals: Vec<Terminal>,
+    diff: Option<DiffView>,
+    last_search: Option<Search>,
+    preview: Option<Preview>,
+    /// running as `--pager`
+    pub pager: bool,
+    /// start of a normal mode command that is waiting for more keys
+    pub pending: PendingKeys,
+    /// the last change, for `.`
+    pub repeat: Repeat,
+    pub keymap: KeyMap,
+    pub registers: Registers,
+    /// the register last run with `@`, for `@@`
+    last_executed: Option<char>,
+    /// how many registers are running inside each other, to stop a register that runs itself
+    executing: usize,
+    /// the same for `:command` aliases
+    aliases: usize,
+    /// `Ctrl-o` was typed in insert mode, so the next normal mode command goes back to it
+    pub insert_once: bool,
+    /// the question on the command line that's waiting for a key
+    confirm: Option<Confirm>,
+    /// when Ctrl-c last had nothing to interrupt, so that pressing it twice leaves the editor. Any
+    /// other key clears it.
+    pub last_interrupt: Cell<Option<Instant>>,
+    /// the background tasks as of the last [`Ctx::process_events`], with the frame of the spinner
+    pub progress: Vec<crate::progress::Task>,
+    pub spinner: usize,
     pub tui: RefCell<TermGrid>,
+    /// something changed since the last render
+    pub dirty: bool,
     pub term_fd: RawFd,
     pub mode: Mode,
 }
@@ -71,19 +164,53 @@ impl Ctx {
         let termios = termios::tcgetattr(term).unwrap();
         let tui = TermGrid::new();
         let window = Window::new(tui.bounds(), Arc::clone(&buf));
-        Self {
-            first_buffer: Arc::clone(&buf),
-            last_buffer: Arc::clone(&buf),
+        focus::set(&buf);
+        focus::set_window(&window);
+        let ret = Self {
             termios: termios.clone(),
             orig_termios: termios,
             term_fd: term,
             command_line: CommandLine::new(&tui),
             tui: tui.into(),
+            dirty: true,
             mode: Mode::Normal,
             focused_buf: buf,
+            listed: Vec::new(),
+            alternate_buf: None,
             focused_win: Arc::clone(&window),
             root: window.into(),
-        }
+            lsp: LspManager::new(),
+            popup: None,
+            key_hints: None,
+            command_window: None,
+            loclist: QfList::new(),
+            qflist: QfList::new(),
+            options: Options::default(),
+            make: None,
+            lint: None,
+            blame: None,
+            man_pages: HashSet::new(),
+            job_log: Buffer::scratch(),
+            terminals: Vec::new(),
+            diff: None,
+            last_search: None,
+            preview: None,
+            pager: false,
+            pending: PendingKeys::default(),
+            repeat: Repeat::default(),
+            keymap: KeyMap::default(),
+            registers: Registers::default(),
+            last_executed: None,
+            executing: 0,
+            aliases: 0,
+            last_interrupt: Cell::new(None),
+            insert_once: false,
+            confirm: None,
+            progress: Vec::new(),
+            spinner: 0,
+        };
+        ret.layout_changed();
+        ret
     }
 }
 
@@ -102,29 +229,87 @@ impl Ctx {
         termios.local_flags.remove(LocalFlags::ECHO);
         termios.local_flags.insert(LocalFlags::ISIG);
         termios::tcsetattr(term, termios::SetArg::TCSANOW, &termios).unwrap();
-        let tui = TermGrid::new();
-        let components = vec![crate::window::Component::RelLineNumbers];
+        crate::startuptime::mark("termios setup");
+        let mut ret = Self::with_termios(term, termios, orig, buf, TermGrid::new());
+        ret.lsp.attach(&ret.focused_buf);
+        ret
+    }
+
+    /// a context that never touches the terminal, for `--headless`
+    pub fn headless(buf: Arc<Buffer>) -> Self {
+        // SAFETY: termios is plain data. It is never applied, since there is no terminal.
+        let termios = Termios::from(unsafe { std::mem::zeroed::<libc::termios>() });
+        Self::with_termios(-1, termios.clone(), termios, buf, TermGrid::new())
+    }
+
+    /// a headless context with a screen of `w` by `h` cells, for tests of what [`Ctx::draw`] puts
+    /// on it
+    #[cfg(test)]
+    pub fn headless_sized(buf: Arc<Buffer>, w: u32, h: u32) -> Self {
+        // SAFETY: as in `headless`
+        let termios = Termios::from(unsafe { std::mem::zeroed::<libc::termios>() });
+        Self::with_termios(-1, termios.clone(), termios, buf, TermGrid::with_size(w, h))
+    }
+
+    fn with_termios(term: RawFd, termios: Termios, orig: Termios, buf: Arc<Buffer>, tui: TermGrid) -> Self {
+        let components = vec![
+            crate::window::Component::SignColumn,
+            crate::window::Component::LineNumbers,
+        ];
         let window = Window::new_withdim(
             term::TermPos { x: 0, y: 0 },
-            tui.dim().0,
-            tui.dim().1 - 2,
+            tui.dim().0.max(MIN_SCREEN.w),
+            tui.dim().1.max(MIN_SCREEN.h) - 2,
             components,
             Arc::clone(&buf),
         );
         let mut ret = Self {
-            first_buffer: Arc::clone(&buf),
-            last_buffer: Arc::clone(&buf),
             termios,
             orig_termios: orig,
             term_fd: term,
             mode: Mode::Normal,
             command_line: CommandLine::new(&tui),
             tui: tui.into(),
+            dirty: true,
             focused_win: Arc::clone(&window),
             focused_buf: Arc::clone(&buf),
+            listed: Vec::new(),
+            alternate_buf: None,
             root: window.into(),
+            lsp: LspManager::new(),
+            popup: None,
+            key_hints: None,
+            command_window: None,
+            loclist: QfList::new(),
+            qflist: QfList::new(),
+            options: Options::default(),
+            make: None,
+            lint: None,
+            blame: None,
+            man_pages: HashSet::new(),
+            job_log: Buffer::scratch(),
+            terminals: Vec::new(),
+            diff: None,
+            last_search: None,
+            preview: None,
+            pager: false,
+            pending: PendingKeys::default(),
+            repeat: Repeat::default(),
+            keymap: KeyMap::default(),
+            registers: Registers::default(),
+            last_executed: None,
+            executing: 0,
+            aliases: 0,
+            last_interrupt: Cell::new(None),
+            insert_once: false,
+            confirm: None,
+            progress: Vec::new(),
+            spinner: 0,
         };
-        // do this to set static
+        if buf.get().path().is_some() {
+            ret.listed.push(Arc::clone(&buf));
+        }
+        // do this to set the focus for Guile
         ret.set_focused_buf(buf);
         ret
     }
@@ -133,54 +318,1115 @@ impl Ctx {
         self.command_line.get_type()
     }
 
+    /// draw the screen and write it to the terminal
     pub fn render(&mut self) {
+        self.draw();
+        let mut stdout = std::io::stdout().lock();
+        self.tui.get_mut().render(&mut stdout).unwrap();
+    }
+
+    /// draw everything to [`Ctx::tui`] without writing it to the terminal
+    pub fn draw(&mut self) {
+        debug_assert_eq!(focus::id(), Some(self.focused_buf.id()), "Guile's focus went stale");
+        self.dirty = false;
         {
             let tui = self.tui.get_mut();
-            if tui.resize_auto() {
+            tui.set_painter(None);
+            let resized = tui.resize_auto();
+            let b = tui.bounds();
+            let min = self.root.min_size();
+            // the command line needs the bottom two rows, with a column for `:` and one for text.
+            // The windows keep their old size until there's room for them again.
+            if b.xlen() < min.w.max(2) || b.ylen() < min.h + 2 {
+                tui.clear();
+                tui.write_line(0, .., Color::default(), "screen too small");
+                tui.set_cursorpos(term::TermPos { x: 0, y: 0 });
+                return;
+            }
+            if resized {
                 self.command_line.reset_visual(tui);
-                let b = tui.bounds();
                 self.root.fit(TermBox::from_ranges(b.xrng(), 0..(b.ylen() - 2)));
             }
         }
         self.command_line.take_general_input(&self.tui.get_mut());
+        if let Some(diff) = &mut self.diff {
+            diff.update();
+        }
+        if let Some(blame) = self.blame.as_mut().filter(|b| b.buf == self.focused_buf.id()) {
+            blame.update(&mut self.focused_buf.get_mut());
+        }
+        for win in self.root.windows() {
+            let lines = win.get().buffer.get().linecnt();
+            win.get_mut().fit_lines(lines);
+        }
         self.root.draw(self);
+        let terminal = self.mode == Mode::Terminal;
+        if terminal {
+            let bounds = self.focused_win.get().inner_bounds();
+            let focused = &self.focused_buf;
+            self.tui.get_mut().set_painter(Some(Owner::Window(self.focused_win.get().id())));
+            if let Some(term) = self.terminals.iter_mut().find(|t| Arc::ptr_eq(t.buffer(), focused)) {
+                term.resize(bounds.xlen(), bounds.ylen());
+                term.draw(self.tui.get_mut(), bounds);
+            }
+        }
+        self.tui.get_mut().set_painter(Some(Owner::Popup));
+        if let Some(popup) = &self.popup {
+            popup.draw(self);
+        }
+        if let Some(hints) = &self.key_hints {
+            hints.draw(self);
+        }
+        self.tui.get_mut().set_painter(Some(Owner::CommandLine));
         let _ = self.command_line.render(self);
 
         match self.mode {
             Mode::Normal | Mode::Insert => {
+                let side = self.diff_side();
                 let tui = self.tui.get_mut();
-                self.focused_win.get().draw_cursor(tui);
+                let win = self.focused_win.get();
+                match self.diff.as_ref().zip(side) {
+                    Some((diff, side)) => {
+                        // filler rows above the cursor move it down
+                        let buf = self.focused_buf.get();
+                        let cursor = buf.cursor;
+                        let row = diff.row_of(side, cursor.pos.y) - diff.top;
+                        let row = (row as u32).min(win.height().saturating_sub(1));
+                        let x = (crate::window::x_to_col(buf.line(cursor.pos.y), cursor.pos.x, win.tabstop) + cursor.coladd) as u32;
+                        let bounds = win.inner_bounds();
+                        tui.set_cursorpos(term::TermPos {
+                            x: (x + bounds.start.x).min(bounds.end.x.saturating_sub(1)),
+                            y: row + bounds.start.y,
+                        });
+                    }
+                    None => win.draw_cursor(tui),
+                }
             }
             Mode::Command => {
                 let tui = self.tui.get_mut();
                 self.command_line.draw_cursor(tui)
             }
+            // placed when the screen was drawn
+            Mode::Terminal => (),
         }
+    }
 
-        let mut stdout = std::io::stdout().lock();
-        self.tui.get_mut().render(&mut stdout).unwrap();
+    pub fn focused_win(&self) -> RwLockReadGuard<'_, WindowInner> {
+        self.focused_win.get()
+    }
+
+    pub fn focused_win_mut(&self) -> RwLockWriteGuard<'_, WindowInner> {
+        self.focused_win.get_mut()
+    }
+
+    /// every window, in the order of the layout
+    pub fn windows(&self) -> Vec<Arc<Window>> {
+        self.root.windows()
     }
 
     pub fn focused_buf(&self) -> RwLockReadGuard<BufferInner> {
         self.focused_buf.get()
     }
 
-    /// sets the focused buffer - buffer must already have been registered
+    pub fn focused_buf_id(&self) -> BufId {
+        self.focused_buf.id()
+    }
+
+    /// the scopes of the mappings for the focused buffer, see [`Scope::of`]
+    pub fn keymap_scopes(&self) -> Vec<Scope> {
+        Scope::of(self.focused_buf_id(), self.focused_buf().filetype())
+    }
+
+    pub fn focused_buf_mut(&self) -> RwLockWriteGuard<'_, BufferInner> {
+        self.focused_buf.get_mut()
+    }
+
+    /// sets the focused buffer - buffer must already have been registered. The focused window
+    /// remembers where it was in the buffer it leaves and goes back to where it was in `buf`.
     pub fn set_focused_buf(&mut self, buf: Arc<Buffer>) {
-        CURRENT_BUF.set(Arc::clone(&buf));
-        if std::ptr::eq(&*buf, &*self.focused_buf) {
-            return
+        focus::set(&buf);
+        focus::set_window(&self.focused_win);
+        if !std::ptr::eq(&*buf, &*self.focused_buf) {
+            let mut win = self.focused_win.get_mut();
+            // unless the window was just focused and shows another buffer already
+            if Arc::ptr_eq(&win.buffer, &self.focused_buf) {
+                win.save_view();
+            }
+            self.alternate_buf = Some(std::mem::replace(&mut self.focused_buf, Arc::clone(&buf)));
+            win.buffer = buf;
+            win.restore_view();
         }
-        self.focused_buf = Arc::clone(&buf);
-        self.focused_win.get_mut().buffer = buf;
+        // every change to the window tree ends with focusing a window
+        self.layout_changed();
+    }
+
+    /// give Guile the shape of the window tree after a window was split, closed or shows another
+    /// buffer
+    fn layout_changed(&self) {
+        focus::set_layout(self.root.shape());
+    }
+
+    /// `Ctrl-^`, edit the alternate buffer
+    fn edit_alternate(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let buf = self.alternate_buf.clone().ok_or("E23: No alternate file")?;
+        let abandoned = self.abandon(false)?.filter(|b| !Arc::ptr_eq(b, &buf));
+        self.open_buffer(buf);
+        if let Some(abandoned) = abandoned {
+            self.delete_buffer(&abandoned, true)?;
+        }
+        Ok(())
+    }
+
+    pub fn alternate_buf(&self) -> Option<&Arc<Buffer>> {
+        self.alternate_buf.as_ref()
     }
 
     pub fn open_buffer(&mut self, buf: Arc<Buffer>) {
-        self.last_buffer = Arc::clone(&buf);
+        if let Some(path) = buf.get().path() {
+            crate::recent::add(path);
+            if !self.listed.iter().any(|b| Arc::ptr_eq(b, &buf)) {
+                self.listed.push(Arc::clone(&buf));
+            }
+        }
+        self.lock_file(&buf);
+        self.lsp.attach(&buf);
         self.set_focused_buf(buf);
         self.tui.borrow_mut().clear();
     }
 
+    /// the buffer already open for `path`
+    pub fn listed_buffer(&self, path: &Path) -> Option<Arc<Buffer>> {
+        self.listed.iter().find(|b| b.get().path() == Some(path)).cloned()
+    }
+
+    /// check that the focused buffer can be switched away from. With `hidden` or when another
+    /// window shows it it stays as it is, otherwise it needs its changes written first, or `force`
+    /// to throw them away. Returns the buffer to close once it's no longer shown in the latter
+    /// case.
+    pub fn abandon(&self, force: bool) -> Result<Option<Arc<Buffer>>, Box<dyn std::error::Error>> {
+        let buf = &self.focused_buf;
+        let shown = self.root.windows().iter().filter(|w| Arc::ptr_eq(&w.get().buffer, buf)).count();
+        if self.options.hidden || shown > 1 || !buf.get().is_modified() {
+            return Ok(None);
+        }
+        if !force {
+            return Err("E37: No write since last change (add ! to override)".into());
+        }
+        Ok(Some(Arc::clone(buf)))
+    }
+
+    /// close `buf`: every window showing it switches to the alternate buffer, another buffer with a
+    /// file, or an empty one, and it's released from the registry. Refuses if it has changes that
+    /// weren't written unless `force` is set.
+    pub fn delete_buffer(&mut self, buf: &Arc<Buffer>, force: bool) -> Result<(), Box<dyn std::error::Error>> {
+        if !force && buf.get().is_modified() {
+            return Err(format!("E89: No write since last change for buffer {} (add ! to override)", buf.id().id()).into());
+        }
+        let replacement = self
+            .alternate_buf
+            .clone()
+            .filter(|b| !Arc::ptr_eq(b, buf))
+            .or_else(|| crate::buffer::registry::all().into_iter().find(|b| !Arc::ptr_eq(b, buf) && b.get().path().is_some()))
+            .unwrap_or_else(Buffer::new);
+        for win in self.root.windows() {
+            let mut win = win.get_mut();
+            if Arc::ptr_eq(&win.buffer, buf) {
+                win.buffer = Arc::clone(&replacement);
+            }
+            win.forget_view(buf.id());
+        }
+        if Arc::ptr_eq(&self.focused_buf, buf) {
+            self.focused_buf = Arc::clone(&replacement);
+            focus::set(&self.focused_buf);
+        }
+        self.layout_changed();
+        if self.alternate_buf.as_ref().is_some_and(|b| Arc::ptr_eq(b, buf) || Arc::ptr_eq(b, &replacement)) {
+            self.alternate_buf = None;
+        }
+        if self.diff.as_ref().is_some_and(|d| d.side_of(buf).is_some()) {
+            self.diff = None;
+        }
+        if self.preview.as_ref().is_some_and(|p| Arc::ptr_eq(&p.target, buf)) {
+            self.preview = None;
+        }
+        self.terminals.retain(|t| !Arc::ptr_eq(t.buffer(), buf));
+        self.listed.retain(|b| !Arc::ptr_eq(b, buf));
+        self.man_pages.remove(&buf.id());
+        self.keymap.forget_buffer(buf.id());
+        self.lsp.detach(&buf.get());
+        buf.get_mut().unlock();
+        crate::buffer::registry::release(buf.id());
+        self.tui.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// a panel at the bottom of the focused window with what can be typed after the keys of the
+    /// normal mode command so far followed by `typed`, which are waiting to become a mapping.
+    /// `None` if nothing can.
+    pub fn key_hints(&self, typed: &[u8]) -> Option<Popup> {
+        if self.mode != Mode::Normal {
+            return None;
+        }
+        let mut keys = self.pending.keys();
+        keys.extend(typed.iter().map(|&b| b as char));
+        let mut hints = crate::input::continuations(&keys);
+        // mappings start after the keys that were already taken as a command
+        if !typed.is_empty() {
+            let scopes = self.keymap_scopes();
+            let longer = self.keymap.list(&scopes, Mode::Normal, typed).into_iter().filter(|(lhs, ..)| lhs.len() > typed.len());
+            for (lhs, mapping, _) in longer {
+                let key = crate::keymap::key_notation(&lhs[typed.len()..][..1]);
+                let more = if lhs.len() > typed.len() + 1 { "+" } else { "" };
+                let desc = format!("{more}{}", crate::keymap::key_notation(&mapping.rhs));
+                hints.retain(|(k, _)| *k != key);
+                hints.push((key, desc));
+            }
+        }
+        if hints.is_empty() {
+            return None;
+        }
+        hints.sort();
+        hints.dedup_by(|a, b| a.0 == b.0);
+        let win = self.focused_win.get();
+        let entries: Vec<String> = hints.iter().map(|(key, desc)| format!("{key:<6}{desc}")).collect();
+        let width = entries.iter().map(|e| e.chars().count()).max().unwrap_or(0) + 2;
+        let columns = (win.outer_bounds().xlen() as usize / width).max(1);
+        let rows = entries.len().div_ceil(columns);
+        let mut text = String::new();
+        for row in 0..rows {
+            for entry in entries.iter().skip(row).step_by(rows) {
+                let _ = write!(text, "{entry:width$}");
+            }
+            text.push('\n');
+        }
+        Some(Popup::panel(&win, &self.focused_buf.get().cursor, text.trim_end()))
+    }
+
+    /// show [`Ctx::key_hints`] for the keys of the normal mode command so far and `typed`
+    pub fn show_key_hints(&mut self, typed: &[u8]) {
+        self.key_hints = self.key_hints(typed);
+        self.dirty = true;
+    }
+
+    pub fn hide_key_hints(&mut self) {
+        if self.key_hints.take().is_some() {
+            self.dirty = true;
+        }
+    }
+
+    pub fn showing_key_hints(&self) -> bool {
+        self.key_hints.is_some()
+    }
+
+    /// open the command-line window below the focused one, with the history of ex commands as
+    /// lines of a buffer and the cursor on an empty line after them. `Enter` runs the cursor line
+    /// and `:q` closes it.
+    pub fn open_command_window(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.command_window.is_some() {
+            return Err("E11: Invalid in command-line window".into());
+        }
+        let mut text: String = self.command_line.history().iter().map(|cmd| format!("{cmd}\n")).collect();
+        text.push('\n');
+        let buf = Buffer::from_string(text);
+        {
+            let mut inner = buf.get_mut();
+            let last = inner.linecnt().saturating_sub(1);
+            inner.cursor.set_pos(DocPos { x: 0, y: last });
+        }
+        let win = self.focused_win.split_off(vec![crate::window::Component::HistoryPrefix], buf);
+        self.root.split_sized(&self.focused_win, Arc::clone(&win), crate::window::org::Arrange::Vertical, COMMAND_WINDOW_HEIGHT)?;
+        self.command_window = Some(CommandWindow {
+            win: Arc::clone(&win),
+            prev: Arc::clone(&self.focused_win),
+            alternate: self.alternate_buf.clone(),
+        });
+        self.focus_window(win);
+        let linecnt = self.focused_buf.get().linecnt();
+        self.focused_win.get().fit_ctx_frame(&mut self.focused_buf.get_mut().cursor, linecnt);
+        self.tui.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// whether the focused window is the command-line window
+    pub fn in_command_window(&self) -> bool {
+        self.command_window.as_ref().is_some_and(|c| Arc::ptr_eq(&c.win, &self.focused_win))
+    }
+
+    /// close the command-line window, going back to the window it was opened from. Returns the
+    /// cursor line it had.
+    pub fn close_command_window(&mut self) -> Option<String> {
+        let cmdwin = self.command_window.take()?;
+        let line = {
+            let buf = Arc::clone(&cmdwin.win.get().buffer);
+            let buf = buf.get();
+            buf.line(buf.cursor.pos.y).to_string()
+        };
+        self.root.close(&cmdwin.win);
+        self.focus_window(cmdwin.prev);
+        self.alternate_buf = cmdwin.alternate;
+        self.tui.borrow_mut().clear();
+        Some(line)
+    }
+
+    /// show the welcome screen in the focused window until its buffer is edited
+    pub fn show_welcome(&mut self) {
+        self.focused_win.get_mut().add_component(crate::window::Component::Welcome);
+    }
+
+    /// give every window the current global options that affect it. Window-local options are set
+    /// on the windows themselves.
+    fn sync_window_options(&self) {
+        let scroll = ScrollOptions { past_end: self.options.scrollpastend };
+        let virtualedit = VirtualEdit::from_flags(&self.options.virtualedit);
+        for win in self.root.windows() {
+            let mut win = win.get_mut();
+            win.scroll = scroll;
+            win.virtualedit = virtualedit;
+            win.tabstop = self.options.tabstop;
+        }
+    }
+
+    /// the directory relative paths are resolved in, the focused window's `:lcd` if it has one
+    pub fn cwd(&self) -> PathBuf {
+        let local = self.focused_win.get().cwd.clone();
+        local.or_else(|| std::env::current_dir().ok()).unwrap_or_default()
+    }
+
+    /// `:cd`, or `:lcd` if `local`, to `dir`
+    pub fn set_cwd(&mut self, dir: PathBuf, local: bool) -> std::io::Result<()> {
+        let mut win = self.focused_win.get_mut();
+        if local {
+            win.cwd = Some(dir);
+            return Ok(());
+        }
+        std::env::set_current_dir(&dir)?;
+        // like vim, `:cd` in a window with a local directory replaces it
+        win.cwd = None;
+        Ok(())
+    }
+
+    /// `gf`
+    fn goto_file(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.file_under_cursor()?;
+        self.open_buffer(Buffer::open(&path)?);
+        Ok(())
+    }
+
+    /// the file whose name is under the cursor. Relative names are looked for in the working
+    /// directory, then next to the current file.
+    fn file_under_cursor(&self) -> Result<PathBuf, Box<dyn std::error::Error>> {
+        let buf = self.focused_buf.get();
+        let range = crate::textobj::file_name(&buf, buf.coff()).ok_or("No file name under cursor")?;
+        let name = buf.to_string()[range].to_owned();
+        let dir = buf.path().and_then(Path::parent).map(Path::to_path_buf);
+        drop(buf);
+        let name = match (name.strip_prefix("~/"), std::env::var_os("HOME")) {
+            (Some(rest), Some(home)) => Path::new(&home).join(rest),
+            _ => PathBuf::from(&name),
+        };
+        let path = [Some(self.cwd()), dir]
+            .into_iter()
+            .flatten()
+            .map(|d| d.join(&name))
+            .find(|p| p.is_file())
+            .ok_or_else(|| format!("Can't find file {name:?}"))?;
+        Ok(path)
+    }
+
+    /// `gx`, open the URL or file under the cursor with the program the system uses for it, without
+    /// waiting for it
+    fn open_external(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let url = {
+            let buf = self.focused_buf.get();
+            crate::textobj::url(&buf, buf.coff()).map(|range| buf.to_string()[range].to_owned())
+        };
+        let target = match url {
+            Some(url) => url.into(),
+            None => self.file_under_cursor()?.into_os_string(),
+        };
+        let opener = if cfg!(target_os = "macos") { "open" } else { "xdg-open" };
+        let mut child = std::process::Command::new(opener)
+            .arg(&target)
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .spawn()
+            .map_err(|e| format!("could not run {opener}: {e}"))?;
+        // reaped in the background so it doesn't stay around as a zombie
+        std::thread::spawn(move || child.wait());
+        Ok(())
+    }
+
+    /// with the `filelock` option, lock the file of `buf`, warning if another editor has it open
+    pub fn lock_file(&mut self, buf: &Buffer) {
+        if !self.options.filelock {
+            return;
+        }
+        let mut inner = buf.get_mut();
+        // a new file is locked once it's written, there's nothing to lock before that
+        let Some(path) = inner.path().filter(|p| !inner.is_locked() && p.exists()).map(Path::to_path_buf) else {
+            return;
+        };
+        match FileLock::try_new(&path) {
+            Ok(Some(lock)) => inner.set_lock(lock),
+            Ok(None) => {
+                drop(inner);
+                let _ = write!(self.warning(), "{path:?} is open in another editor");
+            }
+            Err(e) => {
+                drop(inner);
+                let _ = write!(self.warning(), "could not lock {path:?}: {e}");
+            }
+        }
+    }
+
+    pub fn diff(&self) -> Option<&DiffView> {
+        self.diff.as_ref()
+    }
+
+    /// preview of the command being typed, see [`Preview`]
+    pub fn preview(&self) -> Option<&Preview> {
+        self.preview.as_ref()
+    }
+
+    /// side of the diff the focused buffer is on
+    fn diff_side(&self) -> Option<Side> {
+        self.diff.as_ref()?.side_of(&self.focused_buf)
+    }
+
+    /// open `buf` in a window to the right of the focused one and compare the two
+    pub fn diff_split(&mut self, buf: Arc<Buffer>) -> Result<(), Box<dyn std::error::Error>> {
+        self.lock_file(&buf);
+        let components = vec![
+            crate::window::Component::SignColumn,
+            crate::window::Component::LineNumbers,
+        ];
+        let win = self.focused_win.split_off(components, Arc::clone(&buf));
+        self.root.split(&self.focused_win, Arc::clone(&win), crate::window::org::Arrange::Horizontal)?;
+        self.diff = Some(DiffView::new(Arc::clone(&self.focused_buf), Arc::clone(&buf)));
+        self.lsp.attach(&buf);
+        self.focus_window(win);
+        self.tui.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// close every window but the focused one and split it into `layout`, focusing its first
+    /// window. Files are opened if they aren't already, buffers without one have to still be open.
+    pub fn set_layout(&mut self, layout: &Layout) -> Result<(), Box<dyn std::error::Error>> {
+        // all the buffers first, so that a missing one leaves the windows as they are
+        let mut bufs = Vec::new();
+        for leaf in layout.leaves() {
+            bufs.push(match leaf {
+                LayoutBuf::Path(path) => match self.listed_buffer(path) {
+                    Some(buf) => buf,
+                    None => Buffer::edit(path)?,
+                },
+                LayoutBuf::Id(id) => crate::buffer::registry::get(*id)
+                    .ok_or_else(|| format!("E86: Buffer {} does not exist", id.id()))?,
+            });
+        }
+        if self.in_command_window() {
+            self.close_command_window();
+        }
+        let keep = Arc::clone(&self.focused_win);
+        for win in self.root.windows() {
+            if !Arc::ptr_eq(&win, &keep) {
+                self.root.close(&win);
+            }
+        }
+        let result = self.split_layout(layout, Arc::clone(&keep), &mut bufs.into_iter());
+        self.focus_window(keep);
+        self.tui.borrow_mut().clear();
+        result
+    }
+
+    /// show `layout` in `win`, splitting it for every split of the layout
+    fn split_layout(
+        &mut self,
+        layout: &Layout,
+        win: Arc<Window>,
+        bufs: &mut impl Iterator<Item = Arc<Buffer>>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        match layout {
+            Layout::Leaf(_) => {
+                self.focus_window(win);
+                self.open_buffer(bufs.next().expect("a buffer for every leaf"));
+                Ok(())
+            }
+            Layout::Split { first, second, arrange } => {
+                let buf = Arc::clone(&win.get().buffer);
+                let new = win.split_off(vec![crate::window::Component::LineNumbers], buf);
+                self.root.split(&win, Arc::clone(&new), *arrange)?;
+                self.split_layout(first, win, bufs)?;
+                self.split_layout(second, new, bufs)
+            }
+        }
+    }
+
+    pub fn diff_off(&mut self) {
+        self.diff = None;
+    }
+
+    fn focus_window(&mut self, win: Arc<Window>) {
+        self.focused_win.get_mut().save_view();
+        let buf = Arc::clone(&win.get().buffer);
+        self.focused_win = win;
+        self.set_focused_buf(buf);
+        // windows showing the same buffer each keep their own place in it
+        self.focused_win.get().restore_view();
+    }
+
+    /// run `cmd` in a new terminal buffer and start sending it keys
+    pub fn open_terminal(&mut self, cmd: &str) -> std::io::Result<()> {
+        let bounds = self.focused_win.get().inner_bounds();
+        let term = Terminal::spawn(cmd, bounds.xlen(), bounds.ylen())?;
+        let buf = Arc::clone(term.buffer());
+        self.terminals.push(term);
+        self.open_buffer(buf);
+        self.set_mode(Mode::Terminal);
+        Ok(())
+    }
+
+    fn focused_terminal(&mut self) -> Option<&mut Terminal> {
+        self.terminals
+            .iter_mut()
+            .find(|t| Arc::ptr_eq(t.buffer(), &self.focused_buf))
+    }
+
+    pub fn lsp(&self) -> &LspManager {
+        &self.lsp
+    }
+
+    /// handle events from background subsystems, marking the context dirty if anything happened
+    /// that may need to be redrawn
+    pub fn process_events(&mut self) {
+        let mut dirty = self.poll_terminals();
+        let events = crate::event::drain();
+        dirty |= !events.is_empty();
+        for event in events {
+            crate::buffer::history::undo_break();
+            match event {
+                Event::Message(msg) => self.command_line.show_message(&msg),
+                Event::Keys(keys) => self.normal(&keys, true),
+                Event::Insert { buf, off, text } => {
+                    let Some(buf) = crate::buffer::registry::get(buf) else {
+                        continue;
+                    };
+                    let mut buf = buf.get_mut();
+                    if off == buf.len() {
+                        buf.append(&text);
+                    } else if off < buf.len() {
+                        buf.insert_at(off, &text);
+                    }
+                }
+                Event::Quit => crate::exit(),
+                Event::Interrupt => self.interrupt(),
+                Event::Hook(f) => crate::guile::call_hook(f),
+                Event::Command(cmd) => self.run_command(&cmd),
+                Event::Layout(layout) => {
+                    if let Err(e) = self.set_layout(&layout) {
+                        self.err(&*e);
+                    }
+                }
+            }
+        }
+        if let Some(prompt) = crate::guile::take_prompt() {
+            self.start_prompt(&prompt);
+            dirty = true;
+        }
+        let jobs = crate::job::poll();
+        dirty |= !jobs.is_empty();
+        for event in jobs {
+            match (event.sink, event.kind) {
+                (Sink::Buffer(buf), JobEventKind::Line(_, line)) => {
+                    buf.get_mut().append(&format!("{line}\n"));
+                }
+                (Sink::Buffer(buf), JobEventKind::Exited(code)) => {
+                    let status = code.map_or("killed".to_string(), |c| format!("exited with {c}"));
+                    buf.get_mut().append(&format!("[job {} {status}]\n", event.id));
+                    let _ = write!(self.info(), "job {} {status}", event.id);
+                }
+                (Sink::Guile, JobEventKind::Line(_, line)) => crate::guile::job_line(event.id, &line),
+                (Sink::Guile, JobEventKind::Exited(code)) => crate::guile::job_exit(event.id, code),
+                (Sink::Quickfix, JobEventKind::Line(_, line)) => {
+                    if let Some((_, output)) = self.make.as_mut().filter(|(id, _)| *id == event.id) {
+                        output.push(line);
+                    }
+                }
+                (Sink::Quickfix, JobEventKind::Exited(code)) => {
+                    if let Err(e) = self.finish_make(event.id, code) {
+                        self.err(&*e);
+                    }
+                }
+                (Sink::Lint, JobEventKind::Line(_, line)) => {
+                    if let Some((_, output)) = self.lint.as_mut().filter(|(id, _)| *id == event.id) {
+                        output.push(line);
+                    }
+                }
+                (Sink::Blame, JobEventKind::Line(_, line)) => {
+                    if let Some((_, output)) = self.blame.as_mut().and_then(|b| b.job.as_mut()) {
+                        output.push(line);
+                    }
+                }
+                (Sink::Blame, JobEventKind::Exited(code)) => {
+                    let Some(blame) = self.blame.as_mut().filter(|b| b.job.as_ref().is_some_and(|j| j.0 == event.id))
+                    else {
+                        continue;
+                    };
+                    let (_, output) = blame.job.take().expect("checked above");
+                    if code == Some(0) {
+                        blame.lines = crate::blame::parse_porcelain(&output);
+                    } else {
+                        self.blame = None;
+                        let _ = write!(self.warning(), "git blame failed");
+                    }
+                }
+                (Sink::Lint, JobEventKind::Exited(_)) => {
+                    if let Err(e) = self.finish_lint(event.id) {
+                        self.err(&*e);
+                    }
+                }
+            }
+        }
+        let events = self.lsp.poll();
+        dirty |= !events.is_empty();
+        for event in events {
+            match event {
+                LspEvent::Jump { path, line, character } => {
+                    if let Err(e) = self.jump_to(&path, line, |l| crate::lsp::utf16_to_byte(l, character)) {
+                        self.err(&*e);
+                    }
+                }
+                LspEvent::Diagnostics(path) => self.update_loclist(&path),
+                LspEvent::Hover(text) => {
+                    if self.mode == Mode::Normal {
+                        self.popup = Some(Popup::new(&self.focused_win.get(), &self.focused_buf.get(), &text));
+                    }
+                }
+                LspEvent::Message(m) => {
+                    let _ = write!(self.info(), "{m}");
+                }
+            }
+        }
+        if crate::progress::take_changed() {
+            self.progress = crate::progress::tasks();
+            dirty = true;
+        }
+        if !self.progress.is_empty() {
+            let frame = crate::progress::frame();
+            dirty |= frame != self.spinner;
+            self.spinner = frame;
+        }
+        self.dirty |= dirty;
+    }
+
+    fn poll_terminals(&mut self) -> bool {
+        let mut dirty = false;
+        let mut i = 0;
+        while i < self.terminals.len() {
+            let term = &mut self.terminals[i];
+            let focused = Arc::ptr_eq(term.buffer(), &self.focused_buf);
+            let (changed, status) = term.poll(focused && self.mode == Mode::Terminal);
+            dirty |= changed;
+            let Status::Exited(code) = status else {
+                i += 1;
+                continue;
+            };
+            let term = self.terminals.remove(i);
+            let status = code.map_or("killed".to_string(), |c| format!("exited with {c}"));
+            term.buffer().get_mut().append(&format!("[process {status}]\n"));
+            if focused && self.mode == Mode::Terminal {
+                self.set_mode(Mode::Normal);
+            }
+            dirty = true;
+        }
+        dirty
+    }
+
+    /// rebuild the location list from the diagnostics of `path` if it is the focused buffer
+    fn update_loclist(&mut self, path: &Path) {
+        let buf = self.focused_buf.get();
+        if buf.path().map(crate::lsp::abs_path).as_deref() != Some(path) {
+            return;
+        }
+        let items = self
+            .lsp
+            .diagnostics(path)
+            .iter()
+            .map(|d| QfItem {
+                path: buf.path().expect("checked above").to_owned(),
+                line: d.line,
+                col: if d.line < buf.linecnt() {
+                    crate::lsp::utf16_to_byte(buf.line(d.line), d.character)
+                } else {
+                    0
+                },
+                text: d.message.clone(),
+                severity: d.severity,
+            })
+            .collect();
+        drop(buf);
+        self.loclist.set(items);
+    }
+
+    /// move the cursor to `line` of `path`, opening it if it isn't the focused buffer. The column
+    /// is computed from the text of the destination line.
+    pub fn jump_to(
+        &mut self,
+        path: &Path,
+        line: usize,
+        col: impl FnOnce(&str) -> usize,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let focused = self.focused_buf().path().map(crate::lsp::abs_path);
+        if focused.as_deref() != Some(&*crate::lsp::abs_path(path)) {
+            self.open_buffer(Buffer::open(path)?);
+        }
+        let buf = self.focused_buf();
+        let y = line.min(buf.linecnt().saturating_sub(1));
+        let x = if buf.linecnt() > 0 { col(buf.line(y)) } else { 0 };
+        drop(buf);
+        let mut win = self.focused_win.get_mut();
+        win.set_pos(DocPos { x, y });
+        let mut buf = self.focused_buf.get_mut();
+        let linecnt = buf.linecnt();
+        win.center_view(&mut buf.cursor, linecnt);
+        Ok(())
+    }
+
+    pub fn list(&mut self, kind: ListKind) -> &mut QfList {
+        match kind {
+            ListKind::Quickfix => &mut self.qflist,
+            ListKind::Location => &mut self.loclist,
+        }
+    }
+
+    /// jump to the current item of a list
+    pub fn jump_list(&mut self, kind: ListKind) -> Result<(), Box<dyn std::error::Error>> {
+        let list = self.list(kind);
+        let item = list.current().cloned().ok_or("list is empty")?;
+        let (idx, len) = (list.idx() + 1, list.len());
+        self.jump_to(&item.path, item.line, |_| item.col)?;
+        write!(self.info(), "({idx} of {len}): {}", item.text)?;
+        Ok(())
+    }
+
+    /// run `makeprg` in the background. Its output fills the quickfix list once it exits.
+    pub fn start_make(&mut self, args: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.make.is_some() {
+            return Err("make is already running".into());
+        }
+        let cmd = if args.trim().is_empty() {
+            self.options.makeprg.clone()
+        } else {
+            format!("{} {}", self.options.makeprg, args.trim())
+        };
+        let id = crate::job::start(&cmd, Sink::Quickfix)?;
+        self.make = Some((id, Vec::new()));
+        write!(self.info(), ":!{cmd}")?;
+        Ok(())
+    }
+
+    fn finish_make(&mut self, id: JobId, code: Option<i32>) -> Result<(), Box<dyn std::error::Error>> {
+        let Some((_, output)) = self.make.take_if(|(make, _)| *make == id) else {
+            return Ok(());
+        };
+        let items = crate::errorformat::parse(&self.options.errorformat, output.iter().map(String::as_str))?;
+        let empty = items.is_empty();
+        self.qflist.set(items);
+        if empty {
+            match code {
+                Some(0) => write!(self.info(), "make finished with no errors")?,
+                Some(c) => write!(self.warning(), "make exited with {c}")?,
+                None => write!(self.warning(), "make was killed")?,
+            }
+            return Ok(());
+        }
+        self.jump_list(ListKind::Quickfix)
+    }
+
+    /// run `lintprg` on `file` in the background, replacing a run that hasn't finished
+    pub fn start_lint(&mut self, file: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        if self.options.lintprg.is_empty() {
+            return Ok(());
+        }
+        if let Some((id, _)) = self.lint.take() {
+            let _ = crate::job::kill(id);
+        }
+        let id = crate::job::start(&crate::lint::command(&self.options.lintprg, file), Sink::Lint)?;
+        self.lint = Some((id, Vec::new()));
+        Ok(())
+    }
+
+    fn finish_lint(&mut self, id: JobId) -> Result<(), Box<dyn std::error::Error>> {
+        let Some((_, output)) = self.lint.take_if(|(lint, _)| *lint == id) else {
+            return Ok(());
+        };
+        crate::lint::show(&crate::lint::parse(&self.options.errorformat, &output)?);
+        Ok(())
+    }
+
+    /// stop the running jobs. With nothing to stop it says how to leave the editor, and leaves it
+    /// without checking for changes if it's done twice in a row.
+    pub fn interrupt(&mut self) {
+        const TWICE: Duration = Duration::from_secs(1);
+        let jobs = crate::job::list();
+        if !jobs.is_empty() {
+            for (id, _) in &jobs {
+                let _ = crate::job::kill(*id);
+            }
+            self.last_interrupt.set(None);
+            let _ = write!(self.info(), "interrupted {} job{}", jobs.len(), if jobs.len() == 1 { "" } else { "s" });
+            return;
+        }
+        if self.last_interrupt.take().is_some_and(|t| t.elapsed() < TWICE) {
+            crate::exit();
+            return;
+        }
+        self.last_interrupt.set(Some(Instant::now()));
+        let _ = write!(self.info(), "Type :qa and press Enter to leave, or Ctrl-c again to leave without saving");
+    }
+
+    /// start showing who last changed the cursor line, or stop if it's already shown
+    pub fn toggle_blame(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(blame) = self.blame.take() {
+            if let Some((id, _)) = blame.job {
+                let _ = crate::job::kill(id);
+            }
+            if let Some(buf) = crate::buffer::registry::get(blame.buf) {
+                buf.get_mut().extmarks_mut().clear(crate::blame::NAMESPACE);
+            }
+            return Ok(());
+        }
+        let path = self.focused_buf().path().ok_or("buffer has no file")?.to_owned();
+        let id = crate::job::start(&crate::blame::command(&path), Sink::Blame)?;
+        self.blame = Some(Blame::new(self.focused_buf.id(), id));
+        Ok(())
+    }
+
+    /// show the manual page for `topic` in the focused window
+    pub fn open_man(&mut self, topic: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let width = self.focused_win.get().width();
+        let (text, highlights) = crate::man::page(topic, width)?;
+        let buf = Buffer::from_string(text);
+        for (range, color) in highlights {
+            buf.get_mut().extmarks_mut().add(Extmark::highlight(crate::man::NAMESPACE, range, color));
+        }
+        buf.get_mut().set_modifiable(false);
+        self.man_pages.insert(buf.id());
+        self.open_buffer(buf);
+        Ok(())
+    }
+
+    /// `K` in a manual page, open the page of the word under the cursor
+    fn man_under_cursor(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let topic = {
+            let buf = self.focused_buf.get();
+            let pos = buf.cursor.pos;
+            crate::man::topic_at(buf.line(pos.y), pos.x).ok_or("E349: No identifier under cursor")?
+        };
+        self.open_man(&topic)
+    }
+
+    fn jump_hunk(&mut self, forward: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let side = self.diff_side().ok_or("not in diff mode")?;
+        let diff = self.diff.as_ref().expect("has a side");
+        let y = self.focused_buf.get().cursor.pos.y;
+        let line = if forward {
+            diff.next_hunk(side, y).ok_or("no more hunks")?
+        } else {
+            diff.prev_hunk(side, y).ok_or("no previous hunk")?
+        };
+        self.focused_win.get_mut().set_pos(DocPos { x: 0, y: line });
+        Ok(())
+    }
+
+    /// copy the hunk under the cursor to the other side, or from it if `put` isn't set
+    fn transfer_hunk(&mut self, put: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let side = self.diff_side().ok_or("not in diff mode")?;
+        let diff = self.diff.as_ref().expect("has a side");
+        let y = self.focused_buf.get().cursor.pos.y;
+        let hunk = diff.hunk_at(side, y).ok_or("no difference here")?.clone();
+        diff.copy_hunk(&hunk, if put { side } else { side.other() });
+        Ok(())
+    }
+
+    /// search for `pattern`, or for the last pattern if it's empty
+    pub fn search(&mut self, pattern: &str, backward: bool, offset: Offset) -> Result<(), Box<dyn std::error::Error>> {
+        if pattern.is_empty() {
+            let last = self.last_search.as_mut().ok_or("no previous search pattern")?;
+            last.backward = backward;
+            last.offset = offset;
+        } else {
+            self.last_search = Some(Search::new(pattern, backward, offset, &self.options)?);
+            self.registers.set('/', pattern);
+        }
+        self.search_next(false)
+    }
+
+    /// replace matches of `pattern` in `range` with `replacement`, see [`crate::search`]. An empty
+    /// pattern uses the last search pattern.
+    pub fn substitute(
+        &mut self,
+        range: LineRange,
+        pattern: &str,
+        replacement: &str,
+        flags: &str,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.check_modifiable()?;
+        let flags = substitute_flags(flags)?;
+        let re = if pattern.is_empty() {
+            let last = self.last_search.as_ref().ok_or("no previous search pattern")?;
+            last.pattern.clone()
+        } else {
+            search::compile(pattern, &self.options, flags.ignore_case)?
+        };
+        let template = search::replacement_template(replacement);
+        let mut buf = self.focused_buf.get_mut();
+        let lines = range.resolve(buf.cursor.pos.y, buf.linecnt())?;
+        if flags.confirm {
+            let sub = Substitution::new(&buf, lines, re, template, replacement, flags.global)
+                .ok_or_else(|| EditorError::PatternNotFound(pattern.into()))?;
+            drop(buf);
+            self.ask(Confirm::Substitute(sub));
+            return Ok(());
+        }
+        let text = buf.to_string();
+        let sub = search::substitute(&re, &text[buf.line_offsets(lines.clone())], &template, flags.global)?;
+        if sub.count == 0 {
+            return Err(EditorError::PatternNotFound(pattern.into()).into());
+        }
+        buf.replace_lines(lines, &sub.text);
+        drop(buf);
+        if sub.lines > 1 {
+            write!(self.info(), "{} substitutions on {} lines", sub.count, sub.lines)?;
+        }
+        Ok(())
+    }
+
+    /// move through the undo history of the focused buffer with `step`, `count` times. `forward` is
+    /// which end of the history is reported when `step` can't go any further.
+    pub fn history_step(&mut self, count: usize, forward: bool, mut step: impl FnMut(&mut BufferInner) -> bool) {
+        let mut buf = self.focused_buf.get_mut();
+        let done = (0..count).all(|_| step(&mut buf));
+        let linecnt = buf.linecnt();
+        self.focused_win.get_mut().fit_ctx_frame(&mut buf.cursor, linecnt);
+        drop(buf);
+        if !done {
+            let msg = if forward { "Already at newest change" } else { "Already at oldest change" };
+            let _ = write!(self.info(), "{msg}");
+        }
+    }
+
+    /// `p` or `P` with the text of `register`, reindenting lines to the cursor line with `indent`
+    fn put(&mut self, register: char, before: bool, indent: bool) {
+        let Some(text) = self.registers.get(register).filter(|t| !t.is_empty()).map(str::to_owned) else {
+            self.err(&EmptyRegister(register));
+            return;
+        };
+        let mut buf = self.focused_buf.get_mut();
+        let pos = buf.cursor.pos;
+        if text.ends_with('\n') {
+            let text = if indent {
+                let line = buf.line(pos.y);
+                crate::register::reindent(&text, &line[..line.len() - line.trim_start().len()])
+            } else {
+                text
+            };
+            let y = if before { pos.y } else { (pos.y + 1).min(buf.linecnt()) };
+            buf.replace_lines(y..y, &text);
+            buf.cursor.set_pos(DocPos { x: 0, y });
+        } else {
+            let mut off = buf.pos_to_offset(pos);
+            if !before && off < buf.len() && buf.char_at(off) != '\n' {
+                off += buf.char_at(off).len_utf8();
+            }
+            let start = buf.offset_to_pos(off);
+            buf.cursor.set_pos(start);
+            buf.insert_str(&text);
+            // on the last character that was put
+            let last = text.chars().next_back().map_or(0, char::len_utf8);
+            let end = buf.offset_to_pos(off + text.len() - last);
+            buf.cursor.set_pos(end);
+        }
+        let linecnt = buf.linecnt();
+        self.focused_win.get().fit_ctx_frame(&mut buf.cursor, linecnt);
+    }
+
+    /// text to insert for `Ctrl-r` on the command line
+    fn register_text(&self, name: char) -> Option<String> {
+        let text = if name == '\x17' {
+            let buf = self.focused_buf.get();
+            let word = crate::textobj::inner_word(&buf, buf.coff())?;
+            buf.to_string()[word].to_owned()
+        } else {
+            self.registers.get(name)?.to_owned()
+        };
+        // the command line is a single line
+        Some(text.strip_suffix('\n').unwrap_or(&text).replace('\n', "\r"))
+    }
+
+    /// an error if commands can't change the focused buffer, because of `readonly` or because the
+    /// buffer isn't modifiable
+    pub fn check_modifiable(&self) -> Result<(), EditorError> {
+        if self.options.readonly {
+            return Err(EditorError::ReadOnly);
+        }
+        if !self.focused_buf.get().is_modifiable() {
+            return Err(EditorError::NotModifiable);
+        }
+        Ok(())
+    }
+
+    /// update the preview of the command being typed
+    fn update_preview(&mut self) {
+        self.preview = None;
+        if !self.options.inccommand {
+            return;
+        }
+        let Some(Command::Substitute { range, pattern, replacement, flags }) = self.command_line.preview_command()
+        else {
+            return;
+        };
+        let Ok(flags) = substitute_flags(&flags) else {
+            return;
+        };
+        let Ok(re) = search::compile(&pattern, &self.options, flags.ignore_case) else {
+            return;
+        };
+        let buf = self.focused_buf.get();
+        let Ok(lines) = range.resolve(buf.cursor.pos.y, buf.linecnt()) else {
+            return;
+        };
+        drop(buf);
+        let template = search::replacement_template(&replacement);
+        self.preview = Preview::substitute(self.focused_buf.clone(), lines, &re, &template, flags.global);
+    }
+
+    /// go to the next match of the last search
+    fn search_next(&mut self, reverse: bool) -> Result<(), Box<dyn std::error::Error>> {
+        let search = self.last_search.as_ref().ok_or("no previous search pattern")?;
+        let buf = self.focused_buf.get();
+        let mut off = buf.coff();
+        let mut found = search.find(&buf, off, reverse).ok_or("pattern not found")?;
+        let mut pos = search.target(&buf, found.clone());
+        // with an offset the cursor isn't on the match it came from, so that match can be found
+        // again. Skip over it.
+        if pos == buf.cursor.pos && found.start != off {
+            off = found.start;
+            found = search.find(&buf, off, reverse).ok_or("pattern not found")?;
+            pos = search.target(&buf, found.clone());
+        }
+        let forward = search.backward == reverse;
+        drop(buf);
+        self.focused_win.get_mut().set_pos(pos);
+        if forward && found.start <= off {
+            write!(self.warning(), "search hit BOTTOM, continuing at TOP")?;
+        } else if !forward && found.start >= off {
+            write!(self.warning(), "search hit TOP, continuing at BOTTOM")?;
+        }
+        Ok(())
+    }
+
     pub fn err(&mut self, err: &(impl std::error::Error + ?Sized)) {
         self.command_line.output_severity = TextSeverity::Error;
         self.command_line
@@ -188,6 +1434,197 @@ impl Ctx {
             .unwrap();
     }
 
+    /// run an ex command as if it was typed on the command line
+    pub fn run_command(&mut self, cmd: &str) {
+        let cmd = cmd.trim_start().trim_start_matches(':');
+        if let Some(cmd) = self.command_line.parse(cmd) {
+            if let Err(e) = cmd.exec(self) {
+                self.err(&*e);
+            }
+            // for the draw before the next key, after a `:set`
+            self.sync_window_options();
+        }
+    }
+
+    /// the text of register `name` to run, where `@` is the register run last
+    fn register_to_execute(&mut self, name: char) -> Result<(char, String), Box<dyn std::error::Error>> {
+        let name = match name {
+            '@' => self.last_executed.ok_or("E748: No previously used register")?,
+            name => name,
+        };
+        let text = self.registers.get(name).filter(|t| !t.is_empty()).ok_or(EmptyRegister(name))?;
+        if self.executing >= MAX_EXECUTE_DEPTH {
+            return Err("E169: Command too recursive".into());
+        }
+        self.last_executed = Some(name);
+        Ok((name, text.to_owned()))
+    }
+
+    /// `@`, run register `name` `count` times as typed keys, or as an ex command for `:`
+    pub fn execute_register(&mut self, name: char, count: usize) -> Result<(), Box<dyn std::error::Error>> {
+        let (name, text) = self.register_to_execute(name)?;
+        self.executing += 1;
+        for _ in 0..count {
+            if let Err(e) = crate::busy::check() {
+                self.executing -= 1;
+                return Err(e.into());
+            }
+            if name == ':' {
+                self.run_command(&text);
+            } else {
+                self.normal(text.as_bytes(), true);
+            }
+        }
+        self.executing -= 1;
+        Ok(())
+    }
+
+    /// `:@`, run each line of register `name` as an ex command
+    pub fn execute_register_lines(&mut self, name: char) -> Result<(), Box<dyn std::error::Error>> {
+        let (_, text) = self.register_to_execute(name)?;
+        self.executing += 1;
+        for line in text.lines() {
+            if let Err(e) = crate::busy::check() {
+                self.executing -= 1;
+                return Err(e.into());
+            }
+            self.run_command(line);
+        }
+        self.executing -= 1;
+        Ok(())
+    }
+
+    /// run `cmd` for a `:command` alias, which may run other aliases but not without end
+    pub fn execute_alias(&mut self, cmd: &str) -> Result<(), Box<dyn std::error::Error>> {
+        if self.aliases >= MAX_ALIAS_DEPTH {
+            return Err("E169: Command too recursive".into());
+        }
+        self.aliases += 1;
+        self.run_command(cmd);
+        self.aliases -= 1;
+        Ok(())
+    }
+
+    /// execute `keys` as if they were typed in normal mode, applying mappings if `remap` is set. A
+    /// command that is left incomplete is aborted, so this always ends in normal mode.
+    pub fn normal(&mut self, keys: &[u8], remap: bool) {
+        if self.mode == Mode::Command {
+            self.abort_command_line();
+        }
+        self.set_mode(Mode::Normal);
+        let mut keys = Typeahead::new(keys, remap);
+        // the rest of the keys are dropped when interrupted
+        while keys.poll(0) && crate::busy::check().is_ok() {
+            let action = crate::input::handle_input(self, &mut keys.reader(self));
+            if let Some(action) = action {
+                self.process_action(action);
+            }
+        }
+        self.pending.cancel();
+        if self.mode != Mode::Normal {
+            self.process_action(Operation::SwitchMode(Mode::Normal).into());
+        }
+    }
+
+    /// ask for a line of text on the command line, see [`crate::guile::read_string`]
+    pub fn start_prompt(&mut self, prompt: &str) {
+        self.set_mode(Mode::Command);
+        self.command_line.start_prompt(prompt);
+    }
+
+    /// ask `confirm`'s question on the command line, see [`crate::confirm`]
+    pub fn ask(&mut self, confirm: Confirm) {
+        if let Confirm::Substitute(sub) = &confirm {
+            let mut buf = self.focused_buf_mut();
+            let pos = buf.offset_to_pos(sub.current().start);
+            buf.cursor.set_pos(pos);
+            drop(buf);
+            self.preview = Some(sub.preview(self.focused_buf.clone()));
+        }
+        let question = confirm.question(&self.focused_buf());
+        self.set_mode(Mode::Command);
+        self.command_line.start_confirm(&question);
+        self.confirm = Some(confirm);
+    }
+
+    /// open the command line with `text` typed, listing the candidates of `completion` to pick
+    /// from with Tab
+    pub fn pick(&mut self, text: &str, completion: crate::command::complete::Completion) {
+        self.set_mode(Mode::Command);
+        self.command_line.start_with_candidates(text, completion);
+    }
+
+    /// take the key `c` as the answer to the open question, ignoring keys it doesn't accept
+    fn answer(&mut self, c: char) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(answer) = Answer::from_key(c).filter(|a| self.confirm.as_ref().is_some_and(|q| q.accepts(*a))) else {
+            return Ok(());
+        };
+        let Some(confirm) = self.confirm.take() else {
+            return Ok(());
+        };
+        self.preview = None;
+        self.command_line.clear_command();
+        self.mode = Mode::Normal;
+        match (confirm, answer) {
+            (Confirm::Substitute(mut sub), answer) => {
+                let more = sub.answer(&mut self.focused_buf_mut(), answer);
+                if more {
+                    self.ask(Confirm::Substitute(sub));
+                } else if sub.count > 0 {
+                    write!(self.info(), "{} substitutions", sub.count)?;
+                }
+            }
+            (Confirm::Overwrite { path, range }, Answer::Yes) => {
+                Command::Write { path: Some(path), range, append: false, force: true }.exec(self)?;
+            }
+            (Confirm::Quit, Answer::Yes) => {
+                Command::Write { path: None, range: None, append: false, force: false }.exec(self)?;
+                self.check_hidden_changes()?;
+                crate::exit();
+            }
+            (Confirm::Quit, Answer::No) => {
+                self.check_hidden_changes()?;
+                crate::exit();
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// fails with E162 if a buffer with a file other than the focused one has changes, which
+    /// quitting would lose without a word
+    pub fn check_hidden_changes(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let hidden = crate::buffer::registry::all()
+            .into_iter()
+            .find(|b| !Arc::ptr_eq(b, &self.focused_buf) && b.get().path().is_some() && b.get().is_modified());
+        match hidden {
+            Some(buf) => Err(format!("E162: No write since last change for buffer {:?}", buf.get().name()).into()),
+            None => Ok(()),
+        }
+    }
+
+    /// leave the command line without running anything
+    pub(crate) fn abort_command_line(&mut self) {
+        let prompt = self.cmdtype() == CommandType::Prompt;
+        self.confirm = None;
+        self.preview = None;
+        if let Some(cursor) = self.command_line.abort() {
+            let mut buf = self.focused_buf_mut();
+            // unless something changed the buffer in the meantime
+            if buf.try_pos_to_offset(cursor.pos).is_some() {
+                buf.cursor = cursor;
+            }
+        }
+        if prompt {
+            crate::guile::prompt_answer(None);
+        }
+    }
+
+    /// take the message shown on the command line, if any
+    pub fn take_message(&mut self) -> Option<(String, TextSeverity)> {
+        self.command_line.take_output()
+    }
+
     /// get a handle for info dialogue
     pub fn info(&mut self) -> &mut impl std::fmt::Write {
         self.command_line.output_severity = TextSeverity::Normal;
@@ -200,7 +1637,7 @@ impl Ctx {
         &mut self.command_line
     }
 
-    fn apply_motion(&mut self, motion: Motion) -> Option<Range<usize>> {
+    fn apply_motion(&mut self, motion: Motion) -> Option<DocRange> {
         let start = self.focused_buf().cursor.pos;
         match motion {
             Motion::ScreenSpace { dy, dx } => {
@@ -219,56 +1656,178 @@ impl Ctx {
                 todo!()
             },
         }
-        let buf = self.focused_buf.get_mut();
-        let end = buf.cursor.pos;
-        let (start, end) = {
-            let mut start = start;
-            let mut end = end;
-            if start > end {
-                std::mem::swap(&mut start, &mut end)
-            }
-            (start, end)
-        };
-        Some(buf.pos_to_offset(start)..buf.pos_to_offset(end))
+        let end = self.focused_buf.get().cursor.pos;
+        Some(DocRange::new(start, end))
     }
 
     fn set_mode(&mut self, mode: Mode) {
+        if mode == Mode::Command && self.mode != Mode::Command {
+            let cursor = self.focused_buf().cursor;
+            self.command_line.enter(cursor);
+        }
         if mode == Mode::Command {
             self.command_line
-                .set_type(crate::command::cmdline::CommandType::Ex)
+                .set_type(CommandType::Ex)
+        }
+        // inserting into a terminal buffer means typing into its program
+        let mode = match (mode, self.focused_terminal()) {
+            (Mode::Insert | Mode::Terminal, Some(term)) => {
+                term.sync_buffer(true);
+                Mode::Terminal
+            }
+            (Mode::Terminal, None) => Mode::Normal,
+            (mode, _) => mode,
+        };
+        if (mode == Mode::Terminal) != (self.mode == Mode::Terminal) {
+            // Ctrl-C is for the program, not for us
+            self.termios.local_flags.set(LocalFlags::ISIG, mode != Mode::Terminal);
+            let _ = termios::tcsetattr(self.term_fd, termios::SetArg::TCSANOW, &self.termios);
+        }
+        if self.mode == Mode::Insert && mode != Mode::Insert {
+            crate::buffer::history::undo_break();
         }
         self.mode = mode;
     }
 
+    /// `Ctrl-w` and `Ctrl-u` in insert mode, delete the word or all of the text before the cursor on
+    /// its line. `Ctrl-u` keeps the indentation unless the cursor is in it. At the start of a line
+    /// they join it to the line above.
+    fn delete_before_cursor(&mut self, word: bool) {
+        let mut buf = self.focused_buf.get_mut();
+        let DocPos { x, y } = buf.cursor.pos;
+        if x == 0 {
+            buf.delete_char_before();
+            return;
+        }
+        let line = buf.line(y);
+        let before = &line[..x.min(line.len())];
+        let end = before.len();
+        let start = if word {
+            crate::textobj::word_before(before)
+        } else {
+            let indent = end - before.trim_start().len();
+            if indent < end { indent } else { 0 }
+        };
+        let off = buf.line_offsets(y..y).start;
+        buf.delete_range(off + start..off + end);
+        buf.cursor.set_pos(DocPos { x: start, y });
+    }
+
+    /// break the cursor line at the last whitespace before `textwidth` when text typed before the
+    /// cursor goes past it, as allowed by `formatoptions`
+    fn auto_wrap(&self, buf: &mut BufferInner) {
+        let width = self.options.textwidth;
+        let DocPos { x, y } = buf.cursor.pos;
+        let line = &buf.line(y)[..x];
+        let flag = if crate::format::is_comment(line) { 'c' } else { 't' };
+        if width == 0 || !self.options.formatoptions.contains(flag) {
+            return;
+        }
+        let Some((ws, cont)) = crate::format::break_at(line, width) else {
+            return;
+        };
+        let start = buf.line_offsets(y..y).start;
+        buf.delete_range(start + ws.start..start + ws.end);
+        buf.cursor.set_pos(DocPos { x: ws.start, y });
+        buf.insert_str(&format!("\n{cont}"));
+        buf.cursor.set_pos(DocPos { x: cont.len() + x - ws.end, y: y + 1 });
+    }
+
     pub fn process_action(&mut self, action: Action) {
+        let before = self.mode;
+        self.repeat.action(before, &action);
+        self.apply_action(action);
+        self.repeat.done(self.mode);
+        // back to insert mode after the command of `Ctrl-o`, or the command line it started
+        if self.insert_once && before != Mode::Insert {
+            match self.mode {
+                Mode::Normal => {
+                    self.insert_once = false;
+                    self.set_mode(Mode::Insert);
+                }
+                Mode::Command => (),
+                Mode::Insert | Mode::Terminal => self.insert_once = false,
+            }
+        }
+    }
+
+    fn apply_action(&mut self, action: Action) {
+        self.dirty = true;
+        if self.mode == Mode::Normal {
+            // every normal mode command is its own undo step, along with the insert it may start
+            crate::buffer::history::undo_break();
+        }
+        self.sync_window_options();
         let motion_range = if let Some(m) = action.motion {
             Some(match m {
                 Motion::TextObj(r) => {
                     let buf = self.focused_buf();
                     let pos = buf.coff();
-                    r(&buf, pos)
+                    r(&buf, pos).map(|rng| buf.offsets_to_range(rng))
                 }
                 _ => self.apply_motion(m),
             })
         } else {
             None
         };
+        let refused = if action.operation.is_edit() { self.check_modifiable().err() } else { None };
         match self.mode {
+            Mode::Command if self.confirm.is_some() => {
+                let key = match action.operation {
+                    Operation::Insert(s) => s.chars().next(),
+                    Operation::SwitchMode(_) => Some('\x1b'),
+                    _ => None,
+                };
+                if let Some(Err(e)) = key.map(|c| self.answer(c)) {
+                    self.err(&*e);
+                }
+            }
             Mode::Command => match action.operation {
                 Operation::Insert(s) => {
                     let c = s.chars().next().unwrap();
-                    if c == '\r' {
+                    if c == '\r' && self.cmdtype() == CommandType::Prompt {
+                        let answer = self.command_line.take_answer();
+                        self.mode = Mode::Normal;
+                        crate::guile::prompt_answer(Some(&answer));
+                    } else if c == '\r' {
+                        self.preview = None;
+                        self.command_line.remember(&mut self.registers);
                         self.command_line
                             .complete()
                             .map(|x| x.exec(self))
                             .map(|r| r.map_err(|e| self.err(&*e)));
-                        self.mode = Mode::Normal;
+                        // the command may have switched modes itself, or opened the command line
+                        // again to ask something
+                        if self.mode == Mode::Command && self.cmdtype() == CommandType::None {
+                            self.mode = Mode::Normal;
+                        }
+                    } else if c == '\t' {
+                        let cwd = self.cwd();
+                        self.command_line.tab_complete(false, &self.options, &cwd);
+                        self.update_preview();
                     } else {
                         let _ = self.command_line.input(CommandLineInput::Append(c));
+                        self.update_preview();
                     }
                 }
                 Operation::DeleteBefore => {
                     let _ = self.command_line.input(CommandLineInput::Delete);
+                    self.update_preview();
+                }
+                Operation::CommandLine(input) => {
+                    self.command_line.input(input);
+                    self.update_preview();
+                }
+                Operation::CompletePrev => {
+                    let cwd = self.cwd();
+                    self.command_line.tab_complete(true, &self.options, &cwd);
+                    self.update_preview();
+                }
+                Operation::InsertRegister(name) => {
+                    if let Some(text) = self.register_text(name) {
+                        self.command_line.input(CommandLineInput::Insert(text));
+                        self.update_preview();
+                    }
                 }
                 Operation::DeleteAfter => {
                     panic!("only backspace is implemented for command line")
@@ -276,42 +1835,73 @@ impl Ctx {
                 }
                 Operation::SwitchMode(m) => {
                     if m != Mode::Command {
-                        self.command_line.clear_command();
+                        self.abort_command_line();
                         self.command_line.reset_visual(self.tui.get_mut());
                     }
                     self.mode = m
                 }
-                Operation::Debug => todo!(),
                 Operation::None => (),
                 _ => unreachable!(),
             },
+            Mode::Terminal => match action.operation {
+                Operation::TerminalInput(bytes) => {
+                    let res = self.focused_terminal().map(|t| t.write(&bytes));
+                    if let Some(Err(e)) = res {
+                        self.err(&e);
+                    }
+                }
+                Operation::SwitchMode(m) => self.set_mode(m),
+                Operation::None => (),
+                _ => unreachable!(),
+            },
+            _ if refused.is_some() => {
+                let _ = write!(self.warning(), "{}", refused.expect("checked"));
+            }
             _ => match action.operation {
                 Operation::Change => {
                     let range = motion_range.expect("change requires motion");
                     if let Some(range) = range {
-                        self.focused_buf.get_mut().delete_range(range);
+                        let mut buf = self.focused_buf.get_mut();
+                        let range = buf.range_to_offsets(range);
+                        self.registers.deleted(action.register, buf.delete_range(range));
+                        drop(buf);
                         self.set_mode(Mode::Insert);
                     }
                 }
                 Operation::Delete => {
                     let range = motion_range.expect("delete requires motion");
                     if let Some(range) = range {
-                        self.focused_buf.get_mut().delete_range(range);
+                        let mut buf = self.focused_buf.get_mut();
+                        let range = buf.range_to_offsets(range);
+                        self.registers.deleted(action.register, buf.delete_range(range));
                     }
                 }
                 Operation::Insert(c) => {
                     let mut buf = self.focused_buf.get_mut();
-                    buf.insert_str(c.replace('\r', "\n").as_str());
-                    self.focused_win.get().fit_ctx_frame(&mut buf.cursor);
-                    if let Some(pos) = c.bytes().rev().position(|b| b == b'\r') {
-                        buf.cursor.virtcol = pos
+                    let text = if self.mode == Mode::Insert && self.options.expandtab && c == "\t" {
+                        // spaces up to the next tab stop
+                        let DocPos { x, y } = buf.cursor.pos;
+                        let tabstop = self.options.tabstop.max(1);
+                        " ".repeat(tabstop - crate::format::columns(&buf.line(y)[..x], tabstop) % tabstop)
+                    } else {
+                        c.replace('\r', "\n")
+                    };
+                    buf.insert_str(&text);
+                    if self.mode == Mode::Insert && c.ends_with(|c: char| !c.is_whitespace()) {
+                        self.auto_wrap(&mut buf);
                     }
+                    let linecnt = buf.linecnt();
+                    self.focused_win.get().fit_ctx_frame(&mut buf.cursor, linecnt);
                 }
                 Operation::DeleteBefore => {
                     self.focused_buf.get_mut().delete_char_before();
                 }
                 Operation::DeleteAfter => {
                     let mut buf = self.focused_buf.get_mut();
+                    // there's nothing under a cursor past the end of the line
+                    if buf.cursor.coladd > 0 {
+                        return;
+                    }
                     buf.delete_char();
                     if buf.cursor.pos.x != 0 {
                         drop(buf);
@@ -321,17 +1911,199 @@ impl Ctx {
                 Operation::SwitchMode(m) => self.set_mode(m),
                 Operation::None => (),
                 Operation::Replace(_) => todo!(),
-                Operation::Debug => {
-                    write!(self.warning(), "not yet implemented").unwrap();
+                Operation::Put { before, indent } => self.put(action.register.unwrap_or('"'), before, indent),
+                Operation::DumpScreen => log!("{}", self.tui.borrow().dump_text()),
+                Operation::CommandWindow => {
+                    if let Err(e) = self.open_command_window() {
+                        self.err(&*e);
+                    }
+                }
+                Operation::ExecuteRegister(name) => {
+                    if let Err(e) = self.execute_register(name, action.repeat.unwrap_or(1) as usize) {
+                        self.err(&*e);
+                    }
+                }
+                Operation::Enter if self.in_command_window() => {
+                    let line = self.close_command_window().unwrap_or_default();
+                    self.command_line.add_history(line.clone());
+                    self.run_command(&line);
+                }
+                Operation::Enter => {
+                    self.apply_motion(Motion::ScreenSpace { dy: 1, dx: 0 });
+                }
+                Operation::RepeatChange => {
+                    let keys = self.repeat.keys().to_vec();
+                    for _ in 0..action.repeat.unwrap_or(1) {
+                        self.normal(&keys, false);
+                    }
+                }
+                Operation::Undo => self.history_step(action.repeat.unwrap_or(1) as usize, false, BufferInner::undo),
+                Operation::Redo => self.history_step(action.repeat.unwrap_or(1) as usize, true, BufferInner::redo),
+                Operation::Format => {
+                    let mut buf = self.focused_buf.get_mut();
+                    let lines = match motion_range {
+                        Some(Some(range)) => range.start.y.min(range.end.y)..range.start.y.max(range.end.y) + 1,
+                        Some(None) => return,
+                        None => {
+                            let y = buf.cursor.pos.y;
+                            y..(y + action.repeat.unwrap_or(1) as usize).min(buf.linecnt())
+                        }
+                    };
+                    let width = match self.options.textwidth {
+                        0 => 79,
+                        w => w,
+                    };
+                    let mut text = crate::format::reflow(&buf.get_lines(lines.clone()).join("\n"), width);
+                    // the last line of the buffer may not end in a newline
+                    let end = buf.line_offsets(lines.clone()).end;
+                    if end == buf.len() && (end == 0 || buf.char_at(end - 1) != '\n') {
+                        text.pop();
+                    }
+                    let last = lines.start + text.lines().count().saturating_sub(1);
+                    buf.replace_lines(lines, &text);
+                    buf.cursor.set_pos(DocPos { x: 0, y: last });
+                }
+                Operation::Surround(c) => {
+                    let mut buf = self.focused_buf.get_mut();
+                    let range = match motion_range {
+                        Some(Some(range)) => buf.range_to_offsets(range),
+                        Some(None) => return,
+                        None => {
+                            // the line without its indentation and trailing whitespace
+                            let y = buf.cursor.pos.y;
+                            let line = buf.line(y);
+                            let start = buf.line_offsets(y..y).start + line.len() - line.trim_start().len();
+                            start..start + line.trim().len()
+                        }
+                    };
+                    if !crate::surround::add(&mut buf, range, c) {
+                        drop(buf);
+                        let _ = write!(self.warning(), "no delimiters for {c:?}");
+                    }
+                }
+                Operation::ChangeSurround { old, new } => {
+                    let mut buf = self.focused_buf.get_mut();
+                    let pos = buf.coff();
+                    if !crate::surround::change(&mut buf, pos, old, new) {
+                        drop(buf);
+                        let _ = write!(self.warning(), "no {old:?} around the cursor to change to {new:?}");
+                    }
+                }
+                Operation::DeleteSurround(c) => {
+                    let mut buf = self.focused_buf.get_mut();
+                    let pos = buf.coff();
+                    if !crate::surround::delete(&mut buf, pos, c) {
+                        drop(buf);
+                        let _ = write!(self.warning(), "no {c:?} around the cursor");
+                    }
+                }
+                Operation::RecenterView => {
+                    let mut buf = self.focused_buf.get_mut();
+                    let linecnt = buf.linecnt();
+                    self.focused_win.get_mut().center_view(&mut buf.cursor, linecnt);
+                }
+                Operation::GotoDefinition => {
+                    let res = self.lsp.goto_definition(&self.focused_buf.get());
+                    if let Err(e) = res {
+                        self.err(&e);
+                    }
+                }
+                Operation::GotoFile => {
+                    if let Err(e) = self.goto_file() {
+                        self.err(&*e);
+                    }
+                }
+                Operation::EditAlternate => {
+                    if let Err(e) = self.edit_alternate() {
+                        self.err(&*e);
+                    }
+                }
+                Operation::OpenExternal => {
+                    if let Err(e) = self.open_external() {
+                        self.err(&*e);
+                    }
+                }
+                Operation::Hover => {
+                    let res = if self.man_pages.contains(&self.focused_buf.id()) {
+                        self.man_under_cursor()
+                    } else {
+                        self.lsp.hover(&self.focused_buf.get()).map_err(Into::into)
+                    };
+                    if let Err(e) = res {
+                        self.err(&*e);
+                    }
+                }
+                Operation::InsertRegister(name) => {
+                    let Some(text) = self.registers.get(name).map(str::to_owned) else {
+                        return;
+                    };
+                    let mut buf = self.focused_buf.get_mut();
+                    buf.insert_str(&text);
+                    let linecnt = buf.linecnt();
+                    self.focused_win.get().fit_ctx_frame(&mut buf.cursor, linecnt);
+                }
+                Operation::DeleteWordBefore => self.delete_before_cursor(true),
+                Operation::DeleteLineBefore => self.delete_before_cursor(false),
+                Operation::NormalOnce => {
+                    self.set_mode(Mode::Normal);
+                    self.insert_once = true;
+                }
+                Operation::TerminalInput(_)
+                | Operation::CommandLine(_)
+                | Operation::CompletePrev => (),
+                Operation::NextWindow => {
+                    let windows = self.root.windows();
+                    let idx = windows.iter().position(|w| Arc::ptr_eq(w, &self.focused_win)).unwrap_or(0);
+                    let next = Arc::clone(&windows[(idx + 1) % windows.len()]);
+                    self.focus_window(next);
+                }
+                Operation::StartSearch { backward } => {
+                    self.set_mode(Mode::Command);
+                    self.command_line.set_type(if backward {
+                        CommandType::FindBackward
+                    } else {
+                        CommandType::Find
+                    });
+                }
+                Operation::SearchNext { reverse } => {
+                    if let Err(e) = self.search_next(reverse) {
+                        self.err(&*e);
+                    }
+                }
+                Operation::PageDown => self.focused_win.get_mut().scroll_page(1),
+                Operation::PageUp => self.focused_win.get_mut().scroll_page(-1),
+                Operation::NextHunk | Operation::PrevHunk => {
+                    let res = self.jump_hunk(action.operation == Operation::NextHunk);
+                    if let Err(e) = res {
+                        self.err(&*e);
+                    }
+                }
+                Operation::DiffPut | Operation::DiffGet => {
+                    let res = self.transfer_hunk(action.operation == Operation::DiffPut);
+                    if let Err(e) = res {
+                        self.err(&*e);
+                    }
                 }
-                Operation::RecenterView => self
-                    .focused_win.get_mut()
-                    .center_view(&mut self.focused_buf.get_mut().cursor),
             },
         };
         if let Some(m) = action.post_motion {
             self.apply_motion(m);
         };
+        if let Some(side) = self.diff_side() {
+            let height = self.focused_win.get().height() as usize;
+            let line = self.focused_buf.get().cursor.pos.y;
+            let diff = self.diff.as_mut().expect("has a side");
+            diff.update();
+            diff.scroll_to(side, line, height);
+        }
+        self.lsp.sync(&self.focused_buf.get());
+        let moved = self
+            .popup
+            .as_ref()
+            .is_some_and(|p| p.is_stale(&self.focused_buf.get().cursor));
+        if moved || self.mode != Mode::Normal {
+            self.popup = None;
+        }
     }
 }
 
@@ -360,3 +2132,27 @@ pub fn draw_text(ctx: &Ctx, region: TermBox, content: impl Display, color: Color
             );
         }
 }
+
+/// the flags of `:s`
+#[derive(Default)]
+struct SubstituteFlags {
+    /// replace every match on a line, not just the first
+    global: bool,
+    ignore_case: Option<bool>,
+    /// ask before every replacement, see [`crate::confirm`]
+    confirm: bool,
+}
+
+fn substitute_flags(flags: &str) -> Result<SubstituteFlags, Box<dyn std::error::Error>> {
+    let mut out = SubstituteFlags::default();
+    for flag in flags.chars() {
+        match flag {
+            'g' => out.global = true,
+            'i' => out.ignore_case = Some(true),
+            'I' => out.ignore_case = Some(false),
+            'c' => out.confirm = true,
+            _ => return Err(format!("Invalid flag: {flag}").into()),
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,435 @@
+//! Searching buffers with `/` and `?`, and replacing with `:s`.
+//!
+//! Patterns use the syntax of the [`regex`] crate, which is what Vim calls very magic. Patterns
+//! may start with `\v` to say so explicitly, or with `\m` to use Vim's magic syntax instead,
+//! where `(`, `)`, `|`, `+`, `?` and `{` are literal unless escaped. In both, `\c` anywhere in
+//! the pattern ignores case and `\C` doesn't. Patterns match across lines with `\n`, and `^` and
+//! `$` match at the start and end of every line.
+//!
+//! Searches wrap around the ends of the buffer. A pattern can be followed by an offset, like
+//! `/foo/e` to land on the end of the match.
+use std::fmt::Write;
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+use std::sync::Arc;
+
+use crate::buffer::extmark::Extmark;
+use crate::buffer::{Buffer, BufferInner};
+use crate::options::Options;
+use crate::prelude::*;
+use crate::tui::{BasicColor, Color};
+
+/// extmark namespace of the highlighted matches in a [`Preview`]
+pub const NAMESPACE: &str = "search";
+
+/// highlight of matches and replacements
+pub const MATCH_COLOR: Color = Color { fg: BasicColor::Black, bg: BasicColor::Yellow, ..Color::new() };
+
+/// where the cursor goes relative to a match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offset {
+    /// `[+-]N`, start of the line `N` lines down
+    Line(isize),
+    /// `s[+-]N` or `b[+-]N`, `N` characters right of the start
+    Start(isize),
+    /// `e[+-]N`, `N` characters right of the last character
+    End(isize),
+}
+
+impl Default for Offset {
+    fn default() -> Self {
+        Offset::Start(0)
+    }
+}
+
+impl Offset {
+    pub fn parse(s: &str) -> Option<Self> {
+        if s.is_empty() {
+            return Some(Offset::default());
+        }
+        let (kind, n): (fn(isize) -> Self, _) = match s.as_bytes()[0] {
+            b's' | b'b' => (Offset::Start, &s[1..]),
+            b'e' => (Offset::End, &s[1..]),
+            _ => (Offset::Line, s),
+        };
+        // a lone sign means one, as in `/foo/+`
+        let n = match n {
+            "" => 0,
+            "+" => 1,
+            "-" => -1,
+            n => n.strip_prefix('+').unwrap_or(n).parse().ok()?,
+        };
+        Some(kind(n))
+    }
+}
+
+/// split what was typed after `/` or `?` into the pattern and the offset, if any. The pattern ends
+/// at the first `delim` that isn't escaped with a backslash.
+pub fn split_offset(s: &str, delim: char) -> (&str, Option<&str>) {
+    let mut escaped = false;
+    for (i, c) in s.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            c if c == delim => return (&s[..i], Some(&s[i + c.len_utf8()..])),
+            _ => (),
+        }
+    }
+    (s, None)
+}
+
+pub struct Search {
+    pub pattern: Regex,
+    /// set for `?`
+    pub backward: bool,
+    pub offset: Offset,
+}
+
+impl Search {
+    pub fn new(pattern: &str, backward: bool, offset: Offset, options: &Options) -> Result<Self, regex::Error> {
+        Ok(Self {
+            pattern: compile(pattern, options, None)?,
+            backward,
+            offset,
+        })
+    }
+
+    /// where the cursor goes for the match `m`
+    pub fn target(&self, buf: &BufferInner, m: Range<usize>) -> DocPos {
+        let text = buf.to_string();
+        let off = match self.offset {
+            Offset::Line(n) => {
+                let y = buf.offset_to_pos(m.start).y.saturating_add_signed(n);
+                return DocPos { x: 0, y: y.min(buf.linecnt().saturating_sub(1)) };
+            }
+            Offset::Start(n) => step_chars(&text, m.start, n),
+            Offset::End(n) => {
+                let last = text[..m.end].char_indices().next_back().map_or(0, |(i, _)| i);
+                step_chars(&text, last.max(m.start), n)
+            }
+        };
+        buf.offset_to_pos(off)
+    }
+
+    /// next match starting after `off`, or before it if `reverse` is set. `reverse` flips the
+    /// direction of the search, like `N`.
+    pub fn find(&self, buf: &BufferInner, off: usize, reverse: bool) -> Option<Range<usize>> {
+        let text = buf.to_string();
+        if self.backward != reverse {
+            find_before(&self.pattern, &text, off)
+        } else {
+            find_after(&self.pattern, &text, off)
+        }
+    }
+}
+
+/// build a pattern, see the [module docs](self) for the syntax. Case is ignored according to the
+/// `ignorecase` and `smartcase` options unless `ignore_case` or the pattern itself says otherwise.
+pub fn compile(pattern: &str, options: &Options, ignore_case: Option<bool>) -> Result<Regex, regex::Error> {
+    let (pattern, magic) = match pattern.get(..2) {
+        Some("\\v") => (&pattern[2..], false),
+        Some("\\m") => (&pattern[2..], true),
+        _ => (pattern, false),
+    };
+    let mut out = String::with_capacity(pattern.len());
+    let mut case = ignore_case;
+    let mut has_upper = false;
+    // inside `\{n,m}`, where the closing brace isn't escaped
+    let mut count = false;
+    let mut chars = pattern.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            has_upper |= c.is_uppercase();
+            match c {
+                '(' | ')' | '|' | '+' | '?' | '{' if magic => out.push('\\'),
+                '}' if magic && !count => out.push('\\'),
+                '}' => count = false,
+                _ => (),
+            }
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('c') => case = case.or(Some(true)),
+            Some('C') => case = case.or(Some(false)),
+            Some(c @ ('(' | ')' | '|' | '+' | '?')) if magic => out.push(c),
+            Some('=') if magic => out.push('?'),
+            Some('{') if magic => {
+                count = true;
+                out.push('{');
+            }
+            Some('<' | '>') if magic => out.push_str("\\b"),
+            Some('_') if magic && chars.peek() == Some(&'.') => {
+                chars.next();
+                out.push_str("(?s:.)");
+            }
+            Some(c) => {
+                out.push('\\');
+                out.push(c);
+            }
+            // let the regex crate complain about the trailing backslash
+            None => out.push('\\'),
+        }
+    }
+    let ignore_case = case.unwrap_or(options.ignorecase && !(options.smartcase && has_upper));
+    RegexBuilder::new(&out)
+        .case_insensitive(ignore_case)
+        .multi_line(true)
+        .build()
+}
+
+/// translate the replacement of `:s` to a template for [`regex::Captures::expand`]. `&` and `\0`
+/// are the whole match, `\1` to `\9` are groups and `\r` or `\n` is a line break.
+pub fn replacement_template(rep: &str) -> String {
+    let mut out = String::with_capacity(rep.len());
+    let mut chars = rep.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '&' => out.push_str("${0}"),
+            '$' => out.push_str("$$"),
+            '\\' => match chars.next() {
+                Some(d @ '0'..='9') => write!(out, "${{{d}}}").unwrap(),
+                Some('r' | 'n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('$') => out.push_str("$$"),
+                Some(c) => out.push(c),
+                None => out.push('\\'),
+            },
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// result of [`substitute`]
+pub struct Substituted {
+    pub text: String,
+    /// number of substitutions
+    pub count: usize,
+    /// number of lines with substitutions
+    pub lines: usize,
+    /// where the replacements are in `text`
+    pub replaced: Vec<Range<usize>>,
+}
+
+/// replace matches of `re` in `text` using a template from [`replacement_template`]. Only the
+/// first match on each line is replaced unless `global` is set.
+pub fn substitute(re: &Regex, text: &str, template: &str, global: bool) -> Result<Substituted, EditorError> {
+    let mut out = String::with_capacity(text.len());
+    let mut replaced = Vec::new();
+    let mut copied = 0;
+    let (mut count, mut lines) = (0, 0);
+    // line of the last substitution, counted up to `counted`
+    let (mut line, mut counted, mut last_line) = (0, 0, None);
+    for caps in re.captures_iter(text) {
+        crate::busy::check()?;
+        let m = caps.get(0).expect("group 0 is the whole match");
+        line += text[counted..m.start()].matches('\n').count();
+        counted = m.start();
+        if last_line == Some(line) {
+            if !global {
+                continue;
+            }
+        } else {
+            lines += 1;
+            last_line = Some(line);
+        }
+        out.push_str(&text[copied..m.start()]);
+        let start = out.len();
+        caps.expand(template, &mut out);
+        replaced.push(start..out.len());
+        copied = m.end();
+        count += 1;
+    }
+    out.push_str(&text[copied..]);
+    Ok(Substituted { text: out, count, lines, replaced })
+}
+
+/// what a `:s` that is still being typed would do, drawn instead of the buffer it applies to. See
+/// the `inccommand` option.
+pub struct Preview {
+    pub target: Arc<Buffer>,
+    /// the buffer with the substitutions made, highlighted with extmarks in [`NAMESPACE`]
+    pub buf: BufferInner,
+}
+
+impl Preview {
+    /// preview substituting `lines` of `target`, if anything would change
+    pub fn substitute(target: Arc<Buffer>, lines: Range<usize>, re: &Regex, template: &str, global: bool) -> Option<Self> {
+        let orig = target.get();
+        let text = orig.to_string();
+        let range = orig.line_offsets(lines);
+        let sub = substitute(re, &text[range.clone()], template, global).ok()?;
+        if sub.count == 0 {
+            return None;
+        }
+        let mut buf = BufferInner::from_string(format!("{}{}{}", &text[..range.start], sub.text, &text[range.end..]));
+        buf.cursor = orig.cursor;
+        drop(orig);
+        for r in sub.replaced {
+            let r = (r.start + range.start)..(r.end + range.start);
+            buf.extmarks_mut().add(Extmark::highlight(NAMESPACE, r, MATCH_COLOR));
+        }
+        Some(Self { target, buf })
+    }
+}
+
+/// offset of the character `n` characters after the one at `off`, stopping at the ends of the text
+fn step_chars(text: &str, off: usize, n: isize) -> usize {
+    if n >= 0 {
+        let chars = text[off..].char_indices().map(|(i, _)| off + i);
+        chars.take(n.unsigned_abs() + 1).last().unwrap_or(off)
+    } else {
+        text[..off].char_indices().rev().map(|(i, _)| i).take(n.unsigned_abs()).last().unwrap_or(off)
+    }
+}
+
+fn find_after(re: &Regex, text: &str, off: usize) -> Option<Range<usize>> {
+    let start = text[off.min(text.len())..]
+        .chars()
+        .next()
+        .map_or(text.len(), |c| off + c.len_utf8());
+    re.find_at(text, start.min(text.len()))
+        .or_else(|| re.find(text))
+        .map(|m| m.range())
+}
+
+fn find_before(re: &Regex, text: &str, off: usize) -> Option<Range<usize>> {
+    let mut matches = re.find_iter(text).map(|m| m.range());
+    let last_before = matches.by_ref().take_while(|m| m.start < off).last();
+    last_before.or_else(|| re.find_iter(text).last().map(|m| m.range()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn forward_and_backward_wrap() {
+        let opts = Options::default();
+        let buf = BufferInner::from_str("foo bar\nbar foo\n");
+        let s = Search::new("foo", false, Offset::default(), &opts).unwrap();
+        assert_eq!(s.find(&buf, 0, false), Some(12..15));
+        assert_eq!(s.find(&buf, 12, false), Some(0..3));
+        assert_eq!(s.find(&buf, 12, true), Some(0..3));
+        assert_eq!(s.find(&buf, 0, true), Some(12..15));
+
+        let s = Search::new("ba.", true, Offset::default(), &opts).unwrap();
+        assert_eq!(s.find(&buf, 8, false), Some(4..7));
+        assert_eq!(s.find(&buf, 4, false), Some(8..11));
+        assert!(Search::new("(", false, Offset::default(), &opts).is_err());
+        let s = Search::new("nope", false, Offset::default(), &opts).unwrap();
+        assert_eq!(s.find(&buf, 0, false), None);
+    }
+
+    #[test]
+    fn case_options() {
+        let buf = BufferInner::from_str("Foo foo\n");
+        let mut opts = Options::default();
+        let find = |pat: &str, opts: &Options| {
+            let s = Search::new(pat, false, Offset::default(), opts).unwrap();
+            s.find(&buf, 4, false)
+        };
+        assert_eq!(find("foo", &opts), Some(4..7));
+        assert_eq!(find("fOO", &opts), None);
+        opts.ignorecase = true;
+        assert_eq!(find("fOO", &opts), Some(0..3));
+        opts.smartcase = true;
+        assert_eq!(find("foo", &opts), Some(0..3));
+        assert_eq!(find("Foo", &opts), Some(0..3));
+        assert_eq!(find("fOo", &opts), None);
+    }
+
+    #[test]
+    fn dialect() {
+        let opts = Options::default();
+        let find = |pat: &str, text: &str| compile(pat, &opts, None).unwrap().find(text).map(|m| m.range());
+        assert_eq!(find("(a|b)+", "xab"), Some(1..3));
+        assert_eq!(find("\\v(a|b)+", "xab"), Some(1..3));
+        assert_eq!(find("\\m(a|b)+", "xab(a|b)+"), Some(3..9));
+        assert_eq!(find("\\m\\(a\\|b\\)\\+", "xab"), Some(1..3));
+        assert_eq!(find("\\ma\\{2}}", "aaa}"), Some(1..4));
+        assert_eq!(find("\\m\\<ab", "cab ab"), Some(4..6));
+        assert_eq!(find("b\\na", "ab\nab"), Some(1..4));
+        assert_eq!(find("\\ma\\_.b", "a\nb"), Some(0..3));
+        assert_eq!(find("^b", "ab\nba"), Some(3..4));
+        assert_eq!(find("a$", "ba\nab"), Some(1..2));
+        assert_eq!(find("A\\c", "xa"), Some(1..2));
+        assert_eq!(find("\\\\c", "c\\c"), Some(1..3));
+        assert!(compile("a\\", &opts, None).is_err());
+
+        let opts = Options {
+            ignorecase: true,
+            smartcase: true,
+            ..Options::default()
+        };
+        assert!(compile("A", &opts, None).unwrap().is_match("A"));
+        assert!(!compile("A", &opts, None).unwrap().is_match("a"));
+        // escapes don't count as uppercase for smartcase
+        assert!(compile("\\Sb", &opts, None).unwrap().is_match("aB"));
+        assert!(compile("a\\C", &opts, None).unwrap().is_match("a"));
+        assert!(!compile("a\\C", &opts, None).unwrap().is_match("A"));
+        assert!(!compile("a", &opts, Some(false)).unwrap().is_match("A"));
+    }
+
+    #[test]
+    fn replace() {
+        let opts = Options::default();
+        let sub = |pat: &str, rep: &str, text: &str, global: bool| {
+            let re = compile(pat, &opts, None).unwrap();
+            let sub = substitute(&re, text, &replacement_template(rep), global).unwrap();
+            (sub.text, sub.count, sub.lines)
+        };
+        assert_eq!(sub("a", "b", "aa\naa\ncc\n", false), ("ba\nba\ncc\n".into(), 2, 2));
+        assert_eq!(sub("a", "b", "aa\naa\n", true), ("bb\nbb\n".into(), 4, 2));
+        assert_eq!(sub("(\\w+) (\\w+)", "\\2 \\1", "one two\n", false).0, "two one\n");
+        assert_eq!(sub("o+", "<&>", "foo\n", false).0, "f<oo>\n");
+        assert_eq!(sub("o", "\\&$1\\\\", "o", false).0, "&$1\\");
+        assert_eq!(sub(", ", "\\r", "a, b, c", true), ("a\nb\nc".into(), 2, 1));
+        assert_eq!(sub("a\\nb", "", "xa\nbx\n", false), ("xx\n".into(), 1, 1));
+        assert_eq!(sub("z", "y", "abc", false), ("abc".into(), 0, 0));
+    }
+
+    #[test]
+    fn preview() {
+        let opts = Options::default();
+        let buf = Buffer::from_string("foo\nfoo foo\nfoo\n");
+        let re = compile("o+", &opts, None).unwrap();
+        let p = Preview::substitute(buf.clone(), 1..3, &re, &replacement_template("0\\r"), true).unwrap();
+        assert_eq!(p.buf.to_string(), "foo\nf0\n f0\n\nf0\n\n");
+        assert_eq!(buf.get().to_string(), "foo\nfoo foo\nfoo\n");
+        let marks: Vec<_> = p.buf.extmarks().iter().map(|(_, m)| (m.namespace, m.range.clone())).collect();
+        assert_eq!(marks, [(NAMESPACE, 5..7), (NAMESPACE, 9..11), (NAMESPACE, 13..15)]);
+        assert!(Preview::substitute(buf, 0..3, &compile("x", &opts, None).unwrap(), "", false).is_none());
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(split_offset("foo", '/'), ("foo", None));
+        assert_eq!(split_offset("a\\/b/e+1", '/'), ("a\\/b", Some("e+1")));
+        assert_eq!(split_offset("a/b?c", '?'), ("a/b", Some("c")));
+        assert_eq!(Offset::parse(""), Some(Offset::Start(0)));
+        assert_eq!(Offset::parse("e"), Some(Offset::End(0)));
+        assert_eq!(Offset::parse("e-1"), Some(Offset::End(-1)));
+        assert_eq!(Offset::parse("b+2"), Some(Offset::Start(2)));
+        assert_eq!(Offset::parse("+2"), Some(Offset::Line(2)));
+        assert_eq!(Offset::parse("-"), Some(Offset::Line(-1)));
+        assert_eq!(Offset::parse("3"), Some(Offset::Line(3)));
+        assert_eq!(Offset::parse("x"), None);
+        assert_eq!(Offset::parse("e+x"), None);
+
+        let opts = Options::default();
+        let buf = BufferInner::from_str("one\ntwo three\nfour\n");
+        let target = |offset| Search::new("thr.e", false, offset, &opts).unwrap().target(&buf, 8..13);
+        assert_eq!(target(Offset::Start(0)), DocPos { x: 4, y: 1 });
+        assert_eq!(target(Offset::End(0)), DocPos { x: 8, y: 1 });
+        assert_eq!(target(Offset::End(-2)), DocPos { x: 6, y: 1 });
+        assert_eq!(target(Offset::Start(-4)), DocPos { x: 0, y: 1 });
+        assert_eq!(target(Offset::Start(20)), DocPos { x: 4, y: 2 });
+        assert_eq!(target(Offset::Line(1)), DocPos { x: 0, y: 2 });
+        assert_eq!(target(Offset::Line(-5)), DocPos { x: 0, y: 0 });
+        assert_eq!(target(Offset::Line(5)), DocPos { x: 0, y: 2 });
+    }
+}
@@ -0,0 +1,116 @@
+//! Ex commands from other programs, over a Unix socket.
+//!
+//! With `--listen SOCKET` the editor runs every line written to the socket as an ex command, as
+//! if it was typed after `:`. `edit --server SOCKET --remote-send CMD` is the other end, so that a
+//! build script or a terminal in another window can send `e /abs/path/to/file` and then `42` to
+//! open a file at a line. Paths are relative to the editor's directory, not the sender's.
+//!
+//! Commands are handed to the main loop as [`Event::Command`] and run in the order they arrive.
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use crate::event::{self, Event};
+
+/// the socket being listened on, which is removed when this is dropped
+pub struct Server {
+    path: PathBuf,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// listen on `path` until the returned [`Server`] is dropped. A socket left behind by an editor
+/// that didn't exit cleanly is replaced, one that is still listened on is not.
+pub fn listen(path: &Path) -> io::Result<Server> {
+    let listener = bind(path)?;
+    serve(listener, |cmd| event::post(Event::Command(cmd)));
+    Ok(Server { path: path.to_owned() })
+}
+
+fn bind(path: &Path) -> io::Result<UnixListener> {
+    match UnixListener::bind(path) {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if UnixStream::connect(path).is_ok() {
+                return Err(io::Error::new(io::ErrorKind::AddrInUse, format!("{path:?} is already in use")));
+            }
+            std::fs::remove_file(path)?;
+            UnixListener::bind(path)
+        }
+        res => res,
+    }
+}
+
+/// accept connections in the background, giving every line of them to `on_command`
+fn serve(listener: UnixListener, on_command: impl Fn(String) + Clone + Send + 'static) {
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else {
+                continue;
+            };
+            let on_command = on_command.clone();
+            std::thread::spawn(move || {
+                for line in BufReader::new(stream).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    if !line.trim().is_empty() {
+                        on_command(line);
+                    }
+                }
+            });
+        }
+    });
+}
+
+/// send `commands` to the editor listening on `path`
+pub fn send(path: &Path, commands: &[String]) -> io::Result<()> {
+    let mut stream = UnixStream::connect(path)?;
+    for cmd in commands {
+        // a newline would split it into two commands
+        writeln!(stream, "{}", cmd.replace('\n', " "))?;
+    }
+    stream.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn socket(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("edit-{}-{name}.sock", std::process::id()))
+    }
+
+    #[test]
+    fn commands() {
+        let path = socket("commands");
+        let (tx, rx) = mpsc::channel();
+        serve(bind(&path).unwrap(), move |cmd| tx.send(cmd).unwrap());
+        let recv = |n| (0..n).map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap()).collect::<Vec<_>>();
+        send(&path, &["e /tmp/x".into(), "".into(), "42".into()]).unwrap();
+        assert_eq!(recv(2), ["e /tmp/x", "42"]);
+        // every connection has its own thread, so only the lines of one are in order
+        send(&path, &["split\nvsplit".into()]).unwrap();
+        assert_eq!(recv(1), ["split vsplit"]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn stale_socket() {
+        let path = socket("stale");
+        let server = listen(&path).unwrap();
+        assert_eq!(listen(&path).err().map(|e| e.kind()), Some(io::ErrorKind::AddrInUse));
+        drop(server);
+        assert!(!path.exists());
+        assert!(send(&path, &["q".into()]).is_err());
+        // left behind without anyone listening
+        drop(UnixListener::bind(&path).unwrap());
+        let server = listen(&path).unwrap();
+        drop(server);
+    }
+}
@@ -0,0 +1,115 @@
+//! Sorting lines for `:sort`.
+//!
+//! Lines are compared by their text, or with the `n` flag by the first number in them, where lines
+//! without one come first. A pattern picks the part of the line to compare: the text after the
+//! match, or the match itself with `r`. Lines it doesn't match are kept in their order in front of
+//! the sorted ones, or after them when sorting in reverse.
+use std::cmp::Ordering;
+
+use lazy_regex::regex;
+use regex::Regex;
+
+pub struct Sort {
+    reverse: bool,
+    numeric: bool,
+    unique: bool,
+    ignore_case: bool,
+    /// compare the match of the pattern instead of the text after it
+    matched: bool,
+    pattern: Option<Regex>,
+}
+
+impl Sort {
+    /// a sort with the `flags` of `:sort`, backwards for `:sort!`
+    pub fn new(reverse: bool, flags: &str, pattern: Option<Regex>) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut sort = Sort { reverse, numeric: false, unique: false, ignore_case: false, matched: false, pattern };
+        for flag in flags.chars() {
+            match flag {
+                'n' => sort.numeric = true,
+                'u' => sort.unique = true,
+                'i' => sort.ignore_case = true,
+                'r' => sort.matched = true,
+                _ => return Err(format!("E474: Invalid argument: {flag}").into()),
+            }
+        }
+        Ok(sort)
+    }
+
+    /// the part of `line` that's compared, `None` if the pattern doesn't match it
+    fn key<'a>(&self, line: &'a str) -> Option<&'a str> {
+        let Some(pattern) = &self.pattern else {
+            return Some(line);
+        };
+        let found = pattern.find(line)?;
+        Some(if self.matched { found.as_str() } else { &line[found.end()..] })
+    }
+
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        if self.numeric {
+            let number = |s: &str| regex!(r"-?\d+").find(s).and_then(|m| m.as_str().parse::<i64>().ok());
+            number(a).cmp(&number(b))
+        } else if self.ignore_case {
+            a.to_lowercase().cmp(&b.to_lowercase())
+        } else {
+            a.cmp(b)
+        }
+    }
+
+    /// `lines` in sorted order
+    pub fn sort(&self, lines: &[&str]) -> Vec<String> {
+        let (mut unmatched, mut matched): (Vec<_>, Vec<_>) =
+            lines.iter().map(|line| (self.key(line), *line)).partition(|(key, _)| key.is_none());
+        // stable, so that equal lines keep their order either way
+        matched.sort_by(|(a, _), (b, _)| {
+            let ord = self.compare(a.unwrap(), b.unwrap());
+            if self.reverse { ord.reverse() } else { ord }
+        });
+        if self.unique {
+            matched.dedup_by(|(a, _), (b, _)| self.compare(a.unwrap(), b.unwrap()) == Ordering::Equal);
+        }
+        if self.reverse {
+            unmatched.reverse();
+            matched.append(&mut unmatched);
+        } else {
+            unmatched.append(&mut matched);
+            matched = unmatched;
+        }
+        matched.into_iter().map(|(_, line)| line.to_string()).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sort(reverse: bool, flags: &str, pattern: Option<&str>, lines: &[&str]) -> Vec<String> {
+        let pattern = pattern.map(|p| Regex::new(p).unwrap());
+        Sort::new(reverse, flags, pattern).unwrap().sort(lines)
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(sort(false, "", None, &["b", "C", "a"]), ["C", "a", "b"]);
+        assert_eq!(sort(false, "i", None, &["b", "C", "a"]), ["a", "b", "C"]);
+        assert_eq!(sort(true, "", None, &["b", "C", "a"]), ["b", "a", "C"]);
+        assert_eq!(sort(false, "iu", None, &["b", "a", "B", "a"]), ["a", "b"]);
+        assert!(Sort::new(false, "q", None).is_err());
+    }
+
+    #[test]
+    fn numeric() {
+        let lines = ["x10", "x9", "none", "x-1"];
+        assert_eq!(sort(false, "n", None, &lines), ["none", "x-1", "x9", "x10"]);
+        assert_eq!(sort(true, "n", None, &lines), ["x10", "x9", "x-1", "none"]);
+    }
+
+    #[test]
+    fn pattern() {
+        let lines = ["a 2 z", "no match", "b 1 y"];
+        // after the match, the match itself
+        assert_eq!(sort(false, "", Some(r"\d "), &lines), ["no match", "b 1 y", "a 2 z"]);
+        assert_eq!(sort(false, "r", Some(r"[a-z]$"), &lines), ["no match", "b 1 y", "a 2 z"]);
+        assert_eq!(sort(false, "r", Some(r"^[a-z] "), &lines), ["no match", "a 2 z", "b 1 y"]);
+        assert_eq!(sort(true, "", Some(r"\d"), &lines), ["a 2 z", "b 1 y", "no match"]);
+    }
+}
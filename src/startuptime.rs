@@ -0,0 +1,73 @@
+//! Timing of startup phases, enabled with `--startuptime FILE`.
+//!
+//! Phases are marked with [`mark`] as they finish. Marks are ignored unless timing was enabled
+//! with [`enable`], so they can stay in place permanently.
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+struct Timer {
+    start: Instant,
+    last: Instant,
+    report: PathBuf,
+    /// phase, time since start, time of the phase itself
+    phases: Vec<(&'static str, Duration, Duration)>,
+}
+
+static TIMER: Mutex<Option<Timer>> = Mutex::new(None);
+
+/// start timing from `start`. The report is written to `report` by [`finish`].
+pub fn enable(report: &Path, start: Instant) {
+    *TIMER.lock().unwrap() = Some(Timer {
+        start,
+        last: start,
+        report: report.to_owned(),
+        phases: Vec::new(),
+    });
+}
+
+/// record that `phase` just finished
+pub fn mark(phase: &'static str) {
+    let mut guard = TIMER.lock().unwrap();
+    let Some(timer) = guard.as_mut() else {
+        return;
+    };
+    let now = Instant::now();
+    timer.phases.push((phase, now - timer.start, now - timer.last));
+    timer.last = now;
+}
+
+fn format_report(phases: &[(&'static str, Duration, Duration)]) -> String {
+    let ms = |d: Duration| d.as_secs_f64() * 1000.0;
+    let mut out = String::from("times in msec\n clock   self: phase\n");
+    for (phase, total, own) in phases {
+        let _ = writeln!(out, "{:>7.3} {:>7.3}: {phase}", ms(*total), ms(*own));
+    }
+    out
+}
+
+/// stop timing and write the report, if timing was enabled
+pub fn finish() -> std::io::Result<()> {
+    let Some(timer) = TIMER.lock().unwrap().take() else {
+        return Ok(());
+    };
+    std::fs::write(&timer.report, format_report(&timer.phases))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn report_format() {
+        let phases = [
+            ("parse arguments", Duration::from_micros(120), Duration::from_micros(120)),
+            ("guile init", Duration::from_micros(15_620), Duration::from_micros(15_500)),
+        ];
+        assert_eq!(
+            format_report(&phases),
+            "times in msec\n clock   self: phase\n  0.120   0.120: parse arguments\n 15.620  15.500: guile init\n"
+        );
+    }
+}
@@ -0,0 +1,134 @@
+//! Adding, changing and deleting the delimiters around text, after vim-surround.
+//!
+//! `ys{motion}{char}` surrounds the text of a motion, `cs{old}{new}` changes the delimiters around
+//! the cursor and `ds{char}` deletes them. The delimiters are named by their character, where the
+//! opening bracket of a pair puts spaces inside it and the closing one doesn't, and `b`, `B`, `r`
+//! and `a` stand for `()`, `{}`, `[]` and `<>`. Any other punctuation surrounds with itself. `t` is
+//! the tags of an XML or HTML element, which can be changed and deleted but not added since there's
+//! no way to ask for a name.
+use std::ops::Range;
+
+use crate::prelude::*;
+use crate::textobj;
+
+/// the delimiters `c` adds, `None` if it doesn't name any
+fn delimiters(c: char) -> Option<(String, String)> {
+    let (open, close) = match c {
+        '(' => ("( ", " )"),
+        ')' | 'b' => ("(", ")"),
+        '{' => ("{ ", " }"),
+        '}' | 'B' => ("{", "}"),
+        '[' => ("[ ", " ]"),
+        ']' | 'r' => ("[", "]"),
+        '<' | '>' | 'a' => ("<", ">"),
+        c if c.is_ascii_punctuation() => return Some((c.to_string(), c.to_string())),
+        _ => return None,
+    };
+    Some((open.into(), close.into()))
+}
+
+/// byte ranges of the opening and closing delimiter that `c` names around `pos`. The spaces inside
+/// are part of the delimiters when `c` is an opening bracket.
+fn find(buf: &BufferInner, pos: usize, c: char) -> Option<(Range<usize>, Range<usize>)> {
+    let object: textobj::TextObject = match c {
+        '(' | ')' | 'b' => textobj::a_paren,
+        '{' | '}' | 'B' => textobj::a_curly,
+        '[' | ']' | 'r' => textobj::a_bracket,
+        '<' | '>' | 'a' => textobj::a_angle,
+        '"' => textobj::a_quote,
+        '\'' => textobj::a_tick,
+        '`' => textobj::a_backtick,
+        't' => return textobj::tag_pair(buf, pos),
+        _ => return None,
+    };
+    let range = object(buf, pos)?;
+    let (mut open, mut close) = (range.start..range.start + 1, range.end - 1..range.end);
+    if matches!(c, '(' | '{' | '[') {
+        let blank = |&(_, c): &(usize, char)| c == ' ' || c == '\t';
+        open.end += buf.chars_fwd(open.end).take_while(blank).count().min(close.start - open.end);
+        close.start -= buf.chars_bck(close.start - 1).take_while(blank).count().min(close.start - open.end);
+    }
+    Some((open, close))
+}
+
+/// put `text` at the byte offset `off`
+fn insert_at(buf: &mut BufferInner, off: usize, text: &str) {
+    // there's no cursor position after the end of the buffer
+    if off == buf.len() {
+        buf.append(text);
+    } else {
+        buf.cursor.set_pos(buf.offset_to_pos(off));
+        buf.insert_str(text);
+    }
+}
+
+/// surround the text in `range` with the delimiters `c` names, leaving the cursor on the opening
+/// one. Returns whether `c` names any.
+pub fn add(buf: &mut BufferInner, range: Range<usize>, c: char) -> bool {
+    let Some((open, close)) = delimiters(c) else {
+        return false;
+    };
+    insert_at(buf, range.end, &close);
+    insert_at(buf, range.start, &open);
+    buf.cursor.set_pos(buf.offset_to_pos(range.start));
+    true
+}
+
+/// replace the delimiters `old` names around `pos` with the ones `new` names. Returns whether
+/// there were any.
+pub fn change(buf: &mut BufferInner, pos: usize, old: char, new: char) -> bool {
+    let (Some((open, close)), Some((new_open, new_close))) = (find(buf, pos, old), delimiters(new)) else {
+        return false;
+    };
+    buf.delete_range(close.clone());
+    insert_at(buf, close.start, &new_close);
+    buf.delete_range(open.clone());
+    insert_at(buf, open.start, &new_open);
+    buf.cursor.set_pos(buf.offset_to_pos(open.start));
+    true
+}
+
+/// delete the delimiters `c` names around `pos`. Returns whether there were any.
+pub fn delete(buf: &mut BufferInner, pos: usize, c: char) -> bool {
+    let Some((open, close)) = find(buf, pos, c) else {
+        return false;
+    };
+    buf.delete_range(close);
+    buf.delete_range(open.clone());
+    buf.cursor.set_pos(buf.offset_to_pos(open.start));
+    true
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn run(text: &str, pos: usize, f: impl FnOnce(&mut BufferInner, usize) -> bool) -> String {
+        let mut buf = BufferInner::from_str(text);
+        assert!(f(&mut buf, pos));
+        buf.to_string()
+    }
+
+    #[test]
+    fn adding() {
+        assert_eq!(run("a word b", 2, |buf, _| add(buf, 2..6, ')')), "a (word) b");
+        assert_eq!(run("a word b", 2, |buf, _| add(buf, 2..6, '(')), "a ( word ) b");
+        assert_eq!(run("word", 0, |buf, _| add(buf, 0..4, '*')), "*word*");
+        assert!(!add(&mut BufferInner::from_str("word"), 0..4, 'x'));
+    }
+
+    #[test]
+    fn changing() {
+        assert_eq!(run("f(\"a\")", 3, |buf, pos| change(buf, pos, '"', '\'')), "f('a')");
+        assert_eq!(run("[ a ]", 2, |buf, pos| change(buf, pos, '[', '}')), "{a}");
+        assert_eq!(run("<b>bold</b>", 4, |buf, pos| change(buf, pos, 't', '*')), "*bold*");
+    }
+
+    #[test]
+    fn deleting() {
+        assert_eq!(run("(a (b) c)", 4, |buf, pos| delete(buf, pos, 'b')), "(a b c)");
+        assert_eq!(run("( a )", 2, |buf, pos| delete(buf, pos, '(')), "a");
+        assert_eq!(run("( a )", 2, |buf, pos| delete(buf, pos, ')')), " a ");
+        assert!(!delete(&mut BufferInner::from_str("a"), 0, '"'));
+    }
+}
@@ -0,0 +1,134 @@
+//! Syntax highlighting from rules defined at runtime, currently from Guile with `rs-define-syntax`.
+//!
+//! A rule is a regex and the highlight group its matches are drawn with, for the buffers of one
+//! filetype. A file whose extension isn't one the editor knows has the extension as its filetype,
+//! so rules can be given for languages the editor has never heard of. Rules only see one line at a
+//! time and are only applied to the lines that are shown. Going along a line the match that starts
+//! first wins, and of those starting at the same place the one from the rule defined first.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::tui::{BasicColor, Color};
+
+/// the highlight groups and what they look like
+const GROUPS: &[(&str, Color)] = &[
+    ("Comment", Color { fg: BasicColor::Gray, italic: true, ..Color::new() }),
+    ("Constant", Color { fg: BasicColor::Red, ..Color::new() }),
+    ("String", Color { fg: BasicColor::Green, ..Color::new() }),
+    ("Number", Color { fg: BasicColor::Red, ..Color::new() }),
+    ("Identifier", Color { fg: BasicColor::Cyan, ..Color::new() }),
+    ("Function", Color { fg: BasicColor::BrightBlue, ..Color::new() }),
+    ("Keyword", Color { fg: BasicColor::Magenta, bold: true, ..Color::new() }),
+    ("Type", Color { fg: BasicColor::Yellow, ..Color::new() }),
+    ("PreProc", Color { fg: BasicColor::BrightMagenta, ..Color::new() }),
+    ("Special", Color { fg: BasicColor::BrightCyan, ..Color::new() }),
+    ("Todo", Color { fg: BasicColor::Black, bg: BasicColor::Yellow, ..Color::new() }),
+    ("Error", Color { fg: BasicColor::White, bg: BasicColor::Red, ..Color::new() }),
+];
+
+/// the color of highlight group `name`, ignoring case
+pub fn group_color(name: &str) -> Option<Color> {
+    GROUPS.iter().find(|(g, _)| g.eq_ignore_ascii_case(name)).map(|(_, c)| *c)
+}
+
+struct Rule {
+    re: Regex,
+    color: Color,
+}
+
+thread_local! {
+    static RULES: RefCell<HashMap<String, Vec<Rule>>> = Default::default();
+}
+
+/// add a rule for `filetype`, or change the group of the rule it already has with `pattern`
+pub fn define(filetype: &str, pattern: &str, group: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let color = group_color(group).ok_or_else(|| format!("unknown highlight group {group:?}"))?;
+    let re = Regex::new(pattern)?;
+    RULES.with_borrow_mut(|rules| {
+        let rules = rules.entry(filetype.to_owned()).or_default();
+        match rules.iter_mut().find(|r| r.re.as_str() == pattern) {
+            Some(rule) => rule.color = color,
+            None => rules.push(Rule { re, color }),
+        }
+    });
+    Ok(())
+}
+
+/// remove the rules of `filetype`
+pub fn clear(filetype: &str) {
+    RULES.with_borrow_mut(|rules| rules.remove(filetype));
+}
+
+/// the filetype whose rules apply to `path`
+pub fn filetype(path: &Path) -> Option<String> {
+    match crate::filetype::detect(path) {
+        Some(ft) => Some(ft.to_owned()),
+        None => Some(path.extension()?.to_str()?.to_ascii_lowercase()),
+    }
+}
+
+/// the highlighted parts of `line` in a buffer of `filetype`, in order and without overlaps
+pub fn highlight(filetype: &str, line: &str) -> Vec<(Range<usize>, Color)> {
+    RULES.with_borrow(|rules| {
+        let Some(rules) = rules.get(filetype) else {
+            return Vec::new();
+        };
+        let mut out = Vec::new();
+        let mut off = 0;
+        while off <= line.len() {
+            let first = rules
+                .iter()
+                .filter_map(|r| Some((r.re.find_at(line, off)?, r.color)))
+                .filter(|(m, _)| !m.is_empty())
+                .min_by_key(|(m, _)| m.start());
+            let Some((m, color)) = first else {
+                break;
+            };
+            out.push((m.range(), color));
+            off = m.end();
+        }
+        out
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn groups<'a>(filetype: &str, line: &'a str) -> Vec<(&'a str, Color)> {
+        highlight(filetype, line).into_iter().map(|(r, c)| (&line[r], c)).collect()
+    }
+
+    #[test]
+    fn rules() {
+        let comment = group_color("comment").unwrap();
+        let string = group_color("String").unwrap();
+        let keyword = group_color("Keyword").unwrap();
+        define("test", "//.*", "Comment").unwrap();
+        define("test", r#""[^"]*""#, "String").unwrap();
+        define("test", r"\b(let|fn)\b", "Keyword").unwrap();
+        assert_eq!(
+            groups("test", r#"let s = "a // b"; // c"#),
+            [("let", keyword), (r#""a // b""#, string), ("// c", comment)]
+        );
+        assert_eq!(groups("other", "let"), []);
+        // a rule with the same pattern only changes its group
+        define("test", r"\b(let|fn)\b", "Type").unwrap();
+        assert_eq!(groups("test", "fn"), [("fn", group_color("Type").unwrap())]);
+        assert!(define("test", "x", "NoSuchGroup").is_err());
+        assert!(define("test", "(", "Comment").is_err());
+        clear("test");
+        assert_eq!(groups("test", "fn"), []);
+    }
+
+    #[test]
+    fn filetypes() {
+        assert_eq!(filetype(Path::new("a.rs")).as_deref(), Some("rust"));
+        assert_eq!(filetype(Path::new("a.ZIG")).as_deref(), Some("zig"));
+        assert_eq!(filetype(Path::new("Makefile")), None);
+    }
+}
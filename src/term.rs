@@ -2,13 +2,118 @@ pub use crate::tui::TermPos;
 use std::cell::RefCell;
 use std::fmt::Write;
 use std::{io::stdout, sync::Mutex};
+use std::sync::OnceLock;
+
+/// What the terminal can do, looked up by `$TERM` in a small built-in database. Everything here
+/// is optional, the editor falls back to plain text when a capability is missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Caps {
+    /// alternate screen (`smcup`/`rmcup`)
+    pub altscreen: bool,
+    /// cursor can be hidden while drawing (`civis`/`cnorm`)
+    pub cursor_visibility: bool,
+    /// number of colors, 0 for none. The bright colors need at least 16, with fewer they are
+    /// drawn as their normal counterparts.
+    pub colors: u16,
+    /// italics (`sitm`/`ritm`)
+    pub italics: bool,
+}
+
+impl Caps {
+    /// what we can assume of a terminal that we know nothing about
+    pub const DUMB: Self = Self {
+        altscreen: false,
+        cursor_visibility: false,
+        colors: 0,
+        italics: false,
+    };
+
+    pub const XTERM: Self = Self {
+        altscreen: true,
+        cursor_visibility: true,
+        colors: 16,
+        italics: true,
+    };
+
+    /// capabilities of `term`, the value of `$TERM`. `colorterm` is `$COLORTERM`, which terminals
+    /// set when they support more colors than their terminfo entry claims.
+    pub fn from_term(term: &str, colorterm: Option<&str>) -> Self {
+        let (base, variant) = term.split_once('-').unwrap_or((term, ""));
+        let mut caps = match base {
+            "" | "dumb" | "unknown" => return Self::DUMB,
+            "xterm" | "alacritty" | "kitty" | "foot" | "wezterm" | "tmux" | "rxvt" | "st"
+            | "konsole" | "gnome" | "vte" | "contour" => Self::XTERM,
+            // screen doesn't pass italics through
+            "screen" => Self {
+                italics: false,
+                ..Self::XTERM
+            },
+            "linux" => Self {
+                altscreen: false,
+                colors: 8,
+                italics: false,
+                ..Self::XTERM
+            },
+            "vt100" | "vt102" | "vt220" | "ansi" => Self {
+                cursor_visibility: base == "vt220",
+                colors: if base == "ansi" { 8 } else { 0 },
+                ..Self::DUMB
+            },
+            // most terminals are close enough to xterm, but stay conservative about extras
+            _ => Self {
+                colors: 8,
+                italics: false,
+                ..Self::XTERM
+            },
+        };
+        if caps.colors > 0 && (variant.contains("256color") || colorterm.is_some_and(|c| !c.is_empty())) {
+            caps.colors = 256;
+        }
+        if variant.contains("mono") {
+            caps.colors = 0;
+        }
+        caps
+    }
+
+    fn from_env() -> Self {
+        let term = std::env::var("TERM").unwrap_or_default();
+        let colorterm = std::env::var("COLORTERM").ok();
+        Self::from_term(&term, colorterm.as_deref())
+    }
+
+    pub fn hide_cursor(&self) -> &'static str {
+        if self.cursor_visibility {
+            "\x1b[?25l"
+        } else {
+            ""
+        }
+    }
+
+    pub fn show_cursor(&self) -> &'static str {
+        if self.cursor_visibility {
+            "\x1b[?25h"
+        } else {
+            ""
+        }
+    }
+}
+
+/// capabilities of the terminal we are running in, detected on first use
+pub fn caps() -> &'static Caps {
+    static CAPS: OnceLock<Caps> = OnceLock::new();
+    CAPS.get_or_init(Caps::from_env)
+}
 
 pub fn altbuf_enable() {
-    print!("\x1b[?1049h");
+    if caps().altscreen {
+        print!("\x1b[?1049h");
+    }
 }
 
 pub fn altbuf_disable() {
-    print!("\x1b[?1049l");
+    if caps().altscreen {
+        print!("\x1b[?1049l");
+    }
 }
 
 pub fn goto(_pos: TermPos) {
@@ -18,3 +123,28 @@ pub fn goto(_pos: TermPos) {
 pub fn flush() {
     std::io::Write::flush(&mut std::io::stdout()).unwrap();
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn caps_by_term() {
+        assert_eq!(Caps::from_term("dumb", Some("truecolor")), Caps::DUMB);
+        assert_eq!(Caps::from_term("", None), Caps::DUMB);
+        assert_eq!(Caps::from_term("xterm", None), Caps::XTERM);
+        let caps = Caps::from_term("xterm-256color", None);
+        assert_eq!(caps.colors, 256);
+        assert!(caps.italics && caps.altscreen);
+        let caps = Caps::from_term("screen-256color", None);
+        assert!(caps.altscreen && !caps.italics);
+        assert_eq!(caps.colors, 256);
+        assert!(Caps::from_term("tmux-256color", None).italics);
+        let caps = Caps::from_term("linux", None);
+        assert!(!caps.altscreen && caps.cursor_visibility);
+        assert_eq!(caps.colors, 8);
+        assert_eq!(Caps::from_term("vt100", Some("truecolor")).colors, 0);
+        assert_eq!(Caps::from_term("xterm-mono", None).colors, 0);
+        assert_eq!(Caps::from_term("weird-term", None).colors, 8);
+    }
+}
@@ -0,0 +1,244 @@
+//! Embedded terminals, opened with `:terminal`.
+//!
+//! The program runs on a pseudoterminal and its output is interpreted by a [`Screen`]. While in
+//! [`Mode::Terminal`](crate::Mode::Terminal) the screen is drawn over the window and keys are sent
+//! straight to the program. The terminal's buffer holds the scrollback followed by the screen as
+//! plain text, so it can be moved around and yanked from in normal mode like any other buffer.
+mod screen;
+pub use screen::Screen;
+
+use std::fs::File;
+use std::io::{Read, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::{mpsc, Arc};
+
+use crate::buffer::Buffer;
+use crate::prelude::*;
+use crate::tui::{TermBox, TermPos};
+
+pub enum Status {
+    Running,
+    /// the program exited. The code is `None` if it was killed by a signal.
+    Exited(Option<i32>),
+}
+
+pub struct Terminal {
+    master: File,
+    child: Child,
+    rx: mpsc::Receiver<Vec<u8>>,
+    screen: Screen,
+    buf: Arc<Buffer>,
+}
+
+impl Terminal {
+    /// run `cmd` through `sh -c` on a new pseudoterminal of the given size, at least one cell
+    pub fn spawn(cmd: &str, w: u32, h: u32) -> std::io::Result<Self> {
+        let (w, h) = (w.max(1), h.max(1));
+        let size = winsize(w, h);
+        let pty = nix::pty::openpty(&size, None)?;
+        // SAFETY: openpty just returned these and nothing else owns them
+        let (master, slave) = unsafe { (File::from_raw_fd(pty.master), File::from_raw_fd(pty.slave)) };
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg(cmd)
+            .env("TERM", "vt100")
+            .env("COLUMNS", w.to_string())
+            .env("LINES", h.to_string())
+            .stdin(Stdio::from(slave.try_clone()?))
+            .stdout(Stdio::from(slave.try_clone()?))
+            .stderr(Stdio::from(slave));
+        // SAFETY: only async-signal-safe functions are called between fork and exec
+        unsafe {
+            command.pre_exec(|| {
+                // become the session leader so the pty can be our controlling terminal
+                nix::unistd::setsid()?;
+                if libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+        let child = command.spawn()?;
+        let mut reader = master.try_clone()?;
+        let (tx, rx) = mpsc::channel();
+        std::thread::spawn(move || {
+            let mut chunk = [0; 4096];
+            // reading fails with EIO once the program and its children close the pty
+            while let Ok(n @ 1..) = reader.read(&mut chunk) {
+                if tx.send(chunk[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            master,
+            child,
+            rx,
+            screen: Screen::new(w, h),
+            buf: Buffer::scratch(),
+        })
+    }
+
+    pub fn buffer(&self) -> &Arc<Buffer> {
+        &self.buf
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+
+    /// send input to the program
+    pub fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.master.write_all(bytes)
+    }
+
+    /// resize the screen and let the program know about it, down to one cell
+    pub fn resize(&mut self, w: u32, h: u32) {
+        let (w, h) = (w.max(1), h.max(1));
+        if self.screen.dim() == (w, h) {
+            return;
+        }
+        self.screen.resize(w, h);
+        let size = winsize(w, h);
+        // SAFETY: TIOCSWINSZ only reads from the pointer
+        unsafe { libc::ioctl(self.master_fd(), libc::TIOCSWINSZ, &size) };
+    }
+
+    fn master_fd(&self) -> RawFd {
+        use std::os::unix::io::AsRawFd;
+        self.master.as_raw_fd()
+    }
+
+    /// handle output of the program. If `follow` is set, the buffer's cursor is moved to the
+    /// terminal cursor, otherwise it is left where it is.
+    pub fn poll(&mut self, follow: bool) -> (bool, Status) {
+        let mut changed = false;
+        let mut closed = false;
+        loop {
+            match self.rx.try_recv() {
+                Ok(chunk) => {
+                    self.screen.feed(&chunk);
+                    changed = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    closed = true;
+                    break;
+                }
+            }
+        }
+        if changed {
+            self.sync_buffer(follow);
+        }
+        let status = match self.child.try_wait() {
+            Ok(Some(status)) if closed => Status::Exited(status.code()),
+            Err(_) => Status::Exited(None),
+            _ => Status::Running,
+        };
+        (changed, status)
+    }
+
+    /// replace the text of the buffer with the contents of the terminal
+    pub fn sync_buffer(&self, follow: bool) {
+        let mut text = self.screen.lines().join("\n");
+        text.push('\n');
+        let mut buf = self.buf.get_mut();
+        let cursor = buf.cursor;
+        buf.clear();
+        buf.insert_str(&text);
+        if follow {
+            let TermPos { x, y } = self.screen.cursor();
+            let y = self.screen.scrollback().len() + y as usize;
+            let line = buf.line(y);
+            let x = line
+                .char_indices()
+                .nth(x as usize)
+                .map_or(line.len(), |(i, _)| i);
+            buf.cursor.set_pos(DocPos { x, y });
+            buf.cursor.topline = self.screen.scrollback().len();
+        } else {
+            let y = cursor.pos.y.min(buf.linecnt().saturating_sub(1));
+            let x = cursor.pos.x.min(buf.line(y).len());
+            buf.cursor.set_pos(DocPos { x, y });
+            buf.cursor.virtcol = cursor.virtcol;
+            buf.cursor.topline = cursor.topline.min(y);
+        }
+    }
+
+    /// draw the screen into `bounds` of `tui`, placing the cursor
+    pub fn draw(&self, tui: &mut TermGrid, bounds: TermBox) {
+        let grid = self.screen.grid();
+        let (w, h) = grid.dim();
+        for y in 0..h.min(bounds.ylen()) {
+            for x in 0..w.min(bounds.xlen()) {
+                let pos = TermPos { x, y };
+                let dst = TermPos {
+                    x: x + bounds.start.x,
+                    y: y + bounds.start.y,
+                };
+                tui.put_cell(dst, grid[pos]);
+            }
+        }
+        let TermPos { x, y } = self.screen.cursor();
+        if x < bounds.xlen() && y < bounds.ylen() {
+            tui.set_cursorpos(TermPos {
+                x: x + bounds.start.x,
+                y: y + bounds.start.y,
+            });
+        }
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        // SIGHUP is what a shell expects when its terminal goes away, it passes it on to its jobs
+        let pid = nix::unistd::Pid::from_raw(self.child.id() as i32);
+        let _ = nix::sys::signal::kill(pid, nix::sys::signal::Signal::SIGHUP);
+        if !matches!(self.child.try_wait(), Ok(Some(_))) {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+    }
+}
+
+fn winsize(w: u32, h: u32) -> nix::pty::Winsize {
+    nix::pty::Winsize {
+        ws_row: h as u16,
+        ws_col: w as u16,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn runs_on_a_pty() {
+        let mut term = Terminal::spawn("test -t 1 && printf 'tty\\n%s' $COLUMNS", 20, 5).unwrap();
+        let start = std::time::Instant::now();
+        let code = loop {
+            if let (_, Status::Exited(code)) = term.poll(true) {
+                break code;
+            }
+            assert!(start.elapsed().as_secs() < 5, "terminal did not exit");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        };
+        assert_eq!(code, Some(0));
+        assert_eq!(term.buffer().get().to_string(), "tty\n20\n");
+        assert_eq!(term.buffer().get().cursor.pos, DocPos { x: 2, y: 1 });
+    }
+
+    #[test]
+    fn never_empty() {
+        // a window squeezed to nothing on a tiny screen
+        let mut term = Terminal::spawn("true", 0, 0).unwrap();
+        assert_eq!(term.screen().dim(), (1, 1));
+        term.resize(4, 0);
+        assert_eq!(term.screen().dim(), (4, 1));
+    }
+}
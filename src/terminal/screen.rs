@@ -0,0 +1,375 @@
+//! The screen of an embedded terminal and the parser for the escape sequences that update it.
+//!
+//! Only what common shells and line-oriented programs use is supported: cursor movement,
+//! erasing, basic SGR colors, and scrolling the whole screen. Everything else is consumed and
+//! ignored so it doesn't show up as garbage.
+use crate::prelude::*;
+use crate::tui::{TermCell, TermPos};
+
+/// lines kept after they scroll off the top
+const SCROLLBACK: usize = 1000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    /// the next byte selects a character set and is ignored
+    Charset,
+    Csi(String),
+    Osc,
+    /// saw ESC inside an OSC, expecting `\`
+    OscEscape,
+}
+
+pub struct Screen {
+    grid: TermGrid,
+    /// x may equal the width, which means the next character wraps
+    cursor: TermPos,
+    color: Color,
+    scrollback: Vec<String>,
+    state: State,
+    /// incomplete UTF-8 sequence
+    partial: Vec<u8>,
+}
+
+impl Screen {
+    pub fn new(w: u32, h: u32) -> Self {
+        Self {
+            grid: TermGrid::with_size(w, h),
+            cursor: TermPos { x: 0, y: 0 },
+            color: Color::new(),
+            scrollback: Vec::new(),
+            state: State::Ground,
+            partial: Vec::new(),
+        }
+    }
+
+    pub fn grid(&self) -> &TermGrid {
+        &self.grid
+    }
+
+    pub fn dim(&self) -> (u32, u32) {
+        self.grid.dim()
+    }
+
+    /// cursor position, clamped to the screen
+    pub fn cursor(&self) -> TermPos {
+        let (w, _) = self.dim();
+        TermPos {
+            x: self.cursor.x.min(w.saturating_sub(1)),
+            y: self.cursor.y,
+        }
+    }
+
+    pub fn scrollback(&self) -> &[String] {
+        &self.scrollback
+    }
+
+    /// the contents of the screen are lost, the program is expected to redraw
+    pub fn resize(&mut self, w: u32, h: u32) {
+        self.grid.resize(w, h);
+        self.cursor.x = self.cursor.x.min(w);
+        self.cursor.y = self.cursor.y.min(h.saturating_sub(1));
+    }
+
+    /// every line of output: the scrollback followed by the screen, without trailing blank lines
+    /// below the cursor
+    pub fn lines(&self) -> Vec<String> {
+        let (_, h) = self.dim();
+        let mut out = self.scrollback.clone();
+        out.extend((0..h).map(|y| self.grid.line_text(y)));
+        let min = self.scrollback.len() + self.cursor.y as usize + 1;
+        while out.len() > min && out.last().is_some_and(String::is_empty) {
+            out.pop();
+        }
+        out
+    }
+
+    pub fn feed(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.byte(b);
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        match std::mem::replace(&mut self.state, State::Ground) {
+            State::Ground => self.ground(b),
+            State::Escape => match b {
+                b'[' => self.state = State::Csi(String::new()),
+                b']' => self.state = State::Osc,
+                b'(' | b')' | b'*' | b'+' => self.state = State::Charset,
+                b'c' => {
+                    self.grid.clear();
+                    self.cursor = TermPos { x: 0, y: 0 };
+                    self.color = Color::new();
+                }
+                _ => (),
+            },
+            State::Charset => (),
+            State::Csi(mut params) => {
+                if (0x40..=0x7e).contains(&b) {
+                    self.csi(&params, b as char);
+                } else {
+                    params.push(b as char);
+                    self.state = State::Csi(params);
+                }
+            }
+            State::Osc => match b {
+                0x07 => (),
+                0x1b => self.state = State::OscEscape,
+                _ => self.state = State::Osc,
+            },
+            State::OscEscape => {
+                if b != b'\\' {
+                    self.state = State::Osc;
+                }
+            }
+        }
+    }
+
+    fn ground(&mut self, b: u8) {
+        if !self.partial.is_empty() || b >= 0x80 {
+            self.partial.push(b);
+            match std::str::from_utf8(&self.partial) {
+                Ok(s) => {
+                    let c = s.chars().next().expect("not empty");
+                    self.partial.clear();
+                    self.put(c);
+                }
+                Err(e) if e.error_len().is_some() => {
+                    self.partial.clear();
+                    self.put(char::REPLACEMENT_CHARACTER);
+                }
+                Err(_) => (),
+            }
+            return;
+        }
+        match b {
+            0x1b => self.state = State::Escape,
+            b'\r' => self.cursor.x = 0,
+            b'\n' | 0x0b | 0x0c => self.linefeed(),
+            0x08 => self.cursor.x = self.cursor.x.min(self.dim().0).saturating_sub(1),
+            b'\t' => {
+                let w = self.dim().0;
+                self.cursor.x = ((self.cursor.x / 8 + 1) * 8).min(w.saturating_sub(1));
+            }
+            0x20..=0x7e => self.put(b as char),
+            _ => (),
+        }
+    }
+
+    fn put(&mut self, c: char) {
+        let (w, h) = self.dim();
+        if w == 0 || h == 0 {
+            return;
+        }
+        if self.cursor.x >= w {
+            self.cursor.x = 0;
+            self.linefeed();
+        }
+        self.grid
+            .put_cell(self.cursor, TermCell::colored(c, self.color));
+        self.cursor.x += 1;
+    }
+
+    fn linefeed(&mut self) {
+        let (_, h) = self.dim();
+        if self.cursor.y + 1 < h {
+            self.cursor.y += 1;
+            return;
+        }
+        self.scrollback.push(self.grid.line_text(0));
+        if self.scrollback.len() > SCROLLBACK {
+            self.scrollback.remove(0);
+        }
+        self.grid.scroll_up();
+    }
+
+    /// clear cells of line `y` in `xrng`
+    fn erase(&mut self, y: u32, xrng: std::ops::Range<u32>) {
+        for x in xrng {
+            self.grid[TermPos { x, y }] = TermCell::default();
+        }
+    }
+
+    fn csi(&mut self, params: &str, action: char) {
+        // private modes (e.g. `?25h`) are not supported
+        if params.starts_with(['?', '>', '<', '=']) {
+            return;
+        }
+        let args: Vec<u32> = params
+            .split(';')
+            .map(|p| p.parse().unwrap_or(0))
+            .collect();
+        let arg = |i: usize, default: u32| match args.get(i) {
+            Some(0) | None => default,
+            Some(&n) => n,
+        };
+        let (w, h) = self.dim();
+        let max_x = w.saturating_sub(1);
+        let max_y = h.saturating_sub(1);
+        let TermPos { x, y } = self.cursor();
+        match action {
+            'A' => self.cursor.y = y.saturating_sub(arg(0, 1)),
+            'B' | 'e' => self.cursor.y = (y + arg(0, 1)).min(max_y),
+            'C' | 'a' => self.cursor.x = (x + arg(0, 1)).min(max_x),
+            'D' => self.cursor.x = x.saturating_sub(arg(0, 1)),
+            'E' => self.cursor = TermPos { x: 0, y: (y + arg(0, 1)).min(max_y) },
+            'F' => self.cursor = TermPos { x: 0, y: y.saturating_sub(arg(0, 1)) },
+            'G' | '`' => self.cursor.x = (arg(0, 1) - 1).min(max_x),
+            'd' => self.cursor.y = (arg(0, 1) - 1).min(max_y),
+            'H' | 'f' => {
+                self.cursor = TermPos {
+                    x: (arg(1, 1) - 1).min(max_x),
+                    y: (arg(0, 1) - 1).min(max_y),
+                }
+            }
+            'J' => match args.first().copied().unwrap_or(0) {
+                0 => {
+                    self.erase(y, x..w);
+                    for y in (y + 1)..h {
+                        self.erase(y, 0..w);
+                    }
+                }
+                1 => {
+                    for y in 0..y {
+                        self.erase(y, 0..w);
+                    }
+                    self.erase(y, 0..(x + 1).min(w));
+                }
+                2 => self.grid.clear(),
+                3 => self.scrollback.clear(),
+                _ => (),
+            },
+            'K' => match args.first().copied().unwrap_or(0) {
+                0 => self.erase(y, x..w),
+                1 => self.erase(y, 0..(x + 1).min(w)),
+                2 => self.erase(y, 0..w),
+                _ => (),
+            },
+            'P' => {
+                let n = arg(0, 1).min(w - x);
+                for cx in x..w {
+                    let src = cx + n;
+                    self.grid[TermPos { x: cx, y }] = if src < w {
+                        self.grid[TermPos { x: src, y }]
+                    } else {
+                        TermCell::default()
+                    };
+                }
+            }
+            '@' => {
+                let n = arg(0, 1).min(w - x);
+                for cx in (x..w).rev() {
+                    self.grid[TermPos { x: cx, y }] = if cx >= x + n {
+                        self.grid[TermPos { x: cx - n, y }]
+                    } else {
+                        TermCell::default()
+                    };
+                }
+            }
+            'X' => self.erase(y, x..(x + arg(0, 1)).min(w)),
+            'm' => self.sgr(&args),
+            _ => (),
+        }
+    }
+
+    fn sgr(&mut self, args: &[u32]) {
+        fn basic(n: u32) -> BasicColor {
+            use BasicColor::*;
+            [Black, Red, Green, Yellow, Blue, Magenta, Cyan, White][n as usize % 8]
+        }
+        fn bright(n: u32) -> BasicColor {
+            use BasicColor::*;
+            [Gray, BrightRed, BrightGreen, BrightYellow, BrightBlue, BrightMagenta, BrightCyan, BrightWhite]
+                [n as usize % 8]
+        }
+        let mut args = args.iter().copied();
+        while let Some(a) = args.next() {
+            match a {
+                0 => self.color = Color::new(),
+                1 => self.color.bold = true,
+                3 => self.color.italic = true,
+                22 => self.color.bold = false,
+                23 => self.color.italic = false,
+                30..=37 => self.color.fg = basic(a - 30),
+                39 => self.color.fg = BasicColor::Default,
+                40..=47 => self.color.bg = basic(a - 40),
+                49 => self.color.bg = BasicColor::Default,
+                90..=97 => self.color.fg = bright(a - 90),
+                100..=107 => self.color.bg = bright(a - 100),
+                // 256 and true color are not supported, skip their arguments
+                38 | 48 => match args.next() {
+                    Some(5) => {
+                        args.next();
+                    }
+                    Some(2) => {
+                        args.nth(2);
+                    }
+                    _ => (),
+                },
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn screen(w: u32, h: u32, input: &str) -> Screen {
+        let mut s = Screen::new(w, h);
+        s.feed(input.as_bytes());
+        s
+    }
+
+    #[test]
+    fn plain_text_and_wrap() {
+        let s = screen(4, 3, "abcdef\r\nxy");
+        assert_eq!(s.lines(), vec!["abcd", "ef", "xy"]);
+        assert_eq!(s.cursor(), TermPos { x: 2, y: 2 });
+    }
+
+    #[test]
+    fn scrolls_into_scrollback() {
+        let s = screen(5, 2, "1\r\n2\r\n3\r\n4");
+        assert_eq!(s.scrollback(), ["1", "2"]);
+        assert_eq!(s.lines(), vec!["1", "2", "3", "4"]);
+    }
+
+    #[test]
+    fn cursor_movement_and_erase() {
+        let s = screen(10, 3, "hello\x1b[1;2HE\x1b[K\x1b[3;1Hbottom\x1b[2;4Hmid");
+        assert_eq!(s.lines(), vec!["hE", "   mid", "bottom"]);
+        let s = screen(10, 3, "abc\r\ndef\x1b[2J\x1b[Hx");
+        assert_eq!(s.lines(), vec!["x"]);
+    }
+
+    #[test]
+    fn colors_and_ignored_sequences() {
+        let s = screen(10, 2, "\x1b]0;title\x07\x1b[?2004h\x1b[1;31mred\x1b[0m.");
+        assert_eq!(s.lines(), vec!["red."]);
+        let cell = s.grid()[TermPos { x: 0, y: 0 }];
+        assert_eq!(cell, TermCell::colored('r', Color { fg: BasicColor::Red, bold: true, ..Color::new() }));
+        assert_eq!(s.grid()[TermPos { x: 3, y: 0 }], TermCell::colored('.', Color::new()));
+    }
+
+    #[test]
+    fn utf8_split_across_reads() {
+        let mut s = Screen::new(10, 1);
+        let bytes = "aé😀".as_bytes();
+        s.feed(&bytes[..2]);
+        s.feed(&bytes[2..4]);
+        s.feed(&bytes[4..]);
+        assert_eq!(s.lines(), vec!["aé😀"]);
+    }
+
+    #[test]
+    fn delete_and_insert_chars() {
+        let s = screen(6, 1, "abcdef\x1b[1;2H\x1b[2P");
+        assert_eq!(s.lines(), vec!["adef"]);
+        let s = screen(6, 1, "abcd\x1b[1;2H\x1b[2@");
+        assert_eq!(s.lines(), vec!["a  bcd"]);
+    }
+}
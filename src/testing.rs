@@ -0,0 +1,496 @@
+//! Helpers for tests that need more than a buffer.
+//!
+//! [`print_pos`] and [`print_cursor`] show where a motion or text object ended up in the text for
+//! failed assertions. The rest drives a headless [`Ctx`] with keys as if they were typed and checks
+//! the screen it draws against golden files in `assets/test/screens`, which are rewritten instead
+//! of checked when `UPDATE_GOLDEN` is set.
+use std::fmt::Write;
+use std::ops::{Add, Range};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::buffer::{Buffer, BufferInner};
+use crate::keymap::Typeahead;
+use crate::render::Ctx;
+
+/// `pos` in the text around it, marked with a `^`
+pub fn print_pos(buf: &BufferInner, pos: usize) -> String {
+    let slice_start = pos.saturating_sub(5);
+    let slice_end = pos.add(5).min(buf.len());
+    let s = buf.to_string().replace('\n', "$");
+    let mut out = String::new();
+    writeln!(out, "\n{}", &s[slice_start..slice_end]).unwrap();
+    for i in slice_start..slice_end {
+        let c = if i == pos {
+            '^'
+        } else {
+            ' '
+        };
+        out.push(c);
+    }
+    out.push('\n');
+    out
+}
+
+/// `range` in the text around it, with the start of the motion or object marked with a `^`
+pub fn print_cursor(buf: &BufferInner, range: Range<usize>, start: usize) -> String {
+    let slice_start = range.start.min(start).saturating_sub(5);
+    let slice_end = range.end.max(start).add(5).min(buf.len());
+    let s = buf.to_string().replace('\n', "$");
+    let mut out = String::new();
+    writeln!(out, "\n{}", &s[slice_start..slice_end]).unwrap();
+    let slice = slice_start..slice_end;
+    for i in slice_start..slice_end {
+        let c = if i == 0 && i == range.start {
+            '|'
+        } else if i + 1 == range.start {
+            '>'
+        } else if i == range.end {
+            '<'
+        } else if range.contains(&i) {
+            if i + 1 == slice.end {
+                '|'
+            } else {
+                '-'
+            }
+        } else {
+            ' '
+        };
+        out.push(c);
+    }
+    out.push('\n');
+    for i in slice_start..slice_end {
+        let c = if i == start {
+            '^'
+        } else {
+            ' '
+        };
+        out.push(c);
+    };
+    out.push('\n');
+    out
+}
+
+/// a headless editor for `text` with a screen of `w` by `h` cells
+pub fn editor(text: &str, w: u32, h: u32) -> Ctx {
+    Ctx::headless_sized(Buffer::from_string(text), w, h)
+}
+
+/// handle `keys` as if they were typed, leaving the editor in whatever mode they end in
+pub fn type_keys(ctx: &mut Ctx, keys: &str) {
+    let mut keys = Typeahead::new(keys.as_bytes(), true);
+    while keys.poll(0) {
+        if let Some(action) = crate::input::handle_input(ctx, &mut keys.reader(ctx)) {
+            ctx.process_action(action);
+        }
+    }
+}
+
+/// the text on screen once it's drawn, a line for each row with trailing blanks removed
+pub fn screen(ctx: &mut Ctx) -> String {
+    ctx.draw();
+    let tui = ctx.tui.borrow();
+    (0..tui.dim().1).map(|y| tui.line_text(y) + "\n").collect()
+}
+
+/// change the size of the screen before the next draw, as if the terminal was resized
+pub fn resize(ctx: &mut Ctx, w: u32, h: u32) {
+    ctx.tui.get_mut().resize_later(w, h);
+}
+
+/// check `actual` against the golden file `name`
+pub fn assert_golden(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("assets/test/screens").join(format!("{name}.txt"));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+    let expected = std::fs::read_to_string(&path)
+        .unwrap_or_else(|e| panic!("can't read {path:?}: {e}, run with UPDATE_GOLDEN=1 to make it"));
+    assert!(expected == actual, "screen doesn't match {path:?}:\n{actual}");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TEXT: &str = "fn main() {\n    println!(\"hello\");\n}\n";
+
+    #[test]
+    fn line_numbers() {
+        let mut ctx = editor(TEXT, 30, 8);
+        type_keys(&mut ctx, "j");
+        assert_golden("line_numbers", &screen(&mut ctx));
+    }
+
+    #[test]
+    fn command_line() {
+        let mut ctx = editor(TEXT, 30, 8);
+        type_keys(&mut ctx, ":set tw=40");
+        assert_golden("command_line", &screen(&mut ctx));
+        type_keys(&mut ctx, "\r:nosuchcommand\r");
+        assert_golden("command_error", &screen(&mut ctx));
+    }
+
+    #[test]
+    fn insert_mode() {
+        let mut ctx = editor(TEXT, 30, 8);
+        type_keys(&mut ctx, "ji// hi ");
+        assert_golden("insert_mode", &screen(&mut ctx));
+    }
+
+    #[test]
+    fn insert_keys() {
+        let mut ctx = editor("", 30, 8);
+        type_keys(&mut ctx, "i    let x = foo.bar  \x17");
+        assert_eq!(ctx.focused_buf().to_string(), "    let x = foo.");
+        type_keys(&mut ctx, "\x17\x17\x17zz\x15");
+        assert_eq!(ctx.focused_buf().to_string(), "    ");
+        type_keys(&mut ctx, "\x15a\rb\x15\x15");
+        assert_eq!(ctx.focused_buf().to_string(), "a");
+        ctx.registers.set('r', "a");
+        type_keys(&mut ctx, "\x1b:s/a/one/\r0i\x12r ");
+        assert_eq!(ctx.focused_buf().to_string(), "a one");
+        type_keys(&mut ctx, "\x0f");
+        assert_golden("insert_once", &screen(&mut ctx));
+        type_keys(&mut ctx, "0!");
+        assert_eq!(ctx.mode, crate::Mode::Insert);
+        assert_eq!(ctx.focused_buf().to_string(), "!a one");
+        // an ex command is one command too
+        type_keys(&mut ctx, "\x0f:s/one/two/\r?");
+        assert_eq!((ctx.mode, ctx.focused_buf().to_string()), (crate::Mode::Insert, "!?a two".into()));
+    }
+
+    #[test]
+    fn substitute_confirm() {
+        let mut ctx = editor("a a\na\n", 30, 8);
+        type_keys(&mut ctx, ":%s/a/bb/gc\r");
+        assert_golden("substitute_confirm", &screen(&mut ctx));
+        // other keys wait for an answer
+        type_keys(&mut ctx, "xynl");
+        assert_eq!(ctx.focused_buf().to_string(), "bb a\nbb\n");
+        assert_eq!(ctx.mode, crate::Mode::Normal);
+        assert_eq!(ctx.take_message().unwrap().0, "2 substitutions");
+        type_keys(&mut ctx, ":s/a/c/c\r\x1b");
+        assert_eq!(ctx.focused_buf().to_string(), "bb a\nbb\n");
+        assert_eq!(ctx.mode, crate::Mode::Normal);
+    }
+
+    #[test]
+    fn window_layout() {
+        use crate::window::org::{Arrange, Layout, LayoutBuf};
+        let mut ctx = editor("main\n", 40, 12);
+        let (a, b) = (Buffer::from_string("a\n"), Buffer::from_string("b\n"));
+        let leaf = |buf: &Arc<Buffer>| Box::new(Layout::Leaf(LayoutBuf::Id(buf.id())));
+        let layout = Layout::Split {
+            first: leaf(&ctx.focused_win().buffer),
+            second: Box::new(Layout::Split { first: leaf(&a), second: leaf(&b), arrange: Arrange::Vertical }),
+            arrange: Arrange::Horizontal,
+        };
+        ctx.set_layout(&layout).unwrap();
+        assert_golden("window_layout", &screen(&mut ctx));
+        assert_eq!(ctx.focused_buf().to_string(), "main\n");
+        assert_eq!(crate::buffer::focus::layout(), Some(layout.clone()));
+        // applying it again gives the same windows, not more
+        ctx.set_layout(&layout).unwrap();
+        assert_eq!(ctx.windows().len(), 3);
+        let missing = Layout::Leaf(LayoutBuf::Id(crate::render::BufId::from_id(u64::MAX)));
+        assert!(ctx.set_layout(&missing).is_err());
+        assert_eq!(ctx.windows().len(), 3);
+        // kept up to date as the windows change, not only when they're drawn
+        ctx.delete_buffer(&a, true).unwrap();
+        let layout = crate::buffer::focus::layout().unwrap();
+        assert!(!layout.leaves().contains(&&LayoutBuf::Id(a.id())));
+    }
+
+    #[test]
+    fn views() {
+        use crate::window::org::{Arrange, Layout, LayoutBuf};
+        let mut ctx = editor("a\nb\nc\nd\n", 40, 10);
+        let main = Arc::clone(&ctx.focused_win().buffer);
+        type_keys(&mut ctx, "jj");
+        ctx.open_buffer(Buffer::from_string("x\ny\n"));
+        type_keys(&mut ctx, "j\x1e");
+        assert_eq!(ctx.focused_buf().cursor.pos.y, 2);
+        type_keys(&mut ctx, "\x1e");
+        assert_eq!(ctx.focused_buf().cursor.pos.y, 1);
+        // windows on the same buffer each have a cursor of their own
+        type_keys(&mut ctx, "\x1e");
+        let leaf = || Box::new(Layout::Leaf(LayoutBuf::Id(main.id())));
+        ctx.set_layout(&Layout::Split { first: leaf(), second: leaf(), arrange: Arrange::Horizontal }).unwrap();
+        type_keys(&mut ctx, "\x17wk\x17w");
+        assert_eq!(ctx.focused_buf().cursor.pos.y, 2);
+        type_keys(&mut ctx, "\x17w");
+        assert_eq!(ctx.focused_buf().cursor.pos.y, 1);
+    }
+
+    #[test]
+    fn view_of_changed_line() {
+        let mut ctx = editor("ab\n", 40, 10);
+        let main = Arc::clone(&ctx.focused_win().buffer);
+        type_keys(&mut ctx, "l");
+        ctx.open_buffer(Buffer::from_string("x\n"));
+        main.get_mut().set_lines(0..1, &["é".into()]);
+        // not inside the character the line has now
+        type_keys(&mut ctx, "\x1e");
+        assert_eq!(ctx.focused_buf().cursor.pos.x, 0);
+    }
+
+    #[test]
+    fn abort_command_line() {
+        use crate::tui::TextSeverity;
+        use std::fmt::Write;
+        let mut ctx = editor("abc\nabd\n", 30, 8);
+        type_keys(&mut ctx, "l");
+        write!(ctx.warning(), "careful").unwrap();
+        type_keys(&mut ctx, ":abc\x03");
+        assert_eq!(ctx.mode, crate::Mode::Normal);
+        assert_eq!(ctx.take_message(), Some(("careful".into(), TextSeverity::Warning)));
+        // the cursor comes back to where it was before the command line was entered
+        type_keys(&mut ctx, "/bd");
+        ctx.focused_buf_mut().cursor.pos = crate::buffer::DocPos { x: 2, y: 1 };
+        type_keys(&mut ctx, "\x1b");
+        assert_eq!(ctx.focused_buf().cursor.pos, crate::buffer::DocPos { x: 1, y: 0 });
+        // an operator waiting for a motion is cancelled, not the editor
+        type_keys(&mut ctx, "d\x03x");
+        assert_eq!(ctx.focused_buf().to_string(), "ac\nabd\n");
+        type_keys(&mut ctx, "iz\x03");
+        assert_eq!((ctx.mode, ctx.focused_buf().to_string()), (crate::Mode::Normal, "azc\nabd\n".into()));
+    }
+
+    #[test]
+    fn read_string() {
+        use std::{cell::RefCell, rc::Rc};
+        let mut ctx = editor(TEXT, 30, 8);
+        let answer = Rc::new(RefCell::new(None));
+        let ask = |ctx: &mut Ctx| {
+            let answer = answer.clone();
+            assert!(crate::guile::read_string("name? ", move |a| *answer.borrow_mut() = Some(a.map(String::from))));
+            ctx.start_prompt(&crate::guile::take_prompt().unwrap());
+        };
+        ask(&mut ctx);
+        assert!(!crate::guile::read_string("again? ", |_| ()));
+        assert_eq!(screen(&mut ctx).lines().last(), Some(" name?"));
+        type_keys(&mut ctx, "bob\r");
+        assert_eq!(answer.take(), Some(Some("bob".into())));
+        assert_eq!(ctx.mode, crate::Mode::Normal);
+        // cancelled prompts answer nothing, and the keys after them are normal commands again
+        for cancel in ["\x1b", "\x03"] {
+            ask(&mut ctx);
+            type_keys(&mut ctx, &format!("bo{cancel}x"));
+            assert_eq!(answer.take(), Some(None));
+            assert_eq!(ctx.mode, crate::Mode::Normal);
+        }
+        assert_eq!(ctx.focused_buf().to_string(), TEXT[2..]);
+    }
+
+    #[test]
+    fn cell_owners() {
+        use crate::term::TermPos;
+        use crate::tui::Owner;
+        use crate::window::org::{Arrange, Layout, LayoutBuf};
+        let mut ctx = editor(TEXT, 40, 12);
+        let leaf = || Box::new(Layout::Leaf(LayoutBuf::Id(ctx.focused_win().buffer.id())));
+        let layout = Layout::Split {
+            first: leaf(),
+            second: Box::new(Layout::Split { first: leaf(), second: leaf(), arrange: Arrange::Vertical }),
+            arrange: Arrange::Horizontal,
+        };
+        ctx.set_layout(&layout).unwrap();
+        ctx.tui.borrow_mut().record_owners();
+        screen(&mut ctx);
+        let tui = ctx.tui.borrow();
+        // every window draws all of its own cells and none of the others'
+        for win in ctx.windows() {
+            let win = win.get();
+            let bounds = win.outer_bounds();
+            for (x, y) in bounds.yrng().flat_map(|y| bounds.xrng().map(move |x| (x, y))) {
+                assert_eq!(tui.owner(TermPos { x, y }), Some(Owner::Window(win.id())), "{x}, {y}");
+            }
+        }
+        // and the status and command line are left to the command line
+        for (x, y) in (10..12).flat_map(|y| (0..40).map(move |x| (x, y))) {
+            assert_eq!(tui.owner(TermPos { x, y }), Some(Owner::CommandLine));
+        }
+    }
+
+    #[test]
+    fn undo_keys() {
+        let mut ctx = editor("abc\n", 30, 8);
+        type_keys(&mut ctx, "lxifoo\x1b");
+        assert_eq!(ctx.focused_buf().to_string(), "afooc\n");
+        // the insert is one change, and the cursor goes back to where the change was made
+        type_keys(&mut ctx, "u");
+        assert_eq!(ctx.focused_buf().to_string(), "ac\n");
+        type_keys(&mut ctx, "u");
+        assert_eq!(ctx.focused_buf().to_string(), "abc\n");
+        assert_eq!(ctx.focused_buf().cursor.pos, crate::buffer::DocPos { x: 1, y: 0 });
+        type_keys(&mut ctx, "u");
+        assert_eq!(ctx.take_message().unwrap().0, "Already at oldest change");
+        type_keys(&mut ctx, "\x12\x12");
+        assert_eq!(ctx.focused_buf().to_string(), "afooc\n");
+        type_keys(&mut ctx, "\x12");
+        assert_eq!(ctx.take_message().unwrap().0, "Already at newest change");
+    }
+
+    #[test]
+    fn not_modifiable() {
+        let mut ctx = editor("b\ta\n", 40, 8);
+        ctx.focused_buf_mut().set_modifiable(false);
+        for keys in ["x", "ifoo\x1b", ":s/a/c/\r", ":sort\r", ":retab!\r", ":Align a\r", ":earlier 1\r", ":normal x\r"] {
+            type_keys(&mut ctx, keys);
+            let message = ctx.take_message().unwrap().0;
+            assert!(message.contains("E21"), "{keys:?}: {message}");
+            assert_eq!(ctx.focused_buf().to_string(), "b\ta\n", "{keys:?}");
+        }
+    }
+
+    #[test]
+    fn key_hints() {
+        let mut ctx = editor(TEXT, 60, 10);
+        type_keys(&mut ctx, "g");
+        ctx.show_key_hints(&[]);
+        assert_golden("key_hints", &screen(&mut ctx));
+    }
+
+    #[test]
+    fn command_window() {
+        let mut ctx = editor(TEXT, 40, 16);
+        type_keys(&mut ctx, ":set tw=40\r:set ts=4\rq:");
+        assert_golden("command_window", &screen(&mut ctx));
+        assert_eq!(ctx.focused_win().height(), 7);
+        type_keys(&mut ctx, "kk$x\r");
+        assert_eq!(ctx.options.textwidth, 4);
+        assert!(!ctx.in_command_window());
+        assert_eq!(ctx.windows().len(), 1);
+        type_keys(&mut ctx, "q::q\r");
+        assert_eq!(ctx.windows().len(), 1);
+        // it has the options of the window it was split off from
+        type_keys(&mut ctx, ":setlocal so=2\rq:");
+        assert_eq!(ctx.focused_win().options.scrolloff, 2);
+        type_keys(&mut ctx, ":q\r");
+    }
+
+    #[test]
+    fn split() {
+        let mut ctx = editor(TEXT, 40, 10);
+        // relative to the package, where tests run
+        type_keys(&mut ctx, ":diffsplit assets/test/test.txt\r");
+        assert_golden("split", &screen(&mut ctx));
+    }
+
+    #[test]
+    fn split_cursor_column() {
+        let mut ctx = editor("héllo\n", 40, 10);
+        type_keys(&mut ctx, ":diffsplit assets/test/test.txt\r\x17w$");
+        screen(&mut ctx);
+        let pos = ctx.tui.borrow().cursorpos();
+        // the 'o' is the fifth cell, not the sixth byte
+        let at = screen(&mut ctx).lines().nth(pos.y as usize).unwrap().chars().nth(pos.x as usize);
+        assert_eq!(at, Some('o'));
+    }
+
+    #[test]
+    fn vertical_moves_keep_the_display_column() {
+        let mut ctx = editor("\tab\n漢字x\nabcdefghij\n", 40, 10);
+        let under_cursor = |ctx: &mut Ctx| {
+            screen(ctx);
+            let tui = ctx.tui.borrow();
+            (tui.cursorpos().x, tui[tui.cursorpos()].content())
+        };
+        type_keys(&mut ctx, "l");
+        let (col, c) = under_cursor(&mut ctx);
+        assert_eq!(c, Some('a'));
+        // the tab is drawn as the spaces up to column 8
+        assert!(screen(&mut ctx).lines().next().unwrap().ends_with("        ab"));
+        type_keys(&mut ctx, "j");
+        assert_eq!(under_cursor(&mut ctx), (col - 4, Some('x')));
+        type_keys(&mut ctx, "j");
+        assert_eq!(under_cursor(&mut ctx), (col, Some('i')));
+        type_keys(&mut ctx, "hhhhhk");
+        assert_eq!(under_cursor(&mut ctx), (col - 6, Some('字')));
+        type_keys(&mut ctx, "k");
+        assert_eq!(under_cursor(&mut ctx), (col - 8, Some(' ')));
+        // the cells the wide characters cover aren't shown as blanks
+        assert!(screen(&mut ctx).lines().nth(1).unwrap().ends_with("漢字x"));
+    }
+
+    #[test]
+    fn syntax_colors() {
+        crate::syntax::define("drawtest", r"\bfn\b", "Keyword").unwrap();
+        let colored = |text: &str| {
+            let mut ctx = editor(text, 40, 6);
+            ctx.focused_buf_mut().set_path("x.drawtest".into());
+            let x = screen(&mut ctx).lines().next().unwrap().find("fn").unwrap();
+            let dump = ctx.tui.borrow().dump_text();
+            dump.contains(&format!("\n0:{x}-{} fg=magenta bold\n", x + 2))
+        };
+        assert!(colored("fn main\n"));
+        // not in a file with lines too long to highlight
+        let long = format!("fn main\n{}\n", "x".repeat(crate::buffer::LONG_LINE + 1));
+        assert!(!colored(&long));
+    }
+
+    #[test]
+    fn tiny_screens() {
+        for (w, h) in [(1, 1), (2, 80), (200, 2)] {
+            let mut ctx = editor(TEXT, w, h);
+            type_keys(&mut ctx, "jx:set tw=3\r");
+            let lines = screen(&mut ctx);
+            assert!(lines.starts_with(&"screen too small"[..16.min(w as usize)]), "{w}x{h}:\n{lines}");
+            assert_eq!(ctx.tui.borrow().cursorpos(), crate::tui::TermPos { x: 0, y: 0 });
+        }
+        // just big enough for a cell of text
+        let mut ctx = editor(TEXT, 8, 3);
+        assert_golden("smallest_screen", &screen(&mut ctx));
+    }
+
+    #[test]
+    fn resized() {
+        let mut ctx = editor(TEXT, 40, 10);
+        type_keys(&mut ctx, ":diffsplit assets/test/test.txt\r:set t");
+        screen(&mut ctx);
+        // too narrow for the split, then back
+        resize(&mut ctx, 12, 10);
+        assert!(screen(&mut ctx).starts_with("screen too s"));
+        resize(&mut ctx, 40, 10);
+        assert_golden("resized_back", &screen(&mut ctx));
+        resize(&mut ctx, 30, 6);
+        type_keys(&mut ctx, "w=4\r");
+        assert_eq!(ctx.options.textwidth, 4);
+        assert_golden("resized_smaller", &screen(&mut ctx));
+        // made at a size that's too small, then grown
+        let mut ctx = editor(TEXT, 1, 1);
+        screen(&mut ctx);
+        resize(&mut ctx, 30, 8);
+        assert_eq!(screen(&mut ctx), screen(&mut editor(TEXT, 30, 8)));
+    }
+
+    #[test]
+    fn repeat_change() {
+        let mut ctx = editor("one two three four\n", 40, 8);
+        type_keys(&mut ctx, "x.");
+        assert_eq!(ctx.focused_buf().to_string(), "e two three four\n");
+        // with what was typed in insert mode, and a cancelled command in between isn't a change
+        type_keys(&mut ctx, "ciwX\x1bwd\x1b.");
+        assert_eq!(ctx.focused_buf().to_string(), "X X three four\n");
+        type_keys(&mut ctx, "wysiw)$.");
+        assert_eq!(ctx.focused_buf().to_string(), "X X (three) (four)\n");
+        // undo isn't a change
+        let mut ctx = editor("abc\n", 40, 8);
+        type_keys(&mut ctx, "xu.");
+        assert_eq!(ctx.focused_buf().to_string(), "bc\n");
+    }
+
+    #[test]
+    fn long_line() {
+        let text = format!("{}\nshort\n", "ab".repeat(1 << 20));
+        let mut ctx = editor(&text, 20, 5);
+        type_keys(&mut ctx, "$");
+        let lines = screen(&mut ctx);
+        assert_eq!(lines.lines().next(), Some("  1   ababababababab"));
+        // on the last column, since the line isn't scrolled sideways
+        assert_eq!(ctx.tui.borrow().cursorpos(), crate::tui::TermPos { x: 19, y: 0 });
+    }
+}
@@ -1,3 +1,10 @@
+//! Motions and text objects, the ranges of text that normal mode commands move over or act on.
+//!
+//! The function and class objects, `af`, `if`, `ac` and `ic`, would best come from a parse tree of
+//! the buffer, but the editor has no parser for any language, only highlighting rules that see a
+//! line at a time (see [`crate::syntax`]). They're found from the indentation instead: a line that
+//! looks like the header of a function or class, the lines indented deeper after it, and a closing
+//! `}` or `end` at the header's indentation. Code indented against its structure fools them.
 use std::ops::{Range, RangeBounds};
 
 use crate::{guile::ProtectedScm, prelude::*};
@@ -129,24 +136,24 @@ pub mod motions {
 
     pub(crate) fn word_forward(buf: &BufferInner, pos: usize) -> Option<usize> {
         empty_is_none(buf)?;
-        let mut it = buf.chars_fwd(pos).enumerate().peekable();
-        it.next();
+        let mut it = buf.chars_fwd(pos).peekable();
+        let init = it.next()?.1;
         it.peek()?;
-        it.skip_while(|c| c.1.is_wordchar_extended())
+        it.skip_while(|c| init.is_wordchar_extended() && c.1.is_wordchar_extended())
             .skip_while(|c| c.1.is_whitespace())
-            .map(|(p, _)| p + pos)
+            .map(|(p, _)| p)
             .next()
             .or_else(|| Some(buf.len()))
     }
 
     pub(crate) fn word_subset_forward(buf: &BufferInner, pos: usize) -> Option<usize> {
         empty_is_none(buf)?;
-        let mut it = buf.chars_fwd(pos).enumerate().peekable();
+        let mut it = buf.chars_fwd(pos).peekable();
         let init = it.next()?.1.category();
         it.peek()?;
         it.skip_while(|c| c.1.category() == init)
             .skip_while(|c| c.1.category() == WordCat::Whitespace)
-            .map(|(p, _)| p + pos)
+            .map(|(p, _)| p)
             .next()
             .or_else(|| Some(buf.len()))
     }
@@ -154,7 +161,7 @@ pub mod motions {
     pub(crate) fn word_end_forward(buf: &BufferInner, pos: usize) -> Option<usize> {
         empty_is_none(buf)?;
         let mut it = buf
-            .chars_fwd(pos).enumerate()
+            .chars_fwd(pos)
             .skip(1)
             .skip_while(|c| c.1.category() == WordCat::Whitespace)
             .peekable();
@@ -178,7 +185,7 @@ pub mod motions {
     pub(crate) fn word_end_subset_forward(buf: &BufferInner, pos: usize) -> Option<usize> {
         empty_is_none(buf)?;
         let mut it = buf
-            .chars_fwd(pos).enumerate()
+            .chars_fwd(pos)
             .skip(1)
             .skip_while(|c| c.1.category() == WordCat::Whitespace)
             .peekable();
@@ -197,13 +204,13 @@ pub mod motions {
             ret = *it.peek()?;
             it.next();
         }
-        Some(ret.0 + pos)
+        Some(ret.0)
     }
 
     pub(crate) fn word_backward(buf: &BufferInner, pos: usize) -> Option<usize> {
         empty_is_none(buf)?;
         let mut it = buf
-            .chars_bck(pos).enumerate()
+            .chars_bck(pos)
             .skip(1)
             .skip_while(|c| c.1.category() == WordCat::Whitespace)
             .peekable();
@@ -221,13 +228,13 @@ pub mod motions {
             ret = *it.peek().expect("Checked prior");
             it.next();
         }
-        Some(pos - ret.0)
+        Some(ret.0)
     }
 
     pub(crate) fn word_subset_backward(buf: &BufferInner, pos: usize) -> Option<usize> {
         empty_is_none(buf)?;
         let mut it = buf
-            .chars_bck(pos).enumerate()
+            .chars_bck(pos)
             .skip(1)
             .skip_while(|c| c.1.category() == WordCat::Whitespace)
             .peekable();
@@ -246,20 +253,20 @@ pub mod motions {
             ret = *it.peek().expect("checked prior");
             it.next();
         }
-        Some(pos - ret.0)
+        Some(ret.0)
     }
 
     fn word_end_backward_base(buf: &BufferInner, pos: usize, eq: impl Fn(&char, &char) -> bool) -> Option<usize>{
         empty_is_none(buf)?;
         let first = buf.char_at(pos);
         let pos = pos.saturating_sub(1);
-        let back = buf.chars_bck(pos).enumerate()
+        let back = buf.chars_bck(pos)
         .skip_while(|c| eq(&c.1, &first) && !c.1.is_whitespace())
         .skip_while(|c| c.1.is_whitespace())
         .next()
-            .map_or(pos, |(i, _)| i);
+            .map_or(0, |(i, _)| i);
 
-        Some(pos - back)
+        Some(back)
     }
 
     pub(crate) fn word_end_backward(buf: &BufferInner, pos: usize) -> Option<usize> {
@@ -272,13 +279,22 @@ pub mod motions {
 
     pub(crate) fn start_of_line(buf: &BufferInner, pos: usize) -> Option<usize> {
         empty_is_none(buf)?;
-        Some(buf.chars_bck(pos).enumerate().filter(|&(_, c)| c == '\n').nth(0).map_or(0, |(i, _)| pos - i))
+        Some(buf.chars_bck(pos).skip(1).find(|&(_, c)| c == '\n').map_or(0, |(i, _)| i + 1))
     }
 
     pub(crate) fn end_of_line(buf: &BufferInner, pos: usize) -> Option<usize> {
         empty_is_none(buf)?;
-        Some(buf.chars_fwd(pos).enumerate().filter(|&(_, c)| c == '\n').nth(0)
-            .map_or(buf.len().saturating_sub(1), |(i, _)| pos + i.saturating_sub(1)))
+        // the last character before the line break, or the line break of an empty line
+        let mut last = pos;
+        let mut it = buf.chars_fwd(pos).peekable();
+        while let Some((i, c)) = it.next() {
+            let crlf = c == '\r' && it.peek().is_some_and(|c| c.1 == '\n');
+            if c == '\n' || crlf {
+                break;
+            }
+            last = i;
+        }
+        Some(last)
     }
 
     pub(crate) fn end_of_buffer(buf: &BufferInner, _pos: usize) -> Option<usize> {
@@ -295,28 +311,9 @@ pub mod motions {
 
     #[cfg(test)]
     mod test {
-        use std::fmt::Write;
-        use std::ops::Add;
-
         use super::*;
-
-        fn print_pos(buf: &BufferInner, pos: usize) -> String {
-            let slice_start = pos.saturating_sub(5);
-            let slice_end = pos.add(5).min(buf.len());
-            let s = buf.to_string().replace('\n', "$");
-            let mut out = String::new();
-            writeln!(out, "\n{}", &s[slice_start..slice_end]).unwrap();
-            for i in slice_start..slice_end {
-                let c = if i == pos {
-                    '^'
-                } else {
-                    ' '
-                };
-                out.push(c);
-            }
-            out.push('\n');
-            out
-        }
+        use crate::buffer::test::{long_text, CORPUS};
+        use crate::testing::print_pos;
 
         macro_rules! motion_test {
             ($motion:ident, $({$($check:tt)*}),* $(,)?) => {
@@ -388,6 +385,7 @@ pub mod motions {
             {".,?. a b" => "a"},
             {"{\".,?.\"} a" => "a"},
             {"a'b c" => "c"},
+            {"héé wörd" => "wörd"},
         );
 
         motion_test!(
@@ -432,6 +430,7 @@ pub mod motions {
             {"asdf" => "f"},
             {"01234\n6789" => "4"},
             {"01234\n6789", 4 => "4"},
+            {"aé\nb" => "é"},
         );
 
         motion_test!(
@@ -458,6 +457,7 @@ pub mod motions {
             {"01.3.5", 4 => "0"},
             {"01! .5", 4 => "0"},
             {" 1., 5", 4 => "1"},
+            {"漢字 ab", 7 => "漢字"},
         );
 
         motion_test!(
@@ -467,6 +467,116 @@ pub mod motions {
             {"asdfa 1230", 9 => "0"},
             {"" => None},
         );
+
+        motion_test!(
+            start_of_line,
+            {"0123\n5678", 7 => "5"},
+            {"0123\n5678", 3 => "0"},
+            {"01\n\n45", 3 => "\n4"},
+            {"é\nü漢", 5 => "ü"},
+        );
+
+        /// offsets where a word starts in `text`, with words made of characters that are `same`
+        fn word_starts(text: &str, same: fn(char, char) -> bool) -> Vec<usize> {
+            let mut prev: Option<char> = None;
+            let mut starts = Vec::new();
+            for (i, c) in text.char_indices() {
+                if !c.is_whitespace() && prev.is_none_or(|p| p.is_whitespace() || !same(p, c)) {
+                    starts.push(i);
+                }
+                prev = Some(c);
+            }
+            starts
+        }
+
+        /// offsets of the last characters of words in `text`, except for the one at the very end
+        /// since the motions go to the end of the buffer there
+        fn word_ends(text: &str, same: fn(char, char) -> bool) -> Vec<usize> {
+            let mut it = text.char_indices().peekable();
+            let mut ends = Vec::new();
+            while let Some((i, c)) = it.next() {
+                if let Some(&(_, next)) = it.peek() {
+                    if !c.is_whitespace() && (next.is_whitespace() || !same(c, next)) {
+                        ends.push(i);
+                    }
+                }
+            }
+            ends
+        }
+
+        /// where `motion` stops going from `pos`, until it stops moving or gets to the end
+        fn walk(buf: &BufferInner, mut pos: usize, motion: TextMotion) -> Vec<usize> {
+            let mut stops = Vec::new();
+            while let Some(next) = motion(buf, pos) {
+                if next == pos || next >= buf.len() {
+                    break;
+                }
+                stops.push(next);
+                pos = next;
+            }
+            stops
+        }
+
+        /// the word motions agree with `same` on what a word is everywhere in the corpus
+        fn check_words(same: fn(char, char) -> bool, forward: TextMotion, backward: TextMotion, end: TextMotion) {
+            for &text in CORPUS {
+                let buf = BufferInner::from_str(text);
+                let last = text.char_indices().last().map_or(0, |(i, _)| i);
+                let starts = word_starts(text, same);
+                let fwd: Vec<_> = starts.iter().copied().filter(|&i| i > 0).collect();
+                assert_eq!(walk(&buf, 0, forward), fwd, "forward in {text:?}");
+                let bck: Vec<_> = starts.iter().copied().rev().filter(|&i| i < last).collect();
+                assert_eq!(walk(&buf, last, backward), bck, "backward in {text:?}");
+                let ends: Vec<_> = word_ends(text, same).into_iter().filter(|&i| i > 0).collect();
+                assert_eq!(walk(&buf, 0, end), ends, "ends in {text:?}");
+            }
+        }
+
+        #[test]
+        fn corpus_words() {
+            check_words(|_, _| true, motions::word_forward, motions::word_backward, motions::word_end_forward);
+            check_words(
+                |a, b| a.category() == b.category(),
+                motions::word_subset_forward,
+                motions::word_subset_backward,
+                motions::word_end_subset_forward,
+            );
+        }
+
+        #[test]
+        fn corpus_lines() {
+            for &text in CORPUS {
+                let buf = BufferInner::from_str(text);
+                let mut line_start = 0;
+                for (i, c) in text.char_indices() {
+                    assert_eq!(motions::start_of_line(&buf, i), Some(line_start), "{i} in {text:?}");
+                    let line = &text[line_start..];
+                    let line = &line[..line.find('\n').unwrap_or(line.len())];
+                    let line = line.strip_suffix('\r').unwrap_or(line);
+                    let eol = line.char_indices().last().map_or(line_start, |(j, _)| line_start + j);
+                    if i <= eol {
+                        assert_eq!(motions::end_of_line(&buf, i), Some(eol), "{i} in {text:?}");
+                    }
+                    if c == '\n' {
+                        line_start = i + 1;
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn long_file() {
+            let text = long_text(100_000);
+            let buf = BufferInner::from_str(&text);
+            // "line", "0:", "naïve", "🦀" and "word." on every line
+            assert_eq!(walk(&buf, 0, motions::word_forward).len(), 5 * 100_000 - 1);
+            let last = text[..text.len() - 1].rfind('\n').unwrap() + 1;
+            assert_eq!(motions::start_of_line(&buf, text.len() - 2), Some(last));
+            assert_eq!(motions::end_of_line(&buf, last), Some(text.len() - 2));
+            assert_eq!(motions::word_subset_backward(&buf, last), text[..last - 1].rfind('.'));
+            assert_eq!(motions::word_backward(&buf, text.len() - 1), text.rfind("word"));
+            assert_eq!(motions::end_of_buffer(&buf, 0), Some(text.len() - 1));
+        }
     }
 }
 
@@ -494,30 +604,72 @@ pub mod motions {
 pub fn inner_word(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
     let first = buf.char_at(pos);
     let start = buf
-        .chars_bck(pos).enumerate()
+        .chars_bck(pos)
         .take_while(|c| c.1.category() == first.category())
         .last()
-        .map_or(0, |(i, _)| pos - i);
+        .map_or(0, |(i, _)| i);
     let end = buf
-        .chars_fwd(pos).enumerate()
-        .skip_while(|c| c.1.category() == first.category())
-        .next()
-        .map_or_else(|| buf.len(), |(i, _)| pos + i);
+        .chars_fwd(pos)
+        .find(|c| c.1.category() != first.category())
+        .map_or_else(|| buf.len(), |(i, _)| i);
     assert!(start <= end);
 
     Some(start..end)
 }
 
+/// the start of the last word in `text`, before the whitespace at its end, for `Ctrl-w` in insert
+/// mode
+pub fn word_before(text: &str) -> usize {
+    let text = text.trim_end();
+    let Some(last) = text.chars().next_back() else {
+        return 0;
+    };
+    text.char_indices()
+        .rev()
+        .take_while(|(_, c)| c.category() == last.category())
+        .last()
+        .map_or(0, |(i, _)| i)
+}
+
+/// what `gf` and `gx` open: a URL, or a file name made of the characters that usually are in one
+fn link() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| {
+        let url = r#"(?<url>[a-zA-Z][a-zA-Z0-9+.-]*://[^\s<>"'`()\[\]{}]*[^\s<>"'`()\[\]{}.,;:!?])"#;
+        regex::Regex::new(&format!(r"{url}|[\w/.\-+~=]+")).unwrap()
+    })
+}
+
+/// the match of [`link`] on the line of `pos` that `pos` is in, and whether it's a URL
+fn link_at(buf: &BufferInner, pos: usize) -> Option<(Range<usize>, bool)> {
+    let y = buf.offset_to_pos(pos).y;
+    let line_start = buf.line_offsets(y..y + 1).start;
+    let col = pos - line_start;
+    let caps = link().captures_iter(buf.line(y)).find(|c| c.get(0).unwrap().range().contains(&col))?;
+    let range = caps.get(0).unwrap().range();
+    Some((line_start + range.start..line_start + range.end, caps.name("url").is_some()))
+}
+
+/// the file name at `pos`
+pub fn file_name(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    link_at(buf, pos).filter(|(_, url)| !url).map(|(range, _)| range)
+}
+
+/// the URL at `pos`, not including punctuation after it
+pub fn url(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    link_at(buf, pos).filter(|(_, url)| *url).map(|(range, _)| range)
+}
+
 pub fn a_word(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
     let mut found_white_space = buf.char_at(pos).is_whitespace();
-    let start = pos - buf.chars_bck(pos).take_while(|c| c.is_whitespace()).count().saturating_sub(1);
-    let pos = pos + buf.chars_fwd(pos).take_while(|c| c.is_whitespace()).count();
+    let start = buf.chars_bck(pos).take_while(|(_, c)| c.is_whitespace()).last().map_or(pos, |(i, _)| i);
+    let pos = buf.chars_fwd(pos).find(|(_, c)| !c.is_whitespace()).map_or(buf.len(), |(i, _)| i);
     let first = buf.char_at(pos);
 
     let trail_whitespace = !found_white_space;
     let lead_whitespace = found_white_space;
     let end = buf
-        .chars_fwd(pos).enumerate()
+        .chars_fwd(pos)
         .skip_while(|c| c.1.category() == WordCat::Whitespace)
         .skip_while(|c| c.1.category() == first.category())
         .skip_while(|c| {
@@ -529,25 +681,25 @@ pub fn a_word(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
             }
         })
         .next()
-        .map_or_else(|| buf.len(), |(i, _)| i + pos);
+        .map_or_else(|| buf.len(), |(i, _)| i);
 
-    // eprintln!("{}", test::print_cursor(buf, start..pos, init));
+    // eprintln!("{}", crate::testing::print_cursor(buf, start..pos, init));
 
     let start = if lead_whitespace {
         start
     } else if found_white_space {
         buf
-            .chars_bck(start).enumerate()
+            .chars_bck(start)
             .take_while(|c| c.1.category() == first.category())
             .last()
-            .map_or(start, |(i, _)| start - (i))
+            .map_or(start, |(i, _)| i)
     } else {
         buf
-            .chars_bck(start).enumerate()
+            .chars_bck(start)
             .skip_while(|c| c.1.category() == first.category())
             .take_while(|c| c.1.is_whitespace())
             .last()
-            .map_or(0, |(i, _)| start - (i))
+            .map_or(0, |(i, _)| i)
     };
     Some(start..end)
 }
@@ -556,61 +708,70 @@ pub fn inner_paragraph(_buf: &BufferInner, _pos: usize) -> Option<Range<usize>>
     todo!()
 }
 
-pub fn inner_sentence(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
-    let mut cnt = 1;
+/// the start and end of the sentence at `pos`, without the whitespace around it. A sentence ends
+/// with a `.`, `!` or `?` that's followed by whitespace, or at the end of its line. Whitespace
+/// between two sentences belongs to the one after it.
+fn sentence(buf: &BufferInner, pos: usize) -> (usize, usize) {
+    let mut start = 0;
+    let mut after: Option<char> = None;
+    for (i, c) in buf.chars_bck(pos) {
+        if c == '\n' && i != pos {
+            start = i + 1;
+            break;
+        }
+        if c.is_sentence_delim() && after.is_some_and(char::is_whitespace) {
+            start = i + c.len_utf8();
+            break;
+        }
+        after = Some(c);
+    }
     let start = buf
-        .chars_bck(pos).enumerate()
-        .skip_while(|c| {
-            c.1 != '\n' && {
-                cnt -= 1;
-                cnt >= 0
-            }
-        }) // skip one back but not if it's lf
-        .skip_while(|c| !c.1.is_sentence_delim() && c.1 != '\n')
-        .next()
-        .map_or(0, |(i, _)| pos - i);
-    let mut it = buf.chars_fwd(pos).enumerate().peekable();
-    let mut end = pos;
-    while let Some(c) = it.next() {
-        end = c.0;
-        if c.1.is_sentence_delim()
-            && it
-                .peek()
-                .map_or(true, |p| p.1.category() == WordCat::Whitespace)
-        {
+        .chars_fwd(start)
+        .find(|c| !c.1.is_whitespace() || c.1 == '\n')
+        .map_or(buf.len(), |(i, _)| i);
+
+    let mut it = buf.chars_fwd(start).peekable();
+    let mut end = buf.len();
+    while let Some((i, c)) = it.next() {
+        if c == '\n' {
+            end = i;
+            break;
+        }
+        if c.is_sentence_delim() && it.peek().is_none_or(|p| p.1.is_whitespace()) {
+            end = i + c.len_utf8();
             break;
         }
     }
+    let end = buf
+        .chars_bck(end.saturating_sub(1))
+        .take_while(|c| end > start && c.0 >= start && c.1.is_whitespace())
+        .last()
+        .map_or(end, |(i, _)| i);
+    (start, end)
+}
+
+pub fn inner_sentence(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    let (start, end) = sentence(buf, pos);
     Some(start..end)
 }
 
+/// the sentence at `pos` with the whitespace after it, or before it when there's none after it.
+/// Line breaks are never part of it.
 pub fn a_sentence(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
-    let mut cnt = 1;
-    let start = buf
-        .chars_bck(pos).enumerate()
-        .skip_while(|c| {
-            c.1 != '\n' && {
-                cnt -= 1;
-                cnt >= 0
-            }
-        }) // skip one back but not if it's lf
-        .skip_while(|c| !c.1.is_sentence_delim() && c.1 != '\n')
-        .next()
-        .map_or(0, |(i, _)| pos - i);
-    let mut it = buf.chars_fwd(pos).enumerate().peekable();
-    let mut end = pos;
-    while let Some(c) = it.next() {
-        end = c.0;
-        if c.1.is_sentence_delim()
-            && it
-                .peek()
-                .map_or(true, |p| p.1.category() == WordCat::Whitespace)
-        {
-            break;
-        }
+    let (start, end) = sentence(buf, pos);
+    let blank = |c: &(usize, char)| c.1.is_whitespace() && c.1 != '\n';
+    let trail = buf.chars_fwd(end).find(|c| !blank(c)).map_or(buf.len(), |(i, _)| i);
+    if trail > end {
+        return Some(start..trail);
     }
-    Some(start..end)
+    let lead = buf
+        .chars_bck(start.saturating_sub(1))
+        .take_while(|c| start > 0 && blank(c))
+        .last()
+        .map_or(start, |(i, _)| i);
+    Some(lead..end)
 }
+
 pub fn inner_paren(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
     delim_text_object(buf, pos, '(', ')', true)
 }
@@ -659,6 +820,189 @@ pub fn a_backtick(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
     delim_text_object(buf, pos, '`', '`', false)
 }
 
+pub fn inner_angle(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    delim_text_object(buf, pos, '<', '>', true)
+}
+
+pub fn a_angle(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    delim_text_object(buf, pos, '<', '>', false)
+}
+
+/// the opening and closing tag of the innermost XML or HTML element around `pos`
+pub fn tag_pair(buf: &BufferInner, pos: usize) -> Option<(Range<usize>, Range<usize>)> {
+    static TAG: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let tag = TAG.get_or_init(|| regex::Regex::new(r"<(/?)([A-Za-z][\w:.-]*)[^<>]*?(/?)>").unwrap());
+    let text = buf.to_string();
+    let mut open: Vec<(&str, Range<usize>)> = Vec::new();
+    for caps in tag.captures_iter(&text) {
+        let range = caps.get(0).unwrap().range();
+        let name = caps.get(2).unwrap().as_str();
+        if !caps[3].is_empty() {
+            continue;
+        }
+        if caps[1].is_empty() {
+            open.push((name, range));
+            continue;
+        }
+        // a closing tag without an opening one is ignored, opening tags without a closing one are
+        // dropped, like <br> in HTML
+        let Some(i) = open.iter().rposition(|(n, _)| *n == name) else {
+            continue;
+        };
+        let start = open[i].1.clone();
+        open.truncate(i);
+        // pairs are closed from the inside out, so the first one around `pos` is the innermost
+        if start.start <= pos && pos < range.end {
+            return Some((start, range));
+        }
+    }
+    None
+}
+
+pub fn inner_tag(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    tag_pair(buf, pos).map(|(open, close)| open.end..close.start)
+}
+
+pub fn a_tag(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    tag_pair(buf, pos).map(|(open, close)| open.start..close.end)
+}
+
+/// the indentation of `line` in columns, with a tab as far as the next multiple of 8. `None` for
+/// blank lines, which belong to whatever block is around them.
+fn indent_of(line: &str) -> Option<usize> {
+    if line.trim().is_empty() {
+        return None;
+    }
+    Some(line.chars().take_while(|c| c.is_whitespace()).fold(0, |n, c| if c == '\t' { n / 8 * 8 + 8 } else { n + 1 }))
+}
+
+/// the lines after `header` that are indented deeper than it, without blank lines at the end
+fn block_body(buf: &BufferInner, header: usize) -> Range<usize> {
+    let start = header + 1;
+    let Some(indent) = indent_of(buf.line(header)) else {
+        return start..start;
+    };
+    let mut end = start;
+    for y in start..buf.linecnt() {
+        match indent_of(buf.line(y)) {
+            Some(i) if i <= indent => break,
+            Some(_) => end = y + 1,
+            None => (),
+        }
+    }
+    start..end
+}
+
+/// the line after `body` if it closes the block of `header`, like a `}` or `end` at the same
+/// indentation as the header
+fn block_close(buf: &BufferInner, header: usize, body: &Range<usize>) -> Option<usize> {
+    let y = body.end;
+    if y >= buf.linecnt() {
+        return None;
+    }
+    let line = buf.line(y).trim_end_matches(['\r', '\n']);
+    let closes = indent_of(line) == indent_of(buf.line(header))
+        && (line.trim_start().starts_with(['}', ']', ')']) || line.trim() == "end");
+    closes.then_some(y)
+}
+
+/// the lines of the innermost block around `line` whose header matches `header`, as the header,
+/// its body and the line closing it if there is one, found from the indentation.
+fn structure(buf: &BufferInner, line: usize, header: &regex::Regex) -> Option<(usize, Range<usize>, Option<usize>)> {
+    for y in (0..=line).rev() {
+        if !header.is_match(buf.line(y)) {
+            continue;
+        }
+        let body = block_body(buf, y);
+        let close = block_close(buf, y, &body);
+        if y == line || line < close.map_or(body.end, |c| c + 1) {
+            return Some((y, body, close));
+        }
+    }
+    None
+}
+
+/// headers of functions in the usual languages
+fn function_header() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| {
+        let modifiers = r"(?:(?:pub(?:\([^)]*\))?|async|const|unsafe|export|static|extern\s+\S+)\s+)*";
+        regex::Regex::new(&format!(r"^\s*{modifiers}(?:fn|def|function|func|proc)\b")).unwrap()
+    })
+}
+
+/// headers of classes and similar blocks that group definitions
+fn class_header() -> &'static regex::Regex {
+    static RE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    RE.get_or_init(|| {
+        let modifiers = r"(?:(?:pub(?:\([^)]*\))?|export|abstract|final|unsafe)\s+)*";
+        regex::Regex::new(&format!(r"^\s*{modifiers}(?:class|struct|enum|trait|impl|interface|module|mod)\b")).unwrap()
+    })
+}
+
+/// the whole block, from its header to the line closing it
+fn a_structure(buf: &BufferInner, pos: usize, header: &regex::Regex) -> Option<Range<usize>> {
+    let (start, body, close) = structure(buf, buf.offset_to_pos(pos).y, header)?;
+    Some(buf.line_offsets(start..close.map_or(body.end, |c| c + 1)))
+}
+
+/// the lines inside the block, `None` if it's all on one line
+fn inner_structure(buf: &BufferInner, pos: usize, header: &regex::Regex) -> Option<Range<usize>> {
+    let (_, body, _) = structure(buf, buf.offset_to_pos(pos).y, header)?;
+    (!body.is_empty()).then(|| buf.line_offsets(body))
+}
+
+pub fn a_function(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    a_structure(buf, pos, function_header())
+}
+
+pub fn inner_function(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    inner_structure(buf, pos, function_header())
+}
+
+pub fn a_class(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    a_structure(buf, pos, class_header())
+}
+
+pub fn inner_class(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    inner_structure(buf, pos, class_header())
+}
+
+/// the lines around `line` indented as deep as it or deeper, with the blank lines between them.
+/// A blank line goes by the next line that isn't.
+fn indent_block(buf: &BufferInner, line: usize) -> Option<Range<usize>> {
+    let blank = |y: usize| indent_of(buf.line(y)).is_none();
+    let y = (line..buf.linecnt()).chain((0..line).rev()).find(|&y| !blank(y))?;
+    let indent = indent_of(buf.line(y))?;
+    let inside = |y: usize| indent_of(buf.line(y)).is_none_or(|i| i >= indent);
+    let mut start = y;
+    while start > 0 && inside(start - 1) {
+        start -= 1;
+    }
+    let mut end = y + 1;
+    while end < buf.linecnt() && inside(end) {
+        end += 1;
+    }
+    while blank(start) {
+        start += 1;
+    }
+    while blank(end - 1) {
+        end -= 1;
+    }
+    Some(start..end)
+}
+
+pub fn inner_indent(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    let lines = indent_block(buf, buf.offset_to_pos(pos).y)?;
+    Some(buf.line_offsets(lines))
+}
+
+/// like [`inner_indent`], with the line above that the block belongs to
+pub fn an_indent(buf: &BufferInner, pos: usize) -> Option<Range<usize>> {
+    let lines = indent_block(buf, buf.offset_to_pos(pos).y)?;
+    Some(buf.line_offsets(lines.start.saturating_sub(1)..lines.end))
+}
+
 // FIXME: it can't handle "[]S[]" (starting at 'S')
 #[inline(always)]
 fn delim_text_object(
@@ -671,7 +1015,7 @@ fn delim_text_object(
     let mut right_stack = 0;
     let mut do_skip = false;
     let end = buf
-        .chars_fwd(pos).enumerate()
+        .chars_fwd(pos)
         .skip_while(|c| {
             if c.1 == close {
                 if right_stack == 0 {
@@ -687,11 +1031,11 @@ fn delim_text_object(
             true
         })
         .next()?
-        .0 + pos;
+        .0;
 
     let mut left_stack = 0;
-    let start = pos - buf
-        .chars_bck(pos).enumerate()
+    let start = buf
+        .chars_bck(pos)
         .skip(if do_skip { 1 } else { 0 })
         .skip_while(|c| {
             if c.1 == open {
@@ -721,48 +1065,8 @@ fn delim_text_object(
 
 #[cfg(test)]
 mod test {
-    use std::ops::Add;
-    use std::fmt::Write;
-
     use super::*;
-
-    pub fn print_cursor(buf: &BufferInner, range: Range<usize>, start: usize) -> String {
-        let slice_start = range.start.min(start).saturating_sub(5);
-        let slice_end = range.end.max(start).add(5).min(buf.len());
-        let s = buf.to_string().replace('\n', "$");
-        let mut out = String::new();
-        writeln!(out, "\n{}", &s[slice_start..slice_end]).unwrap();
-        let slice = slice_start..slice_end;
-        for i in slice_start..slice_end {
-            let c = if i == 0 && i == range.start {
-                '|'
-            } else if i + 1 == range.start {
-                '>'
-            } else if i == range.end {
-                '<'
-            } else if range.contains(&i) {
-                if i + 1 == slice.end {
-                    '|'
-                } else {
-                    '-'
-                }
-            } else {
-                ' '
-            };
-            out.push(c);
-        }
-        out.push('\n');
-        for i in slice_start..slice_end {
-            let c = if i == start {
-                '^'
-            } else {
-                ' '
-            };
-            out.push(c);
-        };
-        out.push('\n');
-        out
-    }
+    use crate::testing::print_cursor;
 
     macro_rules! obj_test {
         ($obj:ident, $({$str:expr $(, $idx:expr)? => $res:expr}),* $(,)?) => {
@@ -809,6 +1113,37 @@ mod test {
         {"asdf 1234", 3 => "asdf"},
         {"asdf 1234", 4 => " "},
         {"asdf 1234", 5 => "1234"},
+        {"ab 漢字 c", 6 => "漢字"},
+    }
+
+    #[test]
+    fn before_cursor() {
+        assert_eq!(word_before("let x = foo.bar  "), 12);
+        assert_eq!(word_before("a.."), 1);
+        assert_eq!(word_before("café"), 0);
+        assert_eq!(word_before("  "), 0);
+    }
+
+    obj_test!{
+        file_name,
+        {"see src/main.rs:12", 6 => "src/main.rs"},
+        {"(~/notes.txt)", 1 => "~/notes.txt"},
+        {"a\nb/c.txt\n", 3 => "b/c.txt"},
+    }
+
+    obj_test!{
+        url,
+        {"see https://example.com/a_(b)?q=1.", 6 => "https://example.com/a_"},
+        {"<git+ssh://host/repo.git>, 2", 20 => "git+ssh://host/repo.git"},
+    }
+
+    #[test]
+    fn links() {
+        let buf = BufferInner::from_str("open https://example.com/x or a.txt");
+        assert_eq!(super::file_name(&buf, 8), None);
+        assert_eq!(super::url(&buf, 31), None);
+        assert_eq!(super::url(&buf, 4), None);
+        assert_eq!(super::file_name(&buf, 4), None);
     }
 
     obj_test!{
@@ -820,5 +1155,107 @@ mod test {
         {"asdf 1234", 5 => " 1234"},
         {" a ", 1 => "a "},
         {"  a ", 1 => "  a"},
+        {"é  ü", 1 => "é  "},
+    }
+
+    obj_test!{
+        inner_sentence,
+        {"One. Two!  Drei?", 0 => "One."},
+        {"One. Two!  Drei?", 6 => "Two!"},
+        {"One. Two!  Drei?", 9 => "Drei?"},
+        {"Ein Satz. Noch 🦀 einer.\r\nNeue Zeile.", 15 => "Noch 🦀 einer."},
+        {"Ein Satz. Noch 🦀 einer.\r\nNeue Zeile.", 29 => "Neue Zeile."},
+        {"version 1.2 is out", 3 => "version 1.2 is out"},
+        {"no end\r\n", 1 => "no end"},
+    }
+
+    obj_test!{
+        a_sentence,
+        {"One. Two!  Drei?", 0 => "One. "},
+        {"One. Two!  Drei?", 6 => "Two!  "},
+        {"One. Two!  Drei?", 12 => "  Drei?"},
+        {"Über. Ünter.\nNext", 7 => " Ünter."},
+    }
+
+    #[test]
+    fn corpus_sentences() {
+        for &text in crate::buffer::test::CORPUS {
+            let buf = BufferInner::from_str(text);
+            for (i, c) in text.char_indices() {
+                let inner = super::inner_sentence(&buf, i).unwrap();
+                let outer = super::a_sentence(&buf, i).unwrap();
+                let sentence = &text[inner.clone()];
+                assert!(!sentence.contains('\n'), "{sentence:?} at {i} in {text:?}");
+                assert_eq!(sentence.trim(), sentence, "at {i} in {text:?}");
+                assert!(c.is_whitespace() || inner.contains(&i), "{sentence:?} at {i} in {text:?}");
+                assert!(outer.start <= inner.start && inner.end <= outer.end, "at {i} in {text:?}");
+                assert!(!text[outer].contains('\n'), "at {i} in {text:?}");
+            }
+        }
+    }
+
+    obj_test!{
+        a_tag,
+        {"<a><b>x</b></a>", 6 => "<b>x</b>"},
+        {"<a><b>x</b></a>", 1 => "<a><b>x</b></a>"},
+        {"<p>a<br>b</p>", 5 => "<p>a<br>b</p>"},
+    }
+
+    const CODE: &str = concat!(
+        "struct S;\n\nimpl S {\n    pub fn f(&self) {\n        x();\n\n        y();\n    }\n}\n",
+        "def g():\n    return 1\nafter\n",
+    );
+
+    obj_test!{
+        a_function,
+        {CODE, 40 => "    pub fn f(&self) {\n        x();\n\n        y();\n    }\n"},
+        {CODE, 25 => "    pub fn f(&self) {\n        x();\n\n        y();\n    }\n"},
+        {CODE, 78 => "def g():\n    return 1\n"},
+    }
+
+    obj_test!{
+        inner_function,
+        {CODE, 49 => "        x();\n\n        y();\n"},
+        {CODE, 90 => "    return 1\n"},
+    }
+
+    obj_test!{
+        a_class,
+        {CODE, 40 => "impl S {\n    pub fn f(&self) {\n        x();\n\n        y();\n    }\n}\n"},
+        {CODE, 0 => "struct S;\n"},
+    }
+
+    obj_test!{
+        inner_class,
+        {CODE, 40 => "    pub fn f(&self) {\n        x();\n\n        y();\n    }\n"},
+    }
+
+    const NESTED: &str = "a:\n  b: 1\n  c:\n    d: 2\n\n    e: 3\n\n  f: 4\ng: 5\n";
+
+    obj_test!{
+        inner_indent,
+        {NESTED, 12 => "  b: 1\n  c:\n    d: 2\n\n    e: 3\n\n  f: 4\n"},
+        {NESTED, 20 => "    d: 2\n\n    e: 3\n"},
+        {NESTED, 29 => "    d: 2\n\n    e: 3\n"},
+        {NESTED, 24 => "    d: 2\n\n    e: 3\n"},
+    }
+
+    obj_test!{
+        an_indent,
+        {NESTED, 20 => "  c:\n    d: 2\n\n    e: 3\n"},
+        {NESTED, 0 => "a:\n  b: 1\n  c:\n    d: 2\n\n    e: 3\n\n  f: 4\ng: 5\n"},
+    }
+
+    #[test]
+    fn outside_structure() {
+        let buf = BufferInner::from_str(CODE);
+        assert_eq!(super::a_function(&buf, 2), None);
+        assert_eq!(super::a_class(&buf, 90), None);
+        assert_eq!(super::inner_function(&BufferInner::from_str("fn f() { x }\n"), 0), None);
+    }
+
+    obj_test!{
+        inner_tag,
+        {"<div class=\"x\">text</div>", 16 => "text"},
     }
 }
@@ -0,0 +1,203 @@
+//! Screen dumps for bug reports, from `:screendump` and the debug key.
+//!
+//! The text dump has the rows as they're shown, followed by the colors of the cells that aren't
+//! drawn in the default colors as runs of `row:start-end` with their attributes, and the cursor.
+//! The HTML dump is a page that looks like the screen.
+use std::fmt::Write;
+
+use super::{row_text, BasicColor, Color, TermGrid};
+
+impl BasicColor {
+    /// the name [`BasicColor::from_name`] takes
+    pub const fn name(&self) -> &'static str {
+        use BasicColor::*;
+        match self {
+            Default => "default",
+            Black => "black",
+            Red => "red",
+            Green => "green",
+            Yellow => "yellow",
+            Blue => "blue",
+            Magenta => "magenta",
+            Cyan => "cyan",
+            White => "white",
+            Gray => "gray",
+            BrightRed => "brightred",
+            BrightGreen => "brightgreen",
+            BrightYellow => "brightyellow",
+            BrightBlue => "brightblue",
+            BrightMagenta => "brightmagenta",
+            BrightCyan => "brightcyan",
+            BrightWhite => "brightwhite",
+        }
+    }
+
+    /// CSS color of the usual xterm palette, `None` for the default
+    pub const fn css(&self) -> Option<&'static str> {
+        use BasicColor::*;
+        Some(match self {
+            Default => return None,
+            Black => "#000000",
+            Red => "#cd0000",
+            Green => "#00cd00",
+            Yellow => "#cdcd00",
+            Blue => "#0000ee",
+            Magenta => "#cd00cd",
+            Cyan => "#00cdcd",
+            White => "#e5e5e5",
+            Gray => "#7f7f7f",
+            BrightRed => "#ff0000",
+            BrightGreen => "#00ff00",
+            BrightYellow => "#ffff00",
+            BrightBlue => "#5c5cff",
+            BrightMagenta => "#ff00ff",
+            BrightCyan => "#00ffff",
+            BrightWhite => "#ffffff",
+        })
+    }
+}
+
+impl Color {
+    /// attributes that differ from the default, like `fg=red bold`
+    fn describe(&self) -> String {
+        let mut out = Vec::new();
+        if self.fg != BasicColor::Default {
+            out.push(format!("fg={}", self.fg.name()));
+        }
+        if self.bg != BasicColor::Default {
+            out.push(format!("bg={}", self.bg.name()));
+        }
+        if self.bold {
+            out.push("bold".into());
+        }
+        if self.italic {
+            out.push("italic".into());
+        }
+        out.join(" ")
+    }
+
+    /// CSS for the attributes that differ from the default
+    pub fn style(&self) -> String {
+        let mut out = String::new();
+        if let Some(fg) = self.fg.css() {
+            let _ = write!(out, "color:{fg};");
+        }
+        if let Some(bg) = self.bg.css() {
+            let _ = write!(out, "background:{bg};");
+        }
+        if self.bold {
+            out.push_str("font-weight:bold;");
+        }
+        if self.italic {
+            out.push_str("font-style:italic;");
+        }
+        out
+    }
+}
+
+/// `s` with the characters that mean something in HTML escaped
+pub fn escape_html(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+impl TermGrid {
+    /// runs of cells of the same color in row `y`, as their column range and color
+    fn color_runs(&self, y: u32) -> Vec<(std::ops::Range<u32>, Color)> {
+        let row = &self.cells[self.line_rng(y, ..)];
+        let mut out: Vec<(std::ops::Range<u32>, Color)> = Vec::new();
+        for (x, cell) in row.iter().enumerate() {
+            let x = x as u32;
+            match out.last_mut() {
+                Some((run, color)) if *color == cell.color => run.end = x + 1,
+                _ => out.push((x..x + 1, cell.color)),
+            }
+        }
+        out
+    }
+
+    /// the screen as text with its colors
+    pub fn dump_text(&self) -> String {
+        let mut out = String::new();
+        for y in 0..self.h {
+            let _ = writeln!(out, "{}", self.line_text(y));
+        }
+        let _ = writeln!(out, "--- colors");
+        for y in 0..self.h {
+            for (run, color) in self.color_runs(y) {
+                if color != Color::new() {
+                    let _ = writeln!(out, "{y}:{}-{} {}", run.start, run.end, color.describe());
+                }
+            }
+        }
+        let _ = writeln!(out, "--- cursor {}:{}", self.cursorpos.y, self.cursorpos.x);
+        out
+    }
+
+    /// the screen as an HTML page
+    pub fn dump_html(&self) -> String {
+        let mut out = String::from(
+            "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>screen dump</title></head>\n\
+             <body style=\"background:#000000;color:#e5e5e5\">\n<pre>\n",
+        );
+        for y in 0..self.h {
+            let row = &self.cells[self.line_rng(y, ..)];
+            for (run, color) in self.color_runs(y) {
+                // from the cell before the run, in case a wide character there covers its first cell
+                let text = match run.start {
+                    0 => row_text(&row[..run.end as usize]),
+                    start => row_text(&row[start as usize - 1..run.end as usize]).chars().skip(1).collect(),
+                };
+                let text = escape_html(&text);
+                match color.style() {
+                    style if style.is_empty() => out.push_str(&text),
+                    style => {
+                        let _ = write!(out, "<span style=\"{style}\">{text}</span>");
+                    }
+                }
+            }
+            out.push('\n');
+        }
+        out.push_str("</pre>\n</body>\n</html>\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tui::{TermCell, TermPos};
+
+    fn grid() -> TermGrid {
+        let mut grid = TermGrid::with_size(4, 2);
+        let red = Color { fg: BasicColor::Red, bold: true, ..Color::new() };
+        grid.put_cell(tp!(0, 0), 'a');
+        grid.put_cell(tp!(1, 0), TermCell::colored('<', red));
+        grid.put_cell(tp!(2, 0), TermCell::colored('b', red));
+        grid.set_cursorpos(tp!(1, 1));
+        grid
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(grid().dump_text(), "a<b\n\n--- colors\n0:1-3 fg=red bold\n--- cursor 1:1\n");
+    }
+
+    #[test]
+    fn html() {
+        let html = grid().dump_html();
+        assert!(html.contains("<pre>\na<span style=\"color:#cd0000;font-weight:bold;\">&lt;b</span> \n    \n</pre>"));
+        for c in [BasicColor::Default, BasicColor::Gray, BasicColor::BrightCyan] {
+            assert_eq!(BasicColor::from_name(c.name()), Some(c));
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{debug::log, prelude::*};
+use crate::{debug::log, prelude::*, term::Caps};
 use std::{
     fmt::Write,
     ops::{Range, RangeBounds, RangeInclusive},
@@ -45,6 +45,10 @@ macro_rules! tp {
     };
 }
 
+mod dump;
+
+pub use dump::escape_html;
+
 impl PartialOrd for TermPos {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         Some(self.y.cmp(&other.y).then(self.x.cmp(&other.x)))
@@ -133,6 +137,33 @@ pub enum BasicColor {
     BrightWhite,
 }
 
+impl BasicColor {
+    /// color from its lowercase name, like `red` or `brightred`
+    pub fn from_name(name: &str) -> Option<Self> {
+        use BasicColor::*;
+        Some(match name {
+            "default" => Default,
+            "black" => Black,
+            "red" => Red,
+            "green" => Green,
+            "yellow" => Yellow,
+            "blue" => Blue,
+            "magenta" => Magenta,
+            "cyan" => Cyan,
+            "white" => White,
+            "gray" | "grey" => Gray,
+            "brightred" => BrightRed,
+            "brightgreen" => BrightGreen,
+            "brightyellow" => BrightYellow,
+            "brightblue" => BrightBlue,
+            "brightmagenta" => BrightMagenta,
+            "brightcyan" => BrightCyan,
+            "brightwhite" => BrightWhite,
+            _ => return None,
+        })
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum TextSeverity {
     #[default]
@@ -142,6 +173,15 @@ pub enum TextSeverity {
 }
 
 impl TextSeverity {
+    /// letter shown in the sign column
+    pub const fn sign(&self) -> char {
+        match self {
+            TextSeverity::Error => 'E',
+            TextSeverity::Warning => 'W',
+            TextSeverity::Normal => 'I',
+        }
+    }
+
     pub const fn color(&self) -> Color {
         let fg = match self {
             TextSeverity::Normal => BasicColor::Default,
@@ -155,6 +195,7 @@ impl TextSeverity {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Color {
     pub bold: bool,
+    pub italic: bool,
     pub fg: BasicColor,
     pub bg: BasicColor,
 }
@@ -163,6 +204,7 @@ impl Color {
     pub const fn new() -> Self {
         Self {
             bold: false,
+            italic: false,
             fg: BasicColor::Default,
             bg: BasicColor::Default,
         }
@@ -219,6 +261,51 @@ impl Color {
             22
         }
     }
+
+    const fn italic(&self) -> u8 {
+        if self.italic {
+            3
+        } else {
+            23
+        }
+    }
+
+    /// write the escape sequence that changes the attributes from `prev` to `self`, leaving out
+    /// anything the terminal can't do
+    fn write_change(&self, prev: &Color, caps: &Caps, dest: &mut Vec<u8>) -> std::io::Result<()> {
+        use std::io::Write;
+        // without the bright colors, bright red is drawn as red and so on
+        let dim = |code: u8| if caps.colors < 16 && code >= 90 { code - 60 } else { code };
+        let mut codes = Vec::with_capacity(4);
+        if self.bold != prev.bold {
+            codes.push(self.bold());
+        }
+        if self.italic != prev.italic && caps.italics {
+            codes.push(self.italic());
+        }
+        if self.fg != prev.fg && caps.colors > 0 {
+            codes.push(dim(self.fg()));
+        }
+        if self.bg != prev.bg && caps.colors > 0 {
+            codes.push(dim(self.bg()));
+        }
+        let Some((first, rest)) = codes.split_first() else {
+            return Ok(());
+        };
+        write!(dest, "\x1b[{first}")?;
+        for code in rest {
+            write!(dest, ";{code}")?;
+        }
+        write!(dest, "m")
+    }
+
+    /// the escape sequence that changes the attributes from `prev` to `self`, for text that's
+    /// shown on a terminal we don't know
+    pub fn sgr(&self, prev: &Color) -> String {
+        let mut out = Vec::new();
+        let _ = self.write_change(prev, &Caps::XTERM, &mut out);
+        String::from_utf8(out).expect("escape sequences are ascii")
+    }
 }
 
 impl Default for Color {
@@ -227,7 +314,7 @@ impl Default for Color {
     }
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct TermCell {
     color: Color,
     content: Option<char>,
@@ -240,6 +327,37 @@ impl TermCell {
             content: None,
         }
     }
+
+    pub const fn colored(content: char, color: Color) -> Self {
+        Self {
+            color,
+            content: Some(content),
+        }
+    }
+
+    pub const fn content(&self) -> Option<char> {
+        self.content
+    }
+
+    /// whether the character takes this cell and the next one, which is then left blank
+    fn is_wide(&self) -> bool {
+        self.content.is_some_and(|c| unicode_width::UnicodeWidthChar::width(c) == Some(2))
+    }
+}
+
+/// the text of a row of cells as the terminal shows it, without the cells wide characters cover
+fn row_text(row: &[TermCell]) -> String {
+    let mut covered = false;
+    let mut out = String::with_capacity(row.len());
+    for cell in row {
+        if covered {
+            covered = false;
+            continue;
+        }
+        covered = cell.is_wide();
+        out.push(cell.content.unwrap_or(' '));
+    }
+    out
 }
 
 impl Default for TermCell {
@@ -257,11 +375,26 @@ impl From<char> for TermCell {
     }
 }
 
+/// what drew a cell, for looking at how the screen is laid out, see [`TermGrid::record_owners`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Owner {
+    Window(crate::window::WinId),
+    CommandLine,
+    /// a popup, including the key hints
+    Popup,
+}
+
 pub struct TermGrid {
     w: u32,
     h: u32,
     cells: Vec<TermCell>,
     cursorpos: TermPos,
+    /// the size of a grid made with [`TermGrid::with_size`], which doesn't follow the terminal
+    fixed: Option<TermSz>,
+    /// what draws the cells that are written from now on
+    painter: Option<Owner>,
+    /// what drew each cell, only kept after [`TermGrid::record_owners`]
+    owners: Option<Vec<Option<Owner>>>,
 }
 
 impl std::ops::Index<TermPos> for TermGrid {
@@ -291,11 +424,29 @@ impl TermGrid {
             h: 0,
             cells: Vec::new(),
             cursorpos: tp!(0, 0),
+            fixed: None,
+            painter: None,
+            owners: None,
         };
         out.resize_auto();
         out
     }
 
+    /// create a grid that is not tied to the size of the terminal
+    pub fn with_size(w: u32, h: u32) -> Self {
+        let mut out = Self {
+            w: 0,
+            h: 0,
+            cells: Vec::new(),
+            cursorpos: tp!(0, 0),
+            fixed: Some(TermSz::new(w, h)),
+            painter: None,
+            owners: None,
+        };
+        out.resize(w, h);
+        out
+    }
+
     pub fn bounds(&self) -> TermBox {
         TermBox::from_ranges(0..self.w, 0..self.h)
     }
@@ -312,6 +463,33 @@ impl TermGrid {
         let tcell = c.into();
         assert_ne!(tcell.content, Some('\n'));
         self[pos] = tcell;
+        let i = (self.w * pos.y + pos.x) as usize;
+        self.paint(i..i + 1);
+    }
+
+    /// draw the cells written from now on as `owner`
+    pub fn set_painter(&mut self, owner: Option<Owner>) {
+        self.painter = owner;
+    }
+
+    /// keep track of what draws each cell from now on, see [`TermGrid::owner`]
+    #[cfg(test)]
+    pub fn record_owners(&mut self) {
+        self.owners = Some(vec![None; self.cells.len()]);
+    }
+
+    /// what last drew the cell at `pos`, `None` if it was cleared or isn't recorded
+    #[cfg(test)]
+    pub fn owner(&self, pos: TermPos) -> Option<Owner> {
+        assert!(pos.x < self.w && pos.y < self.h);
+        self.owners.as_ref()?[(self.w * pos.y + pos.x) as usize]
+    }
+
+    /// the cells in `rng` were written by the painter
+    fn paint(&mut self, rng: Range<usize>) {
+        if let Some(owners) = &mut self.owners {
+            owners[rng].fill(self.painter);
+        }
     }
 
     /// resize the grid to given dimensions, returns true if resize occured;
@@ -321,13 +499,30 @@ impl TermGrid {
         }
         self.clear();
         self.cells.resize_with((w * h) as usize, || TermCell::new());
+        if let Some(owners) = &mut self.owners {
+            owners.resize((w * h) as usize, None);
+        }
         self.w = w;
         self.h = h;
+        if self.fixed.is_some() {
+            self.fixed = Some(TermSz::new(w, h));
+        }
         true
     }
 
+    /// change the size of a fixed grid on the next [`TermGrid::resize_auto`], like the terminal
+    /// does when its window is resized
+    #[cfg(test)]
+    pub fn resize_later(&mut self, w: u32, h: u32) {
+        assert!(self.fixed.is_some(), "only fixed grids can be resized later");
+        self.fixed = Some(TermSz::new(w, h));
+    }
+
     /// resize the grid to fit the terminal, returns true if resize occurred.
     pub fn resize_auto(&mut self) -> bool {
+        if let Some(TermSz { w, h }) = self.fixed {
+            return self.resize(w, h);
+        }
         let (w, h) =
             terminal_size::terminal_size().map_or((80, 40), |(w, h)| (w.0 as u32, h.0 as u32));
         self.resize(w, h)
@@ -335,6 +530,30 @@ impl TermGrid {
 
     pub fn clear(&mut self) {
         self.cells.fill(TermCell::new());
+        if let Some(owners) = &mut self.owners {
+            owners.fill(None);
+        }
+    }
+
+    /// move every line up by one. The top line is lost and the bottom line is blank.
+    pub fn scroll_up(&mut self) {
+        let w = self.w as usize;
+        if w == 0 || self.cells.is_empty() {
+            return;
+        }
+        self.cells.rotate_left(w);
+        let len = self.cells.len();
+        self.cells[(len - w)..].fill(TermCell::new());
+        if let Some(owners) = &mut self.owners {
+            owners.rotate_left(w);
+            owners[(len - w)..].fill(None);
+        }
+    }
+
+    /// text of a line with trailing blanks removed
+    pub fn line_text(&self, y: u32) -> String {
+        let rng = self.line_rng(y, ..);
+        row_text(&self.cells[rng]).trim_end().to_owned()
     }
 
     fn line_rng(&self, y: u32, xrng: impl RangeBounds<u32>) -> Range<usize> {
@@ -348,7 +567,8 @@ impl TermGrid {
     pub fn clear_bounds(&mut self, bounds: TermBox) {
         for y in bounds.yrng() {
             let rng = self.line_rng(y, bounds.xrng());
-            self.cells[rng].fill(TermCell::new());
+            self.cells[rng.clone()].fill(TermCell::new());
+            self.paint(rng);
         }
     }
 
@@ -379,6 +599,8 @@ impl TermGrid {
         start..end
     }
 
+    /// write `content` to row `y` from the start of `xrng`, blanking the rest of it. Wide
+    /// characters take two cells, or a blank one if only one is left. Returns the cells written.
     pub fn write_line(
         &mut self,
         y: u32,
@@ -386,27 +608,32 @@ impl TermGrid {
         color: Color,
         content: &str,
     ) -> usize {
-        let mut cnt = 0;
         let xrng = self.normalize_xrng(xrng);
         let mut last = xrng.start;
-        for (c, x) in content.chars().zip(xrng.clone()) {
-            last = x;
-            if c == '\n' {
+        for c in content.chars() {
+            if c == '\n' || last >= xrng.end {
+                break;
+            }
+            let cell = TermCell { color, content: Some(c) };
+            if !cell.is_wide() {
+                self.put_cell(tp!(last, y), cell);
+                last += 1;
+            } else if last + 1 < xrng.end {
+                self.put_cell(tp!(last, y), cell);
+                self.put_cell(tp!(last + 1, y), TermCell { color, content: None });
+                last += 2;
+            } else {
+                // half of it would be cut off
+                self.put_cell(tp!(last, y), TermCell { color, content: Some(' ') });
+                last += 1;
                 break;
             }
-            self.put_cell(
-                tp!(x, y),
-                TermCell {
-                    color,
-                    content: Some(c),
-                },
-            );
-            last += 1;
-            cnt += 1;
         }
+        let cnt = (last - xrng.start) as usize;
         let rng = self.line_rng(y, last..xrng.end);
         // log!("{content:?} => {} - {}", xrng.len() , rng.len());
-        self.cells[rng].fill(TermCell::new());
+        self.cells[rng.clone()].fill(TermCell::new());
+        self.paint(rng);
         cnt
     }
 
@@ -427,50 +654,47 @@ impl TermGrid {
     }
 
     pub fn render(&self, dest: &mut impl std::io::Write) -> std::io::Result<()> {
+        self.render_with(dest, crate::term::caps())
+    }
+
+    /// render for a terminal with capabilities `caps`
+    pub fn render_with(&self, dest: &mut impl std::io::Write, caps: &Caps) -> std::io::Result<()> {
         use std::io::Write;
         let mut render_buf = Vec::<u8>::with_capacity(self.cells.len() * 3);
         let mut curr = Color::new();
 
         // hide the cursor and go to first cell
-        write!(render_buf, "\x1b[25l\x1b[1;1H")?;
+        write!(render_buf, "{}\x1b[1;1H", caps.hide_cursor())?;
+        let mut covered = false;
         for (i, cell) in self.cells.iter().enumerate() {
             if i as u32 % self.w == 0 && i != 0 {
                 // it might help with render issues to have one of these lines
                 // write!(dest, "\n\x1b[1G")?;
                 // write!(dest, "\n\r")?;
+                covered = false;
+            }
+            // the terminal already moved past the second cell of a wide character
+            if covered {
+                covered = false;
+                continue;
             }
+            covered = cell.is_wide();
             let Some(content) = cell.content else {
-                if curr.bg != BasicColor::Default {
-                    curr.bg = BasicColor::Default;
-                    write!(render_buf, "\x1b[{}m ", curr.bg())?;
-                } else {
-                    write!(render_buf, " ")?;
-                }
+                let blank = Color {
+                    bg: BasicColor::Default,
+                    ..curr
+                };
+                blank.write_change(&curr, caps, &mut render_buf)?;
+                curr = blank;
+                write!(render_buf, " ")?;
                 continue;
             };
-            let color = cell.color;
-            match (
-                color.fg == curr.fg,
-                color.bg == curr.bg,
-                color.bold == curr.bold,
-            ) {
-                (true, true, true) => (),
-                (true, true, false) => write!(render_buf, "\x1b[{}m", color.bold())?,
-                (false, true, true) => write!(render_buf, "\x1b[{}m", color.fg())?,
-                (true, false, true) => write!(render_buf, "\x1b[{}m", color.bg())?,
-                _ => write!(
-                    render_buf,
-                    "\x1b[{};{};{}m",
-                    color.fg(),
-                    color.bg(),
-                    color.bold()
-                )?,
-            }
-            curr = color;
+            cell.color.write_change(&curr, caps, &mut render_buf)?;
+            curr = cell.color;
             write!(render_buf, "{}", content)?;
         }
         // show the cursor and go to expected cursor position
-        write!(render_buf, "\x1b[25h")?;
+        write!(render_buf, "{}", caps.show_cursor())?;
         // write!(dest, "X")?;
         write!(
             render_buf,
@@ -505,6 +729,10 @@ impl TermGrid {
         }
     }
 
+    pub fn cursorpos(&self) -> TermPos {
+        self.cursorpos
+    }
+
     pub fn set_cursorpos(&mut self, pos: TermPos) {
         assert!(
             pos.x < self.w,
@@ -561,7 +789,8 @@ impl Write for TermGridBox<'_> {
             }
             if c == '\n' {
                 let rng = self.grid.line_rng(y, x..self.range.end.x);
-                self.grid.cells[rng].fill(TermCell::new());
+                self.grid.cells[rng.clone()].fill(TermCell::new());
+                self.grid.paint(rng);
                 x = self.range.start.x;
                 self.cursor.x = 0;
                 y += 1;
@@ -620,4 +849,39 @@ mod test {
         assert!(h.is_subset_of(&h));
         assert!(i.is_subset_of(&i));
     }
+
+    #[test]
+    fn render_for_caps() {
+        let mut grid = TermGrid::with_size(3, 1);
+        let red = Color {
+            fg: BasicColor::BrightRed,
+            italic: true,
+            ..Color::new()
+        };
+        grid.put_cell(tp!(0, 0), TermCell::colored('a', red));
+        grid.put_cell(tp!(1, 0), TermCell::colored('b', Color::new()));
+        let render = |caps: &Caps| {
+            let mut out = Vec::new();
+            grid.render_with(&mut out, caps).unwrap();
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(render(&Caps::XTERM), "\x1b[?25l\x1b[1;1H\x1b[3;91ma\x1b[23;39mb \x1b[?25h\x1b[1;1H");
+        let linux = Caps { colors: 8, italics: false, ..Caps::XTERM };
+        assert_eq!(render(&linux), "\x1b[?25l\x1b[1;1H\x1b[31ma\x1b[39mb \x1b[?25h\x1b[1;1H");
+        assert_eq!(render(&Caps::DUMB), "\x1b[1;1Hab \x1b[1;1H");
+    }
+
+    #[test]
+    fn wide_characters() {
+        let mut grid = TermGrid::with_size(5, 2);
+        assert_eq!(grid.write_line(0, .., Color::new(), "a漢b"), 4);
+        assert_eq!(grid[tp!(3, 0)].content(), Some('b'));
+        assert_eq!(grid.line_text(0), "a漢b");
+        // no room for the second half
+        assert_eq!(grid.write_line(1, 2..4, Color::new(), "x漢"), 2);
+        assert_eq!(grid.line_text(1), "  x");
+        let mut out = Vec::new();
+        grid.render_with(&mut out, &Caps::DUMB).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(), "\x1b[1;1Ha漢b   x  \x1b[1;1H");
+    }
 }
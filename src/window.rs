@@ -1,15 +1,21 @@
 mod components;
 pub use components::*;
 pub mod org;
+mod popup;
+pub use popup::*;
 
 use crate::debug::{log, sleep};
 use crate::prelude::*;
 use crate::render::BufId;
 use crate::tui::{TermBox, TermSz};
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Write;
+use std::path::PathBuf;
 use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::buffer::{Buffer, DocPos};
+use crate::diff::{Row, RowKind};
+use crate::options::WinOptions;
 use crate::render::Ctx;
 use crate::term;
 use crate::term::TermPos;
@@ -17,6 +23,104 @@ use crate::term::TermPos;
 use terminal_size::terminal_size;
 use unicode_truncate::UnicodeTruncateStr;
 
+/// rows past the end of the buffer
+const EOB_COLOR: Color = Color { fg: BasicColor::Gray, ..Color::new() };
+
+/// the start of `line` that can be in the first `width` columns, since every character takes at
+/// least one. Lines are neither wrapped nor scrolled sideways, so drawing never needs more of a
+/// line than this, however long it is.
+fn visible(line: &str, width: usize) -> &str {
+    line.char_indices().nth(width).map_or(line, |(i, _)| &line[..i])
+}
+
+/// options for scrolling a window, copied from [`crate::options::Options`] by [`Ctx`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScrollOptions {
+    /// the view can scroll until the last line is at the top
+    pub past_end: bool,
+}
+
+impl Default for ScrollOptions {
+    fn default() -> Self {
+        ScrollOptions { past_end: true }
+    }
+}
+
+/// where the cursor can go past the end of a line, from the `virtualedit` option
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum VirtualEdit {
+    /// only on characters, except in insert mode
+    #[default]
+    None,
+    /// also just after the last character
+    OneMore,
+    /// any column, edits there pad the line with spaces first
+    All,
+}
+
+/// columns `c` takes when it's drawn from column `col`: a tab goes on to the next multiple of
+/// `tabstop`, wide characters take two and everything else one
+fn char_cols(c: char, col: usize, tabstop: usize) -> usize {
+    let tabstop = tabstop.max(1);
+    match c {
+        '\t' => tabstop - col % tabstop,
+        c => unicode_width::UnicodeWidthChar::width(c).unwrap_or(1).clamp(1, 2),
+    }
+}
+
+/// the display column of the character at byte offset `x` of `line`
+pub fn x_to_col(line: &str, x: usize, tabstop: usize) -> usize {
+    line.char_indices()
+        .take_while(|&(i, _)| i < x)
+        .fold(0, |col, (_, c)| col + char_cols(c, col, tabstop))
+}
+
+/// the byte offset of the character of `line` that covers display column `col`, and how many
+/// columns past the end of the line `col` is, the opposite of [`x_to_col`]
+fn col_to_x(line: &str, col: usize, tabstop: usize) -> (usize, usize) {
+    let mut start = 0;
+    for (i, c) in line.char_indices() {
+        let end = start + char_cols(c, start, tabstop);
+        if col < end {
+            return (i, 0);
+        }
+        start = end;
+    }
+    (line.len(), col - start)
+}
+
+/// `text` as it's drawn from column `col`, with its tabs turned into spaces
+fn expand(text: &str, col: usize, tabstop: usize) -> std::borrow::Cow<'_, str> {
+    if !text.contains('\t') {
+        return text.into();
+    }
+    let mut out = String::with_capacity(text.len());
+    let mut col = col;
+    for c in text.chars() {
+        let w = char_cols(c, col, tabstop);
+        match c {
+            '\t' => out.extend(std::iter::repeat_n(' ', w)),
+            c => out.push(c),
+        }
+        col += w;
+    }
+    out.into()
+}
+
+impl VirtualEdit {
+    /// from the comma separated flags of the option
+    pub fn from_flags(flags: &str) -> Self {
+        let has = |flag: &str| flags.split(',').any(|f| f == flag);
+        if has("all") {
+            VirtualEdit::All
+        } else if has("onemore") {
+            VirtualEdit::OneMore
+        } else {
+            VirtualEdit::None
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Padding {
     top: u32,
@@ -37,8 +141,41 @@ impl Padding {
     const fn h(&self) -> u32 {
         self.top + self.bottom
     }
+
+    /// leave at least a column for the text of a window `w` wide, taking from the left first,
+    /// where the line numbers are
+    fn fit(&mut self, w: u32) {
+        let room = w.saturating_sub(1);
+        self.right = self.right.min(room);
+        self.left = self.left.min(room - self.right);
+    }
+
+    /// the padding all of `components` need together
+    fn of(components: &[Component], options: &WinOptions, lines: usize) -> Self {
+        components.iter().fold(
+            Padding {
+                top: 0,
+                bottom: 0,
+                left: 0,
+                right: 0,
+            },
+            |acc, x| {
+                let pad = x.padding(options, lines);
+                Padding {
+                    top: acc.top + pad.top,
+                    bottom: acc.bottom + pad.bottom,
+                    left: acc.left + pad.left,
+                    right: acc.right + pad.right,
+                }
+            },
+        )
+    }
 }
 
+/// identifies a window for as long as the editor runs
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WinId(u64);
+
 /// A Window. Equality is done via pointer equality
 pub struct Window {
     inner: RwLock<WindowInner>
@@ -54,40 +191,34 @@ impl Window {
     }
 
     pub fn new(bounds: TermBox, buffer: Arc<Buffer>) -> Arc<Self> {
-        let components = vec![Component::RelLineNumbers];
+        let components = vec![Component::LineNumbers];
         Self::new_withdim(bounds.start, bounds.sz().w, bounds.sz().h, components, buffer)
     }
 
+    /// a window with `components` showing `buffer`, to be split off from this one. It starts out
+    /// in this one's place, with its working directory and window-local options.
+    pub fn split_off(&self, components: Vec<Component>, buffer: Arc<Buffer>) -> Arc<Self> {
+        let inner = self.get();
+        let bounds = inner.outer_bounds();
+        let new = Self::new_withdim(bounds.start, bounds.xlen(), bounds.ylen(), components, buffer);
+        new.get_mut().cwd = inner.cwd.clone();
+        new.get_mut().set_options(inner.options.clone());
+        new
+    }
+
     pub fn new_withdim(
         topleft: TermPos,
         width: u32,
         height: u32,
-        mut components: Vec<Component>,
+        components: Vec<Component>,
         buffer: Arc<Buffer>,
     ) -> Arc<Self> {
-        let dirty = true;
-        if !dirty {
-            components.push(Component::Welcome);
-        }
-
-        let padding = components.iter().fold(
-            Padding {
-                top: 0,
-                bottom: 0,
-                left: 0,
-                right: 0,
-            },
-            |acc, x| {
-                let pad = x.padding();
-                Padding {
-                    top: acc.top + pad.top,
-                    bottom: acc.bottom + pad.bottom,
-                    left: acc.left + pad.left,
-                    right: acc.right + pad.right,
-                }
-            },
-        );
+        let options = WinOptions::default();
+        static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+        let id = WinId(NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed));
+        let padding = Padding::of(&components, &options, 0);
         let out = WindowInner {
+            id,
             bounds: TermBox {
                 start: TermPos {
                     x: topleft.x + padding.left,
@@ -100,9 +231,15 @@ impl Window {
             },
             components,
             padding,
-            dirty,
             next: None,
             prev: None,
+            cwd: None,
+            scroll: ScrollOptions::default(),
+            options,
+            virtualedit: VirtualEdit::default(),
+            tabstop: 8,
+            lines: 0,
+            views: HashMap::new(),
             buffer,
         };
         out.bounds.assert_valid();
@@ -127,21 +264,115 @@ impl From<WindowInner> for Window {
 
 
 pub struct WindowInner {
+    id: WinId,
     pub buffer: Arc<Buffer>,
     pub next: Option<Arc<Window>>,
     pub prev: Option<Arc<Window>>,
+    /// working directory of just this window, set with `:lcd`
+    pub cwd: Option<PathBuf>,
+    pub scroll: ScrollOptions,
+    /// options local to this window, see [`WinOptions`]
+    pub options: WinOptions,
+    pub virtualedit: VirtualEdit,
+    /// columns a tab goes on to the next multiple of
+    pub tabstop: usize,
     bounds: TermBox,
     components: Vec<Component>,
     padding: Padding,
-    dirty: bool,
+    /// line count of the buffer the padding was made for, see [`WindowInner::fit_lines`]
+    lines: usize,
+    /// where the cursor and view were in each buffer when the window last left it
+    views: HashMap<BufId, Cursor>,
 }
 
 impl WindowInner {
+    pub fn id(&self) -> WinId {
+        self.id
+    }
+
+    /// add a component after the window was made, making room for its padding
+    pub fn add_component(&mut self, component: Component) {
+        let pad = component.padding(&self.options, self.lines);
+        self.bounds.start.x += pad.left;
+        self.bounds.start.y += pad.top;
+        self.bounds.end.x -= pad.right;
+        self.bounds.end.y -= pad.bottom;
+        self.padding = Padding {
+            top: self.padding.top + pad.top,
+            bottom: self.padding.bottom + pad.bottom,
+            left: self.padding.left + pad.left,
+            right: self.padding.right + pad.right,
+        };
+        self.components.push(component);
+    }
+
+    /// apply a `:setlocal` argument, making room for the components again if their padding changed
+    pub fn set_option(&mut self, arg: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.options.set(arg)?;
+        self.relayout();
+        Ok(())
+    }
+
+    /// replace all window-local options, like when a window is split off from another
+    pub fn set_options(&mut self, options: WinOptions) {
+        self.options = options;
+        self.relayout();
+    }
+
+    /// remember the cursor of the buffer shown, for [`WindowInner::restore_view`]
+    pub fn save_view(&mut self) {
+        let cursor = self.buffer.get().cursor;
+        self.views.insert(self.buffer.id(), cursor);
+    }
+
+    /// forget where the cursor was in buffer `id`, once it's deleted
+    pub fn forget_view(&mut self, id: BufId) {
+        self.views.remove(&id);
+    }
+
+    /// put the cursor of the buffer shown back where it was when this window last left it, as far
+    /// as the buffer still goes. A buffer the window hasn't shown before keeps its cursor.
+    pub fn restore_view(&self) {
+        let Some(mut cursor) = self.views.get(&self.buffer.id()).copied() else {
+            return;
+        };
+        let mut buf = self.buffer.get_mut();
+        cursor.pos.y = cursor.pos.y.min(buf.linecnt().saturating_sub(1));
+        // the line may have changed, back to the start of the character the cursor is in
+        let line = buf.line(cursor.pos.y);
+        cursor.pos.x = (0..=cursor.pos.x.min(line.len())).rev().find(|&x| line.is_char_boundary(x)).unwrap_or(0);
+        cursor.topline = cursor.topline.min(cursor.pos.y);
+        let linecnt = buf.linecnt();
+        self.fit_ctx_frame(&mut cursor, linecnt);
+        buf.cursor = cursor;
+    }
+
+    /// make room for the line numbers of a buffer of `lines` lines, which is done before every
+    /// draw so that the number column grows and shrinks with the buffer
+    pub fn fit_lines(&mut self, lines: usize) {
+        let changed = LineNumbers::width(&self.options, lines) != LineNumbers::width(&self.options, self.lines);
+        self.lines = lines;
+        if changed {
+            self.relayout();
+        }
+    }
+
+    /// recompute the padding of the components, keeping the outer bounds
+    fn relayout(&mut self) {
+        let outer = self.outer_bounds();
+        self.set_bounds_outer(outer);
+    }
 
     pub fn inner_bounds(&self) -> TermBox {
         self.bounds
     }
 
+    /// the smallest outer size that leaves a cell for the text
+    pub fn min_size(&self) -> TermSz {
+        let pad = self.padding.sz();
+        TermSz::new(pad.w + 1, pad.h + 1)
+    }
+
     pub fn outer_bounds(&self) -> TermBox {
         let start = TermPos {
             x: self.bounds.start.x - self.padding.left,
@@ -154,7 +385,10 @@ impl WindowInner {
         TermBox { start, end }
     }
 
+    /// place the window at `bounds`, with its components as wide as there's room for
     pub fn set_bounds_outer(&mut self, bounds: TermBox) {
+        self.padding = Padding::of(&self.components, &self.options, self.lines);
+        self.padding.fit(bounds.xlen());
         let start = TermPos {
             x: bounds.start.x + self.padding.left,
             y: bounds.start.y + self.padding.top,
@@ -173,11 +407,8 @@ impl WindowInner {
 
     /// do not use directly - should be through window org
     pub fn set_size_outer(&mut self, w: u32, h: u32) {
-        let w = w - self.padding.left - self.padding.right;
-        let h = h - self.padding.top - self.padding.bottom;
-        self.bounds.end.x = self.bounds.start.x + w;
-        self.bounds.end.y = self.bounds.start.y + h;
-        self.bounds.assert_valid();
+        let start = self.outer_bounds().start;
+        self.set_bounds_outer(TermBox { start, end: TermPos { x: start.x + w, y: start.y + h } });
     }
 
     /// do not use directly - should be through window org
@@ -233,7 +464,83 @@ impl WindowInner {
     }
 
     pub fn draw(&self, ctx: &Ctx) {
-        self.draw_buf_colored(ctx, &self.buffer.get(), Color::default());
+        match ctx.preview().filter(|p| Arc::ptr_eq(&p.target, &self.buffer)) {
+            Some(preview) => {
+                self.draw_buf(ctx, &preview.buf);
+                self.draw_extmarks(ctx, &preview.buf);
+            }
+            None => {
+                let buf = self.buffer.get();
+                self.draw_buf_colored(ctx, &buf, Color::default());
+                self.draw_syntax(ctx, &buf);
+                self.draw_extmarks(ctx, &buf);
+            }
+        }
+    }
+
+    /// recolor the lines of `buf` in the window with the rules of [`crate::syntax`]
+    fn draw_syntax(&self, ctx: &Ctx, buf: &BufferInner) {
+        let Some(filetype) = buf.path().and_then(crate::syntax::filetype) else {
+            return;
+        };
+        if buf.has_long_lines() {
+            return;
+        }
+        let rows = self.rows(buf, ctx);
+        let mut tui = ctx.tui.borrow_mut();
+        for (y, row) in rows.iter().enumerate() {
+            // the colors of diff rows stay
+            let Some(l) = row.line.filter(|_| row.kind.color().is_none()) else {
+                continue;
+            };
+            let y = y as u32 + self.bounds.start.y;
+            let line = visible(buf.line(l), self.width() as usize);
+            for (range, color) in crate::syntax::highlight(&filetype, line) {
+                self.write_part(&mut tui, y, line, range, color);
+            }
+        }
+    }
+
+    /// highlights and virtual text of the extmarks of `buf`
+    fn draw_extmarks(&self, ctx: &Ctx, buf: &BufferInner) {
+        for (_, mark) in buf.extmarks().iter() {
+            if let Some(color) = mark.highlight {
+                let range = buf.offsets_to_range(mark.range.clone());
+                self.draw_highlights(ctx, buf, &[range], color);
+            }
+        }
+        self.draw_virt_text(ctx, buf);
+    }
+
+    /// virtual text of extmarks after the end of their lines, cut off at the edge of the window
+    fn draw_virt_text(&self, ctx: &Ctx, buf: &BufferInner) {
+        let mut texts: BTreeMap<usize, Vec<_>> = BTreeMap::new();
+        for (_, mark) in buf.extmarks().iter() {
+            if let Some(virt) = &mark.virt_text {
+                texts.entry(buf.offset_to_pos(mark.range.start).y).or_default().push(virt);
+            }
+        }
+        if texts.is_empty() {
+            return;
+        }
+        let rows = self.rows(buf, ctx);
+        let mut tui = ctx.tui.borrow_mut();
+        let xend = self.bounds.end.x;
+        for (y, row) in rows.iter().enumerate() {
+            let Some(virts) = row.line.and_then(|l| texts.get(&l)) else {
+                continue;
+            };
+            let y = y as u32 + self.bounds.start.y;
+            // one column of space after the text
+            let line = visible(buf.line(row.line.unwrap()), self.width() as usize);
+            let mut x = self.bounds.start.x + x_to_col(line, line.len(), self.tabstop) as u32 + 1;
+            for (text, color) in virts {
+                if x >= xend {
+                    break;
+                }
+                x += tui.write_line(y, x..xend, *color, text) as u32 + 1;
+            }
+        }
     }
 
     pub fn draw_colored(&self, ctx: &Ctx, color: Color) {
@@ -244,57 +551,130 @@ impl WindowInner {
         self.draw_buf_colored(ctx, buf, Color::default());
     }
 
+    /// what each row of the window shows. Usually these are consecutive lines from the top line,
+    /// but a window in diff mode also has filler rows.
+    pub fn rows(&self, buf: &BufferInner, ctx: &Ctx) -> Vec<Row> {
+        let h = self.height() as usize;
+        let past_end = Row { line: None, kind: RowKind::Same };
+        if let Some((diff, side)) = ctx.diff().and_then(|d| Some((d, d.side_of(&self.buffer)?))) {
+            return diff
+                .rows(side)
+                .into_iter()
+                .skip(diff.top)
+                .chain(std::iter::repeat(past_end))
+                .take(h)
+                .collect();
+        }
+        (buf.cursor.topline..)
+            .take(h)
+            .map(|line| Row {
+                // an empty buffer still has an empty first line, not a fillchar
+                line: (line < buf.linecnt().max(1)).then_some(line),
+                kind: RowKind::Same,
+            })
+            .collect()
+    }
+
     fn draw_buf_colored(&self, ctx: &Ctx, buf: &BufferInner, color: Color) {
+        let rows = self.rows(buf, ctx);
         {
             let mut tui = ctx.tui.borrow_mut();
             debug_assert!(self.outer_bounds().is_subset_of(&tui.bounds()));
-            let range = buf.cursor.topline
-                ..(buf.cursor.topline + self.height() as usize).min(buf.linecnt());
-            for (y, line) in buf
-                .get_lines(range.clone())
-                .into_iter()
-                .chain(std::iter::repeat(""))
-                .take(self.height() as usize)
-                .enumerate()
-            {
-                tui.write_line(
-                    y as u32 + self.bounds.start.y,
-                    self.bounds.xrng(),
-                    color,
-                    line,
-                );
+            let w = self.width() as usize;
+            for (y, row) in rows.iter().enumerate() {
+                let y = y as u32 + self.bounds.start.y;
+                let line = visible(row.line.map_or("", |l| buf.line(l)), w);
+                let line = &*expand(line, 0, self.tabstop);
+                match row.kind.color() {
+                    None if row.line.is_none() => {
+                        let eob = ctx.options.fillchar("eob").to_string();
+                        tui.write_line(y, self.bounds.xrng(), EOB_COLOR, &eob)
+                    }
+                    None => tui.write_line(y, self.bounds.xrng(), color, line),
+                    Some(c) if row.kind == RowKind::Filler => {
+                        let fill = ctx.options.fillchar("diff").to_string();
+                        tui.write_line(y, self.bounds.xrng(), c, &fill.repeat(w))
+                    }
+                    // pad so the whole row is highlighted
+                    Some(c) => tui.write_line(y, self.bounds.xrng(), c, &format!("{line:w$}")),
+                };
             }
         }
         self.components.iter().for_each(|x| x.draw(self, &buf, ctx));
     }
 
+    /// recolor the parts of `buf` covered by `ranges`, after it was drawn
+    fn draw_highlights(&self, ctx: &Ctx, buf: &BufferInner, ranges: &[DocRange], color: Color) {
+        let rows = self.rows(buf, ctx);
+        let mut tui = ctx.tui.borrow_mut();
+        for (y, row) in rows.iter().enumerate() {
+            let Some(l) = row.line else {
+                continue;
+            };
+            let y = y as u32 + self.bounds.start.y;
+            let line = visible(buf.line(l), self.width() as usize);
+            for r in ranges.iter().filter(|r| r.start.y <= l && l <= r.end.y) {
+                let start = if r.start.y == l { r.start.x.min(line.len()) } else { 0 };
+                let end = if r.end.y == l { r.end.x.min(line.len()) } else { line.len() };
+                self.write_part(&mut tui, y, line, start..end.max(start), color);
+            }
+        }
+    }
+
+    /// redraw the bytes `range` of `line`, which is on row `y`, in `color`
+    fn write_part(&self, tui: &mut TermGrid, y: u32, line: &str, range: std::ops::Range<usize>, color: Color) {
+        let col = x_to_col(line, range.start, self.tabstop);
+        let text = expand(&line[range], col, self.tabstop);
+        let x = self.bounds.start.x + col as u32;
+        let cols: usize = text.chars().map(|c| char_cols(c, 0, 1)).sum();
+        if x < self.bounds.end.x {
+            tui.write_line(y, x..(x + cols as u32).min(self.bounds.end.x), color, &text);
+        }
+    }
+
     pub fn draw_cursor(&self, tui: &mut TermGrid) {
         debug_assert!(self.outer_bounds().is_subset_of(&tui.bounds()));
-        self.buffer.get().cursor.draw(self, tui)
+        let buf = self.buffer.get();
+        buf.cursor.draw(self, buf.line(buf.cursor.pos.y), tui)
     }
 
     pub fn move_cursor(&mut self, dx: isize, dy: isize) {
+        let ve = self.virtualedit;
         let mut buf = self.buffer.get_mut();
-        let newy = buf
-            .cursor
-            .pos
-            .y
-            .saturating_add_signed(dy)
-            .clamp(0, buf.linecnt().saturating_sub(1));
+        let cursor = buf.cursor;
+        // horizontal moves go by whole characters, and by columns past the end of the line.
+        // Vertical moves keep the display column that moving sideways went to, so that tabs and
+        // wide characters don't shift it.
+        let ts = self.tabstop;
+        let (chars, want) = {
+            let line = buf.line(cursor.pos.y);
+            let chars = line[..cursor.pos.x.min(line.len())].chars().count() + cursor.coladd;
+            (chars, cursor.virtcol.unwrap_or(x_to_col(line, cursor.pos.x, ts) + cursor.coladd))
+        };
+        let newy = cursor.pos.y.saturating_add_signed(dy).clamp(0, buf.linecnt().saturating_sub(1));
         let line = &buf.line(newy);
-        let newx = buf
-            .cursor
-            .virtcol
-            .saturating_add_signed(dx)
-            .clamp(0, line.len().saturating_sub(1));
-
-        if dx != 0 {
-            buf.cursor.virtcol = newx;
-        }
+        // the furthest the cursor can be on the line before going into virtual columns
+        let last = match ve {
+            VirtualEdit::None => line.char_indices().last().map_or(0, |(i, _)| i),
+            VirtualEdit::OneMore | VirtualEdit::All => line.len(),
+        };
+        let (x, past) = if dx != 0 {
+            let target = chars.saturating_add_signed(dx);
+            match line.char_indices().nth(target) {
+                Some((i, _)) => (i, 0),
+                None => (line.len(), target - line.chars().count()),
+            }
+        } else {
+            col_to_x(line, want, ts)
+        };
+        let (newx, coladd) = (x.min(last), if ve == VirtualEdit::All { past } else { 0 });
 
+        buf.cursor.virtcol = if dx != 0 { Some(x_to_col(line, newx, ts) + coladd) } else { Some(want) };
         buf.cursor.pos.x = newx;
         buf.cursor.pos.y = newy;
-        self.fit_ctx_frame(&mut buf.cursor);
+        buf.cursor.coladd = coladd;
+        let linecnt = buf.linecnt();
+        self.fit_ctx_frame(&mut buf.cursor, linecnt);
     }
 
     pub fn set_pos(&mut self, pos: DocPos) {
@@ -303,20 +683,65 @@ impl WindowInner {
         buf.cursor.pos.y = newy;
         let line = &buf.line(newy);
         buf.cursor.pos.x = pos.x.clamp(0, line.len());
-        buf.cursor.virtcol = buf.cursor.pos.x;
-        self.fit_ctx_frame(&mut buf.cursor);
+        buf.cursor.virtcol = None;
+        buf.cursor.coladd = 0;
+        let linecnt = buf.linecnt();
+        self.fit_ctx_frame(&mut buf.cursor, linecnt);
     }
 
-    pub fn fit_ctx_frame(&self, cursor: &mut Cursor) {
+    /// scroll so that the cursor is in view along with `scrolloff` lines around it, as far as the
+    /// buffer of `linecnt` lines goes
+    pub fn fit_ctx_frame(&self, cursor: &mut Cursor, linecnt: usize) {
         let y = cursor.pos.y;
-        let top = cursor.topline;
+        let h = (self.height() as usize).max(1);
+        let so = self.options.scrolloff.min((h - 1) / 2);
+        let lowest = (y + so + 1).saturating_sub(h).min(linecnt.saturating_sub(h)).max((y + 1).saturating_sub(h));
+        let highest = y.saturating_sub(so).max(lowest);
+        cursor.topline = cursor.topline.clamp(lowest, highest);
+    }
+
+    /// the highest top line for a buffer of `linecnt` lines
+    fn max_topline(&self, linecnt: usize) -> usize {
+        if self.scroll.past_end {
+            linecnt.saturating_sub(1)
+        } else {
+            linecnt.saturating_sub(self.height() as usize)
+        }
+    }
+
+    /// scroll by whole pages, keeping two lines of the previous page in view. The cursor is moved
+    /// along if it would go off screen.
+    pub fn scroll_page(&mut self, pages: isize) {
+        let step = (self.height() as usize).saturating_sub(2).max(1) as isize;
+        self.scroll_lines(step * pages);
+    }
+
+    /// scroll down by `lines`, or up if it's negative. The cursor is moved along if it would go
+    /// off screen.
+    pub fn scroll_lines(&mut self, lines: isize) {
         let h = self.height() as usize;
-        cursor.topline = top.clamp(y.saturating_sub(h - 1), y);
+        let mut buf = self.buffer.get_mut();
+        let last = buf.linecnt().saturating_sub(1);
+        let top = if lines >= 0 {
+            (buf.cursor.topline + lines as usize).min(self.max_topline(buf.linecnt()))
+        } else {
+            buf.cursor.topline.saturating_sub(lines.unsigned_abs())
+        };
+        buf.cursor.topline = top;
+        let y = buf.cursor.pos.y.clamp(top, (top + h).saturating_sub(1).min(last).max(top));
+        let cursor = buf.cursor;
+        let want = cursor.virtcol.unwrap_or_else(|| x_to_col(buf.line(cursor.pos.y), cursor.pos.x, self.tabstop));
+        let line = buf.line(y);
+        let last = line.char_indices().last().map_or(0, |(i, _)| i);
+        let x = col_to_x(line, want, self.tabstop).0.min(last);
+        buf.cursor.pos = DocPos { x, y };
+        buf.cursor.virtcol = Some(want);
+        buf.cursor.coladd = 0;
     }
 
-    pub fn center_view(&mut self, cursor: &mut Cursor) {
+    pub fn center_view(&mut self, cursor: &mut Cursor, linecnt: usize) {
         let y = cursor.pos.y;
-        cursor.topline = y.saturating_sub(self.height() as usize / 2);
+        cursor.topline = y.saturating_sub(self.height() as usize / 2).min(self.max_topline(linecnt));
     }
 
     // pub fn insert_char<B: Buffer>(&mut self,
@@ -326,6 +751,160 @@ impl WindowInner {
 mod test {
     use super::*;
 
+    #[test]
+    fn local_options() {
+        let win = Window::new(TermBox::from_ranges(0..40, 0..10), Buffer::new());
+        let mut win = win.get_mut();
+        assert_eq!(win.width(), 36);
+        win.set_option("nonu").unwrap();
+        assert_eq!(win.width(), 36);
+        win.set_option("nornu").unwrap();
+        assert_eq!((win.width(), win.outer_bounds().xlen()), (40, 40));
+        assert!(win.set_option("so=x").is_err());
+        let mut options = win.options.clone();
+        options.relativenumber = true;
+        win.set_options(options);
+        assert_eq!(win.width(), 36);
+    }
+
+    #[test]
+    fn number_width() {
+        let win = Window::new(TermBox::from_ranges(0..40, 0..10), Buffer::new());
+        let mut win = win.get_mut();
+        win.fit_lines(999);
+        assert_eq!(win.width(), 36);
+        win.fit_lines(1000);
+        assert_eq!(win.width(), 35);
+        win.fit_lines(123_456);
+        assert_eq!(win.width(), 33);
+        win.set_option("nuw=10").unwrap();
+        assert_eq!(win.width(), 30);
+        win.fit_lines(5);
+        assert_eq!(win.width(), 30);
+        win.set_option("nuw=1").unwrap();
+        assert_eq!(win.width(), 38);
+    }
+
+    #[test]
+    fn narrow_numbers() {
+        let win = Window::new(TermBox::from_ranges(0..8, 0..5), Buffer::new());
+        let mut win = win.get_mut();
+        win.set_option("nuw=20").unwrap();
+        assert_eq!((win.width(), win.outer_bounds().xlen()), (1, 8));
+        win.set_bounds_outer(TermBox::from_ranges(0..40, 0..5));
+        assert_eq!(win.width(), 20);
+        win.set_size_outer(4, 5);
+        win.fit_lines(usize::MAX);
+        assert_eq!((win.width(), win.outer_bounds().xlen()), (1, 4));
+
+        let mut ctx = crate::testing::editor("abc\n", 12, 6);
+        crate::testing::type_keys(&mut ctx, ":set nuw=20\r:vsplit\r");
+        assert!(crate::testing::screen(&mut ctx).contains('a'));
+    }
+
+    #[test]
+    fn visible_part() {
+        assert_eq!(visible("abc", 5), "abc");
+        assert_eq!(visible("abcdef", 3), "abc");
+        assert_eq!(visible("äöüß", 2), "äö");
+        let long = "x".repeat(1 << 20);
+        assert_eq!(visible(&long, 80).len(), 80);
+    }
+
+    #[test]
+    fn scrolloff() {
+        let buf = Buffer::new();
+        let win = Window::new(TermBox::from_ranges(0..40, 0..10), Arc::clone(&buf));
+        win.get_mut().options.scrolloff = 3;
+        let win = win.get();
+        let mut cursor = buf.get().cursor;
+        let top = |cursor: &mut Cursor, y| {
+            cursor.set_pos(DocPos { x: 0, y });
+            win.fit_ctx_frame(cursor, 100);
+            cursor.topline
+        };
+        assert_eq!(top(&mut cursor, 6), 0);
+        assert_eq!(top(&mut cursor, 7), 1);
+        assert_eq!(top(&mut cursor, 50), 44);
+        assert_eq!(top(&mut cursor, 46), 43);
+        assert_eq!(top(&mut cursor, 99), 90);
+        assert_eq!(top(&mut cursor, 0), 0);
+    }
+
+    #[test]
+    fn scroll_lines() {
+        let text: String = (0..30).map(|i| format!("line {i}\n")).collect();
+        let buf = Buffer::from_string(&text);
+        let win = Window::new(TermBox::from_ranges(0..40, 0..10), Arc::clone(&buf));
+        win.get_mut().scroll_lines(5);
+        assert_eq!(buf.get().cursor.topline, 5);
+        assert_eq!(buf.get().cursor.pos.y, 5);
+        win.get_mut().scroll_lines(-3);
+        assert_eq!(buf.get().cursor.topline, 2);
+        assert_eq!(buf.get().cursor.pos.y, 5);
+        win.get_mut().scroll_lines(-10);
+        assert_eq!(buf.get().cursor.topline, 0);
+        assert_eq!(buf.get().cursor.pos.y, 5);
+    }
+
+    #[test]
+    fn virtualedit() {
+        let buf = Buffer::from_string("aé漢\nab\n");
+        let win = Window::new(TermBox::from_ranges(0..40, 0..10), Arc::clone(&buf));
+        let pos = || {
+            let c = buf.get().cursor;
+            (c.pos.x, c.pos.y, c.coladd)
+        };
+        // by characters, stopping on the last one
+        win.get_mut().move_cursor(2, 0);
+        assert_eq!(pos(), (3, 0, 0));
+        win.get_mut().move_cursor(1, 0);
+        assert_eq!(pos(), (3, 0, 0));
+        win.get_mut().virtualedit = VirtualEdit::OneMore;
+        win.get_mut().move_cursor(5, 0);
+        assert_eq!(pos(), (6, 0, 0));
+        win.get_mut().virtualedit = VirtualEdit::All;
+        win.get_mut().move_cursor(2, 0);
+        assert_eq!(pos(), (6, 0, 2));
+        // 漢 is two columns wide
+        win.get_mut().move_cursor(0, 1);
+        assert_eq!(pos(), (2, 1, 4));
+        // padding only goes in once something is typed
+        assert_eq!(buf.get().to_string(), "aé漢\nab\n");
+        buf.get_mut().insert_str("x");
+        assert_eq!(buf.get().to_string(), "aé漢\nab    x\n");
+        win.get_mut().virtualedit = VirtualEdit::None;
+        win.get_mut().move_cursor(0, -1);
+        assert_eq!(pos(), (3, 0, 0));
+    }
+
+    #[test]
+    fn display_columns() {
+        let buf = Buffer::from_string("\tx\n漢字x\nabcdefghij\n");
+        let win = Window::new(TermBox::from_ranges(0..40, 0..10), Arc::clone(&buf));
+        let pos = || {
+            let buf = buf.get();
+            (buf.cursor.pos, buf.cursor.win_pos(buf.line(buf.cursor.pos.y), 8).x)
+        };
+        win.get_mut().move_cursor(1, 0);
+        assert_eq!(pos(), (DocPos { x: 1, y: 0 }, 8));
+        // past the end of the second line, so it goes to the last character
+        win.get_mut().move_cursor(0, 1);
+        assert_eq!(pos(), (DocPos { x: 6, y: 1 }, 4));
+        // and back to column 8 after it
+        win.get_mut().move_cursor(0, 1);
+        assert_eq!(pos(), (DocPos { x: 8, y: 2 }, 8));
+        // columns inside a wide character or a tab go to its start
+        win.get_mut().move_cursor(-5, 0);
+        win.get_mut().move_cursor(0, -1);
+        assert_eq!(pos(), (DocPos { x: 3, y: 1 }, 2));
+        win.get_mut().move_cursor(0, -1);
+        assert_eq!(pos(), (DocPos { x: 0, y: 0 }, 0));
+        win.get_mut().tabstop = 4;
+        win.get_mut().move_cursor(1, 0);
+        assert_eq!(buf.get().cursor.virtcol, Some(4));
+    }
+
     // fn basic_context() -> Ctx {
     //     let b = BufferInner::from_str("0\n1\n22\n333\n4444\n\nnotrnc\ntruncated line");
     //     let mut ctx = Ctx::new_testing(b);
@@ -1,7 +1,11 @@
 use crate::window::Padding;
 use std::fmt::Write;
+use std::path::Path;
+use unicode_truncate::UnicodeTruncateStr;
+use unicode_width::UnicodeWidthStr;
 use crate::tui::TermPos;
 use crate::window::WindowInner;
+use crate::options::WinOptions;
 use crate::prelude::*;
 
 
@@ -9,71 +13,155 @@ pub trait DispComponent {
     /// write the component
     fn draw(&self, win: &WindowInner, buffer: &BufferInner, ctx: &Ctx);
 
-    /// amount of padding needed left, top, bottom, right
-    fn padding(&self) -> Padding;
+    /// amount of padding needed left, top, bottom, right with the window's `options`, for a buffer
+    /// of `lines` lines
+    fn padding(&self, options: &WinOptions, lines: usize) -> Padding;
 }
 
 pub enum Component {
-    RelLineNumbers,
+    SignColumn,
+    LineNumbers,
     StatusLine,
     Welcome,
     CommandPrefix,
+    HistoryPrefix,
 }
 
 impl DispComponent for Component {
     fn draw(&self, win: &WindowInner, buffer: &BufferInner, ctx: &Ctx) {
         match self {
-            Component::RelLineNumbers => RelLineNumbers.draw(win, buffer, ctx),
+            Component::SignColumn => SignColumn.draw(win, buffer, ctx),
+            Component::LineNumbers => LineNumbers.draw(win, buffer, ctx),
             Component::StatusLine => StatusLine.draw(win, buffer, ctx),
             Component::Welcome => Welcome.draw(win, buffer, ctx),
             Component::CommandPrefix => CommandPrefix.draw(win, buffer, ctx),
+            Component::HistoryPrefix => HistoryPrefix.draw(win, buffer, ctx),
         }
     }
 
-    fn padding(&self) -> Padding {
+    fn padding(&self, options: &WinOptions, lines: usize) -> Padding {
         match self {
-            Component::RelLineNumbers => RelLineNumbers.padding(),
-            Component::StatusLine => StatusLine.padding(),
-            Component::Welcome => Welcome.padding(),
-            Component::CommandPrefix => CommandPrefix.padding(),
+            Component::SignColumn => SignColumn.padding(options, lines),
+            Component::LineNumbers => LineNumbers.padding(options, lines),
+            Component::StatusLine => StatusLine.padding(options, lines),
+            Component::Welcome => Welcome.padding(options, lines),
+            Component::CommandPrefix => CommandPrefix.padding(options, lines),
+            Component::HistoryPrefix => HistoryPrefix.padding(options, lines),
         }
     }
 }
 
-pub struct RelLineNumbers;
-impl DispComponent for RelLineNumbers {
+/// Line numbers as set by the window's `number` and `relativenumber`, which take no room when
+/// both are off. The column is as wide as the largest number needs, but at least `numberwidth`.
+pub struct LineNumbers;
+
+impl LineNumbers {
+    /// columns taken by the numbers of a buffer of `lines` lines, with the space after them
+    pub fn width(options: &WinOptions, lines: usize) -> u32 {
+        if !options.number && !options.relativenumber {
+            return 0;
+        }
+        let digits = lines.max(1).ilog10() + 1;
+        (digits + 1).max(options.numberwidth as u32)
+    }
+}
+
+impl DispComponent for LineNumbers {
     fn draw(&self, win: &WindowInner, buffer: &BufferInner, ctx: &Ctx) {
-        let linecnt = buffer.linecnt();
-        let y = buffer.cursor.win_pos(win).y;
+        let WinOptions { number, relativenumber, .. } = win.options;
+        if !number && !relativenumber {
+            return;
+        }
+        let y = buffer.cursor.pos.y;
+        // as wide as the padding made room for, which may lag behind the buffer by a frame or be
+        // cut short by a narrow window
+        let room = win.inner_bounds().start.x - win.outer_bounds().start.x;
+        let width = Self::width(&win.options, win.lines).min(room) as usize;
+        if width == 0 {
+            return;
+        }
+        let rows = win.rows(buffer, ctx);
         let mut tui = ctx.tui.borrow_mut();
 
-        for l in 0..win.height() {
-            let winbase = win.reltoabs(TermPos { x: 0, y: l });
+        for (l, row) in rows.iter().enumerate() {
+            let winbase = win.reltoabs(TermPos { x: 0, y: l as u32 });
 
             let mut target = tui
-                .refline(winbase.y, (winbase.x - 5)..(winbase.x));
+                .refline(winbase.y, (winbase.x - width as u32)..(winbase.x));
 
-            // write!(target, "X").unwrap();
-            // continue;
             let fg = BasicColor::Green;
             let bg = BasicColor::Default;
-            if l == y {
-                target.set_color(Color { fg, bg, ..Color::new()});
-                write!(target, " {:<3} ", l as usize + buffer.cursor.topline + 1).unwrap();
-            } else if l as usize + buffer.cursor.topline < linecnt {
-                target.set_color(Color { fg, bg, ..Color::new()});
-                write!(target, "{:>4} ", y.abs_diff(l)).unwrap();
+            match row.line {
+                Some(line) if line == y && number && relativenumber => {
+                    target.set_color(Color { fg, bg, ..Color::new()});
+                    write!(target, "{:<1$} ", line + 1, width - 1).unwrap();
+                }
+                Some(line) if relativenumber => {
+                    target.set_color(Color { fg, bg, ..Color::new()});
+                    write!(target, "{:>1$} ", y.abs_diff(line), width - 1).unwrap();
+                }
+                Some(line) => {
+                    target.set_color(Color { fg, bg, ..Color::new()});
+                    write!(target, "{:>1$} ", line + 1, width - 1).unwrap();
+                }
+                None => write!(target, "{:1$}", ' ', width).unwrap(),
+            }
+        }
+    }
+
+    fn padding(&self, options: &WinOptions, lines: usize) -> Padding {
+        let left = Self::width(options, lines);
+        Padding {
+            top: 0,
+            bottom: 0,
+            left,
+            right: 0,
+        }
+    }
+}
+
+/// Diagnostic signs. This must be the leftmost component since it draws at the outer edge of the
+/// window.
+pub struct SignColumn;
+impl DispComponent for SignColumn {
+    fn draw(&self, win: &WindowInner, buffer: &BufferInner, ctx: &Ctx) {
+        let diags = buffer.path().map_or(&[][..], |p| ctx.lsp().diagnostics(p));
+        let buf_signs: Vec<_> = buffer
+            .extmarks()
+            .iter()
+            .filter_map(|(_, m)| Some((buffer.offset_to_pos(m.range.start).y, m.sign?)))
+            .collect();
+        let x = win.outer_bounds().start.x;
+        let rows = win.rows(buffer, ctx);
+        let mut tui = ctx.tui.borrow_mut();
+
+        for (l, row) in rows.iter().enumerate() {
+            let y = win.reltoabs(TermPos { x: 0, y: l as u32 }).y;
+            let mut target = tui.refline(y, x..(x + 2));
+            // most severe diagnostic of the line wins
+            let diag = diags
+                .iter()
+                .filter(|d| Some(d.line) == row.line)
+                .max_by_key(|d| d.severity as u8);
+            // then signs of extmarks, like those of the linter
+            let sign = diag.map(|d| (d.sign(), d.severity.color())).or_else(|| {
+                let line = row.line?;
+                buf_signs.iter().find(|(l, _)| *l == line).map(|(_, s)| *s)
+            });
+            if let Some((sign, color)) = sign {
+                target.set_color(Color { fg: color.fg, bold: true, ..Color::new() });
+                write!(target, "{sign} ").unwrap();
             } else {
-                write!(target, "{:5}", ' ').unwrap();
+                write!(target, "  ").unwrap();
             }
         }
     }
 
-    fn padding(&self) -> Padding {
+    fn padding(&self, _options: &WinOptions, _lines: usize) -> Padding {
         Padding {
             top: 0,
             bottom: 0,
-            left: 5,
+            left: 2,
             right: 0,
         }
     }
@@ -81,22 +169,31 @@ impl DispComponent for RelLineNumbers {
 
 pub struct Welcome;
 impl DispComponent for Welcome {
-    fn draw(&self, win: &WindowInner, _buffer: &BufferInner, ctx: &Ctx) {
-        if !win.dirty {
-            let s = include_str!("../../assets/welcome.txt");
-            let top = (win.height() - s.lines().count() as u32) / 2;
-            let mut target = ctx.tui.borrow_mut();
-            s.lines()
-                .enumerate()
-                .map(|(idx, line)| {
-                    let mut refline = target.refline(top + idx as u32, ..);
-                    write!(refline, "{:^w$}", line, w = win.width() as usize).unwrap();
-                })
-                .last();
+    /// centered in the window until the buffer is edited. Only the text itself is drawn, so the
+    /// rows keep their `~`.
+    fn draw(&self, win: &WindowInner, buffer: &BufferInner, ctx: &Ctx) {
+        if buffer.len() != 0 || buffer.is_modified() {
+            return;
+        }
+        let home = std::env::var_os("HOME");
+        let cwd = ctx.cwd();
+        let recent = crate::recent::at_startup().iter().take(WELCOME_RECENT);
+        let recent: Vec<_> = recent.map(|p| display_path(p, &cwd, home.as_deref().map(Path::new))).collect();
+        let lines = welcome_lines(&recent);
+        let bounds = win.inner_bounds();
+        let top = bounds.start.y + win.height().saturating_sub(lines.len() as u32) / 2;
+        let mut target = ctx.tui.borrow_mut();
+        for (line, y) in lines.iter().zip(top..bounds.end.y) {
+            let w = line.width() as u32;
+            if w > bounds.xlen() {
+                continue;
+            }
+            let x = bounds.start.x + (bounds.xlen() - w) / 2;
+            let _ = target.refline(y, x..x + w).write_str(line);
         }
     }
 
-    fn padding(&self) -> Padding {
+    fn padding(&self, _options: &WinOptions, _lines: usize) -> Padding {
         Padding {
             top: 0,
             bottom: 0,
@@ -106,6 +203,31 @@ impl DispComponent for Welcome {
     }
 }
 
+/// how many recent files the welcome screen lists
+const WELCOME_RECENT: usize = 5;
+
+/// the text of the welcome screen, listing the `recent` files
+fn welcome_lines(recent: &[String]) -> Vec<String> {
+    let mut lines: Vec<String> = include_str!("../../assets/welcome.txt").lines().map(String::from).collect();
+    lines.push(String::new());
+    lines.push(format!("edit v{}", env!("CARGO_PKG_VERSION")));
+    lines.push(String::new());
+    let width = recent.iter().map(|r| r.width()).max().unwrap_or(0).max(24);
+    if !recent.is_empty() {
+        lines.push(format!("{:width$}", "Recent files"));
+        lines.extend(recent.iter().map(|r| format!("{r:width$}")));
+        lines.push(String::new());
+    }
+    let hints = [
+        ("i", "start typing"),
+        (":e {file}", "open a file"),
+        (":set {option}", "change an option"),
+        (":q", "quit"),
+    ];
+    lines.extend(hints.iter().map(|(keys, what)| format!("{keys:<14}{what:<w$}", w = width - 14)));
+    lines
+}
+
 pub struct CommandPrefix;
 impl DispComponent for CommandPrefix {
     fn draw(&self, win: &WindowInner, _buffer: &BufferInner, ctx: &Ctx) {
@@ -113,8 +235,9 @@ impl DispComponent for CommandPrefix {
         let base = win.reltoabs(TermPos { x: 0, y: 0 });
         let lead = match ctx.cmdtype() {
             CommandType::Ex => ':',
-            CommandType::None => ' ',
+            CommandType::None | CommandType::Prompt | CommandType::Confirm => ' ',
             CommandType::Find => '/',
+            CommandType::FindBackward => '?',
         };
 
         let mut target = ctx.tui.borrow_mut();
@@ -127,7 +250,30 @@ impl DispComponent for CommandPrefix {
         );
     }
 
-    fn padding(&self) -> Padding {
+    fn padding(&self, _options: &WinOptions, _lines: usize) -> Padding {
+        Padding {
+            top: 0,
+            bottom: 0,
+            left: 1,
+            right: 0,
+        }
+    }
+}
+
+/// `:` before the lines of the command-line window, see [`Ctx::open_command_window`]
+pub struct HistoryPrefix;
+impl DispComponent for HistoryPrefix {
+    fn draw(&self, win: &WindowInner, buffer: &BufferInner, ctx: &Ctx) {
+        let rows = win.rows(buffer, ctx);
+        let mut tui = ctx.tui.borrow_mut();
+        for (l, row) in rows.iter().enumerate() {
+            let base = win.reltoabs(TermPos { x: 0, y: l as u32 });
+            let lead = if row.line.is_some() { ':' } else { ' ' };
+            tui.put_cell(TermPos { x: base.x - 1, y: base.y }, lead);
+        }
+    }
+
+    fn padding(&self, _options: &WinOptions, _lines: usize) -> Padding {
         Padding {
             top: 0,
             bottom: 0,
@@ -139,7 +285,7 @@ impl DispComponent for CommandPrefix {
 
 pub struct StatusLine;
 impl DispComponent for StatusLine {
-    fn padding(&self) -> Padding {
+    fn padding(&self, _options: &WinOptions, _lines: usize) -> Padding {
         Padding {
             top: 1,
             bottom: 0,
@@ -152,11 +298,31 @@ impl DispComponent for StatusLine {
         let base = win.reltoabs(TermPos { x: 0, y: 0 });
 
         let (color, mode_str) = match ctx.mode {
+            crate::Mode::Normal if ctx.pending.operator() => (
+                Color {
+                    fg: BasicColor::Black,
+                    bg: BasicColor::Cyan,
+                    bold: true,
+                    italic: false,
+                },
+                " OP PENDING ",
+            ),
+            // a command typed with `Ctrl-o` in insert mode
+            crate::Mode::Normal if ctx.insert_once => (
+                Color {
+                    fg: BasicColor::Black,
+                    bg: BasicColor::Yellow,
+                    bold: true,
+                    italic: false,
+                },
+                " (INSERT) ",
+            ),
             crate::Mode::Normal => (
                 Color {
                     fg: BasicColor::Black,
                     bg: BasicColor::Blue,
                     bold: true,
+                    italic: false,
                 },
                 " NORMAL ",
             ),
@@ -165,6 +331,7 @@ impl DispComponent for StatusLine {
                     fg: BasicColor::Black,
                     bg: BasicColor::Yellow,
                     bold: true,
+                    italic: false,
                 },
                 " INSERT ",
             ),
@@ -173,22 +340,120 @@ impl DispComponent for StatusLine {
                     fg: BasicColor::Black,
                     bg: BasicColor::Green,
                     bold: true,
+                    italic: false,
                 },
                 " COMMAND ",
             ),
+            crate::Mode::Terminal => (
+                Color {
+                    fg: BasicColor::Black,
+                    bg: BasicColor::Magenta,
+                    bold: true,
+                    italic: false,
+                },
+                " TERMINAL ",
+            ),
         };
         let mut target = ctx.tui.borrow_mut();
         let w = target.dim().0;
         let y = base.y - 1;
         let mut refline = target.refline(y, ..).colored(color);
-        write!(refline, "{mode_str}").unwrap();
-        let buf = ctx.focused_buf();
-        let name = buf.name();
+        let _ = write!(refline, "{mode_str}");
+        // `win` is the command line's, the status is that of the focused window and its buffer
+        let shown = ctx.focused_win();
+        let buf = shown.buffer.get();
+        let pos = scroll_position(buf.cursor.topline, shown.height() as usize, buf.linecnt());
+        let name = match buf.path() {
+            Some(path) => display_path(path, &ctx.cwd(), std::env::var_os("HOME").as_deref().map(Path::new)),
+            None => buf.name().to_owned(),
+        };
+        let pos = match crate::progress::segment(&ctx.progress, ctx.spinner) {
+            Some(tasks) => format!("{tasks}  {pos}"),
+            None => pos,
+        };
+        // the mode and position stay, the name gets what's left between them
+        let name = truncate_path(&name, (w as usize).saturating_sub(mode_str.len() + pos.width() + 3));
         refline.set_color(Color {
             bg: BasicColor::Black,
             ..Color::default()
         });
-        write!(refline, " {name}").unwrap();
+        let _ = write!(refline, " {name}");
+        let used = mode_str.len() + 1 + name.width();
+        let pad = (w as usize).saturating_sub(used + pos.width() + 1);
+        let _ = write!(refline, "{:pad$}{pos} ", "");
         let _ = write!(refline, "{:x$}", "", x = w as usize);
     }
 }
+
+/// `path` relative to `cwd` if it's inside it, otherwise with the home directory as `~`
+fn display_path(path: &Path, cwd: &Path, home: Option<&Path>) -> String {
+    if let Ok(rel) = path.strip_prefix(cwd) {
+        return rel.to_string_lossy().into_owned();
+    }
+    match home.and_then(|h| path.strip_prefix(h).ok()) {
+        Some(rel) => format!("~/{}", rel.to_string_lossy()),
+        None => path.to_string_lossy().into_owned(),
+    }
+}
+
+/// shorten `path` to `width` columns. Directories become their first letter starting from the
+/// outermost, then the start is cut off.
+fn truncate_path(path: &str, width: usize) -> String {
+    if path.width() <= width {
+        return path.into();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut parts: Vec<String> = path.split('/').map(String::from).collect();
+    for i in 0..parts.len() - 1 {
+        // `.config` would just be `.`
+        let keep = if parts[i].starts_with('.') { 2 } else { 1 };
+        parts[i] = parts[i].chars().take(keep).collect();
+        let short = parts.join("/");
+        if short.width() <= width {
+            return short;
+        }
+    }
+    let short = parts.join("/");
+    let (tail, _) = short.unicode_truncate_start(width - 1);
+    format!("…{tail}")
+}
+
+/// how far through the buffer the window is, like vim's ruler
+fn scroll_position(topline: usize, height: usize, linecnt: usize) -> String {
+    let at_end = topline + height >= linecnt;
+    match (topline == 0, at_end) {
+        (true, true) => "All".into(),
+        (true, false) => "Top".into(),
+        (false, true) => "Bot".into(),
+        (false, false) => format!("{}%", topline * 100 / (linecnt - height)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn welcome_screen() {
+        let lines = welcome_lines(&[]);
+        assert!(lines.iter().all(|l| !l.contains("Recent")));
+        let lines = welcome_lines(&["~/notes.txt".into()]);
+        let at = lines.iter().position(|l| l.trim() == "Recent files").unwrap();
+        assert_eq!(lines[at + 1].trim(), "~/notes.txt");
+        // the lists line up
+        assert_eq!(lines[at].len(), lines.last().unwrap().len());
+    }
+
+    #[test]
+    fn truncated_paths() {
+        let path = "~/.config/editor/plugins/example.scm";
+        assert_eq!(truncate_path(path, 100), path);
+        assert_eq!(truncate_path(path, 30), "~/.c/e/plugins/example.scm");
+        assert_eq!(truncate_path(path, 20), "~/.c/e/p/example.scm");
+        assert_eq!(truncate_path(path, 8), "…ple.scm");
+        assert_eq!(truncate_path("日本語/ファイル.txt", 15), "日/ファイル.txt");
+        assert_eq!(truncate_path(path, 0), "");
+    }
+}
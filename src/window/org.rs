@@ -1,12 +1,14 @@
 //! Window organization and heirarchy
 //!
 
+use std::path::PathBuf;
 use std::sync::Arc;
 
-use crate::{render::Ctx, tui::TermBox, utils::unit_err, TermGrid};
+use crate::{render::{BufId, Ctx}, tui::{Owner, TermBox, TermSz}, utils::unit_err, TermGrid};
 
 use super::Window;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Arrange {
     Horizontal,
     Vertical,
@@ -23,11 +25,47 @@ pub enum NodeTy {
         first: Box<Node>,
         second: Box<Node>,
         arrange: Arrange,
+        /// rows or columns `second` keeps as far as `first` leaves room, half of them if `None`
+        len: Option<u32>,
     }
 }
 
 unit_err!(DoesNotFit: "not enough room");
 
+/// the shape of the window tree without the windows, to save it and make it again later
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    Leaf(LayoutBuf),
+    Split {
+        first: Box<Layout>,
+        second: Box<Layout>,
+        arrange: Arrange,
+    },
+}
+
+/// what a window of a [`Layout`] shows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LayoutBuf {
+    /// the buffer of a file, which is opened again if it was closed
+    Path(PathBuf),
+    /// a buffer without a file
+    Id(BufId),
+}
+
+impl Layout {
+    /// the windows from left to right and top to bottom, like [`Node::windows`]
+    pub fn leaves(&self) -> Vec<&LayoutBuf> {
+        match self {
+            Layout::Leaf(buf) => vec![buf],
+            Layout::Split { first, second, .. } => {
+                let mut out = first.leaves();
+                out.extend(second.leaves());
+                out
+            }
+        }
+    }
+}
+
 impl Node {
     pub fn merge(&mut self, _other: Self, _arrange: Arrange) {
         todo!()
@@ -38,32 +76,159 @@ impl Node {
             return;
         }
         self.bounds = bounds;
+        self.layout();
+    }
+
+    /// put `new` next to `target`, splitting the space `target` had between them
+    pub fn split(&mut self, target: &Arc<Window>, new: Arc<Window>, arrange: Arrange) -> Result<(), DoesNotFit> {
+        self.split_inner(target, new, arrange, None)
+    }
+
+    /// put `new` next to `target` with `len` rows or columns of text, or as many as `target`
+    /// leaves room for
+    pub fn split_sized(&mut self, target: &Arc<Window>, new: Arc<Window>, arrange: Arrange, len: u32) -> Result<(), DoesNotFit> {
+        self.split_inner(target, new, arrange, Some(len))
+    }
+
+    fn split_inner(&mut self, target: &Arc<Window>, new: Arc<Window>, arrange: Arrange, sized: Option<u32>) -> Result<(), DoesNotFit> {
+        let min = match arrange {
+            Arrange::Horizontal => new.get().outer_bounds().xlen() - new.get().width(),
+            Arrange::Vertical => new.get().outer_bounds().ylen() - new.get().height(),
+        };
+        let node = self.find(target).expect("target is in the tree");
+        let len = match arrange {
+            Arrange::Horizontal => node.bounds.xlen(),
+            Arrange::Vertical => node.bounds.ylen(),
+        };
+        if len / 2 <= min {
+            return Err(DoesNotFit);
+        }
+        let bounds = node.bounds;
+        let old = std::mem::replace(&mut node.ty, NodeTy::Terminal(Arc::clone(&new)));
+        node.ty = NodeTy::Nonterminal {
+            first: Box::new(Node { bounds, ty: old }),
+            second: Box::new(Node { bounds, ty: NodeTy::Terminal(new) }),
+            arrange,
+            len: sized.map(|len| len + min),
+        };
+        node.layout();
+        Ok(())
+    }
+
+    /// remove `target`, giving its space to what was split off with it. Returns false if it isn't
+    /// in the tree or it's the only window.
+    pub fn close(&mut self, target: &Arc<Window>) -> bool {
+        let NodeTy::Nonterminal { first, second, .. } = &mut self.ty else {
+            return false;
+        };
+        let is_target = |node: &Node| matches!(&node.ty, NodeTy::Terminal(w) if Arc::ptr_eq(w, target));
+        let keep_first = is_target(second);
+        if !keep_first && !is_target(first) {
+            return first.close(target) || second.close(target);
+        }
+        let NodeTy::Nonterminal { first, second, .. } = std::mem::replace(&mut self.ty, NodeTy::Terminal(Arc::clone(target))) else {
+            unreachable!()
+        };
+        self.ty = if keep_first { first.ty } else { second.ty };
+        self.layout();
+        true
+    }
+
+    fn find(&mut self, target: &Arc<Window>) -> Option<&mut Node> {
+        if let NodeTy::Terminal(w) = &self.ty {
+            return Arc::ptr_eq(w, target).then_some(self);
+        }
+        let NodeTy::Nonterminal { first, second, .. } = &mut self.ty else {
+            unreachable!()
+        };
+        first.find(target).or_else(|| second.find(target))
+    }
+
+    /// the smallest bounds that all the windows fit in. Splits are halved, so they need twice the
+    /// larger of their two sides, which is also enough for one with a fixed size.
+    pub fn min_size(&self) -> TermSz {
+        match &self.ty {
+            NodeTy::Terminal(w) => w.get().min_size(),
+            NodeTy::Nonterminal { first, second, arrange, .. } => {
+                let (a, b) = (first.min_size(), second.min_size());
+                match arrange {
+                    Arrange::Horizontal => TermSz::new(2 * a.w.max(b.w), a.h.max(b.h)),
+                    Arrange::Vertical => TermSz::new(a.w.max(b.w), 2 * a.h.max(b.h)),
+                }
+            }
+        }
+    }
+
+    /// every window, left to right and top to bottom
+    pub fn windows(&self) -> Vec<Arc<Window>> {
+        match &self.ty {
+            NodeTy::Terminal(w) => vec![Arc::clone(w)],
+            NodeTy::Nonterminal { first, second, .. } => {
+                let mut out = first.windows();
+                out.extend(second.windows());
+                out
+            }
+        }
+    }
+
+    /// the shape of the tree and what each window shows
+    pub fn shape(&self) -> Layout {
+        match &self.ty {
+            NodeTy::Terminal(w) => {
+                let buf = Arc::clone(&w.get().buffer);
+                let path = buf.get().path().map(PathBuf::from);
+                Layout::Leaf(path.map_or(LayoutBuf::Id(buf.id()), LayoutBuf::Path))
+            }
+            NodeTy::Nonterminal { first, second, arrange, .. } => Layout::Split {
+                first: Box::new(first.shape()),
+                second: Box::new(second.shape()),
+                arrange: *arrange,
+            },
+        }
+    }
+
+    /// set the bounds of the children from our own
+    fn layout(&mut self) {
+        let bounds = self.bounds;
         match &mut self.ty {
             NodeTy::Terminal(win) => win.get_mut().set_bounds_outer(bounds),
-            NodeTy::Nonterminal { first, second, arrange } => {
+            NodeTy::Nonterminal { first, second, arrange, len } => {
+                let first_min = first.min_size();
                 let (b1, b2) = match arrange {
                     Arrange::Horizontal => {
                         let start = bounds.xrng().start;
-                        let mid = (bounds.xlen() + start) / 2;
+                        let mid = match len {
+                            Some(len) => bounds.xrng().end - (*len).min(bounds.xlen().saturating_sub(first_min.w)),
+                            None => start + bounds.xlen() / 2,
+                        };
                         let end = bounds.xrng().end;
                         (TermBox::from_ranges(start..mid, bounds.yrng()), TermBox::from_ranges(mid..end, bounds.yrng()))
                     },
                     Arrange::Vertical => {
                         let start = bounds.yrng().start;
-                        let mid = (bounds.ylen() + start) / 2;
+                        let mid = match len {
+                            Some(len) => bounds.yrng().end - (*len).min(bounds.ylen().saturating_sub(first_min.h)),
+                            None => start + bounds.ylen() / 2,
+                        };
                         let end = bounds.yrng().end;
                         (TermBox::from_ranges(bounds.xrng(), start..mid), TermBox::from_ranges(bounds.xrng(), mid..end))
                     },
                 };
-                first.fit(b1);
-                second.fit(b2);
+                first.bounds = b1;
+                first.layout();
+                second.bounds = b2;
+                second.layout();
             },
         }
     }
 
     pub fn draw(&self, ctx: &Ctx) {
         match &self.ty {
-            NodeTy::Terminal(w) => w.get().draw(ctx),
+            NodeTy::Terminal(w) => {
+                let w = w.get();
+                ctx.tui.borrow_mut().set_painter(Some(Owner::Window(w.id())));
+                w.draw(ctx);
+            }
             NodeTy::Nonterminal { first, second, .. } => {
                 // draw back to front
                 second.draw(ctx);
@@ -0,0 +1,153 @@
+use std::fmt::Write;
+
+use unicode_truncate::UnicodeTruncateStr;
+
+use crate::prelude::*;
+use crate::tui::TermBox;
+use crate::window::WindowInner;
+
+const MAX_WIDTH: usize = 80;
+const MAX_HEIGHT: usize = 15;
+
+/// A floating window drawn over everything else. It belongs to the cursor position it was opened
+/// at and should be closed once the cursor moves away.
+pub struct Popup {
+    anchor: DocPos,
+    bounds: TermBox,
+    lines: Vec<String>,
+}
+
+impl Popup {
+    /// open a popup showing `text` next to the cursor of `buf`, which `win` shows. It is placed
+    /// below the cursor if there is room and above it otherwise.
+    pub fn new(win: &WindowInner, buf: &BufferInner, text: &str) -> Self {
+        let cursor = &buf.cursor;
+        let area = win.outer_bounds();
+        let max_w = MAX_WIDTH.min(area.xlen() as usize).max(1);
+        let lines = wrap(text, max_w, area);
+        let w = lines
+            .iter()
+            .map(|l| unicode_width::UnicodeWidthStr::width(l.as_str()))
+            .max()
+            .unwrap_or(0)
+            .clamp(1, max_w) as u32;
+        let h = lines.len() as u32;
+
+        let pos = cursor.term_pos(win, buf.line(cursor.pos.y));
+        let y = if pos.y + 1 + h <= area.end.y {
+            pos.y + 1
+        } else {
+            pos.y.saturating_sub(h).max(area.start.y)
+        };
+        let x = pos.x.min(area.end.x - w).max(area.start.x);
+        Popup {
+            anchor: cursor.pos,
+            bounds: TermBox::from_ranges(x..(x + w), y..(y + h)),
+            lines,
+        }
+    }
+
+    /// open a panel showing `text` across the bottom of `win`
+    pub fn panel(win: &WindowInner, cursor: &Cursor, text: &str) -> Self {
+        let area = win.outer_bounds();
+        let lines = wrap(text, area.xlen().max(1) as usize, area);
+        let h = lines.len() as u32;
+        Popup {
+            anchor: cursor.pos,
+            bounds: TermBox::from_ranges(area.xrng(), (area.end.y - h)..area.end.y),
+            lines,
+        }
+    }
+
+    /// whether the popup should be closed because the cursor moved
+    pub fn is_stale(&self, cursor: &Cursor) -> bool {
+        self.anchor != cursor.pos
+    }
+
+    pub fn bounds(&self) -> TermBox {
+        self.bounds
+    }
+
+    pub fn draw(&self, ctx: &Ctx) {
+        let color = Color {
+            fg: BasicColor::White,
+            bg: BasicColor::Gray,
+            bold: false,
+            italic: false,
+        };
+        let mut tui = ctx.tui.borrow_mut();
+        let w = self.bounds.xlen() as usize;
+        for (line, y) in self.lines.iter().zip(self.bounds.yrng()) {
+            let mut target = tui.refline(y, self.bounds.xrng()).colored(color);
+            let (line, lw) = line.unicode_truncate(w);
+            let _ = write!(target, "{line}{:pad$}", "", pad = w - lw);
+        }
+    }
+}
+
+/// the lines of `text` wrapped to `width`, as many as fit in `area`
+fn wrap(text: &str, width: usize, area: TermBox) -> Vec<String> {
+    text.lines()
+        .flat_map(|l| textwrap::wrap(l, width).into_iter().map(|l| l.into_owned()))
+        .take(MAX_HEIGHT.min(area.ylen() as usize))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::Buffer;
+    use crate::window::Window;
+    use std::sync::Arc;
+
+    fn popup_at(y: usize, text: &str) -> Popup {
+        let buf = Buffer::new();
+        buf.get_mut().insert_str(&"\n".repeat(40));
+        let win = Window::new(TermBox::from_ranges(0..40, 0..20), Arc::clone(&buf));
+        let win = win.get();
+        let mut buf = buf.get_mut();
+        buf.cursor.set_pos(DocPos { x: 0, y });
+        win.fit_ctx_frame(&mut buf.cursor, 41);
+        Popup::new(&win, &buf, text)
+    }
+
+    #[test]
+    fn placed_below_cursor() {
+        let p = popup_at(2, "ab\nabcd");
+        assert!(p.bounds().start.y > 2);
+        assert_eq!((p.bounds().xlen(), p.bounds().ylen()), (4, 2));
+    }
+
+    #[test]
+    fn panel_at_bottom() {
+        let buf = Buffer::new();
+        let win = Window::new(TermBox::from_ranges(0..40, 0..20), Arc::clone(&buf));
+        let p = Popup::panel(&win.get(), &buf.get().cursor, "a\nb");
+        assert_eq!(p.bounds(), TermBox::from_ranges(0..40, 18..20));
+    }
+
+    #[test]
+    fn flips_above_near_bottom() {
+        let p = popup_at(19, "a\nb\nc");
+        assert!(p.bounds().end.y <= 19);
+        assert_eq!(p.bounds().ylen(), 3);
+    }
+
+    #[test]
+    fn wraps_to_window() {
+        let p = popup_at(0, &"word ".repeat(30));
+        assert!(p.bounds().end.x <= 40);
+        assert!(p.bounds().ylen() > 1);
+    }
+
+    #[test]
+    fn stale_on_movement() {
+        let buf = Buffer::new();
+        let win = Window::new(TermBox::from_ranges(0..40, 0..20), Arc::clone(&buf));
+        let mut cursor = buf.get().cursor;
+        let p = Popup::new(&win.get(), &buf.get(), "doc");
+        assert!(!p.is_stale(&cursor));
+        cursor.set_pos(DocPos { x: 1, y: 0 });
+        assert!(p.is_stale(&cursor));
+    }
+}
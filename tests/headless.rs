@@ -0,0 +1,43 @@
+//! Runs the editor with `--headless` on files and checks what it leaves behind.
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// a directory of its own for test `name`, so that the recent files list goes there too
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("edit-headless-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn edit(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_edit"))
+        .arg("--headless")
+        .args(args)
+        .env("XDG_STATE_HOME", dir)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn commands() {
+    let dir = temp_dir("commands");
+    let path = dir.join("file");
+    std::fs::write(&path, "a\nb\na\n").unwrap();
+    let out = edit(&dir, &["-c", "%s/a/c/", "-c", "w", path.to_str().unwrap()]);
+    assert!(out.status.success(), "{}", String::from_utf8_lossy(&out.stderr));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "c\nb\nc\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_command() {
+    let dir = temp_dir("failed");
+    let path = dir.join("file");
+    std::fs::write(&path, "a\n").unwrap();
+    // the commands after it still run, but the exit status tells
+    let out = edit(&dir, &["-c", "nosuchcommand", "-c", "s/a/b/", "-c", "w", path.to_str().unwrap()]);
+    assert!(!out.status.success());
+    assert!(!out.stderr.is_empty());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "b\n");
+    std::fs::remove_dir_all(&dir).unwrap();
+}